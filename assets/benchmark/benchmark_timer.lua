@@ -0,0 +1,125 @@
+-- Timer service benchmark.
+--
+--   moon_rs benchmark_timer.lua [wheel|btree] [shards]
+--
+-- Every worker arms `ntimer` one-shot timers with mixed delays plus the same
+-- number of long timers that are cancelled straight away, then waits for the
+-- short ones to fire. Reports insert+cancel throughput, drain time and firing
+-- lateness (actual fire clock minus scheduled clock).
+--
+-- "btree" is an ordered-map store run by the same sharded timer tasks, so the
+-- two backends compare the stores only. It is not the single-task timer the
+-- wheel replaced, which could not cancel timers.
+if _G["__init__"] then
+    local arg = ...
+    return {
+        timer = arg[1] or "wheel",
+        timer_shards = tonumber(arg[2]) or 1,
+    }
+end
+
+local moon = require("moon")
+
+local conf = ...
+
+local nworker = 8
+local ntimer = 50000
+local max_delay = 2000
+
+if conf.worker then
+    local command = {}
+
+    command.RUN = function()
+        local fired = 0
+        local late_sum = 0
+        local late_max = 0
+        local done
+
+        local st = moon.clock()
+        for i = 1, ntimer do
+            local delay = 1 + (i * 7919) % max_delay
+            local expect = st + delay / 1000
+            moon.timeout(delay, function()
+                local late = (moon.clock() - expect) * 1000
+                late_sum = late_sum + late
+                if late > late_max then
+                    late_max = late
+                end
+                fired = fired + 1
+                if fired == ntimer then
+                    done()
+                end
+            end)
+            moon.remove_timer(moon.timeout(60000 + delay, function()
+                error("cancelled timer fired")
+            end))
+        end
+        local insert_cost = moon.clock() - st
+
+        local co = coroutine.running()
+        done = function()
+            coroutine.resume(co)
+        end
+        coroutine.yield()
+
+        return insert_cost, moon.clock() - st, late_sum / ntimer, late_max
+    end
+
+    moon.dispatch('lua', function(sender, session, cmd, ...)
+        local f = command[cmd]
+        if f then
+            moon.response('lua', sender, session, f(...))
+        else
+            error(string.format("Unknown command %s", tostring(cmd)))
+        end
+    end)
+    return
+end
+
+moon.async(function()
+    local workers = {}
+    for i = 1, nworker do
+        workers[i] = moon.new_service({
+            name = "timer_worker" .. i,
+            source = "benchmark_timer.lua",
+            worker = true,
+        })
+    end
+
+    local result = {}
+    local finished = 0
+    local st = moon.clock()
+    for i, id in ipairs(workers) do
+        moon.async(function()
+            result[i] = { moon.call('lua', id, "RUN") }
+            finished = finished + 1
+        end)
+    end
+    while finished < nworker do
+        moon.sleep(100)
+    end
+    local total = moon.clock() - st
+
+    local insert_cost, late_avg, late_max = 0, 0, 0
+    for _, r in ipairs(result) do
+        insert_cost = math.max(insert_cost, r[1])
+        late_avg = late_avg + r[3] / nworker
+        late_max = math.max(late_max, r[4])
+    end
+
+    local ops = nworker * ntimer * 3 -- insert, insert, cancel
+    print(string.format("timers: %d x %d, delays 1..%dms", nworker, ntimer, max_delay))
+    print(string.format("insert+cancel %.03fs, %d op/s", insert_cost, math.floor(ops / insert_cost)))
+    print(string.format("drain %.03fs, lateness avg %.02fms max %.02fms", total, late_avg, late_max))
+    print("pending timers", moon.server_stats("timer.count"))
+
+    for _, id in ipairs(workers) do
+        moon.kill(id)
+    end
+    moon.quit()
+    moon.exit(0)
+end)
+
+moon.shutdown(function()
+    moon.quit()
+end)
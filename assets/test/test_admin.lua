@@ -0,0 +1,83 @@
+---
+--- test_admin.lua — admin console (`admin_addr` + `admin_token`), driven over
+--- TCP like a telnet session.
+---
+--- Run: moon_rs assets/test/test_admin.lua
+---
+
+if _G["__init__"] then
+    return { admin_addr = "127.0.0.1:29100", admin_token = "secret" }
+end
+
+local moon = require "moon"
+local socket = require "moon.socket"
+
+local conf = ...
+
+if conf.role then
+    local hotfix = require "hotfix"
+
+    local sources = {
+        counter = "local n = 0 return { get = function() n = n + 1 return n end }",
+        counter_v2 = "local n = 0 return { get = function() n = n + 10 return n end }",
+    }
+    hotfix.addsearcher(function(name)
+        return sources[name] and load(sources[name], "=counter")
+    end)
+    local counter = hotfix.require("counter")
+
+    moon.dispatch("lua", function(sender, session)
+        moon.response("lua", sender, session, counter.get())
+    end)
+    return
+end
+
+moon.async(function()
+    local worker = moon.new_service({ name = "worker", source = "test_admin.lua", role = "worker", unique = true })
+
+    local fd = assert(socket.connect("127.0.0.1:29100"))
+    socket.write(fd, "auth wrong\n")
+    assert(socket.read(fd, "\n") == "error: authenticate with 'auth <token>'")
+    socket.close(fd)
+
+    fd = assert(socket.connect("127.0.0.1:29100"))
+    local function command(line)
+        socket.write(fd, line .. "\n")
+        return assert(socket.read(fd, "\n"))
+    end
+
+    assert(command("auth secret") == "ok")
+    assert(command("services"):find("^id "))
+    local line
+    repeat
+        line = assert(socket.read(fd, "\n"))
+    until line:find("worker", 1, true)
+    assert(line:find("^" .. worker .. " "), "the newest service comes last")
+    print("PASS: auth")
+
+    assert(command("debug worker ping") == "pong")
+    assert(command("debug " .. worker .. " mem"):match("^[%d%.]+$"))
+    assert(command("eval worker 1 + 2") == "3")
+    assert(command("eval worker moon.name") == "worker")
+    assert(command("eval worker moon.sleep(10) return 'slept'") == "slept")
+    assert(command("eval worker admin_set = 1") == "ok")
+    assert(command("eval worker admin_set") == "1")
+    assert(command("eval worker error('boom')"):find("^error: .*boom"))
+    assert(command("eval nobody 1") == "error: service 'nobody' not found")
+    print("PASS: eval")
+
+    assert(command("loglevel worker DBUG") == "ok")
+    assert(command("loglevel worker LOUD") == "error: unknown log level 'LOUD'")
+    assert(moon.call("lua", worker, "get") == 1)
+    assert(command("hotfix worker counter counter_v2") == "ok")
+    assert(moon.call("lua", worker, "get") == 11, "hotfixed function keeps its upvalue")
+    assert(command("hotfix worker nosuch"):find("^error: .*nosuch"))
+    print("PASS: hotfix")
+
+    assert(command("kill worker") == "ok")
+    moon.sleep(100)
+    assert(command("service worker") == "error: service 'worker' not found")
+    socket.close(fd)
+    print("PASS: admin")
+    moon.exit(0)
+end)
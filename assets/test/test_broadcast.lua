@@ -0,0 +1,57 @@
+---
+--- test_broadcast.lua — one message to many services (`moon.broadcast`).
+---
+--- Run: moon_rs assets/test/test_broadcast.lua
+---
+
+local moon = require "moon"
+
+local conf = ...
+
+if conf.role then
+    local received = {}
+    moon.dispatch("lua", function(sender, session, cmd, ...)
+        if cmd == "received" then
+            moon.response("lua", sender, session, received)
+            received = {}
+        else
+            table.insert(received, table.concat({ cmd, ... }, ":"))
+        end
+    end)
+    moon.raw_dispatch("text", function(m)
+        table.insert(received, "text:" .. moon.decode(m, "Z"))
+    end)
+    return
+end
+
+moon.async(function()
+    local ids = {}
+    for i = 1, 5 do
+        ids[i] = moon.new_service({ name = "receiver" .. i, source = "test_broadcast.lua", role = "receiver" })
+    end
+
+    print("--- lua protocol ---")
+    assert(moon.broadcast("lua", ids, "hello", 1, 2) == 5)
+    for _, id in ipairs(ids) do
+        local r = moon.call("lua", id, "received")
+        assert(#r == 1 and r[1] == "hello:1:2", tostring(r[1]))
+    end
+    print("PASS: lua")
+
+    print("--- raw text protocol ---")
+    assert(moon.broadcast("text", ids, "plain") == 5)
+    local r = moon.call("lua", ids[3], "received")
+    assert(#r == 1 and r[1] == "text:plain")
+    print("PASS: text")
+
+    print("--- dead receivers are skipped ---")
+    moon.kill(ids[5])
+    moon.sleep(50)
+    assert(moon.broadcast("lua", ids, "again") == 4)
+    assert(moon.broadcast("lua", {}, "nobody") == 0)
+    assert(not pcall(moon.broadcast, "lua", { "x" }, "bad"))
+    print("PASS: skip")
+
+    print("\n=== All broadcast tests passed! ===")
+    moon.exit(0)
+end)
@@ -0,0 +1,155 @@
+---
+--- test_debugger.lua — DAP debugger (`debug_addr`), driven over TCP like an
+--- editor would.
+---
+--- Run: moon_rs assets/test/test_debugger.lua
+---
+
+if _G["__init__"] then
+    return { debug_addr = "127.0.0.1:29229" }
+end
+
+local moon = require "moon"
+local socket = require "moon.socket"
+local json = require "json"
+local fs = require "fs"
+
+local conf = ...
+
+if conf.role then
+    local state = { count = 0 }
+
+    local function bump(n)
+        local doubled = n * 2 -- line 23: breakpoint
+        state.count = state.count + doubled
+        return state.count
+    end
+
+    moon.dispatch("lua", function(sender, session, n)
+        local total = bump(n)
+        moon.response("lua", sender, session, total)
+    end)
+    return
+end
+
+local BREAK_LINE = 23
+
+moon.async(function()
+    local worker = moon.new_service({ name = "worker", source = "test_debugger.lua", role = "worker" })
+    local fd = assert(socket.connect("127.0.0.1:29229"))
+
+    local seq = 0
+    local events = {}
+
+    local function read_message()
+        local head = assert(socket.read(fd, "\r\n\r\n"))
+        local n = tonumber(head:match("Content%-Length: (%d+)"))
+        return json.decode(assert(socket.read(fd, n)))
+    end
+
+    local function request(command, arguments)
+        seq = seq + 1
+        local body = json.encode({ seq = seq, type = "request", command = command, arguments = arguments })
+        socket.write(fd, "Content-Length: " .. #body .. "\r\n\r\n" .. body)
+        while true do
+            local m = read_message()
+            if m.type == "response" and m.request_seq == seq then
+                return m
+            end
+            events[#events + 1] = m
+        end
+    end
+
+    local function event(name)
+        while true do
+            local m = table.remove(events, 1) or read_message()
+            if m.type == "event" and m.event == name then
+                return m.body
+            end
+        end
+    end
+
+    local function variables(reference)
+        local res = {}
+        for _, v in ipairs(request("variables", { variablesReference = reference }).body.variables) do
+            res[v.name] = v
+        end
+        return res
+    end
+
+    local function top_frame()
+        local frames = request("stackTrace", { threadId = worker }).body.stackFrames
+        local scopes = request("scopes", { frameId = frames[1].id }).body.scopes
+        return frames[1], variables(scopes[1].variablesReference), variables(scopes[2].variablesReference)
+    end
+
+    assert(request("initialize", { adapterID = "moon" }).success)
+    event("initialized")
+    local path = fs.abspath("test_debugger.lua")
+    local res = request("setBreakpoints", { source = { path = path }, breakpoints = { { line = BREAK_LINE } } })
+    assert(res.body.breakpoints[1].verified)
+    assert(not request("attach", { service = "nobody" }).success)
+    assert(request("attach", { service = worker }).success)
+    assert(request("configurationDone").success)
+    assert(request("threads").body.threads[1].id == worker)
+
+    local result
+    moon.async(function()
+        result = moon.call("lua", worker, 5)
+    end)
+
+    assert(event("stopped").reason == "breakpoint")
+    assert(not result, "the dispatch is held")
+    assert(moon.call("debug", moon.id, "ping") == "pong", "other services keep running")
+    local frame, locals, upvalues = top_frame()
+    assert(frame.name == "bump" and frame.line == BREAK_LINE, frame.name .. ":" .. frame.line)
+    assert(frame.source.path:find("test_debugger.lua", 1, true))
+    assert(locals.n.value == "5" and not locals.doubled)
+    local fields = variables(upvalues.state.variablesReference)
+    assert(fields.count.value == "0")
+    print("PASS: breakpoint")
+
+    assert(request("next", { threadId = worker }).success)
+    assert(event("stopped").reason == "step")
+    frame, locals = top_frame()
+    assert(frame.line == BREAK_LINE + 1 and locals.doubled.value == "10")
+
+    assert(request("stepOut", { threadId = worker }).success)
+    assert(event("stopped").reason == "step")
+    frame, locals = top_frame()
+    assert(frame.line == BREAK_LINE + 7 and locals.total.value == "10", frame.line)
+    print("PASS: step")
+
+    assert(request("continue", { threadId = worker }).success)
+    while not result do
+        moon.sleep(10)
+    end
+    assert(result == 10)
+    assert(not request("stackTrace", { threadId = worker }).success, "not paused")
+
+    request("setBreakpoints", { source = { path = path }, breakpoints = {} })
+    assert(request("pause", { threadId = worker }).success)
+    result = nil
+    moon.async(function()
+        result = moon.call("lua", worker, 1)
+    end)
+    assert(event("stopped").reason == "pause")
+    assert(request("continue", { threadId = worker }).success)
+    while not result do
+        moon.sleep(10)
+    end
+    assert(result == 12)
+    print("PASS: pause")
+
+    assert(request("disconnect").success)
+    socket.close(fd)
+    assert(moon.call("lua", worker, 1) == 14, "detached")
+
+    fd = assert(socket.connect("127.0.0.1:29229"))
+    assert(request("attach", { service = worker }).success)
+    moon.kill(worker)
+    event("terminated")
+    socket.close(fd)
+    print("PASS: debugger")
+    moon.exit(0)
+end)
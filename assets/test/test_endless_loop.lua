@@ -23,7 +23,7 @@ else
         })
     end)
 
-    moon.system("slow_message", function (who, what)
-        print(who..":",  what)
+    moon.system("watchdog", function (who, report)
+        print(who..":", report.event, report.blocked_ms)
     end)
 end
\ No newline at end of file
@@ -0,0 +1,99 @@
+---
+--- test_group.lua — service groups (`group`, `balance`, `moon.send_group`).
+---
+--- Run: moon_rs assets/test/test_group.lua
+---
+
+local moon = require "moon"
+
+local conf = ...
+
+if conf.group then
+    local seen = {}
+    moon.dispatch("lua", function(sender, session, cmd, key)
+        if cmd == "who" then
+            moon.response("lua", sender, session, moon.id)
+        elseif cmd == "seen" then
+            moon.response("lua", sender, session, seen)
+            seen = {}
+        elseif cmd == "block" then
+            -- Hold the service so later messages stay queued.
+            local deadline = moon.clock() + 0.1
+            while moon.clock() < deadline do end
+        else
+            -- `cmd` is the routing key of a hash group.
+            seen[cmd] = true
+        end
+    end)
+    return
+end
+
+local function new(group, balance)
+    return moon.new_service({ name = group, source = "test_group.lua", group = group, balance = balance })
+end
+
+moon.async(function()
+    print("--- round robin ---")
+    local ids = { new("rr"), new("rr"), new("rr") }
+    table.sort(ids)
+    local members = moon.group_members("rr")
+    assert(#members == 3 and members[1] == ids[1] and members[3] == ids[3])
+    local counts = {}
+    for _ = 1, 6 do
+        local id = moon.call_group("lua", "rr", "who")
+        counts[id] = (counts[id] or 0) + 1
+    end
+    for _, id in ipairs(ids) do
+        assert(counts[id] == 2, "each member answers twice")
+    end
+    print("PASS: round robin")
+
+    print("--- least queue ---")
+    local a = new("lq", "least_queue")
+    local b = new("lq")
+    local busy = moon.send_group("lua", "lq", "block")
+    moon.send("lua", busy, "block")
+    moon.send("lua", busy, "block")
+    for _ = 1, 4 do
+        local id = moon.call_group("lua", "lq", "who")
+        assert(id ~= busy, "the busy member is avoided")
+    end
+    assert((busy == a or busy == b))
+    print("PASS: least queue")
+
+    print("--- hash ---")
+    new("h", "hash")
+    new("h")
+    new("h")
+    local route = {}
+    for i = 1, 30 do
+        local key = "user" .. i
+        route[key] = moon.send_group("lua", "h", key)
+        assert(moon.send_group("lua", "h", key) == route[key], "same key, same member")
+    end
+    for _, id in ipairs(moon.group_members("h")) do
+        local seen = moon.call("lua", id, "seen")
+        for key in pairs(seen) do
+            assert(route[key] == id)
+        end
+    end
+    assert(moon.group_pick("h", 42) == moon.group_pick("h", "42"))
+    assert(not pcall(moon.group_pick, "h"), "hash groups need a key")
+    print("PASS: hash")
+
+    print("--- conflicts and cleanup ---")
+    assert(new("h", "round_robin") == 0, "balance must match the group")
+    assert(moon.send_group("lua", "none", "x") == 0)
+    local ok, err = moon.call_group("lua", "none", "who")
+    assert(ok == false and err:find("no member"))
+    for _, id in ipairs(moon.group_members("rr")) do
+        moon.kill(id)
+    end
+    moon.sleep(50)
+    assert(#moon.group_members("rr") == 0)
+    assert(moon.server_stats("group.count") == 2)
+    print("PASS: cleanup")
+
+    print("\n=== All group tests passed! ===")
+    moon.exit(0)
+end)
@@ -0,0 +1,90 @@
+---
+--- test_hot_reload.lua — `hot_reload`: editing a required module patches every
+--- service that loaded it, and each reports back to bootstrap.
+---
+--- Run: moon_rs assets/test/test_hot_reload.lua
+---
+
+if _G["__init__"] then
+    return { hot_reload = true }
+end
+
+local moon = require "moon"
+local fs = require "fs"
+
+local DIR = "/tmp/moon_test_hot_reload"
+
+local conf = ...
+
+if conf.role then
+    package.path = DIR .. "/?.lua;" .. package.path
+    local counter = conf.role == "user" and require("counter")
+
+    moon.dispatch("lua", function(sender, session)
+        moon.response("lua", sender, session, counter and counter.get() or "none")
+    end)
+    return
+end
+
+local function write(path, source)
+    local f = assert(io.open(path, "w"))
+    f:write(source)
+    f:close()
+end
+
+local function version(step)
+    return string.format("local n = 0\nreturn { get = function() n = n + %d return n end }\n", step)
+end
+
+if fs.exists(DIR) then
+    fs.remove(DIR)
+end
+fs.mkdir(DIR)
+write(DIR .. "/counter.lua", version(1))
+
+local reports = {}
+moon.system("hotfix", function(_, report)
+    reports[#reports + 1] = report
+end)
+
+local function wait_reports(n)
+    for _ = 1, 300 do
+        if #reports >= n then
+            local res = reports
+            reports = {}
+            return res
+        end
+        moon.sleep(10)
+    end
+    error("missing hotfix reports")
+end
+
+moon.async(function()
+    local a = moon.new_service({ name = "a", source = "test_hot_reload.lua", role = "user" })
+    local b = moon.new_service({ name = "b", source = "test_hot_reload.lua", role = "user" })
+    local other = moon.new_service({ name = "other", source = "test_hot_reload.lua", role = "other" })
+    assert(moon.call("lua", a, "get") == 1)
+
+    write(DIR .. "/counter.lua", version(10))
+    local res = wait_reports(2)
+    moon.sleep(200)
+    assert(#reports == 0, "only services that required the module")
+    table.sort(res, function(x, y) return x.id < y.id end)
+    assert(res[1].id == a and res[2].id == b)
+    assert(res[1].module == "counter" and res[1].ok and res[2].ok)
+    assert(moon.call("lua", a, "get") == 11, "patched, upvalues kept")
+    assert(moon.call("lua", b, "get") == 10)
+    assert(moon.call("lua", other, "get") == "none")
+    print("PASS: hot reload")
+
+    -- Editors often save by renaming a new file over the old one.
+    write(DIR .. "/counter.tmp", "return {")
+    assert(os.rename(DIR .. "/counter.tmp", DIR .. "/counter.lua"))
+    res = wait_reports(2)
+    assert(not res[1].ok and res[1].error, "a broken file is reported")
+    assert(moon.call("lua", a, "get") == 21, "and the service keeps the old code")
+    print("PASS: broken module")
+
+    fs.remove(DIR)
+    moon.exit(0)
+end)
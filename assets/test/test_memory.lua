@@ -0,0 +1,99 @@
+---
+--- test_memory.lua — memory limits (`memlimit`, `mem_soft_limit`, `mem_action`).
+---
+--- Run: moon_rs assets/test/test_memory.lua
+---
+
+local moon = require "moon"
+
+local conf = ...
+
+if conf.role then
+    local cache = {}
+    local pressure = 0
+    moon.on_memory_pressure(function(memory, limit)
+        assert(memory > limit)
+        pressure = pressure + 1
+        cache = {}
+    end)
+    moon.dispatch("lua", function(sender, session, cmd, n)
+        if cmd == "fill" then
+            -- About 1KB per entry, kept alive by the cache.
+            for i = 1, n do
+                cache[#cache + 1] = string.rep("x", 1000) .. i
+            end
+            moon.response("lua", sender, session, #cache)
+        elseif cmd == "try" then
+            -- A memory error the service handles itself.
+            local t = {}
+            local ok = pcall(function()
+                for i = 1, n do
+                    t[i] = string.rep("x", 1000) .. i
+                end
+            end)
+            t = nil
+            moon.response("lua", sender, session, ok)
+        elseif cmd == "pressure" then
+            moon.response("lua", sender, session, pressure, #cache)
+        elseif cmd == "limits" then
+            moon.response("lua", sender, session, moon.set_memlimit(n, 0))
+        end
+    end)
+    return
+end
+
+local reports = {}
+moon.system("memory", function(sender, report)
+    reports[#reports + 1] = report
+end)
+
+local function new(name, opts)
+    opts.name = name
+    opts.source = "test_memory.lua"
+    opts.role = name
+    return moon.new_service(opts)
+end
+
+moon.async(function()
+    print("--- soft limit ---")
+    local MB = 1024 * 1024
+    local soft = new("soft", { mem_soft_limit = 2 * MB })
+    moon.call("lua", soft, "fill", 3000)
+    moon.sleep(50)
+    local pressure, size = moon.call("lua", soft, "pressure")
+    assert(pressure == 1 and size == 0, "the pressure handler dropped the cache")
+    print("PASS: soft limit")
+
+    print("--- hard limit, error ---")
+    local hard = new("hard", { memlimit = 2 * MB })
+    local ok = moon.call("lua", hard, "fill", 3000)
+    assert(not ok, "the dispatch failed")
+    moon.sleep(50)
+    assert(#reports == 1 and reports[1].event == "hard_limit" and reports[1].id == hard)
+    assert(reports[1].action == "error" and reports[1].limit == 2 * MB)
+    assert(moon.call("lua", hard, "pressure") == 0, "the service carries on")
+    assert(moon.call("lua", hard, "try", 3000) == false)
+    moon.sleep(50)
+    assert(#reports == 1, "a handled memory error is no hard-limit event")
+    print("PASS: hard limit error")
+
+    print("--- runtime change ---")
+    local old = moon.call("lua", hard, "limits", 8 * MB)
+    assert(old == 2 * MB)
+    assert(moon.call("lua", hard, "fill", 3000) >= 3000, "the raised limit allows more")
+    moon.set_memlimit(1 * MB, nil, hard)
+    assert(not moon.call("lua", hard, "fill", 10), "the lowered limit applies")
+    moon.sleep(50)
+    print("PASS: runtime change")
+
+    print("--- hard limit, kill ---")
+    reports = {}
+    local killed = new("killed", { memlimit = 2 * MB, mem_action = "kill" })
+    assert(not moon.call("lua", killed, "fill", 3000))
+    moon.sleep(50)
+    assert(#reports == 1 and reports[1].id == killed and reports[1].action == "kill")
+    assert(not moon.call("lua", killed, "pressure"), "a killed service is gone")
+    print("PASS: hard limit kill")
+    -- "hard" is still above its 1MB limit and must shut down anyway.
+    moon.exit(0)
+end)
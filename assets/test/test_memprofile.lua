@@ -0,0 +1,77 @@
+---
+--- test_memprofile.lua — allocation profiler (`moon.memprofile`).
+---
+--- Run: moon_rs assets/test/test_memprofile.lua
+---
+
+local moon = require "moon"
+
+local conf = ...
+
+if conf.role then
+    local kept = {}
+
+    local function leak(n)
+        for i = 1, n do
+            kept[#kept + 1] = string.rep("x", 256) .. i
+        end
+    end
+
+    local function churn(n)
+        local x
+        for i = 1, n do
+            x = string.rep("y", 256) .. i
+        end
+        return x
+    end
+
+    moon.dispatch("lua", function(sender, session, n)
+        leak(n)
+        churn(n * 4)
+        moon.response("lua", sender, session, true)
+    end)
+    return
+end
+
+moon.async(function()
+    local worker = moon.new_service({ name = "worker", source = "test_memprofile.lua", role = "worker" })
+
+    moon.call("debug", worker, "memprofile", "start", 4096)
+    for _ = 1, 10 do
+        moon.call("lua", worker, 1000)
+    end
+    moon.call("debug", worker, "gc")
+
+    local live = moon.call("debug", worker, "memprofile", "report", "live", 5)
+    for _, s in ipairs(live) do
+        print("live", s.site, s.live, s.live_count)
+    end
+    assert(live[1].site:find("test_memprofile.lua:16", 1, true), "the leaking line holds the most")
+
+    local allocated = moon.call("debug", worker, "memprofile", "report", "allocated")
+    local leaked, churned = 0, 0
+    for _, s in ipairs(allocated) do
+        if s.site:find("test_memprofile.lua:16", 1, true) then
+            leaked = s.allocated
+        elseif s.site:find("test_memprofile.lua:23", 1, true) then
+            churned = s.allocated
+        end
+    end
+    print("allocated", "leak", leaked, "churn", churned)
+    assert(churned > leaked, "the churning line allocates the most")
+
+    assert(moon.call("debug", worker, "memprofile", "stop") == true)
+    assert(moon.call("debug", worker, "memprofile", "stop") == false)
+
+    moon.memprofile.start(1)
+    assert(not pcall(moon.memprofile.start), "one profile at a time")
+    local t = {}
+    for i = 1, 100 do
+        t[i] = { i }
+    end
+    assert(#moon.memprofile.report("live") > 0)
+    moon.memprofile.stop()
+    assert(not pcall(moon.memprofile.report), "the profile is over")
+    print("PASS: memprofile")
+    moon.exit(0)
+end)
@@ -0,0 +1,67 @@
+---
+--- test_profile.lua — sampling CPU profiler (`moon.profile`).
+---
+--- Run: moon_rs assets/test/test_profile.lua
+---
+
+local moon = require "moon"
+
+local conf = ...
+
+if conf.role then
+    local function hot(n)
+        local x = 0
+        for i = 1, n do
+            x = x + math.sin(i)
+        end
+        return x
+    end
+
+    local function cold(n)
+        local x = 0
+        for i = 1, n // 10 do
+            x = x + i
+        end
+        return x
+    end
+
+    moon.dispatch("lua", function(sender, session, n)
+        hot(n)
+        cold(n)
+        moon.response("lua", sender, session, true)
+    end)
+    return
+end
+
+moon.async(function()
+    local worker = moon.new_service({ name = "worker", source = "test_profile.lua", role = "worker" })
+
+    moon.profile.start(worker, 1000)
+    assert(not pcall(moon.profile.start, worker), "one profile at a time")
+    for _ = 1, 20 do
+        moon.call("lua", worker, 300000)
+    end
+    local path = "test_profile.svg"
+    local folded, samples = moon.profile.stop(path)
+    print(samples .. " samples")
+    assert(samples > 0)
+
+    local hot, cold = 0, 0
+    for stack, count in folded:gmatch("([^\n]+) (%d+)\n") do
+        if stack:find("hot (test_profile.lua:12)", 1, true) then
+            hot = hot + tonumber(count)
+        elseif stack:find("cold (test_profile.lua:20)", 1, true) then
+            cold = cold + tonumber(count)
+        end
+    end
+    print("hot", hot, "cold", cold)
+    assert(hot > cold, "the hot function has most samples")
+
+    local f = assert(io.open(path))
+    assert(f:read("a"):find("<svg", 1, true), "a flamegraph was written")
+    f:close()
+    os.remove(path)
+    assert(not pcall(moon.profile.stop, worker), "the profile is over")
+    print("PASS: profile")
+    moon.exit(0)
+end)
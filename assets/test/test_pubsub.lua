@@ -0,0 +1,109 @@
+---
+--- test_pubsub.lua — topic subscriptions (`moon.subscribe`, `moon.publish`).
+---
+--- Run: moon_rs assets/test/test_pubsub.lua
+---
+
+local moon = require "moon"
+
+local conf = ...
+
+if conf.role then
+    local received = {}
+    moon.subscribe("room.1.chat", function(sender, topic, text)
+        table.insert(received, table.concat({ "exact", topic, text }, ":"))
+    end)
+    if conf.role == "pattern" then
+        moon.subscribe("room.*.chat", function(sender, topic, text)
+            table.insert(received, table.concat({ "star", topic, text }, ":"))
+        end)
+        moon.subscribe("room.#", function(sender, topic, text)
+            table.insert(received, table.concat({ "hash", topic, text }, ":"))
+        end)
+    elseif conf.role == "churn" then
+        -- Swaps its own subscriptions while a message is being dispatched.
+        moon.subscribe("game.#", function(sender, topic, text)
+            table.insert(received, "hash:" .. text)
+            moon.unsubscribe("game.#")
+            for i = 1, 32 do
+                moon.subscribe("game.start." .. i, print)
+            end
+            moon.subscribe("game.*", function(_, _, next_text)
+                table.insert(received, "star:" .. next_text)
+            end)
+        end)
+    end
+
+    moon.dispatch("lua", function(sender, session, cmd, topic)
+        if cmd == "received" then
+            moon.response("lua", sender, session, received)
+            received = {}
+        elseif cmd == "unsubscribe" then
+            moon.response("lua", sender, session, moon.unsubscribe(topic))
+        end
+    end)
+    return
+end
+
+local function new(role)
+    return moon.new_service({ name = role, source = "test_pubsub.lua", role = role })
+end
+
+moon.async(function()
+    local plain = new("plain")
+    local pattern = new("pattern")
+
+    print("--- exact and wildcard subscribers ---")
+    assert(moon.publish("room.1.chat", "hi") == 2)
+    moon.sleep(50)
+    local r = moon.call("lua", plain, "received")
+    assert(#r == 1 and r[1] == "exact:room.1.chat:hi")
+    r = moon.call("lua", pattern, "received")
+    table.sort(r)
+    assert(#r == 3, "one delivery, every matching handler called")
+    assert(r[1] == "exact:room.1.chat:hi" and r[2] == "hash:room.1.chat:hi" and r[3] == "star:room.1.chat:hi")
+    print("PASS: fan-out")
+
+    print("--- patterns only ---")
+    assert(moon.publish("room.2.chat", "yo") == 1)
+    assert(moon.publish("room.2", "x") == 1)
+    assert(moon.publish("lobby", "x") == 0)
+    moon.sleep(50)
+    r = moon.call("lua", pattern, "received")
+    table.sort(r)
+    assert(#r == 3 and r[1] == "hash:room.2.chat:yo" and r[2] == "hash:room.2:x" and r[3] == "star:room.2.chat:yo")
+    print("PASS: patterns")
+
+    print("--- validation ---")
+    assert(not pcall(moon.publish, "room.*.chat", "x"), "cannot publish to a pattern")
+    assert(not pcall(moon.subscribe, "room..chat", print))
+    assert(moon.topic_match("room.#", "room") and not moon.topic_match("room.*", "room"))
+    print("PASS: validation")
+
+    print("--- handlers changing subscriptions ---")
+    local churn = new("churn")
+    assert(moon.publish("game.start", "a") == 1)
+    moon.sleep(50)
+    assert(moon.publish("game.start", "b") == 1)
+    moon.sleep(50)
+    r = moon.call("lua", churn, "received")
+    assert(#r == 2 and r[1] == "hash:a" and r[2] == "star:b", "new handlers wait for the next message")
+    moon.kill(churn)
+    moon.sleep(50)
+    print("PASS: churn")
+
+    print("--- unsubscribe and exit cleanup ---")
+    assert(moon.call("lua", pattern, "unsubscribe", "room.#") == true)
+    assert(moon.call("lua", pattern, "unsubscribe", "room.#") == false)
+    assert(moon.publish("room.2", "x") == 0)
+    assert(moon.server_stats("topic.count") == 2)
+    moon.kill(plain)
+    moon.kill(pattern)
+    moon.sleep(50)
+    assert(moon.server_stats("topic.count") == 0)
+    assert(moon.publish("room.1.chat", "x") == 0)
+    print("PASS: cleanup")
+
+    print("\n=== All pubsub tests passed! ===")
+    moon.exit(0)
+end)
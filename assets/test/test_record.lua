@@ -0,0 +1,74 @@
+---
+--- test_record.lua — message recording (`record`) and `moon_rs --replay`.
+---
+--- Run: moon_rs assets/test/test_record.lua (Linux only)
+---
+--- Records a service to a temporary file, then replays it with
+--- `moon_rs --replay` in a second process and expects the same `digest`.
+---
+
+if _G["__init__"] then
+    -- The replay runs in this directory too; find lualib from here.
+    return { path = "../../lualib/?.lua" }
+end
+
+local moon = require "moon"
+
+local conf = ...
+
+if conf.role == "player" then
+    local state = { hp = 100, log = {} }
+    moon.dispatch("lua", function(sender, session, cmd, n)
+        if cmd == "hit" then
+            -- Random damage comes back unchanged on replay, so does the order
+            -- of hits and timer ticks.
+            local dmg = math.random(1, n)
+            state.hp = state.hp - dmg
+            state.log[#state.log + 1] = tostring(dmg)
+        elseif cmd == "digest" then
+            local digest = string.format("hp=%d hits=%s", state.hp, table.concat(state.log, ","))
+            print("digest", digest)
+            moon.response("lua", sender, session, digest)
+        end
+    end)
+    moon.timeout(20, function()
+        state.log[#state.log + 1] = "heal"
+        state.hp = state.hp + 10
+    end)
+    return
+end
+
+-- The running moon_rs executable, to start the replay with.
+local function self_exe()
+    local f = assert(io.open("/proc/self/stat"))
+    local pid = f:read("n")
+    f:close()
+    return "/proc/" .. pid .. "/exe"
+end
+
+moon.async(function()
+    local path = os.tmpname()
+    local id = moon.new_service({ name = "player", source = "test_record.lua", record = path, role = "player" })
+    assert(id > 0)
+    for i = 1, 5 do
+        moon.send("lua", id, "hit", 10 * i)
+        moon.sleep(10)
+    end
+    local digest = moon.call("lua", id, "digest")
+    assert(digest:find("heal", 1, true), "the timer is recorded too")
+    moon.kill(id)
+
+    local f = assert(io.open(path, "rb"))
+    assert(f:read(8) == "MOONREC1")
+    f:close()
+    print("PASS: recorded to " .. path)
+
+    local replay = assert(io.popen(self_exe() .. " --replay " .. path .. " test_record.lua 2>&1"))
+    local output = replay:read("a")
+    replay:close()
+    os.remove(path)
+    local replayed = output:match("digest%s+(hp=%d+ hits=%S*)")
+    assert(replayed == digest, "the replay reaches the same state:\n" .. output)
+    print("PASS: replay digest " .. replayed)
+    moon.exit(0)
+end)
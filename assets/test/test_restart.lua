@@ -0,0 +1,94 @@
+---
+--- test_restart.lua — service restart policies (`restart`, `max_restarts`, `within`).
+---
+--- Run: moon_rs assets/test/test_restart.lua
+---
+
+local moon = require "moon"
+
+local conf = ...
+
+if conf.role then
+    -- Count incarnations per role in the shared env so a service can fail a
+    -- fixed number of times.
+    local key = "restart_" .. conf.role
+    local n = tonumber(moon.env(key) or "0") + 1
+    moon.env(key, tostring(n))
+
+    if conf.role == "flaky" and n == 1 then
+        error("flaky init")
+    elseif conf.role == "broken" then
+        error("broken init")
+    end
+
+    moon.dispatch("lua", function(sender, session, cmd)
+        if cmd == "incarnation" then
+            moon.response("lua", sender, session, n)
+        elseif cmd == "quit" then
+            moon.quit()
+        end
+    end)
+    return
+end
+
+local restarts = {}
+moon.system("service_restart", function(sender, old_id, new_id, reason)
+    table.insert(restarts, {
+        sender = sender,
+        old_id = tonumber(old_id),
+        new_id = tonumber(new_id),
+        reason = reason,
+    })
+end)
+
+local function new(role, opts)
+    opts.name = role
+    opts.source = "test_restart.lua"
+    opts.unique = true
+    opts.role = role
+    return moon.new_service(opts)
+end
+
+moon.async(function()
+    print("--- init failure is retried before new_service returns ---")
+    local id = new("flaky", { restart = "transient" })
+    assert(id > 0, "flaky service should start on its second attempt")
+    assert(moon.call("lua", id, "incarnation") == 2)
+    assert(#restarts == 0, "the creator learns the id from new_service, not a notification")
+    print("PASS: init retry")
+
+    print("--- transient: a normal quit is final ---")
+    moon.send("lua", id, "quit")
+    moon.sleep(100)
+    assert(moon.query("flaky") == 0)
+    assert(#restarts == 0)
+    print("PASS: transient")
+
+    print("--- permanent: restarted with the same name, creator notified ---")
+    local first = new("steady", { restart = "permanent" })
+    moon.send("lua", first, "quit")
+    moon.sleep(100)
+    local second = moon.query("steady")
+    assert(second > 0 and second ~= first)
+    assert(moon.call("lua", second, "incarnation") == 2)
+    assert(#restarts == 1)
+    assert(restarts[1].old_id == first and restarts[1].new_id == second)
+    assert(restarts[1].sender == second and restarts[1].reason == "normal")
+    print("PASS: permanent")
+
+    print("--- kill from another service is final ---")
+    moon.kill(second)
+    moon.sleep(100)
+    assert(moon.query("steady") == 0)
+    assert(#restarts == 1)
+    print("PASS: kill")
+
+    print("--- max_restarts: give up ---")
+    local broken = new("broken", { restart = "permanent", max_restarts = 2, within = 10 })
+    assert(broken == 0, "a service that never starts is given up on")
+    assert(tonumber(moon.env("restart_broken")) == 3, "one start plus two restarts")
+    print("PASS: max_restarts")
+
+    print("\n=== All restart tests passed! ===")
+    moon.exit(0)
+end)
@@ -0,0 +1,72 @@
+---
+--- test_schedule.lua — priority lanes (`priority_lanes`) and dispatch budgets.
+---
+--- Run: moon_rs assets/test/test_schedule.lua
+---
+
+local moon = require "moon"
+
+local conf = ...
+
+if conf.role == "echo" then
+    moon.dispatch("lua", function(sender, session)
+        moon.response("lua", sender, session, true)
+    end)
+    return
+end
+
+if conf.role == "worker" then
+    local noise = 0
+    moon.dispatch("lua", function(sender, session, cmd, echo)
+        if cmd == "noise" then
+            -- Slow bulk work, so the mailbox stays long.
+            local deadline = moon.clock() + 0.0002
+            while moon.clock() < deadline do end
+            noise = noise + 1
+        elseif cmd == "start" then
+            moon.call("lua", echo, "ping")
+            moon.response("lua", sender, session, noise)
+        end
+    end)
+    return
+end
+
+local NOISE = 500
+
+local function run(priority_lanes)
+    local echo = moon.new_service({ name = "echo", source = "test_schedule.lua", role = "echo" })
+    local worker = moon.new_service({
+        name = "worker",
+        source = "test_schedule.lua",
+        role = "worker",
+        priority_lanes = priority_lanes,
+        budget = 16,
+        budget_ms = 2,
+    })
+    local n
+    moon.async(function()
+        n = moon.call("lua", worker, "start", echo)
+    end)
+    for _ = 1, NOISE do
+        moon.send("lua", worker, "noise")
+    end
+    while not n do
+        moon.sleep(10)
+    end
+    moon.kill(worker)
+    moon.kill(echo)
+    return n
+end
+
+moon.async(function()
+    local fifo = run(false)
+    print("noise before the response, fifo:", fifo)
+    assert(fifo > NOISE / 2, "without lanes the response waits behind the noise")
+    print("PASS: fifo mailbox")
+
+    local lanes = run(true)
+    print("noise before the response, priority lanes:", lanes)
+    assert(lanes < NOISE / 2, "the response overtakes the noise")
+    print("PASS: priority lanes")
+    moon.exit(0)
+end)
@@ -0,0 +1,57 @@
+---
+--- test_socket_tls.lua — TLS listen/connect over the raw socket API.
+---
+--- Run: moon_rs assets/test/test_socket_tls.lua
+---
+
+local socket = require "moon.socket"
+local moon   = require "moon"
+
+local TLS = {
+    cert = "tls/server.pem",
+    key = "tls/server.key",
+}
+
+moon.async(function()
+    print("--- TLS raw read/write ---")
+
+    local listenfd = assert(socket.listen("127.0.0.1:28443", function(fd)
+        local line = socket.read(fd, "\n")
+        if not line then
+            return
+        end
+        socket.write(fd, "echo:" .. line .. "\n")
+        local buf = socket.read_frame(fd)
+        socket.write_frame(fd, buf)
+    end, { tls = TLS }))
+
+    local fd = assert(socket.connect_host("localhost", 28443, {
+        tls = { ca = "tls/ca.pem" },
+    }))
+    socket.write(fd, "hello\n")
+    assert(socket.read(fd, "\n") == "echo:hello")
+    print("PASS: read_until over tls")
+
+    socket.write_frame(fd, string.rep("x", 70000))
+    local buf = assert(socket.read_frame(fd))
+    local buffer = require "buffer"
+    assert(buffer.size(buf) == 70000)
+    buffer.drop(buf)
+    print("PASS: frames over tls")
+    socket.close(fd)
+
+    print("--- TLS verification ---")
+    local bad, err = socket.connect("localhost:28443", 5000, { tls = {} })
+    assert(not bad and err:find("certificate"), "self-signed chain must fail against webpki roots")
+    local bad2, err2 = socket.connect_host("127.0.0.1", 28443, {
+        tls = { sni = "wrong.example", ca = "tls/ca.pem" },
+    })
+    assert(not bad2 and err2, "sni mismatch must fail")
+    local lfd, lerr = socket.listen("127.0.0.1:28444", function() end, { tls = { cert = "missing.pem", key = "missing.key" } })
+    assert(not lfd and lerr:find("missing.pem"), "listen with missing cert must fail")
+    print("PASS: verification errors")
+
+    socket.close(listenfd)
+    print("\n=== All tls socket tests passed! ===")
+    moon.quit()
+end)
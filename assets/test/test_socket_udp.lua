@@ -0,0 +1,54 @@
+---
+--- test_socket_udp.lua — UDP bind/sendto/connect/close tests.
+---
+--- Run: moon_rs assets/test/test_socket_udp.lua
+---
+
+local socket = require "moon.socket"
+local moon   = require "moon"
+
+moon.async(function()
+    print("--- UDP sendto/echo ---")
+
+    local server_fd
+    server_fd = assert(socket.udp(function(data, from)
+        assert(socket.sendto(server_fd, from, "echo:" .. data))
+    end, "127.0.0.1:29090"))
+
+    local replies = {}
+    local client_fd = assert(socket.udp(function(data, from)
+        table.insert(replies, { data = data, from = from })
+    end))
+
+    assert(socket.sendto(client_fd, "127.0.0.1:29090", "hello"))
+    moon.sleep(100)
+    assert(#replies == 1, "expected one echo reply")
+    assert(replies[1].data == "echo:hello")
+    assert(replies[1].from == "127.0.0.1:29090")
+    print("PASS: sendto/echo")
+
+    print("--- UDP connect/send ---")
+    assert(socket.udp_connect(client_fd, "127.0.0.1:29090"))
+    assert(socket.udp_send(client_fd, "again"))
+    moon.sleep(100)
+    assert(#replies == 2 and replies[2].data == "echo:again")
+    print("PASS: connect/send")
+
+    print("--- UDP errors ---")
+    local ok, err = socket.sendto(client_fd, "127.0.0.1:29090", string.rep("x", 65508))
+    assert(not ok and err:find("exceeds limit"), "oversized datagram should be rejected")
+    ok, err = socket.sendto(client_fd, "localhost:29090", "x")
+    assert(not ok and err:find("literal"), "sendto must not resolve host names")
+    local fd, bind_err = socket.udp(function() end, "127.0.0.1:29090")
+    assert(not fd and bind_err, "binding a used port should fail")
+    print("PASS: errors")
+
+    socket.close(client_fd)
+    socket.close(server_fd)
+    moon.sleep(50)
+    ok = socket.sendto(client_fd, "127.0.0.1:29090", "closed")
+    assert(not ok, "sendto on closed fd should fail")
+
+    print("\n=== All udp socket tests passed! ===")
+    moon.quit()
+end)
@@ -0,0 +1,81 @@
+---
+--- test_watchdog_config.lua — per-service watchdog thresholds and actions.
+---
+--- Run: moon_rs assets/test/test_watchdog_config.lua
+---
+
+local moon = require "moon"
+
+local conf = ...
+
+if conf.role then
+    moon.dispatch("lua", function(sender, session, ms)
+        -- Busy for `ms` milliseconds, or forever.
+        local deadline = ms and moon.clock() + ms / 1000
+        while not deadline or moon.clock() < deadline do end
+        moon.response("lua", sender, session, "done")
+    end)
+    return
+end
+
+local reports = {}
+moon.system("watchdog", function(sender, report)
+    reports[#reports + 1] = report
+end)
+
+local function new(name, watchdog)
+    return moon.new_service({ name = name, source = "test_watchdog_config.lua", role = name, watchdog = watchdog })
+end
+
+local function wait_for(pred)
+    for _ = 1, 200 do
+        if pred() then
+            return true
+        end
+        moon.sleep(20)
+    end
+    return false
+end
+
+moon.async(function()
+    print("--- log ---")
+    local slow = new("report", { timeout_ms = 100, strikes = 1, action = "log" })
+    local res = moon.call("lua", slow, 450)
+    assert(res == "done", "a `log` service finishes its work")
+    local n = 0
+    for _, r in ipairs(reports) do
+        assert(r.id == slow and r.event == "slow" and r.action == "log")
+        assert(r.ptype == moon.PTYPE_LUA and r.from == moon.id and r.session < 0)
+        n = n + 1
+    end
+    assert(n >= 3, "reported once per period")
+    assert(reports[n].blocked_ms >= 300 and reports[n].strike == n)
+    print("PASS: log action", n)
+
+    print("--- kill ---")
+    reports = {}
+    local stuck = new("stuck", { timeout_ms = 200, strikes = 2, action = "kill" })
+    moon.send("lua", stuck, false)
+    assert(wait_for(function()
+        return #reports > 0 and reports[#reports].event == "kill"
+    end), "killed")
+    local last = reports[#reports]
+    assert(last.id == stuck and last.strike == 2 and last.error:find("dispatch message error", 1, true))
+    assert(reports[1].event == "slow" and reports[1].strike == 1)
+    assert(not moon.call("lua", stuck, 1), "a killed service is gone")
+    print("PASS: kill action")
+
+    print("--- interrupt ---")
+    reports = {}
+    local busy = new("busy", { timeout_ms = 200, strikes = 1 })
+    local ok, err = moon.call("lua", busy, false)
+    assert(not ok and err, "the blocked call fails")
+    assert(reports[#reports].event == "interrupt")
+    assert(moon.call("lua", busy, 10) == "done", "an interrupted service carries on")
+    print("PASS: interrupt action")
+
+    local created, err2 = pcall(new, "zero", { timeout_ms = 0 })
+    assert(not created and err2:find("timeout_ms", 1, true), "a timeout below the monitor period is refused")
+    print("PASS: minimum timeout")
+    moon.exit(0)
+end)
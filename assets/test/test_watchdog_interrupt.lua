@@ -19,8 +19,10 @@ end)
 -- Send a message to self to trigger the infinite loop
 moon.send("lua", moon.id, "trigger")
 
-moon.system("timeout_kill", function(sender, ...)
-    moon.error("test error", ...)
+moon.system("watchdog", function(sender, report)
+    if report.event == "interrupt" then
+        moon.error("test error", report.error)
+    end
 end)
 
 print("Message sent, waiting for watchdog interrupt...")
@@ -0,0 +1,412 @@
+//! Debug Adapter Protocol server, enabled with `debug_addr` in the bootstrap
+//! `__init__` table. Each connection debugs one service, chosen by the
+//! `service` argument of the `attach` request (an id or a unique service
+//! name). The service is held on its own thread while stopped, see
+//! `moon_runtime::debugger`; every other service keeps running.
+
+use std::{collections::HashMap, io, net::SocketAddr, path::Path, time::Duration};
+
+use moon_runtime::{
+    context::CONTEXT,
+    debugger::{Command, DebugSession, Event, Reference, Reply, Resume},
+};
+use serde_json::{Value, json};
+use tokio::{
+    io::{AsyncBufRead, AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader},
+    net::{TcpListener, TcpStream, tcp::OwnedWriteHalf},
+    sync::mpsc,
+};
+
+/// Largest message body accepted.
+const MAX_MESSAGE: usize = 1024 * 1024;
+
+/// Bind `addr` and serve debug sessions on the IO runtime. Binding happens
+/// before this returns, so a bad address or a port in use fails startup.
+pub fn run_dap(addr: &str) -> io::Result<SocketAddr> {
+    let listener = std::net::TcpListener::bind(addr)?;
+    listener.set_nonblocking(true)?;
+    let local_addr = listener.local_addr()?;
+    CONTEXT.io_runtime().spawn(async move {
+        let listener = match TcpListener::from_std(listener) {
+            Ok(listener) => listener,
+            Err(err) => {
+                log::error!("debug listener {} failed: {}", local_addr, err);
+                return;
+            }
+        };
+        loop {
+            match listener.accept().await {
+                Ok((stream, peer)) => {
+                    tokio::spawn(async move {
+                        if let Err(err) = handle_connection(stream).await {
+                            log::warn!("debug connection {}: {}", peer, err);
+                        }
+                    });
+                }
+                Err(err) => {
+                    log::warn!("debug accept failed: {}", err);
+                    tokio::time::sleep(Duration::from_millis(100)).await;
+                }
+            }
+        }
+    });
+    Ok(local_addr)
+}
+
+async fn handle_connection(stream: TcpStream) -> io::Result<()> {
+    let (reader, writer) = stream.into_split();
+    // Reading a message is not cancel safe, so it gets its own task.
+    let (tx, mut rx) = mpsc::unbounded_channel();
+    tokio::spawn(async move {
+        let mut reader = BufReader::new(reader);
+        loop {
+            match read_message(&mut reader).await {
+                Ok(Some(message)) => {
+                    if tx.send(message).is_err() {
+                        break;
+                    }
+                }
+                Ok(None) => break,
+                Err(err) => {
+                    log::debug!("debug connection: {}", err);
+                    break;
+                }
+            }
+        }
+    });
+
+    let mut adapter = Adapter {
+        writer,
+        seq: 0,
+        session: None,
+        service: String::new(),
+        breakpoints: HashMap::new(),
+    };
+    loop {
+        tokio::select! {
+            message = rx.recv() => {
+                let Some(message) = message else {
+                    break;
+                };
+                if !adapter.handle(message).await? {
+                    break;
+                }
+            }
+            event = next_event(&mut adapter.session) => adapter.on_event(event).await?,
+        }
+    }
+    Ok(())
+}
+
+async fn next_event(session: &mut Option<DebugSession>) -> Option<Event> {
+    match session {
+        Some(session) => session.next_event().await,
+        None => std::future::pending().await,
+    }
+}
+
+/// Read one `Content-Length` framed message; `None` at the end of the stream.
+async fn read_message<R: AsyncBufRead + Unpin>(reader: &mut R) -> io::Result<Option<Value>> {
+    let mut length = None;
+    let mut line = String::new();
+    loop {
+        line.clear();
+        if reader.read_line(&mut line).await? == 0 {
+            return Ok(None);
+        }
+        let header = line.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':')
+            && name.eq_ignore_ascii_case("Content-Length")
+        {
+            length = value.trim().parse::<usize>().ok();
+        }
+    }
+    let length = length
+        .filter(|length| *length <= MAX_MESSAGE)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "bad Content-Length"))?;
+    let mut body = vec![0; length];
+    reader.read_exact(&mut body).await?;
+    serde_json::from_slice(&body)
+        .map(Some)
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+}
+
+struct Adapter {
+    writer: OwnedWriteHalf,
+    seq: i64,
+    session: Option<DebugSession>,
+    /// The `service` argument of the attach request.
+    service: String,
+    /// Breakpoints by file. Editors send them around the attach request, so
+    /// they are kept here and handed to the session once there is one.
+    breakpoints: HashMap<String, Vec<i32>>,
+}
+
+impl Adapter {
+    async fn send(&mut self, mut message: Value) -> io::Result<()> {
+        self.seq += 1;
+        message["seq"] = json!(self.seq);
+        let body = message.to_string();
+        let head = format!("Content-Length: {}\r\n\r\n", body.len());
+        self.writer.write_all(head.as_bytes()).await?;
+        self.writer.write_all(body.as_bytes()).await
+    }
+
+    async fn send_event(&mut self, event: &str, body: Value) -> io::Result<()> {
+        self.send(json!({ "type": "event", "event": event, "body": body }))
+            .await
+    }
+
+    /// Answer one request. False once the client disconnected.
+    async fn handle(&mut self, request: Value) -> io::Result<bool> {
+        if request["type"] != "request" {
+            return Ok(true);
+        }
+        let command = request["command"].as_str().unwrap_or_default().to_string();
+        let args = &request["arguments"];
+        let result = match command.as_str() {
+            "initialize" => Ok(json!({ "supportsConfigurationDoneRequest": true })),
+            "attach" => self.attach(args),
+            "launch" => Err("launch is not supported, attach to a running service".to_string()),
+            "setBreakpoints" => Ok(self.set_breakpoints(args)),
+            "configurationDone" => Ok(Value::Null),
+            "threads" => Ok(self.threads()),
+            "pause" => self.session().map(|session| {
+                session.pause();
+                Value::Null
+            }),
+            "continue" => self
+                .resume(Resume::Continue)
+                .await
+                .map(|_| json!({ "allThreadsContinued": false })),
+            "next" => self.resume(Resume::Next).await,
+            "stepIn" => self.resume(Resume::StepIn).await,
+            "stepOut" => self.resume(Resume::StepOut).await,
+            "stackTrace" => self.stack_trace().await,
+            "scopes" => Ok(scopes(args)),
+            "variables" => self.variables(args).await,
+            "disconnect" => {
+                self.session = None;
+                Ok(Value::Null)
+            }
+            _ => Err(format!("'{}' is not supported", command)),
+        };
+
+        let mut response = json!({
+            "type": "response",
+            "request_seq": request["seq"],
+            "command": command,
+            "success": result.is_ok(),
+        });
+        match result {
+            Ok(Value::Null) => {}
+            Ok(body) => response["body"] = body,
+            Err(message) => response["message"] = json!(message),
+        }
+        self.send(response).await?;
+
+        match command.as_str() {
+            "initialize" => self.send_event("initialized", json!({})).await?,
+            "disconnect" => return Ok(false),
+            _ => {}
+        }
+        Ok(true)
+    }
+
+    async fn on_event(&mut self, event: Option<Event>) -> io::Result<()> {
+        let id = self.session.as_ref().map(|session| session.id);
+        match (event, id) {
+            (Some(Event::Stopped { reason }), Some(id)) => {
+                self.send_event(
+                    "stopped",
+                    json!({ "reason": reason, "threadId": id, "allThreadsStopped": false }),
+                )
+                .await
+            }
+            _ => {
+                self.session = None;
+                self.send_event("terminated", json!({})).await
+            }
+        }
+    }
+
+    fn session(&self) -> Result<&DebugSession, String> {
+        self.session
+            .as_ref()
+            .ok_or_else(|| "not attached to a service".to_string())
+    }
+
+    fn attach(&mut self, args: &Value) -> Result<Value, String> {
+        let service = match &args["service"] {
+            Value::String(name) => name.clone(),
+            Value::Number(id) => id.to_string(),
+            _ => return Err("attach needs a 'service': an id or a unique name".to_string()),
+        };
+        let session = CONTEXT.debug_attach(&service)?;
+        for (path, lines) in &self.breakpoints {
+            session.set_breakpoints(path, lines);
+        }
+        log::info!(
+            "debugger attached to service {:08x} ({}).",
+            session.id,
+            service
+        );
+        self.session = Some(session);
+        self.service = service;
+        Ok(Value::Null)
+    }
+
+    fn set_breakpoints(&mut self, args: &Value) -> Value {
+        let path = args["source"]["path"]
+            .as_str()
+            .unwrap_or_default()
+            .to_string();
+        let lines: Vec<i32> = args["breakpoints"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter_map(|bp| bp["line"].as_i64())
+            .map(|line| line as i32)
+            .collect();
+        if let Some(session) = self.session.as_ref() {
+            session.set_breakpoints(&path, &lines);
+        }
+        let breakpoints: Vec<Value> = lines
+            .iter()
+            .map(|line| json!({ "verified": true, "line": line }))
+            .collect();
+        self.breakpoints.insert(path, lines);
+        json!({ "breakpoints": breakpoints })
+    }
+
+    fn threads(&self) -> Value {
+        let threads: Vec<Value> = self
+            .session
+            .iter()
+            .map(|session| json!({ "id": session.id, "name": self.service }))
+            .collect();
+        json!({ "threads": threads })
+    }
+
+    async fn resume(&self, resume: Resume) -> Result<Value, String> {
+        self.session()?.request(Command::Resume(resume)).await?;
+        Ok(Value::Null)
+    }
+
+    async fn stack_trace(&self) -> Result<Value, String> {
+        let Reply::Frames(frames) = self.session()?.request(Command::StackTrace).await? else {
+            return Err("unexpected reply".to_string());
+        };
+        let frames: Vec<Value> = frames
+            .into_iter()
+            .map(|frame| {
+                let mut f = json!({
+                    "id": frame.id,
+                    "name": frame.name,
+                    "line": frame.line.max(0),
+                    "column": 1,
+                });
+                if let Some(source) = frame.source {
+                    // Relative chunk names are relative to the bootstrap
+                    // script's directory, the working directory.
+                    let path = std::env::current_dir()
+                        .map(|dir| dir.join(&source))
+                        .unwrap_or_else(|_| source.clone().into());
+                    let name = Path::new(&source)
+                        .file_name()
+                        .map(|name| name.to_string_lossy().into_owned())
+                        .unwrap_or(source);
+                    f["source"] = json!({ "name": name, "path": path.to_string_lossy() });
+                }
+                f
+            })
+            .collect();
+        Ok(json!({ "stackFrames": frames, "totalFrames": frames.len() }))
+    }
+
+    async fn variables(&self, args: &Value) -> Result<Value, String> {
+        let reference = args["variablesReference"]
+            .as_i64()
+            .and_then(Reference::from_id)
+            .ok_or_else(|| "bad variablesReference".to_string())?;
+        let Reply::Variables(vars) = self
+            .session()?
+            .request(Command::Variables(reference))
+            .await?
+        else {
+            return Err("unexpected reply".to_string());
+        };
+        let vars: Vec<Value> = vars
+            .into_iter()
+            .map(|var| {
+                json!({
+                    "name": var.name,
+                    "value": var.value,
+                    "type": var.kind,
+                    "variablesReference": var.reference,
+                })
+            })
+            .collect();
+        Ok(json!({ "variables": vars }))
+    }
+}
+
+/// Locals and upvalues of a frame; frame ids are stack levels + 1.
+fn scopes(args: &Value) -> Value {
+    let level = args["frameId"].as_i64().unwrap_or(1).max(1) as usize - 1;
+    json!({ "scopes": [
+        {
+            "name": "Locals",
+            "presentationHint": "locals",
+            "variablesReference": Reference::Locals(level).id(),
+            "expensive": false,
+        },
+        {
+            "name": "Upvalues",
+            "variablesReference": Reference::Upvalues(level).id(),
+            "expensive": false,
+        },
+    ]})
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_framed_messages() {
+        let input =
+            b"Content-Length: 15\r\n\r\n{\"seq\":1,\"a\":2}content-length: 2\r\nX: y\r\n\r\n{}";
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap();
+        runtime.block_on(async {
+            let mut reader = BufReader::new(&input[..]);
+            let first = read_message(&mut reader).await.unwrap().unwrap();
+            assert_eq!(first["a"], 2);
+            let second = read_message(&mut reader).await.unwrap().unwrap();
+            assert_eq!(second, json!({}));
+            assert!(read_message(&mut reader).await.unwrap().is_none());
+
+            let mut reader = BufReader::new(&b"X: y\r\n\r\n{}"[..]);
+            assert!(
+                read_message(&mut reader).await.is_err(),
+                "no Content-Length"
+            );
+            let mut reader = BufReader::new(&b"Content-Length: 3\r\n\r\n{}}"[..]);
+            assert!(read_message(&mut reader).await.is_err(), "not JSON");
+        });
+    }
+
+    #[test]
+    fn scopes_of_a_frame() {
+        let body = scopes(&json!({ "frameId": 3 }));
+        let locals = body["scopes"][0]["variablesReference"].as_i64().unwrap();
+        let upvalues = body["scopes"][1]["variablesReference"].as_i64().unwrap();
+        assert_eq!(Reference::from_id(locals), Some(Reference::Locals(2)));
+        assert_eq!(Reference::from_id(upvalues), Some(Reference::Upvalues(2)));
+    }
+}
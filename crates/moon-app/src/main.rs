@@ -1,3 +1,5 @@
+mod dap;
+
 use mimalloc::MiMalloc;
 use moon_base::{
     self, cstr, ffi,
@@ -5,8 +7,12 @@ use moon_base::{
 };
 use moon_runtime::{lua_actor, not_null_wrapper};
 use moon_runtime::{
-    context::{self, CLUSTER_ACTOR_ADDR, CONTEXT, LOGGER, LuaActorParam},
+    admin, bundle,
+    context::{self, CLUSTER_ACTOR_ADDR, CONTEXT, LOGGER, LuaActorParam, MailboxPolicy},
     error::{Error, Result},
+    hotreload,
+    log::{LogFormat, LogRotation},
+    timer::{self, TimerBackend, TimerConfig},
 };
 use tokio::sync::mpsc;
 use std::{
@@ -22,9 +28,98 @@ static GLOBAL: MiMalloc = MiMalloc;
 
 fn print_usage() {
     println!("Usage:");
-    println!("    moon_rs script.lua [args]\n");
+    println!("    moon_rs script.lua [args]");
+    println!("    moon_rs --replay record_file service.lua");
+    println!("    moon_rs pack script.lua -o output [--bytecode] [--encrypt key] [--sign key]");
+    println!("    moon_rs keygen prefix\n");
     println!("Examples:");
-    println!("    moon_rs main.lua hello\n");
+    println!("    moon_rs main.lua hello");
+    println!("    moon_rs --replay battle.rec service_battle.lua");
+    println!("    moon_rs pack main.lua -o server --bytecode");
+    println!("    moon_rs keygen game");
+    println!("    moon_rs pack main.lua -o server --encrypt game.key --sign game.sign\n");
+}
+
+/// The lualib directory: in the current directory, or next to the executable.
+fn find_lualib() -> Result<PathBuf> {
+    let mut lualib = env::current_dir()?.join("lualib");
+    if !lualib.is_dir() {
+        lualib = env::current_exe()?.canonicalize()?;
+        lualib.pop();
+        lualib.push("lualib");
+    }
+    if !lualib.is_dir() {
+        return Err(Error::Custom(format!(
+            "lualib dir not found: {}",
+            lualib.to_string_lossy()
+        )));
+    }
+    Ok(lualib)
+}
+
+/// `moon_rs pack script.lua -o output [--bytecode] [--encrypt key] [--sign key]`:
+/// write a copy of this executable with lualib and the scripts next to
+/// `script.lua` appended.
+fn pack(args: &[String]) -> Result<()> {
+    let mut script = None;
+    let mut output = None;
+    let mut bytecode = false;
+    let mut keys = bundle::Keys::default();
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "-o" => output = iter.next(),
+            "--bytecode" => bytecode = true,
+            "--encrypt" | "--sign" => {
+                let Some(file) = iter.next() else {
+                    print_usage();
+                    return Err(Error::Custom(format!("{} needs a key file", arg)));
+                };
+                if arg == "--encrypt" {
+                    keys.cipher = Some(bundle::read_key(Path::new(file))?);
+                } else {
+                    keys.signing = Some(bundle::read_signing_key(Path::new(file))?);
+                }
+            }
+            _ => script = Some(arg),
+        }
+    }
+    let (Some(script), Some(output)) = (script, output) else {
+        print_usage();
+        return Err(Error::Custom(
+            "pack needs a script and -o output".to_string(),
+        ));
+    };
+
+    let bundle = bundle::pack(Path::new(script), &find_lualib()?, bytecode)?;
+    bundle.write_executable(&env::current_exe()?, Path::new(output), &keys)?;
+    println!("packed {} files into {}", bundle.len(), output);
+    Ok(())
+}
+
+/// `moon_rs keygen prefix`: write the keys for `pack --encrypt` and `--sign`.
+fn keygen(args: &[String]) -> Result<()> {
+    let Some(prefix) = args.first() else {
+        print_usage();
+        return Err(Error::Custom("keygen needs a file prefix".to_string()));
+    };
+    bundle::keygen(Path::new(prefix))?;
+    println!(
+        "wrote {0}.key (cipher key), {0}.sign (signing key) and {0}.pub (public key)",
+        prefix
+    );
+    Ok(())
+}
+
+/// Whether the bootstrap script returns startup options when `__init__` is
+/// set. Compiled scripts only keep the string constant.
+fn has_init_options(contents: &[u8]) -> bool {
+    let pattern: &[u8] = if contents.starts_with(ffi::LUA_SIGNATURE) {
+        b"__init__"
+    } else {
+        b"_G[\"__init__\"]"
+    };
+    contents.windows(pattern.len()).any(|w| w == pattern)
 }
 
 fn setup_signal() {
@@ -58,6 +153,20 @@ fn setup_signal() {
                 CONTEXT.shutdown(v.0);
             }
         });
+
+        // SIGHUP only reopens the log file, so logrotate-style tools can move
+        // it away without stopping the system.
+        CONTEXT.io_runtime().spawn(async {
+            let mut stream_hangup = tokio::signal::unix::signal(SignalKind::hangup()).unwrap();
+            while stream_hangup.recv().await.is_some() {
+                LOGGER.reopen();
+                log::info!(
+                    "'hangup' signal received, log file reopened. ({}:{})",
+                    file!(),
+                    line!()
+                );
+            }
+        });
     }
 
     #[cfg(target_os = "windows")]
@@ -139,35 +248,68 @@ async fn async_main() -> Result<()> {
     let mut enable_stdout = true;
     let mut loglevel = String::new();
     let mut logfile: Option<String> = None;
+    let mut timer_config = TimerConfig::default();
+    let mut log_rotation = LogRotation::default();
+    let mut log_format = LogFormat::Text;
+    let mut admin_addr: Option<String> = None;
+    let mut admin_token: Option<String> = None;
+    let mut debug_addr: Option<String> = None;
+    let mut hot_reload = false;
 
     let args: Vec<String> = env::args().collect();
-    let mut argn = 1;
-    if args.len() <= argn {
-        print_usage();
-        return Err(Error::Custom("invalid arguments".to_string()));
+    let bundle = bundle::load()?;
+    if bundle.is_none() {
+        match args.get(1).map(String::as_str) {
+            Some("pack") => return pack(&args[2..]),
+            Some("keygen") => return keygen(&args[2..]),
+            _ => {}
+        }
     }
 
-    let mut bootstrap = args[argn].clone();
-    let path = Path::new(&bootstrap);
-    if !path.is_file() {
-        print_usage();
-        return Err(Error::Custom(format!(
-            "bootstrap file not found: {}",
-            bootstrap
-        )));
+    let mut argn = 1;
+    let mut replay_file: Option<PathBuf> = None;
+    if args.get(argn).map(String::as_str) == Some("--replay") {
+        let Some(file) = args.get(argn + 1) else {
+            print_usage();
+            return Err(Error::Custom("--replay needs a record file".to_string()));
+        };
+        // Resolved before the working directory changes to the script's.
+        replay_file = Some(Path::new(file).canonicalize()?);
+        argn += 2;
     }
 
-    if path.extension().and_then(std::ffi::OsStr::to_str) != Some("lua") {
-        print_usage();
-        return Err(Error::Custom(format!(
-            "bootstrap is not a lua file: {}",
-            bootstrap
-        )));
-    }
+    // A packed executable runs its bundled entry and passes every argument to it.
+    let (mut bootstrap, bootstrap_path) = match bundle {
+        Some(bundle) => (bundle.entry().to_string(), None),
+        None => {
+            if args.len() <= argn {
+                print_usage();
+                return Err(Error::Custom("invalid arguments".to_string()));
+            }
 
-    let bootstrap_path = path.canonicalize()?;
+            let bootstrap = args[argn].clone();
+            let path = Path::new(&bootstrap);
+            if !path.is_file() {
+                print_usage();
+                return Err(Error::Custom(format!(
+                    "bootstrap file not found: {}",
+                    bootstrap
+                )));
+            }
 
-    argn += 1;
+            if path.extension().and_then(std::ffi::OsStr::to_str) != Some("lua") {
+                print_usage();
+                return Err(Error::Custom(format!(
+                    "bootstrap is not a lua file: {}",
+                    bootstrap
+                )));
+            }
+
+            let bootstrap_path = path.canonicalize()?;
+            argn += 1;
+            (bootstrap, Some(bootstrap_path))
+        }
+    };
 
     let mut arg = String::new();
     arg.push_str("return {");
@@ -176,8 +318,12 @@ async fn async_main() -> Result<()> {
     }
     arg.push('}');
 
-    let contents = fs::read_to_string(&bootstrap_path)?;
-    if contents.contains("_G[\"__init__\"]") {
+    let contents = match (&bootstrap_path, bundle) {
+        (Some(path), _) => fs::read(path)?,
+        (None, Some(bundle)) => bundle.get(&bootstrap).unwrap_or_default().to_vec(),
+        (None, None) => Vec::new(),
+    };
+    if has_init_options(&contents) {
         //has init options
         unsafe {
             let lua = LuaState::new(ffi::luaL_newstate());
@@ -189,10 +335,14 @@ async fn async_main() -> Result<()> {
             ffi::lua_pushcfunction(lua_state.as_ptr(), not_null_wrapper!(laux::lua_traceback));
             assert_eq!(ffi::lua_gettop(lua_state.as_ptr()), 1);
 
+            let chunkname = CString::new(format!("@{}", bootstrap))?;
             if ffi::LUA_OK
-                != ffi::luaL_loadstring(
+                != ffi::luaL_loadbufferx(
                     lua_state.as_ptr(),
-                    CString::new(contents.as_str())?.as_ptr(),
+                    contents.as_ptr() as *const _,
+                    contents.len(),
+                    chunkname.as_ptr(),
+                    std::ptr::null(),
                 )
             {
                 return Err(Error::Custom(format!(
@@ -222,6 +372,25 @@ async fn async_main() -> Result<()> {
             logfile = laux::opt_field(lua_state, -1, "logfile");
             enable_stdout = laux::opt_field(lua_state, -1, "enable_stdout").unwrap_or(true);
             loglevel = laux::opt_field(lua_state, -1, "loglevel").unwrap_or_default();
+            if let Some(format) = laux::opt_field::<String>(lua_state, -1, "log_format") {
+                log_format = LogFormat::parse(&format)
+                    .ok_or_else(|| Error::Custom(format!("unknown log format '{}'", format)))?;
+            }
+            log_rotation = LogRotation {
+                max_size: laux::opt_field(lua_state, -1, "log_max_size").unwrap_or(0),
+                daily: laux::opt_field(lua_state, -1, "log_daily").unwrap_or(false),
+                max_files: laux::opt_field(lua_state, -1, "log_max_files").unwrap_or(0),
+                append: laux::opt_field(lua_state, -1, "log_append").unwrap_or(false),
+            };
+            if let Some(backend) = laux::opt_field::<String>(lua_state, -1, "timer") {
+                timer_config.backend = TimerBackend::parse(&backend)
+                    .ok_or_else(|| Error::Custom(format!("unknown timer backend '{}'", backend)))?;
+            }
+            timer_config.shards = laux::opt_field(lua_state, -1, "timer_shards").unwrap_or(1);
+            admin_addr = laux::opt_field(lua_state, -1, "admin_addr");
+            admin_token = laux::opt_field(lua_state, -1, "admin_token");
+            debug_addr = laux::opt_field(lua_state, -1, "debug_addr");
+            hot_reload = laux::opt_field(lua_state, -1, "hot_reload").unwrap_or(false);
             let mut path: String = laux::opt_field(lua_state, -1, "path").unwrap_or_default();
             if !path.is_empty() {
                 path = format!("package.path='{};'..package.path;", path);
@@ -230,26 +399,24 @@ async fn async_main() -> Result<()> {
         }
     }
 
-    if CONTEXT.get_env("PATH").is_none() {
-        let mut search_path = env::current_dir()?.canonicalize()?;
-        if !search_path.join("lualib").is_dir() {
-            search_path = env::current_exe()?.canonicalize()?.join("lualib");
-        }
-
-        if !search_path.is_dir() {
-            return Err(Error::Custom(format!(
-                "lualib dir not found: {}",
-                search_path.to_str().unwrap_or("")
-            )));
-        }
-
+    if bundle.is_some() {
+        // Bundled modules are found through the relative entries of
+        // package.path; see `bundle`.
+        let path = CONTEXT
+            .get_env("PATH")
+            .map(|p| String::from_utf8_lossy(&p).into_owned())
+            .unwrap_or_default();
+        let package_path = format!("package.path='./lualib/?.lua;'..package.path;{}", path);
+        CONTEXT.set_env("PATH", package_path.as_bytes());
+    } else if CONTEXT.get_env("PATH").is_none() {
+        let mut search_path = find_lualib()?;
         if let Some(path_with_no_prefix) = search_path.to_string_lossy().strip_prefix(r"\\?\") {
             search_path = PathBuf::from(path_with_no_prefix);
         }
 
         let strpath = search_path.to_string_lossy().replace('\\', "/");
         //Lualib directories are added to the lua search path
-        let package_path = format!("package.path='{}/lualib/?.lua;'..package.path;", strpath);
+        let package_path = format!("package.path='{}/?.lua;'..package.path;", strpath);
 
         CONTEXT.set_env("PATH", package_path.as_bytes());
     }
@@ -290,20 +457,24 @@ async fn async_main() -> Result<()> {
         CONTEXT.set_env("PATH", format!("{}{}", package_path, cpath).as_bytes());
     }
 
-    let cwd = bootstrap_path.parent().unwrap_or(Path::new("./"));
-    //Change the working directory to the directory where the opened file is located.
-    env::set_current_dir(cwd)?;
-
-    bootstrap = bootstrap_path
-        .file_name()
-        .unwrap_or_default()
-        .to_string_lossy()
-        .as_ref()
-        .to_string();
+    if let Some(bootstrap_path) = bootstrap_path {
+        let cwd = bootstrap_path.parent().unwrap_or(Path::new("./"));
+        //Change the working directory to the directory where the opened file is located.
+        env::set_current_dir(cwd)?;
+
+        bootstrap = bootstrap_path
+            .file_name()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .as_ref()
+            .to_string();
+    }
 
     CONTEXT.set_env("ARG", arg.as_bytes());
 
-    if let Err(err) = LOGGER.setup_logger(enable_stdout, logfile, loglevel) {
+    if let Err(err) =
+        LOGGER.setup_logger(enable_stdout, logfile, loglevel, log_rotation, log_format)
+    {
         return Err(Error::Custom(err.to_string()));
     }
 
@@ -314,13 +485,51 @@ async fn async_main() -> Result<()> {
 
     context::run_monitor();
 
-    context::run_timer();
+    timer::run_timer(timer_config);
 
     // Build the message-decoder dispatch table once, before any actor spawns.
     moon_runtime::init_message_decoders();
 
     log::info!("system start. ({}:{})", file!(), line!());
 
+    if let Some(addr) = admin_addr {
+        let console = admin_token.as_deref().is_some_and(|t| !t.is_empty());
+        match admin::run_admin(&addr, admin_token) {
+            Ok(local) => {
+                log::info!("admin listening on http://{}/metrics", local);
+                if console {
+                    log::info!("admin console enabled on {}", local);
+                }
+            }
+            Err(err) => {
+                return Err(Error::Custom(format!(
+                    "admin listen '{}' failed: {}",
+                    addr, err
+                )));
+            }
+        }
+    }
+
+    if let Some(addr) = debug_addr {
+        match dap::run_dap(&addr) {
+            Ok(local) => log::info!("debug adapter listening on {}", local),
+            Err(err) => {
+                return Err(Error::Custom(format!(
+                    "debug listen '{}' failed: {}",
+                    addr, err
+                )));
+            }
+        }
+    }
+
+    if hot_reload && bundle.is_some() {
+        log::warn!("hot reload is not available for bundled scripts");
+    } else if hot_reload {
+        hotreload::enable()
+            .map_err(|err| Error::Custom(format!("hot reload failed: {}", err)))?;
+        log::info!("hot reload enabled");
+    }
+
     // Pre-register the cluster pseudo-actor so `next_actor_id()` skips its
     // reserved ID (2), preventing a collision if a user actor is spawned
     // before `cluster.init()` runs. The dummy channel is replaced by the real
@@ -330,17 +539,36 @@ async fn async_main() -> Result<()> {
         CONTEXT.register_pseudo_actor(CLUSTER_ACTOR_ADDR, dummy_tx);
     }
 
-    lua_actor::new_actor(LuaActorParam {
-        id: context::BOOTSTRAP_ACTOR_ADDR,
-        unique: true,
-        creator: 0,
-        session: 0,
-        memlimit: 0,
-        name: "bootstrap".to_string(),
-        source: bootstrap,
-        params: package_path,
-        block: true,
-    });
+    if let Some(file) = replay_file {
+        lua_actor::replay_actor(&file.to_string_lossy(), bootstrap).map_err(Error::Custom)?;
+    } else {
+        lua_actor::new_actor(LuaActorParam {
+            id: context::BOOTSTRAP_ACTOR_ADDR,
+            unique: true,
+            creator: 0,
+            session: 0,
+            memlimit: 0,
+            mem_soft_limit: 0,
+            mem_action: Default::default(),
+            name: "bootstrap".to_string(),
+            source: bootstrap,
+            params: package_path,
+            block: true,
+            mailbox_capacity: 0,
+            mailbox_policy: MailboxPolicy::Block,
+            log_level: 0,
+            log_file: None,
+            restart: Default::default(),
+            restart_log: Default::default(),
+            group: String::new(),
+            balance: None,
+            record: None,
+            seed: None,
+            budget: Default::default(),
+            priority_lanes: false,
+            watchdog: Default::default(),
+        });
+    }
 
     let mut last_report = std::time::Instant::now();
     loop {
@@ -62,5 +62,6 @@ fn main() {
             .compile("lua_sharetable");
     }
 
+    println!("cargo:rerun-if-changed=lua55");
     println!("cargo:rerun-if-changed=lualib-src");
 }
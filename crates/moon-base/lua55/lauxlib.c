@@ -1240,6 +1240,11 @@ luaL_initcodecache(void) {
 	SPIN_INIT(&CC);
 }
 
+LUALIB_API void
+luaL_clearcodecache(void) {
+	clearcache();
+}
+
 static const void *
 load_proto(const char *key) {
   lua_State *L;
@@ -1371,6 +1376,52 @@ LUALIB_API int luaL_loadfilex (lua_State *L, const char *filename,
   return LUA_OK;
 }
 
+/* Same as luaL_loadfilex, for a chunk in memory cached under its 'name'. */
+LUALIB_API int luaL_loadbuffercache (lua_State *L, const char *buff, size_t sz,
+                                     const char *name, const char *mode) {
+  int level = cache_level(L);
+  const void * proto;
+  lua_State * eL;
+  int err;
+  const void * oldv;
+  if (level == CACHE_OFF) {
+    return luaL_loadbufferx(L, buff, sz, name, mode);
+  }
+  proto = load_proto(name);
+  if (proto) {
+    lua_clonefunction(L, proto);
+    return LUA_OK;
+  }
+  if (level == CACHE_EXIST) {
+    return luaL_loadbufferx(L, buff, sz, name, mode);
+  }
+  eL = newState(L);
+  if (eL == NULL) {
+    lua_pushliteral(L, "New state failed");
+    return LUA_ERRMEM;
+  }
+  err = luaL_loadbufferx(eL, buff, sz, name, mode);
+  if (err != LUA_OK) {
+    size_t len = 0;
+    const char * msg = lua_tolstring(eL, -1, &len);
+    lua_pushlstring(L, msg, len);
+    lua_close(eL);
+    return err;
+  }
+  lua_sharefunction(eL, -1);
+  proto = lua_topointer(eL, -1);
+  oldv = save_proto(L, name, proto);
+  if (oldv) {
+    lua_close(eL);
+    lua_clonefunction(L, oldv);
+  } else {
+    lua_clonefunction(L, proto);
+    /* Never close it. notice: memory leak */
+  }
+
+  return LUA_OK;
+}
+
 static int
 cache_clear(lua_State *L) {
 	(void)(L);
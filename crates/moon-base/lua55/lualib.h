@@ -24,6 +24,9 @@ LUAMOD_API int (luaopen_package) (lua_State *L);
 #define LUA_CACHELIB
 LUAMOD_API int (luaopen_cache) (lua_State *L);
 LUALIB_API void (luaL_initcodecache) (void);
+LUALIB_API void (luaL_clearcodecache) (void);
+LUALIB_API int (luaL_loadbuffercache) (lua_State *L, const char *buff, size_t sz,
+                                     const char *name, const char *mode);
 
 #define LUA_COLIBNAME	"coroutine"
 #define LUA_COLIBK	(LUA_LOADLIBK << 1)
@@ -72,8 +72,15 @@ static const char *l_str2int(const char *s, size_t len, lua_Integer *result) {
     return s+i;
 }
 
-static void decode_one(lua_State* L, yyjson_val* value, const json_options* opt)
+/* Mirror of `MAX_DECODE_DEPTH` in lua_json.rs. */
+#define JSON_DECODE_MAX_DEPTH 64
+
+/* Returns 0 on success, -1 when the nesting exceeds JSON_DECODE_MAX_DEPTH. The
+ * caller raises the Lua error so the yyjson document can be freed first. */
+static int decode_one(lua_State* L, yyjson_val* value, const json_options* opt, int depth)
 {
+    if (depth > JSON_DECODE_MAX_DEPTH)
+        return -1;
     yyjson_type type = yyjson_get_type(value);
     switch (type)
     {
@@ -86,7 +93,8 @@ static void decode_one(lua_State* L, yyjson_val* value, const json_options* opt)
         yyjson_arr_iter_init(value, &iter);
         while (nullptr != (value = yyjson_arr_iter_next(&iter)))
         {
-            decode_one(L, value, opt);
+            if (decode_one(L, value, opt, depth + 1) != 0)
+                return -1;
             lua_rawseti(L, -2, pos++);
         }
         if (opt->has_metatfield)
@@ -124,7 +132,8 @@ static void decode_one(lua_State* L, yyjson_val* value, const json_options* opt)
                 {
                     lua_pushlstring(L, key_str, key_len);
                 }
-                decode_one(L, val, opt);
+                if (decode_one(L, val, opt, depth + 1) != 0)
+                    return -1;
                 lua_rawset(L, -3);
             }
         }
@@ -176,6 +185,7 @@ static void decode_one(lua_State* L, yyjson_val* value, const json_options* opt)
     default:
         break;
     }
+    return 0;
 }
 
 LUALIB_API int lua_json_decode(lua_State* L)
@@ -211,7 +221,9 @@ LUALIB_API int lua_json_decode(lua_State* L)
     {
         return luaL_error(L, "decode error: %s code: %d at position: %d\n", err.msg, (int)err.code, (int)err.pos);
     }
-    decode_one(L, yyjson_doc_get_root(doc), opt);
+    int rc = decode_one(L, yyjson_doc_get_root(doc), opt, 0);
     yyjson_doc_free(doc);
+    if (rc != 0)
+        return luaL_error(L, "decode error: too deep");
     return 1;
 }
@@ -50,6 +50,16 @@ unsafe extern "C-unwind" {
     pub fn luaL_openselectedlibs(L: *mut lua_State, load: c_int, preload: c_int);
 
     pub fn luaL_initcodecache();
+    /// Forget cached file chunks, so files are compiled again on next load.
+    pub fn luaL_clearcodecache();
+    /// Load a chunk from memory through the code cache, keyed by `name`.
+    pub fn luaL_loadbuffercache(
+        L: *mut lua_State,
+        buff: *const c_char,
+        sz: usize,
+        name: *const c_char,
+        mode: *const c_char,
+    ) -> c_int;
 }
 
 pub unsafe fn luaL_openlibs(L: *mut lua_State) { unsafe {
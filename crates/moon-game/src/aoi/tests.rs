@@ -10,6 +10,7 @@ const F: i32 = FIXED;
 const H: i32 = HIDE;
 
 /// Insert helper with `user = 0` (the C++ `insert(...)` without extra args).
+#[allow(clippy::too_many_arguments)]
 fn ins(a: &mut Aoi, handle: Handle, x: i32, y: i32, w: i32, h: i32, layer: i32, mode: i32) -> bool {
     a.insert(handle, x, y, w, h, layer, mode, 0)
 }
@@ -1,4 +1,13 @@
-use crate::context::{ActorId, LuaActorParam, Watchdog};
+use crate::{
+    context::{
+        ActorId, ExitReason, LuaActorParam, MailboxPolicy, MemoryAction, RestartMode, Watchdog,
+        WatchdogConfig,
+    },
+    group::Balance,
+    log::LogRoute,
+    memprofile::AllocProfile,
+    record::Recorder,
+};
 use moon_base::laux::{LuaGlobalState, LuaState, LuaThread};
 
 pub use moon_base as ffi;
@@ -14,6 +23,38 @@ pub struct LuaActor {
     pub mem: isize,
     pub mem_limit: isize,
     pub mem_warning: isize,
+    /// Soft limit: crossing it triggers a full GC after the dispatch and, if
+    /// that is not enough, `moon.on_memory_pressure` (0 = none).
+    pub mem_soft_limit: isize,
+    pub mem_action: MemoryAction,
+    /// Set by the allocator, handled between dispatches: the soft limit was
+    /// crossed, and whether memory is above the soft limit since the last
+    /// pressure event.
+    pub mem_pressure: bool,
+    pub mem_above_soft: bool,
+    /// The last dispatch failed with a memory error, handled between
+    /// dispatches as a hard-limit event.
+    pub mem_failed: bool,
+    /// Allocation profile, while `moon.memprofile` runs, see `memprofile.rs`.
+    pub memprofile: Option<Box<AllocProfile>>,
+    pub mailbox_capacity: usize,
+    pub mailbox_policy: MailboxPolicy,
+    pub watchdog_config: WatchdogConfig,
+    /// Log level override (0 = follow the global level), see `lua_actor_log`.
+    pub log_level: u8,
+    /// Dedicated log file, when the service was created with `logfile`.
+    pub log_route: Option<LogRoute>,
+    /// Restart mode from the service params. A supervised service is stopped
+    /// (and restarted) after a watchdog timeout or memory error instead of
+    /// carrying on with whatever state the failed dispatch left behind.
+    pub restart: RestartMode,
+    /// Why the service is stopping, set before its run loop exits.
+    pub exit_reason: Option<ExitReason>,
+    /// Service group joined in `add_actor` (empty = none).
+    pub group: String,
+    pub balance: Option<Balance>,
+    /// Set when the service was created with `record`, see `record.rs`.
+    pub recorder: Option<Recorder>,
     /// Raw pointer to the per-actor Watchdog (kept alive by Arc<Watchdog> in
     /// ActorEntry). Used by lua_coroutine.rs switchL and signal_hook via
     /// extraspace chain.
@@ -35,6 +76,22 @@ impl LuaActor {
             mem: 0,
             mem_limit: params.memlimit as isize,
             mem_warning: 8 * 1024 * 1024,
+            mem_soft_limit: params.mem_soft_limit as isize,
+            mem_action: params.mem_action,
+            mem_pressure: false,
+            mem_above_soft: false,
+            mem_failed: false,
+            memprofile: None,
+            mailbox_capacity: params.mailbox_capacity,
+            mailbox_policy: params.mailbox_policy,
+            watchdog_config: params.watchdog,
+            log_level: params.log_level,
+            log_route: None,
+            restart: params.restart.mode,
+            exit_reason: None,
+            group: params.group.clone(),
+            balance: params.balance,
+            recorder: None,
             watchdog: std::ptr::null(),
         }
     }
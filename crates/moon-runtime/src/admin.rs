@@ -0,0 +1,699 @@
+//! Built-in admin HTTP listener, enabled with `admin_addr` in the bootstrap
+//! `__init__` table. It runs on the IO runtime, independent of any Lua
+//! service, so it keeps answering while services are busy or blocked.
+//!
+//! Routes:
+//! - `GET /metrics`: Prometheus text format, see `metrics.rs`.
+//! - `POST /console`: run the console command in the request body and return
+//!   its output as text. Needs `Authorization: Bearer <admin_token>`.
+//!
+//! The console is only enabled when `admin_token` is set. Besides HTTP, the
+//! listener then takes line-based console sessions from telnet or `nc`: a
+//! connection whose first line is not an HTTP request line is a session, and
+//! that line must be `auth <token>`. See [`HELP`] for the commands.
+//!
+//! Commands that run inside a service (`debug`, `eval`, `hotfix`) are sent to
+//! it as the `admin` system command, from a pseudo-actor owned by the console;
+//! `moon.lua` runs them in a coroutine and sends the output back as a text or
+//! error message.
+
+use aws_lc_rs::{constant_time, digest};
+use std::{
+    collections::HashMap,
+    io,
+    net::SocketAddr,
+    sync::{
+        Arc, Mutex, OnceLock,
+        atomic::{AtomicI64, Ordering},
+    },
+    time::Duration,
+};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader},
+    net::{TcpListener, TcpStream},
+    sync::{mpsc, oneshot},
+    time::timeout,
+};
+
+use crate::{
+    context::{
+        ActorId, CLUSTER_ACTOR_ADDR, CONTEXT, LOGGER, Message, MessageBody, PTYPE_ERROR,
+        PTYPE_SYSTEM, PTYPE_TEXT,
+    },
+    log::Logger,
+    lua_actor, metrics,
+};
+
+/// Largest request head accepted.
+const MAX_REQUEST_HEAD: usize = 8 * 1024;
+/// Largest console command, as an HTTP body or a console line.
+const MAX_COMMAND: usize = 64 * 1024;
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+/// A console session is closed after this long without a command.
+const CONSOLE_IDLE_TIMEOUT: Duration = Duration::from_secs(600);
+/// How long a service gets to answer a command.
+const COMMAND_TIMEOUT: Duration = Duration::from_secs(5);
+
+const LOG_LEVELS: [&str; 5] = ["EROR", "WARN", "INFO", "DBUG", "TRCE"];
+
+pub const HELP: &str = "\
+services                        list services
+service <service>               show one service
+kill <service>                  stop a service
+debug <service> <cmd> [args]    run a debug command: gc, mem, ping, state, latency, memprofile
+eval <service> <lua>            run a Lua chunk or expression in the service
+loglevel <level>                set the global log level: EROR, WARN, INFO, DBUG, TRCE
+loglevel <service> <level>      override a service's log level; 'default' follows the global one
+hotfix <service> <module> [new] hotfix a module with hotfix.update
+help                            show this help
+quit                            close the session
+<service> is an id (decimal or 0x hex) or a unique service name.
+";
+
+/// Bind `addr` and serve admin requests on the IO runtime. Binding happens
+/// before this returns, so a bad address or a port in use fails startup.
+/// `token` enables the console.
+pub fn run_admin(addr: &str, token: Option<String>) -> io::Result<SocketAddr> {
+    let listener = std::net::TcpListener::bind(addr)?;
+    listener.set_nonblocking(true)?;
+    let local_addr = listener.local_addr()?;
+    let console = token.filter(|t| !t.is_empty()).map(Console::new);
+    CONTEXT.io_runtime().spawn(async move {
+        let listener = match TcpListener::from_std(listener) {
+            Ok(listener) => listener,
+            Err(err) => {
+                log::error!("admin listener {} failed: {}", local_addr, err);
+                return;
+            }
+        };
+        loop {
+            match listener.accept().await {
+                Ok((stream, _)) => {
+                    let console = console.clone();
+                    tokio::spawn(async move {
+                        if let Err(err) = handle_connection(stream, console).await {
+                            log::debug!("admin connection: {}", err);
+                        }
+                    });
+                }
+                Err(err) => {
+                    log::warn!("admin accept failed: {}", err);
+                    tokio::time::sleep(Duration::from_millis(100)).await;
+                }
+            }
+        }
+    });
+    Ok(local_addr)
+}
+
+fn timed_out() -> io::Error {
+    io::Error::new(io::ErrorKind::TimedOut, "request timeout")
+}
+
+async fn handle_connection(stream: TcpStream, console: Option<Arc<Console>>) -> io::Result<()> {
+    let mut reader = BufReader::new(stream);
+    let Some(first) = timeout(REQUEST_TIMEOUT, read_line(&mut reader, MAX_REQUEST_HEAD))
+        .await
+        .map_err(|_| timed_out())??
+    else {
+        return Ok(());
+    };
+    match console {
+        Some(console) if !is_request_line(&first) => serve_console(reader, first, console).await,
+        console => serve_http(reader, first, console).await,
+    }
+}
+
+/// One line, without its line ending; `None` at end of stream.
+async fn read_line(reader: &mut BufReader<TcpStream>, limit: usize) -> io::Result<Option<String>> {
+    let mut line = Vec::new();
+    let n = (&mut *reader)
+        .take(limit as u64 + 1)
+        .read_until(b'\n', &mut line)
+        .await?;
+    if n == 0 {
+        return Ok(None);
+    }
+    if line.len() > limit {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "line too long"));
+    }
+    let line = String::from_utf8_lossy(&line);
+    Ok(Some(line.trim_end_matches(['\r', '\n']).to_string()))
+}
+
+fn is_request_line(line: &str) -> bool {
+    line.rsplit(' ')
+        .next()
+        .is_some_and(|v| v.starts_with("HTTP/"))
+}
+
+async fn serve_http(
+    mut reader: BufReader<TcpStream>,
+    first: String,
+    console: Option<Arc<Console>>,
+) -> io::Result<()> {
+    let head = timeout(REQUEST_TIMEOUT, read_head(&mut reader, first))
+        .await
+        .map_err(|_| timed_out())??;
+    let (status, content_type, body) = if request_path(&head) == "/console" {
+        console_route(&mut reader, &head, console.as_deref()).await?
+    } else {
+        route(&head)
+    };
+    let stream = reader.get_mut();
+    let response = format!(
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        status,
+        content_type,
+        body.len()
+    );
+    stream.write_all(response.as_bytes()).await?;
+    stream.write_all(body.as_bytes()).await?;
+    stream.shutdown().await
+}
+
+/// Read the header lines following the request line `first`.
+async fn read_head(reader: &mut BufReader<TcpStream>, first: String) -> io::Result<String> {
+    let mut head = first;
+    head.push_str("\r\n");
+    loop {
+        let line = read_line(reader, MAX_REQUEST_HEAD)
+            .await?
+            .unwrap_or_default();
+        if line.is_empty() {
+            break;
+        }
+        head.push_str(&line);
+        head.push_str("\r\n");
+        if head.len() > MAX_REQUEST_HEAD {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "request head too large",
+            ));
+        }
+    }
+    head.push_str("\r\n");
+    Ok(head)
+}
+
+fn request_path(head: &str) -> &str {
+    let target = head
+        .lines()
+        .next()
+        .unwrap_or_default()
+        .split(' ')
+        .nth(1)
+        .unwrap_or_default();
+    target.split('?').next().unwrap_or_default()
+}
+
+/// Value of the header `name`, matched case-insensitively.
+fn header<'a>(head: &'a str, name: &str) -> Option<&'a str> {
+    head.lines().skip(1).find_map(|line| {
+        let (key, value) = line.split_once(':')?;
+        key.trim().eq_ignore_ascii_case(name).then(|| value.trim())
+    })
+}
+
+/// Map a request head to `(status line, content type, body)`.
+fn route(head: &str) -> (&'static str, &'static str, String) {
+    let method = head.split(' ').next().unwrap_or_default();
+    match (method, request_path(head)) {
+        ("GET", "/metrics") => ("200 OK", metrics::CONTENT_TYPE, metrics::render()),
+        (_, "/metrics") => (
+            "405 Method Not Allowed",
+            "text/plain",
+            "method not allowed\n".to_string(),
+        ),
+        _ => ("404 Not Found", "text/plain", "not found\n".to_string()),
+    }
+}
+
+async fn console_route(
+    reader: &mut BufReader<TcpStream>,
+    head: &str,
+    console: Option<&Console>,
+) -> io::Result<(&'static str, &'static str, String)> {
+    let Some(console) = console else {
+        return Ok((
+            "403 Forbidden",
+            "text/plain",
+            "console disabled: set admin_token\n".to_string(),
+        ));
+    };
+    if !head.starts_with("POST ") {
+        return Ok((
+            "405 Method Not Allowed",
+            "text/plain",
+            "method not allowed\n".to_string(),
+        ));
+    }
+    let token = header(head, "Authorization").and_then(|v| v.strip_prefix("Bearer "));
+    if !token.is_some_and(|t| console.check_token(t.trim())) {
+        return Ok((
+            "401 Unauthorized",
+            "text/plain",
+            "unauthorized\n".to_string(),
+        ));
+    }
+    let length = header(head, "Content-Length")
+        .and_then(|v| v.parse::<usize>().ok())
+        .unwrap_or(0);
+    if length > MAX_COMMAND {
+        return Ok((
+            "413 Payload Too Large",
+            "text/plain",
+            "command too large\n".to_string(),
+        ));
+    }
+    let mut body = vec![0u8; length];
+    timeout(REQUEST_TIMEOUT, reader.read_exact(&mut body))
+        .await
+        .map_err(|_| timed_out())??;
+    Ok(
+        match console.execute(&String::from_utf8_lossy(&body)).await {
+            Ok(output) => ("200 OK", "text/plain", output),
+            Err(err) => ("400 Bad Request", "text/plain", format!("error: {}\n", err)),
+        },
+    )
+}
+
+async fn serve_console(
+    mut reader: BufReader<TcpStream>,
+    first: String,
+    console: Arc<Console>,
+) -> io::Result<()> {
+    let mut line = first;
+    let mut authenticated = false;
+    loop {
+        let command = line.trim();
+        let output = if command.is_empty() {
+            String::new()
+        } else if command == "quit" || command == "exit" {
+            break;
+        } else if !authenticated {
+            match command.strip_prefix("auth ") {
+                Some(token) if console.check_token(token.trim()) => {
+                    authenticated = true;
+                    "ok\n".to_string()
+                }
+                _ => {
+                    reader
+                        .get_mut()
+                        .write_all(b"error: authenticate with 'auth <token>'\n")
+                        .await?;
+                    break;
+                }
+            }
+        } else {
+            match console.execute(command).await {
+                Ok(output) => output,
+                Err(err) => format!("error: {}\n", err),
+            }
+        };
+        reader.get_mut().write_all(output.as_bytes()).await?;
+        line = match timeout(CONSOLE_IDLE_TIMEOUT, read_line(&mut reader, MAX_COMMAND)).await {
+            Ok(line) => match line? {
+                Some(line) => line,
+                None => break,
+            },
+            Err(_) => break,
+        };
+    }
+    reader.get_mut().shutdown().await
+}
+
+/// Console state of one listener: the token and the pseudo-actor that sends
+/// commands to services and receives their answers.
+struct Console {
+    token: String,
+    id: OnceLock<ActorId>,
+    session: AtomicI64,
+    pending: Arc<Mutex<HashMap<i64, oneshot::Sender<Message>>>>,
+}
+
+impl Console {
+    fn new(token: String) -> Arc<Console> {
+        Arc::new(Console {
+            token,
+            id: OnceLock::new(),
+            session: AtomicI64::new(1),
+            pending: Default::default(),
+        })
+    }
+
+    /// The console's pseudo-actor, registered on first use. The listener
+    /// starts before the bootstrap service, which must still get the first id.
+    fn id(&self) -> ActorId {
+        *self.id.get_or_init(|| {
+            let (tx, mut rx) = mpsc::unbounded_channel::<Message>();
+            let id = CONTEXT.next_actor_id();
+            CONTEXT.register_pseudo_actor(id, tx);
+            let waiting = self.pending.clone();
+            CONTEXT.io_runtime().spawn(async move {
+                while let Some(m) = rx.recv().await {
+                    let waiter = waiting.lock().ok().and_then(|mut p| p.remove(&m.session));
+                    if let Some(waiter) = waiter {
+                        let _ = waiter.send(m);
+                    }
+                }
+            });
+            id
+        })
+    }
+
+    /// Compare SHA-256 digests in constant time, so neither the token nor its
+    /// length can be guessed from the time a check takes.
+    fn check_token(&self, token: &str) -> bool {
+        let expected = digest::digest(&digest::SHA256, self.token.as_bytes());
+        let given = digest::digest(&digest::SHA256, token.as_bytes());
+        constant_time::verify_slices_are_equal(expected.as_ref(), given.as_ref()).is_ok()
+    }
+
+    /// Run one command line; the output ends with a newline, and is `ok` when
+    /// the command printed nothing.
+    async fn execute(&self, line: &str) -> Result<String, String> {
+        let line = line.trim();
+        let (cmd, rest) = split_word(line);
+        let mut output = match cmd {
+            "help" => HELP.to_string(),
+            "services" => self.services(),
+            "service" => self.service(rest)?,
+            "kill" => {
+                let id = CONTEXT.resolve_service(rest)?;
+                lua_actor::remove_actor(id, self.id())?;
+                "ok".to_string()
+            }
+            "debug" => {
+                let (service, args) = split_word(rest);
+                let args: Vec<serde_json::Value> = args.split_whitespace().map(argument).collect();
+                if args.is_empty() {
+                    return Err("usage: debug <service> <cmd> [args]".to_string());
+                }
+                self.call(service, "debug", args).await?
+            }
+            "eval" => {
+                let (service, code) = split_word(rest);
+                if code.is_empty() {
+                    return Err("usage: eval <service> <lua>".to_string());
+                }
+                self.call(service, "eval", vec![code.into()]).await?
+            }
+            "loglevel" => self.loglevel(rest)?,
+            "hotfix" => {
+                let (service, args) = split_word(rest);
+                let args: Vec<serde_json::Value> =
+                    args.split_whitespace().map(|a| a.into()).collect();
+                if args.is_empty() || args.len() > 2 {
+                    return Err("usage: hotfix <service> <module> [new]".to_string());
+                }
+                self.call(service, "hotfix", args).await?
+            }
+            "" => return Ok(String::new()),
+            _ => return Err(format!("unknown command '{}', try 'help'", cmd)),
+        };
+        if output.is_empty() {
+            // A command always answers, so a client can wait for one line.
+            output.push_str("ok");
+        }
+        if !output.ends_with('\n') {
+            output.push('\n');
+        }
+        Ok(output)
+    }
+
+    fn services(&self) -> String {
+        let mut stats: Vec<_> = CONTEXT
+            .actor_stats()
+            .into_iter()
+            .filter(|s| s.id != CLUSTER_ACTOR_ADDR && Some(&s.id) != self.id.get())
+            .collect();
+        stats.sort_by_key(|s| s.id);
+        let mut out = format!(
+            "{:<10} {:<20} {:>12} {:>10} {:>10} {:>8}\n",
+            "id", "name", "memory", "messages", "cpu_ms", "queue"
+        );
+        for s in stats {
+            out.push_str(&format!(
+                "{:<10} {:<20} {:>12} {:>10} {:>10} {:>8}\n",
+                s.id,
+                s.name.as_deref().unwrap_or("-"),
+                s.memory,
+                s.messages,
+                s.cpu_ms,
+                s.queue
+            ));
+        }
+        out
+    }
+
+    fn service(&self, service: &str) -> Result<String, String> {
+        let id = CONTEXT.resolve_service(service)?;
+        let s = CONTEXT
+            .actor_stats()
+            .into_iter()
+            .find(|s| s.id == id)
+            .ok_or_else(|| format!("service {} not found", id))?;
+        let mut out = format!(
+            "id: {}\nname: {}\nmemory: {}\nmessages: {}\ncpu_ms: {}\nqueue: {}\ncapacity: {}\ndropped: {}\n",
+            s.id,
+            s.name.as_deref().unwrap_or("-"),
+            s.memory,
+            s.messages,
+            s.cpu_ms,
+            s.queue,
+            s.capacity,
+            s.dropped
+        );
+        for l in s.latency {
+            out.push_str(&format!(
+                "latency ptype {}: count {} wait p99 {}us dispatch p99 {}us\n",
+                l.ptype, l.dispatch.count, l.wait.p99_us, l.dispatch.p99_us
+            ));
+        }
+        Ok(out)
+    }
+
+    fn loglevel(&self, args: &str) -> Result<String, String> {
+        let args: Vec<&str> = args.split_whitespace().collect();
+        let level = |lv: &str| {
+            let upper = lv.to_uppercase();
+            if LOG_LEVELS.contains(&upper.as_str()) {
+                Ok(upper)
+            } else {
+                Err(format!("unknown log level '{}'", lv))
+            }
+        };
+        match args[..] {
+            [lv] => {
+                LOGGER.set_log_level(Logger::string_to_level(level(lv)?));
+            }
+            [service, lv] => {
+                let id = CONTEXT.resolve_service(service)?;
+                let lv = if lv == "default" {
+                    String::new()
+                } else {
+                    level(lv)?
+                };
+                let msg = Message::new(
+                    self.id(),
+                    id,
+                    0,
+                    MessageBody::Buffer(
+                        PTYPE_SYSTEM,
+                        Box::new(format!("_loglevel,{}", lv).into_bytes().into()),
+                    ),
+                );
+                if CONTEXT.send(msg).is_some() {
+                    return Err(format!("service {} not found", id));
+                }
+            }
+            _ => return Err("usage: loglevel [<service>] <level>".to_string()),
+        }
+        Ok("ok".to_string())
+    }
+
+    /// Run `cmd` inside `service` and wait for its output.
+    async fn call(
+        &self,
+        service: &str,
+        cmd: &str,
+        args: Vec<serde_json::Value>,
+    ) -> Result<String, String> {
+        let id = CONTEXT.resolve_service(service)?;
+        let session = self.session.fetch_add(1, Ordering::Relaxed);
+        let (tx, rx) = oneshot::channel();
+        self.lock_pending().insert(session, tx);
+        let request = serde_json::json!({ "session": session, "cmd": cmd, "args": args });
+        let msg = Message::new(
+            self.id(),
+            id,
+            0,
+            MessageBody::Buffer(
+                PTYPE_SYSTEM,
+                Box::new(format!("admin,{}", request).into_bytes().into()),
+            ),
+        );
+        if CONTEXT.send(msg).is_some() {
+            self.lock_pending().remove(&session);
+            return Err(format!("service {} not found", id));
+        }
+        let reply = match timeout(COMMAND_TIMEOUT, rx).await {
+            Ok(Ok(reply)) => reply,
+            _ => {
+                self.lock_pending().remove(&session);
+                return Err(format!(
+                    "service {} did not answer within {}s",
+                    id,
+                    COMMAND_TIMEOUT.as_secs()
+                ));
+            }
+        };
+        let (ptype, text) = match &reply.data {
+            MessageBody::Buffer(ptype, buf) => {
+                (*ptype, String::from_utf8_lossy(buf.as_slice()).into_owned())
+            }
+            body => (body.ptype(), String::new()),
+        };
+        match ptype {
+            PTYPE_TEXT => Ok(text),
+            PTYPE_ERROR => Err(text),
+            _ => Err(format!("unexpected reply of type {}", ptype)),
+        }
+    }
+
+    fn lock_pending(&self) -> std::sync::MutexGuard<'_, HashMap<i64, oneshot::Sender<Message>>> {
+        self.pending.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// Split off the first word of `s`.
+fn split_word(s: &str) -> (&str, &str) {
+    let s = s.trim_start();
+    match s.split_once(char::is_whitespace) {
+        Some((word, rest)) => (word, rest.trim_start()),
+        None => (s, ""),
+    }
+}
+
+/// A command argument: numbers and booleans keep their type, anything else is
+/// a string.
+fn argument(s: &str) -> serde_json::Value {
+    match serde_json::from_str::<serde_json::Value>(s) {
+        Ok(v) if v.is_number() || v.is_boolean() => v,
+        _ => s.into(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn route_serves_metrics_only() {
+        let (status, content_type, body) = route("GET /metrics?x=1 HTTP/1.1\r\nHost: a\r\n\r\n");
+        assert_eq!(status, "200 OK");
+        assert_eq!(content_type, metrics::CONTENT_TYPE);
+        assert!(body.contains("# TYPE moon_services gauge"));
+
+        assert_eq!(
+            route("POST /metrics HTTP/1.1\r\n\r\n").0,
+            "405 Method Not Allowed"
+        );
+        assert_eq!(route("GET / HTTP/1.1\r\n\r\n").0, "404 Not Found");
+        assert_eq!(route("").0, "404 Not Found");
+    }
+
+    #[test]
+    fn admin_listener_answers_over_tcp() {
+        let addr = run_admin("127.0.0.1:0", None).unwrap();
+        let body = CONTEXT.io_runtime().block_on(async move {
+            let mut stream = TcpStream::connect(addr).await.unwrap();
+            stream
+                .write_all(b"GET /metrics HTTP/1.1\r\nHost: test\r\n\r\n")
+                .await
+                .unwrap();
+            let mut response = String::new();
+            stream.read_to_string(&mut response).await.unwrap();
+            response
+        });
+        assert!(body.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(body.contains("moon_uptime_seconds"));
+    }
+
+    #[test]
+    fn parses_commands_and_headers() {
+        assert!(is_request_line("POST /console HTTP/1.1"));
+        assert!(!is_request_line("auth secret"));
+        assert_eq!(
+            split_word("  eval 12  return 1 + 1"),
+            ("eval", "12  return 1 + 1")
+        );
+        assert_eq!(split_word("services"), ("services", ""));
+        assert_eq!(argument("64"), serde_json::json!(64));
+        assert_eq!(argument("true"), serde_json::json!(true));
+        assert_eq!(argument("live"), serde_json::json!("live"));
+        let head = "POST /console HTTP/1.1\r\nauthorization: Bearer abc\r\n\r\n";
+        assert_eq!(header(head, "Authorization"), Some("Bearer abc"));
+        assert_eq!(header(head, "Content-Length"), None);
+    }
+
+    #[test]
+    fn check_token_needs_the_whole_token() {
+        let console = Console::new("secret".to_string());
+        assert!(console.check_token("secret"));
+        for wrong in ["", "secre", "secrets", "Secret"] {
+            assert!(!console.check_token(wrong), "{}", wrong);
+        }
+    }
+
+    #[test]
+    fn console_requires_the_token() {
+        let addr = run_admin("127.0.0.1:0", Some("secret".to_string())).unwrap();
+        let exchange = |request: &'static [u8]| {
+            CONTEXT.io_runtime().block_on(async move {
+                let mut stream = TcpStream::connect(addr).await.unwrap();
+                stream.write_all(request).await.unwrap();
+                let mut response = String::new();
+                stream.read_to_string(&mut response).await.unwrap();
+                response
+            })
+        };
+
+        let res = exchange(b"POST /console HTTP/1.1\r\nContent-Length: 4\r\n\r\nhelp");
+        assert!(res.starts_with("HTTP/1.1 401 Unauthorized\r\n"), "{}", res);
+        let res = exchange(
+            b"POST /console HTTP/1.1\r\nAuthorization: Bearer secret\r\nContent-Length: 4\r\n\r\nhelp",
+        );
+        assert!(res.starts_with("HTTP/1.1 200 OK\r\n"), "{}", res);
+        assert!(res.ends_with(HELP));
+        let res = exchange(
+            b"POST /console HTTP/1.1\r\nAuthorization: Bearer secret\r\nContent-Length: 11\r\n\r\nkill nobody",
+        );
+        assert!(res.contains("error: service 'nobody' not found"), "{}", res);
+
+        let res = exchange(b"auth wrong\nservices\n");
+        assert_eq!(res, "error: authenticate with 'auth <token>'\n");
+        let res = exchange(b"auth secret\nservices\nfrobnicate\nquit\n");
+        let mut lines = res.lines();
+        assert_eq!(lines.next(), Some("ok"));
+        assert!(lines.next().unwrap().starts_with("id "));
+        assert!(res.ends_with("error: unknown command 'frobnicate', try 'help'\n"));
+    }
+
+    #[test]
+    fn console_is_disabled_without_a_token() {
+        let addr = run_admin("127.0.0.1:0", None).unwrap();
+        let res = CONTEXT.io_runtime().block_on(async move {
+            let mut stream = TcpStream::connect(addr).await.unwrap();
+            stream
+                .write_all(b"POST /console HTTP/1.1\r\nContent-Length: 4\r\n\r\nhelp")
+                .await
+                .unwrap();
+            let mut response = String::new();
+            stream.read_to_string(&mut response).await.unwrap();
+            response
+        });
+        assert!(res.starts_with("HTTP/1.1 403 Forbidden\r\n"), "{}", res);
+    }
+}
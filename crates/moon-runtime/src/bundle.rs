@@ -0,0 +1,1000 @@
+//! Script bundles for single-file deployment.
+//!
+//! `moon_rs pack main.lua -o server` collects `lualib` and the game scripts
+//! into a [`Bundle`] and appends it to a copy of the executable. At startup
+//! the executable reads the bundle back from its own file (see [`load`]): the
+//! bootstrap script and the `source` of new services are looked up in it
+//! first, and a searcher in front of the file searcher serves `require` from
+//! it, using the relative entries of `package.path`.
+//!
+//! An encrypted or signed bundle is the only source of files to run: the file
+//! and C searchers are removed, `loadfile` and `dofile` read from the bundle,
+//! and a `source` missing from the bundle fails. `load` of a string is left
+//! alone.
+//!
+//! A bundle can be encrypted and signed with the [`Keys`] from `moon_rs
+//! keygen`. Each file is sealed with AES-256-GCM under its own nonce, with its
+//! name as associated data, and a GCM tag over the rest of the payload (the
+//! MAC) authenticates the entry and the file set. An Ed25519 signature covers
+//! the whole payload. The keys are given at startup through the environment
+//! (see [`Keys::from_env`]); a bundle that fails to verify or decrypt is
+//! refused before any script runs. File names are not encrypted.
+//!
+//! Layout of a packed executable:
+//!
+//! ```text
+//! executable | entry | flags: u8 | file count | (name, data)... | mac? | signature? | payload length: u64 | MAGIC
+//! ```
+//!
+//! Strings and data are prefixed with their length as a little-endian `u32`.
+//! Encrypted data is `nonce | ciphertext | tag`, and the MAC `nonce | tag`.
+
+use std::{
+    collections::BTreeMap,
+    ffi::{CString, c_int, c_void},
+    fs,
+    io::{Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+    sync::OnceLock,
+};
+
+use aws_lc_rs::{
+    aead::{AES_256_GCM, Aad, LessSafeKey, NONCE_LEN, Nonce, UnboundKey},
+    signature::{ED25519, Ed25519KeyPair, KeyPair, UnparsedPublicKey},
+};
+use moon_base::{
+    cstr, ffi,
+    laux::{self, LuaState},
+};
+
+use crate::error::{Error, Result};
+
+pub const MAGIC: &[u8; 8] = b"MOONPACK";
+
+const TRAILER_LEN: usize = 8 + MAGIC.len();
+
+const ENCRYPTED: u8 = 1;
+const SIGNED: u8 = 2;
+
+const SIGNATURE_LEN: usize = 64;
+
+const MAC_LEN: usize = NONCE_LEN + 16;
+
+/// Keys protecting a bundle. Packing uses `cipher` and `signing`; loading
+/// uses `cipher` and `verify`.
+#[derive(Default)]
+pub struct Keys {
+    /// AES-256-GCM key.
+    pub cipher: Option<[u8; 32]>,
+    pub signing: Option<Ed25519KeyPair>,
+    /// Ed25519 public key.
+    pub verify: Option<[u8; 32]>,
+}
+
+impl Keys {
+    /// Keys for loading: the cipher key from `MOON_PACK_KEY`, or the file
+    /// named by `MOON_PACK_KEY_FILE`, and the public key from
+    /// `MOON_PACK_PUBKEY` or `MOON_PACK_PUBKEY_FILE`. Keys are hex encoded.
+    pub fn from_env() -> Result<Keys> {
+        Ok(Keys {
+            cipher: env_key("MOON_PACK_KEY")?,
+            signing: None,
+            verify: env_key("MOON_PACK_PUBKEY")?,
+        })
+    }
+
+    fn seal(&self, name: &str, data: &[u8]) -> Result<Vec<u8>> {
+        if self.cipher.is_none() {
+            return Ok(data.to_vec());
+        }
+        self.seal_with(name.as_bytes(), data)
+    }
+
+    fn open(&self, name: &str, sealed: &[u8]) -> Result<Vec<u8>> {
+        self.open_with(name.as_bytes(), sealed)
+            .map_err(|_| Error::Custom(format!("'{}' is tampered or the key is wrong", name)))
+    }
+
+    /// Authenticate `body` under the cipher key: the GCM tag of an empty
+    /// message with `body` as associated data.
+    fn mac(&self, body: &[u8]) -> Result<Vec<u8>> {
+        self.seal_with(body, &[])
+    }
+
+    fn check_mac(&self, body: &[u8], mac: &[u8]) -> Result<()> {
+        self.open_with(body, mac)
+            .map(|_| ())
+            .map_err(|_| Error::custom("bundle is tampered or the key is wrong"))
+    }
+
+    /// `nonce | ciphertext | tag` of `data`, with `aad` as associated data.
+    fn seal_with(&self, aad: &[u8], data: &[u8]) -> Result<Vec<u8>> {
+        let key = self.cipher_key()?;
+        let mut nonce = [0u8; NONCE_LEN];
+        aws_lc_rs::rand::fill(&mut nonce).map_err(|_| Error::custom("no random source"))?;
+        let mut sealed = data.to_vec();
+        key.seal_in_place_append_tag(
+            Nonce::assume_unique_for_key(nonce),
+            Aad::from(aad),
+            &mut sealed,
+        )
+        .map_err(|_| Error::custom("encryption failed"))?;
+        sealed.splice(0..0, nonce);
+        Ok(sealed)
+    }
+
+    /// Inverse of [`Keys::seal_with`]; fails when `sealed` or `aad` changed.
+    fn open_with(&self, aad: &[u8], sealed: &[u8]) -> Result<Vec<u8>> {
+        let key = self.cipher_key()?;
+        let failed = || Error::custom("authentication failed");
+        if sealed.len() < NONCE_LEN {
+            return Err(failed());
+        }
+        let (nonce, data) = sealed.split_at(NONCE_LEN);
+        let nonce = Nonce::try_assume_unique_for_key(nonce).map_err(|_| failed())?;
+        let mut data = data.to_vec();
+        let len = key
+            .open_in_place(nonce, Aad::from(aad), &mut data)
+            .map_err(|_| failed())?
+            .len();
+        data.truncate(len);
+        Ok(data)
+    }
+
+    fn cipher_key(&self) -> Result<LessSafeKey> {
+        let Some(key) = &self.cipher else {
+            return Err(Error::custom(
+                "bundle is encrypted; set MOON_PACK_KEY or MOON_PACK_KEY_FILE",
+            ));
+        };
+        let key = UnboundKey::new(&AES_256_GCM, key).map_err(|_| Error::custom("invalid key"))?;
+        Ok(LessSafeKey::new(key))
+    }
+}
+
+/// Write a new cipher key to `<prefix>.key`, a signing key to `<prefix>.sign`
+/// and its public key to `<prefix>.pub`.
+pub fn keygen(prefix: &Path) -> Result<()> {
+    let mut cipher = [0u8; 32];
+    let mut seed = [0u8; 32];
+    aws_lc_rs::rand::fill(&mut cipher).map_err(|_| Error::custom("no random source"))?;
+    aws_lc_rs::rand::fill(&mut seed).map_err(|_| Error::custom("no random source"))?;
+    let pair = Ed25519KeyPair::from_seed_unchecked(&seed)
+        .map_err(|err| Error::Custom(format!("invalid signing key: {}", err)))?;
+
+    let path = |ext: &str| {
+        let mut path = prefix.as_os_str().to_owned();
+        path.push(ext);
+        PathBuf::from(path)
+    };
+    write_secret(&path(".key"), &to_hex(&cipher))?;
+    write_secret(&path(".sign"), &to_hex(&seed))?;
+    fs::write(path(".pub"), to_hex(pair.public_key().as_ref()) + "\n")?;
+    Ok(())
+}
+
+/// Read a hex key file written by [`keygen`].
+pub fn read_key(path: &Path) -> Result<[u8; 32]> {
+    let text = fs::read_to_string(path)
+        .map_err(|err| Error::Custom(format!("read key '{}': {}", path.display(), err)))?;
+    parse_key(&text)
+        .ok_or_else(|| Error::Custom(format!("key '{}' is not 64 hex characters", path.display())))
+}
+
+/// The signing key in the file written by [`keygen`].
+pub fn read_signing_key(path: &Path) -> Result<Ed25519KeyPair> {
+    Ed25519KeyPair::from_seed_unchecked(&read_key(path)?)
+        .map_err(|err| Error::Custom(format!("invalid signing key: {}", err)))
+}
+
+fn env_key(name: &str) -> Result<Option<[u8; 32]>> {
+    if let Ok(text) = std::env::var(name) {
+        return parse_key(&text)
+            .map(Some)
+            .ok_or_else(|| Error::Custom(format!("{} is not 64 hex characters", name)));
+    }
+    match std::env::var(format!("{}_FILE", name)) {
+        Ok(path) => read_key(Path::new(&path)).map(Some),
+        Err(_) => Ok(None),
+    }
+}
+
+fn write_secret(path: &Path, text: &str) -> Result<()> {
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    options
+        .open(path)?
+        .write_all(format!("{}\n", text).as_bytes())?;
+    Ok(())
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn parse_key(text: &str) -> Option<[u8; 32]> {
+    let text = text.trim().as_bytes();
+    if text.len() != 64 {
+        return None;
+    }
+    let mut key = [0u8; 32];
+    for (i, pair) in text.chunks(2).enumerate() {
+        let pair = std::str::from_utf8(pair).ok()?;
+        key[i] = u8::from_str_radix(pair, 16).ok()?;
+    }
+    Some(key)
+}
+
+/// Files of a bundle, keyed by path relative to the bootstrap script's
+/// directory. `lualib` is stored under `lualib/`.
+#[derive(Debug, Default, PartialEq)]
+pub struct Bundle {
+    entry: String,
+    files: BTreeMap<String, Vec<u8>>,
+    /// Loaded from an encrypted or signed payload.
+    sealed: bool,
+}
+
+impl Bundle {
+    pub fn new(entry: &str) -> Bundle {
+        Bundle {
+            entry: normalize(entry),
+            files: BTreeMap::new(),
+            sealed: false,
+        }
+    }
+
+    /// The bootstrap script.
+    pub fn entry(&self) -> &str {
+        &self.entry
+    }
+
+    pub fn insert(&mut self, name: &str, data: Vec<u8>) {
+        self.files.insert(normalize(name), data);
+    }
+
+    pub fn get(&self, name: &str) -> Option<&[u8]> {
+        self.files.get(&normalize(name)).map(Vec::as_slice)
+    }
+
+    /// Whether the bundle was encrypted or signed. Only bundled code runs
+    /// then.
+    pub fn is_sealed(&self) -> bool {
+        self.sealed
+    }
+
+    pub fn len(&self) -> usize {
+        self.files.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.files.is_empty()
+    }
+
+    /// Find module `name` with the relative templates of `package_path`.
+    pub fn search(&self, name: &str, package_path: &str) -> Option<String> {
+        let name = name.replace('.', "/");
+        package_path
+            .split(';')
+            .filter(|template| is_relative(template))
+            .map(|template| normalize(&template.replace('?', &name)))
+            .find(|file| self.files.contains_key(file))
+    }
+
+    /// Serialize the bundle, trailer included, ready to append to an
+    /// executable. Files are encrypted when `keys` has a cipher key, and the
+    /// payload is signed when it has a signing key.
+    pub fn encode(&self, keys: &Keys) -> Result<Vec<u8>> {
+        let size: usize = self.files.iter().map(|(k, v)| 36 + k.len() + v.len()).sum();
+        let mut buf = Vec::with_capacity(96 + self.entry.len() + size + TRAILER_LEN);
+        put(&mut buf, self.entry.as_bytes());
+        let mut flags = 0;
+        if keys.cipher.is_some() {
+            flags |= ENCRYPTED;
+        }
+        if keys.signing.is_some() {
+            flags |= SIGNED;
+        }
+        buf.push(flags);
+        buf.extend_from_slice(&(self.files.len() as u32).to_le_bytes());
+        for (name, data) in &self.files {
+            put(&mut buf, name.as_bytes());
+            put(&mut buf, &keys.seal(name, data)?);
+        }
+        if keys.cipher.is_some() {
+            let mac = keys.mac(&buf)?;
+            buf.extend_from_slice(&mac);
+        }
+        if let Some(signing) = &keys.signing {
+            let signature = signing.sign(&buf);
+            buf.extend_from_slice(signature.as_ref());
+        }
+        let payload_len = buf.len() as u64;
+        buf.extend_from_slice(&payload_len.to_le_bytes());
+        buf.extend_from_slice(MAGIC);
+        Ok(buf)
+    }
+
+    /// Parse a payload, without the trailer. A signed payload must verify
+    /// with `keys.verify`, and an encrypted one must match its MAC and
+    /// decrypt with `keys.cipher`. Given either key, the payload must be
+    /// signed or encrypted too, so a plain bundle cannot be swapped in.
+    pub fn decode(payload: &[u8], keys: &Keys) -> Result<Bundle> {
+        let mut reader = Reader { buf: payload };
+        let entry = reader.string()?;
+        let flags = reader.take(1)?[0];
+        let header_len = payload.len() - reader.buf.len();
+        let truncated = || Error::custom("bundle is truncated");
+
+        let mut body = payload;
+        if flags & SIGNED != 0 {
+            let Some(verify) = &keys.verify else {
+                return Err(Error::custom(
+                    "bundle is signed; set MOON_PACK_PUBKEY or MOON_PACK_PUBKEY_FILE",
+                ));
+            };
+            let split = body
+                .len()
+                .checked_sub(SIGNATURE_LEN)
+                .ok_or_else(truncated)?;
+            let (signed, signature) = body.split_at(split);
+            UnparsedPublicKey::new(&ED25519, verify)
+                .verify(signed, signature)
+                .map_err(|_| Error::custom("bundle signature does not verify"))?;
+            body = signed;
+        } else if keys.verify.is_some() {
+            return Err(Error::custom("bundle is not signed"));
+        }
+        if flags & ENCRYPTED != 0 {
+            let split = body.len().checked_sub(MAC_LEN).ok_or_else(truncated)?;
+            let (authenticated, mac) = body.split_at(split);
+            keys.cipher_key()?;
+            keys.check_mac(authenticated, mac)?;
+            body = authenticated;
+        } else if keys.cipher.is_some() {
+            return Err(Error::custom("bundle is not encrypted"));
+        }
+        reader.buf = body.get(header_len..).ok_or_else(truncated)?;
+
+        let count = reader.u32()?;
+        let mut files = BTreeMap::new();
+        for _ in 0..count {
+            let name = reader.string()?;
+            let mut data = reader.bytes()?.to_vec();
+            if flags & ENCRYPTED != 0 {
+                data = keys.open(&name, &data)?;
+            }
+            files.insert(name, data);
+        }
+        if !reader.buf.is_empty() {
+            return Err(truncated());
+        }
+        Ok(Bundle {
+            entry,
+            files,
+            sealed: flags & (ENCRYPTED | SIGNED) != 0,
+        })
+    }
+
+    /// Read the bundle appended to the file at `path`, if it has one.
+    pub fn read_appended(path: &Path, keys: &Keys) -> Result<Option<Bundle>> {
+        match read_payload(path)? {
+            Some(payload) => Bundle::decode(&payload, keys).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    /// Write a copy of the executable `exe`, without any bundle it already
+    /// carries, to `output` with this bundle appended.
+    pub fn write_executable(&self, exe: &Path, output: &Path, keys: &Keys) -> Result<()> {
+        let mut file = fs::File::open(exe)?;
+        let end = match payload_offset(&mut file)? {
+            Some(offset) => offset,
+            None => file.seek(SeekFrom::End(0))?,
+        };
+        let mut image = Vec::with_capacity(end as usize);
+        file.seek(SeekFrom::Start(0))?;
+        file.take(end).read_to_end(&mut image)?;
+        image.extend_from_slice(&self.encode(keys)?);
+        fs::write(output, image)?;
+        fs::set_permissions(output, fs::metadata(exe)?.permissions())?;
+        Ok(())
+    }
+}
+
+/// The payload of the bundle appended to the file at `path`, if it has one.
+fn read_payload(path: &Path) -> Result<Option<Vec<u8>>> {
+    let mut file = fs::File::open(path)?;
+    let Some(offset) = payload_offset(&mut file)? else {
+        return Ok(None);
+    };
+    let len = file.seek(SeekFrom::End(0))? - TRAILER_LEN as u64 - offset;
+    let mut payload = vec![0; len as usize];
+    file.seek(SeekFrom::Start(offset))?;
+    file.read_exact(&mut payload)?;
+    Ok(Some(payload))
+}
+
+/// Where the payload of the bundle appended to `file` starts.
+fn payload_offset(file: &mut fs::File) -> Result<Option<u64>> {
+    let size = file.seek(SeekFrom::End(0))?;
+    if size < TRAILER_LEN as u64 {
+        return Ok(None);
+    }
+    let mut trailer = [0u8; TRAILER_LEN];
+    file.seek(SeekFrom::End(-(TRAILER_LEN as i64)))?;
+    file.read_exact(&mut trailer)?;
+    if &trailer[8..] != MAGIC {
+        return Ok(None);
+    }
+    let len = u64::from_le_bytes(trailer[..8].try_into().unwrap_or_default());
+    match (size - TRAILER_LEN as u64).checked_sub(len) {
+        Some(offset) => Ok(Some(offset)),
+        None => Err(Error::custom("bundle length exceeds the file")),
+    }
+}
+
+fn put(buf: &mut Vec<u8>, data: &[u8]) {
+    buf.extend_from_slice(&(data.len() as u32).to_le_bytes());
+    buf.extend_from_slice(data);
+}
+
+struct Reader<'a> {
+    buf: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, n: usize) -> Result<&'a [u8]> {
+        if self.buf.len() < n {
+            return Err(Error::custom("bundle is truncated"));
+        }
+        let (head, tail) = self.buf.split_at(n);
+        self.buf = tail;
+        Ok(head)
+    }
+
+    fn u32(&mut self) -> Result<u32> {
+        let bytes = self.take(4)?;
+        Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    fn bytes(&mut self) -> Result<&'a [u8]> {
+        let len = self.u32()? as usize;
+        self.take(len)
+    }
+
+    fn string(&mut self) -> Result<String> {
+        String::from_utf8(self.bytes()?.to_vec())
+            .map_err(|_| Error::custom("bundle file name is not utf-8"))
+    }
+}
+
+fn normalize(path: &str) -> String {
+    let path = path.replace('\\', "/");
+    let mut path = path.as_str();
+    while let Some(rest) = path.strip_prefix("./") {
+        path = rest;
+    }
+    path.to_string()
+}
+
+fn is_relative(template: &str) -> bool {
+    !template.is_empty()
+        && !template.starts_with('/')
+        && !template.starts_with('\\')
+        && !template.contains(':')
+}
+
+/// Bundle the `.lua` files below the directory of `script`, with `script` as
+/// the entry, and the files of `lualib` under `lualib/`. Hidden directories
+/// are skipped. With `bytecode`, files are stored compiled.
+pub fn pack(script: &Path, lualib: &Path, bytecode: bool) -> Result<Bundle> {
+    let script = script.canonicalize()?;
+    let root = script.parent().unwrap_or(Path::new("/"));
+    let entry = script.file_name().unwrap_or_default().to_string_lossy();
+    let mut bundle = Bundle::new(&entry);
+    for (prefix, dir) in [("", root), ("lualib/", lualib)] {
+        for file in lua_files(dir)? {
+            let relative = file.strip_prefix(dir).unwrap_or(&file);
+            let name = format!("{}{}", prefix, relative.to_string_lossy());
+            let mut data = fs::read(&file)?;
+            if bytecode {
+                data = compile(&name, &data)?;
+            }
+            bundle.insert(&name, data);
+        }
+    }
+    Ok(bundle)
+}
+
+fn lua_files(dir: &Path) -> Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    let mut stack = vec![dir.to_path_buf()];
+    while let Some(dir) = stack.pop() {
+        for entry in fs::read_dir(&dir)? {
+            let entry = entry?;
+            let path = entry.path();
+            let file_type = entry.file_type()?;
+            if file_type.is_dir() {
+                if !entry.file_name().to_string_lossy().starts_with('.') {
+                    stack.push(path);
+                }
+            } else if path.extension().is_some_and(|ext| ext == "lua") {
+                files.push(path);
+            }
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+static BUNDLE: OnceLock<Bundle> = OnceLock::new();
+
+/// Read the bundle appended to the running executable, with the keys from
+/// the environment, and make it the source of scripts. `None` when the
+/// executable carries no bundle; the keys are only read when it does.
+pub fn load() -> Result<Option<&'static Bundle>> {
+    let exe = std::env::current_exe()?;
+    let Some(payload) = read_payload(&exe)? else {
+        return Ok(None);
+    };
+    let _ = BUNDLE.set(Bundle::decode(&payload, &Keys::from_env()?)?);
+    Ok(BUNDLE.get())
+}
+
+pub fn get() -> Option<&'static Bundle> {
+    BUNDLE.get()
+}
+
+/// Compile Lua `source` to bytecode, keeping debug information so errors
+/// still report file and line.
+pub fn compile(name: &str, source: &[u8]) -> Result<Vec<u8>> {
+    unsafe extern "C-unwind" fn writer(
+        _: *mut ffi::lua_State,
+        p: *const c_void,
+        sz: usize,
+        ud: *mut c_void,
+    ) -> c_int {
+        if sz == 0 {
+            return 0;
+        }
+        unsafe {
+            let out = &mut *(ud as *mut Vec<u8>);
+            out.extend_from_slice(std::slice::from_raw_parts(p as *const u8, sz));
+        }
+        0
+    }
+
+    let chunkname = CString::new(format!("@{}", normalize(name)))?;
+    unsafe {
+        let state = ffi::luaL_newstate();
+        if state.is_null() {
+            return Err(Error::custom("not enough memory"));
+        }
+        let res = if ffi::luaL_loadbufferx(
+            state,
+            source.as_ptr() as *const _,
+            source.len(),
+            chunkname.as_ptr(),
+            cstr!("t"),
+        ) == ffi::LUA_OK
+        {
+            let mut out = Vec::new();
+            ffi::lua_dump(state, writer, &mut out as *mut Vec<u8> as *mut c_void, 0);
+            Ok(out)
+        } else {
+            let state = LuaState::new(state).unwrap();
+            Err(Error::Custom(
+                laux::lua_opt(state, -1).unwrap_or("unknown error".to_string()),
+            ))
+        };
+        ffi::lua_close(state);
+        res
+    }
+}
+
+/// Load bundled file `name` as a chunk onto the stack. Like files loaded with
+/// `luaL_loadfile`, the chunk goes through the code cache, so each bundled
+/// file is compiled once per process. Returns the status of the load.
+///
+/// # Safety
+/// `state` must be a valid Lua state.
+pub unsafe fn load_chunk(state: LuaState, name: &str, data: &[u8]) -> c_int {
+    unsafe { load_chunk_mode(state, name, data, None) }
+}
+
+unsafe fn load_chunk_mode(state: LuaState, name: &str, data: &[u8], mode: Option<&str>) -> c_int {
+    let chunkname = CString::new(format!("@{}", normalize(name))).unwrap_or_default();
+    let mode = mode.map(|mode| CString::new(mode).unwrap_or_default());
+    unsafe {
+        ffi::luaL_loadbuffercache(
+            state.as_ptr(),
+            data.as_ptr() as *const _,
+            data.len(),
+            chunkname.as_ptr(),
+            mode.as_ref().map_or(std::ptr::null(), |mode| mode.as_ptr()),
+        )
+    }
+}
+
+/// Load the bundled file at argument 1, the way `loadfile` does. Pushes the
+/// chunk, or nil and a message.
+unsafe fn load_bundled(state: LuaState, bundle: &Bundle) -> c_int {
+    unsafe {
+        let name = laux::lua_check_str(state, 1);
+        let Some(data) = bundle.get(name) else {
+            laux::lua_pushnil(state);
+            laux::lua_push(state, format!("cannot open {}: not in the bundle", name));
+            return 2;
+        };
+        if load_chunk_mode(state, name, data, laux::lua_opt_str(state, 2)) != ffi::LUA_OK {
+            laux::lua_pushnil(state);
+            ffi::lua_insert(state.as_ptr(), -2);
+            return 2;
+        }
+        1
+    }
+}
+
+/// `loadfile` of a sealed bundle: files load from the bundle only.
+extern "C-unwind" fn lua_bundle_loadfile(state: LuaState) -> c_int {
+    let Some(bundle) = get() else {
+        return 0;
+    };
+    unsafe {
+        let has_env = ffi::lua_isnone(state.as_ptr(), 3) == 0;
+        ffi::lua_settop(state.as_ptr(), 3);
+        let n = load_bundled(state, bundle);
+        if n == 1 && has_env {
+            ffi::lua_pushvalue(state.as_ptr(), 3);
+            if ffi::lua_setupvalue(state.as_ptr(), -2, 1).is_null() {
+                ffi::lua_pop(state.as_ptr(), 1);
+            }
+        }
+        n
+    }
+}
+
+/// `dofile` of a sealed bundle: files run from the bundle only.
+extern "C-unwind" fn lua_bundle_dofile(state: LuaState) -> c_int {
+    let Some(bundle) = get() else {
+        return 0;
+    };
+    unsafe {
+        ffi::lua_settop(state.as_ptr(), 1);
+        if load_bundled(state, bundle) != 1 {
+            ffi::lua_error(state.as_ptr());
+        }
+        ffi::lua_call(state.as_ptr(), 0, ffi::LUA_MULTRET);
+        ffi::lua_gettop(state.as_ptr()) - 1
+    }
+}
+
+/// `package.searchers` entry that serves modules from the bundle.
+extern "C-unwind" fn lua_bundle_searcher(state: LuaState) -> c_int {
+    let Some(bundle) = get() else {
+        return 0;
+    };
+    let name = unsafe { laux::lua_check_str(state, 1) };
+    let package_path = unsafe {
+        ffi::lua_getglobal(state.as_ptr(), cstr!("package"));
+        ffi::lua_getfield(state.as_ptr(), -1, cstr!("path"));
+        laux::lua_opt_str(state, -1).unwrap_or_default()
+    };
+    let Some(file) = bundle.search(name, package_path) else {
+        laux::lua_push(state, format!("no bundled file for '{}'", name));
+        return 1;
+    };
+    let data = bundle.get(&file).unwrap_or_default();
+    if unsafe { load_chunk(state, &file, data) } != ffi::LUA_OK {
+        let err = laux::lua_opt(state, -1).unwrap_or("unknown error".to_string());
+        laux::lua_error(
+            state,
+            format!(
+                "error loading module '{}' from bundled file '{}':\n\t{}",
+                name, file, err
+            ),
+        );
+    }
+    laux::lua_push(state, file.as_str());
+    2
+}
+
+/// Put the bundle searcher in front of the file searcher, when the
+/// executable carries a bundle. A sealed bundle replaces the file and C
+/// searchers, keeping only `package.preload`, and `loadfile` and `dofile`
+/// read from the bundle instead of the disk.
+pub fn open_searcher(state: LuaState) {
+    let Some(bundle) = get() else {
+        return;
+    };
+    if bundle.is_sealed() {
+        unsafe {
+            ffi::lua_getglobal(state.as_ptr(), cstr!("package"));
+            ffi::lua_getfield(state.as_ptr(), -1, cstr!("searchers"));
+            ffi::lua_pushcfunction(
+                state.as_ptr(),
+                crate::not_null_wrapper!(lua_bundle_searcher),
+            );
+            ffi::lua_rawseti(state.as_ptr(), -2, 2);
+            let len = ffi::lua_rawlen(state.as_ptr(), -1) as ffi::lua_Integer;
+            for i in (3..=len).rev() {
+                ffi::lua_pushnil(state.as_ptr());
+                ffi::lua_rawseti(state.as_ptr(), -2, i);
+            }
+            ffi::lua_pop(state.as_ptr(), 2);
+
+            ffi::lua_pushcfunction(
+                state.as_ptr(),
+                crate::not_null_wrapper!(lua_bundle_loadfile),
+            );
+            ffi::lua_setglobal(state.as_ptr(), cstr!("loadfile"));
+            ffi::lua_pushcfunction(state.as_ptr(), crate::not_null_wrapper!(lua_bundle_dofile));
+            ffi::lua_setglobal(state.as_ptr(), cstr!("dofile"));
+        }
+        return;
+    }
+    unsafe {
+        ffi::lua_getglobal(state.as_ptr(), cstr!("table"));
+        ffi::lua_getfield(state.as_ptr(), -1, cstr!("insert"));
+        ffi::lua_getglobal(state.as_ptr(), cstr!("package"));
+        ffi::lua_getfield(state.as_ptr(), -1, cstr!("searchers"));
+        ffi::lua_remove(state.as_ptr(), -2);
+        ffi::lua_pushinteger(state.as_ptr(), 2);
+        ffi::lua_pushcfunction(
+            state.as_ptr(),
+            crate::not_null_wrapper!(lua_bundle_searcher),
+        );
+        ffi::lua_call(state.as_ptr(), 3, 0);
+        ffi::lua_pop(state.as_ptr(), 1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample() -> Bundle {
+        let mut bundle = Bundle::new("./main.lua");
+        bundle.insert("main.lua", b"print(1)".to_vec());
+        bundle.insert("lualib/moon.lua", b"return {}".to_vec());
+        bundle.insert("service\\battle/init.lua", b"return 1".to_vec());
+        bundle
+    }
+
+    fn sealed_sample() -> Bundle {
+        Bundle {
+            sealed: true,
+            ..sample()
+        }
+    }
+
+    #[test]
+    fn encode_decode_roundtrip() {
+        let bundle = sample();
+        let buf = bundle.encode(&Keys::default()).unwrap();
+        assert_eq!(&buf[buf.len() - MAGIC.len()..], MAGIC);
+        let decoded = Bundle::decode(&buf[..buf.len() - TRAILER_LEN], &Keys::default()).unwrap();
+        assert_eq!(decoded, bundle);
+        assert_eq!(decoded.entry(), "main.lua");
+        assert!(!decoded.is_sealed());
+        assert_eq!(decoded.get("./lualib/moon.lua"), Some(&b"return {}"[..]));
+
+        assert!(Bundle::decode(&buf[..20], &Keys::default()).is_err());
+    }
+
+    fn secure_keys() -> (Keys, Keys) {
+        let seed = [7u8; 32];
+        let signing = Ed25519KeyPair::from_seed_unchecked(&seed).unwrap();
+        let verify = signing.public_key().as_ref().try_into().unwrap();
+        let pack = Keys {
+            cipher: Some([3; 32]),
+            signing: Some(signing),
+            verify: None,
+        };
+        let load = Keys {
+            cipher: Some([3; 32]),
+            signing: None,
+            verify: Some(verify),
+        };
+        (pack, load)
+    }
+
+    fn payload(buf: &[u8]) -> &[u8] {
+        &buf[..buf.len() - TRAILER_LEN]
+    }
+
+    #[test]
+    fn encrypted_and_signed() {
+        let (pack, load) = secure_keys();
+        let buf = sample().encode(&pack).unwrap();
+        assert!(
+            !buf.windows(8).any(|w| w == b"print(1)"),
+            "sources are encrypted"
+        );
+        assert_eq!(
+            Bundle::decode(payload(&buf), &load).unwrap(),
+            sealed_sample()
+        );
+
+        // Flipping any byte of the payload is caught.
+        for i in [0, 12, payload(&buf).len() / 2, payload(&buf).len() - 1] {
+            let mut tampered = payload(&buf).to_vec();
+            tampered[i] ^= 1;
+            assert!(Bundle::decode(&tampered, &load).is_err(), "byte {}", i);
+        }
+
+        let missing = Bundle::decode(payload(&buf), &Keys::default()).unwrap_err();
+        assert!(missing.to_string().contains("MOON_PACK_PUBKEY"));
+        let wrong_key = Keys {
+            cipher: Some([4; 32]),
+            ..secure_keys().1
+        };
+        let err = Bundle::decode(payload(&buf), &wrong_key).unwrap_err();
+        assert!(err.to_string().contains("tampered or the key is wrong"));
+    }
+
+    #[test]
+    fn encryption_without_signature() {
+        let keys = Keys {
+            cipher: Some([3; 32]),
+            ..Keys::default()
+        };
+        let buf = sample().encode(&keys).unwrap();
+        assert_eq!(
+            Bundle::decode(payload(&buf), &keys).unwrap(),
+            sealed_sample()
+        );
+
+        let err = Bundle::decode(payload(&buf), &Keys::default()).unwrap_err();
+        assert!(err.to_string().contains("MOON_PACK_KEY"));
+
+        // Rebuild the payload from its sealed files, keeping the MAC.
+        let mut reader = Reader { buf: payload(&buf) };
+        reader.string().unwrap();
+        reader.take(1).unwrap();
+        let count = reader.u32().unwrap();
+        let files: Vec<_> = (0..count)
+            .map(|_| (reader.string().unwrap(), reader.bytes().unwrap()))
+            .collect();
+        let mac = reader.buf;
+        let rebuild = |entry: &str, files: &[(String, &[u8])]| {
+            let mut raw = Vec::new();
+            put(&mut raw, entry.as_bytes());
+            raw.push(ENCRYPTED);
+            raw.extend_from_slice(&(files.len() as u32).to_le_bytes());
+            for (name, data) in files {
+                put(&mut raw, name.as_bytes());
+                put(&mut raw, data);
+            }
+            raw.extend_from_slice(mac);
+            raw
+        };
+        assert_eq!(
+            Bundle::decode(&rebuild("main.lua", &files), &keys).unwrap(),
+            sealed_sample()
+        );
+        // Every file decrypts, but the set or the entry changed.
+        let err = Bundle::decode(&rebuild("main.lua", &files[1..]), &keys).unwrap_err();
+        assert!(err.to_string().contains("tampered"));
+        assert!(Bundle::decode(&rebuild("extra.lua", &files), &keys).is_err());
+        // So is a module moved to another name.
+        let mut moved = files.clone();
+        moved[0].0 = "extra.lua".to_string();
+        assert!(Bundle::decode(&rebuild("main.lua", &moved), &keys).is_err());
+
+        // Keys at startup refuse a plain bundle.
+        let plain = sample().encode(&Keys::default()).unwrap();
+        let err = Bundle::decode(payload(&plain), &keys).unwrap_err();
+        assert!(err.to_string().contains("not encrypted"));
+        let (_, load) = secure_keys();
+        let err = Bundle::decode(payload(&buf), &load).unwrap_err();
+        assert!(err.to_string().contains("not signed"));
+    }
+
+    #[test]
+    fn keygen_writes_hex_keys() {
+        let dir = std::env::temp_dir().join(format!("moon_keygen_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        keygen(&dir.join("game")).unwrap();
+        let pack = Keys {
+            cipher: Some(read_key(&dir.join("game.key")).unwrap()),
+            signing: Some(read_signing_key(&dir.join("game.sign")).unwrap()),
+            verify: None,
+        };
+        let load = Keys {
+            cipher: pack.cipher,
+            signing: None,
+            verify: Some(read_key(&dir.join("game.pub")).unwrap()),
+        };
+        let buf = sample().encode(&pack).unwrap();
+        assert_eq!(
+            Bundle::decode(payload(&buf), &load).unwrap(),
+            sealed_sample()
+        );
+
+        fs::write(dir.join("bad.key"), "abc").unwrap();
+        assert!(read_key(&dir.join("bad.key")).is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn search_uses_relative_templates() {
+        let bundle = sample();
+        let path = "/usr/share/lua/?.lua;./lualib/?.lua;./?.lua;./?/init.lua";
+        assert_eq!(
+            bundle.search("moon", path).as_deref(),
+            Some("lualib/moon.lua")
+        );
+        assert_eq!(
+            bundle.search("service.battle", path).as_deref(),
+            Some("service/battle/init.lua")
+        );
+        assert_eq!(bundle.search("json", path), None);
+        assert_eq!(bundle.search("moon", "/abs/lualib/?.lua"), None);
+    }
+
+    #[test]
+    fn appended_bundle_survives_repacking() {
+        let dir = std::env::temp_dir().join(format!("moon_bundle_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let exe = dir.join("exe");
+        fs::write(&exe, b"\x7fELF binary").unwrap();
+        let keys = Keys::default();
+        assert_eq!(Bundle::read_appended(&exe, &keys).unwrap(), None);
+
+        let packed = dir.join("packed");
+        sample().write_executable(&exe, &packed, &keys).unwrap();
+        assert_eq!(
+            Bundle::read_appended(&packed, &keys).unwrap(),
+            Some(sample())
+        );
+
+        let mut other = Bundle::new("other.lua");
+        other.insert("other.lua", b"return 2".to_vec());
+        let repacked = dir.join("repacked");
+        other.write_executable(&packed, &repacked, &keys).unwrap();
+        let image = fs::read(&repacked).unwrap();
+        assert!(image.starts_with(b"\x7fELF binary"));
+        assert_eq!(image.len(), 11 + other.encode(&keys).unwrap().len());
+        assert_eq!(
+            Bundle::read_appended(&repacked, &keys).unwrap(),
+            Some(other)
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn pack_collects_lua_files() {
+        let dir = std::env::temp_dir().join(format!("moon_pack_{}", std::process::id()));
+        let lualib = dir.join("lualib");
+        fs::create_dir_all(dir.join("game/service")).unwrap();
+        fs::create_dir_all(dir.join("game/.git")).unwrap();
+        fs::create_dir_all(lualib.join("moon")).unwrap();
+        fs::write(dir.join("game/main.lua"), "return 1").unwrap();
+        fs::write(dir.join("game/service/battle.lua"), "return 2").unwrap();
+        fs::write(dir.join("game/notes.txt"), "").unwrap();
+        fs::write(dir.join("game/.git/hook.lua"), "").unwrap();
+        fs::write(lualib.join("moon/socket.lua"), "return 3").unwrap();
+
+        let bundle = pack(&dir.join("game/main.lua"), &lualib, false).unwrap();
+        assert_eq!(bundle.entry(), "main.lua");
+        assert_eq!(bundle.len(), 3);
+        assert_eq!(bundle.get("service/battle.lua"), Some(&b"return 2"[..]));
+        assert_eq!(bundle.get("lualib/moon/socket.lua"), Some(&b"return 3"[..]));
+
+        let bundle = pack(&dir.join("game/main.lua"), &lualib, true).unwrap();
+        assert!(bundle.get("main.lua").unwrap().starts_with(b"\x1bLua"));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn compile_to_bytecode() {
+        let code = compile("main.lua", b"return 1 + 1").unwrap();
+        assert!(code.starts_with(b"\x1bLua"));
+        let err = compile("bad.lua", b"return {").unwrap_err();
+        assert!(err.to_string().contains("bad.lua:1"));
+    }
+}
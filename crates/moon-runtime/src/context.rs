@@ -2,23 +2,33 @@ use chrono::{DateTime, Utc};
 use dashmap::DashMap;
 use lazy_static::lazy_static;
 use std::{
-    collections::BTreeSet,
     ffi::c_void,
     sync::{
-        Arc, Mutex, OnceLock,
+        Arc, Condvar, Mutex, OnceLock, RwLock,
         atomic::{
-            AtomicI32, AtomicI64, AtomicIsize, AtomicPtr, AtomicU8, AtomicU32, AtomicU64,
-            AtomicUsize, Ordering,
+            AtomicBool, AtomicI32, AtomicI64, AtomicIsize, AtomicPtr, AtomicU8, AtomicU32,
+            AtomicU64, AtomicUsize, Ordering,
         },
     },
     thread,
     time::{Duration, Instant},
 };
-use tokio::{runtime::Builder, sync::mpsc, time::timeout};
-
-use crate::escape_print;
-
-use super::{actor::LuaActor, buffer::Buffer, log::Logger};
+use tokio::{runtime::Builder, sync::mpsc};
+
+use crate::{escape_print, pubsub};
+
+use super::{
+    actor::LuaActor,
+    buffer::Buffer,
+    debugger::{DebugSession, Debugger},
+    group::{Balance, GroupTable},
+    latency::{LatencyStat, LatencyTable},
+    log::Logger,
+    profiler::{Profile, Profiler},
+    pubsub::TopicTable,
+    schedule::Budget,
+    timer::TimerOp,
+};
 
 use moon_base::ffi as lua_ffi;
 
@@ -48,6 +58,7 @@ pub const PTYPE_HTTPD: u8 = 18;
 pub const PTYPE_PG: u8 = 19;
 pub const PTYPE_REDIS: u8 = 20;
 pub const PTYPE_GRPC: u8 = 21;
+pub const PTYPE_PUBSUB: u8 = 22;
 
 pub type ActorId = u32;
 
@@ -72,11 +83,16 @@ lazy_static! {
             pending_timers: AtomicUsize::new(0),
             actors: DashMap::new(),
             unique_actors: DashMap::new(),
+            topics: RwLock::new(TopicTable::default()),
+            groups: RwLock::new(GroupTable::default()),
             clock: Instant::now(),
             env: DashMap::new(),
             timer_tx: OnceLock::new(),
             now: Utc::now(),
             time_offset: AtomicU64::new(0),
+            replaying: AtomicBool::new(false),
+            replay_now_ms: AtomicI64::new(0),
+            replay_clock_us: AtomicU64::new(0),
             io_runtime,
             main_handle: std::sync::OnceLock::new(),
             unique_threads: Mutex::new(Vec::new()),
@@ -133,6 +149,9 @@ pub enum MessageBody {
     ISize(u8, isize),
     Buffer(u8, Box<Buffer>),
     Boxed(u8, Box<BoxedValue>),
+    /// Read-only payload shared by several messages, e.g. one `broadcast` or
+    /// `publish` delivered to many actors. Decoders read it like `Buffer`.
+    Shared(u8, Arc<Buffer>),
     None(u8),
 }
 
@@ -143,6 +162,7 @@ impl MessageBody {
             MessageBody::ISize(p, _) => *p,
             MessageBody::Buffer(p, _) => *p,
             MessageBody::Boxed(p, _) => *p,
+            MessageBody::Shared(p, _) => *p,
             MessageBody::None(p) => *p,
         }
     }
@@ -156,6 +176,9 @@ impl std::fmt::Display for MessageBody {
                 write!(f, "Buffer(\"{}\")", escape_print(data.as_slice()))
             }
             MessageBody::Boxed(_, b) => write!(f, "Boxed({:p})", b.ptr),
+            MessageBody::Shared(_, data) => {
+                write!(f, "Shared(\"{}\")", escape_print(data.as_slice()))
+            }
             MessageBody::None(_) => write!(f, "None"),
         }
     }
@@ -166,9 +189,24 @@ pub struct Message {
     pub to: ActorId,
     pub session: i64,
     pub data: MessageBody,
+    /// Runtime clock (`clock_us`) when the message entered the receiver's
+    /// mailbox; stamped by the mailbox push, 0 until then. Used to measure
+    /// queue wait.
+    pub(crate) enqueue_us: u64,
 }
 
 impl Message {
+    /// A message ready to send; the mailbox push stamps `enqueue_us`.
+    pub fn new(from: ActorId, to: ActorId, session: i64, data: MessageBody) -> Self {
+        Message {
+            from,
+            to,
+            session,
+            data,
+            enqueue_us: 0,
+        }
+    }
+
     #[inline]
     pub fn ptype(&self) -> u8 {
         self.data.ptype()
@@ -180,6 +218,245 @@ impl Message {
     }
 }
 
+/// What `send_bounded` does when the receiver's mailbox is at capacity.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum MailboxPolicy {
+    /// Park the sender until the receiver drains below capacity (bounded by
+    /// `LIMITS.mailbox_block_timeout_ms`, after which the message is rejected).
+    #[default]
+    Block,
+    /// Discard the new message and count it in `dropped`; a pending `call`
+    /// gets an error response.
+    DropNewest,
+    /// Refuse the new message; a pending `call` gets an error response.
+    Reject,
+}
+
+impl MailboxPolicy {
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "block" => Some(MailboxPolicy::Block),
+            "drop_newest" => Some(MailboxPolicy::DropNewest),
+            "reject" => Some(MailboxPolicy::Reject),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            MailboxPolicy::Block => "block",
+            MailboxPolicy::DropNewest => "drop_newest",
+            MailboxPolicy::Reject => "reject",
+        }
+    }
+}
+
+/// When a stopped service is started again, see `supervise` in `lua_actor.rs`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum RestartMode {
+    /// Never restarted.
+    #[default]
+    Temporary,
+    /// Restarted whenever it stops, including a normal `moon.quit()`.
+    Permanent,
+    /// Restarted only after an abnormal exit, see [`ExitReason::is_abnormal`].
+    Transient,
+}
+
+impl RestartMode {
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "temporary" => Some(RestartMode::Temporary),
+            "permanent" => Some(RestartMode::Permanent),
+            "transient" => Some(RestartMode::Transient),
+            _ => None,
+        }
+    }
+}
+
+/// What the watchdog does once a dispatch has been blocked for `strikes`
+/// consecutive `timeout_ms` periods.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum WatchdogAction {
+    /// Raise a Lua error in the blocked dispatch.
+    #[default]
+    Interrupt,
+    /// Only report; the service is expected to run long.
+    Log,
+    /// Interrupt the dispatch and stop the service.
+    Kill,
+}
+
+impl WatchdogAction {
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "interrupt" => Some(WatchdogAction::Interrupt),
+            "log" => Some(WatchdogAction::Log),
+            "kill" => Some(WatchdogAction::Kill),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            WatchdogAction::Interrupt => "interrupt",
+            WatchdogAction::Log => "log",
+            WatchdogAction::Kill => "kill",
+        }
+    }
+}
+
+/// Watchdog thresholds of a service (`watchdog` in `moon.new_service`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WatchdogConfig {
+    /// A dispatch running this long is reported, once per period.
+    pub timeout_ms: u64,
+    /// Consecutive reports before `action` is taken.
+    pub strikes: u32,
+    pub action: WatchdogAction,
+}
+
+impl Default for WatchdogConfig {
+    fn default() -> Self {
+        WatchdogConfig {
+            timeout_ms: 10_000,
+            strikes: 3,
+            action: WatchdogAction::Interrupt,
+        }
+    }
+}
+
+/// What happens when a service reaches its hard memory limit (`memlimit`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum MemoryAction {
+    /// The allocation fails with a Lua memory error; an unsupervised service
+    /// carries on.
+    #[default]
+    Error,
+    /// The allocation fails and the service is stopped.
+    Kill,
+}
+
+impl MemoryAction {
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "error" => Some(MemoryAction::Error),
+            "kill" => Some(MemoryAction::Kill),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            MemoryAction::Error => "error",
+            MemoryAction::Kill => "kill",
+        }
+    }
+}
+
+/// Why a service stopped.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExitReason {
+    /// The service quit itself (`moon.quit()`).
+    Normal,
+    /// Another service killed it (`moon.kill(id)`).
+    Killed,
+    /// Its source script failed to load or run.
+    InitFailed,
+    /// The watchdog interrupted a dispatch that ran too long (`watchdog`).
+    Timeout,
+    /// A dispatch ran out of memory (`memlimit`).
+    OutOfMemory,
+}
+
+impl ExitReason {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ExitReason::Normal => "normal",
+            ExitReason::Killed => "killed",
+            ExitReason::InitFailed => "init_failed",
+            ExitReason::Timeout => "timeout",
+            ExitReason::OutOfMemory => "out_of_memory",
+        }
+    }
+
+    /// Init failure, watchdog timeout or memory error. A dispatch that fails
+    /// with any other error does not stop the service, and quitting with
+    /// `moon.quit()` afterwards is a normal exit.
+    pub fn is_abnormal(&self) -> bool {
+        matches!(
+            self,
+            ExitReason::InitFailed | ExitReason::Timeout | ExitReason::OutOfMemory
+        )
+    }
+}
+
+/// Restart policy of a service (`restart`, `max_restarts` and `within` in
+/// `moon.new_service`). More than `max_restarts` restarts within `within_secs`
+/// means the service keeps crashing, and it is left stopped.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RestartPolicy {
+    pub mode: RestartMode,
+    pub max_restarts: u32,
+    pub within_secs: u64,
+}
+
+impl Default for RestartPolicy {
+    fn default() -> Self {
+        RestartPolicy {
+            mode: RestartMode::Temporary,
+            max_restarts: 3,
+            within_secs: 5,
+        }
+    }
+}
+
+impl RestartPolicy {
+    /// Whether the mode asks for a restart after `reason`. Being killed by
+    /// another service is a deliberate stop and never restarts.
+    pub fn wants_restart(&self, reason: ExitReason) -> bool {
+        match self.mode {
+            RestartMode::Temporary => false,
+            RestartMode::Permanent => reason != ExitReason::Killed,
+            RestartMode::Transient => reason.is_abnormal(),
+        }
+    }
+}
+
+/// Restart bookkeeping carried from one incarnation of a service to the next.
+#[derive(Debug, Clone, Default)]
+pub struct RestartLog {
+    /// `clock_ms` of the restarts still inside the policy window.
+    pub history: Vec<u64>,
+    /// Id of the incarnation this one replaced and why it stopped; `None` for
+    /// the first start.
+    pub previous: Option<(ActorId, ExitReason)>,
+}
+
+impl RestartLog {
+    /// Record a restart at `now_ms` if `policy` still allows one.
+    pub fn admit(&mut self, policy: &RestartPolicy, now_ms: u64) -> bool {
+        let window_ms = policy.within_secs.saturating_mul(1000);
+        self.history.retain(|&t| now_ms.saturating_sub(t) < window_ms);
+        if self.history.len() >= policy.max_restarts as usize {
+            return false;
+        }
+        self.history.push(now_ms);
+        true
+    }
+}
+
+/// Failure of [`LuaActorServer::send_bounded`]. Each variant hands the message
+/// back so the caller can report it (e.g. `response_error`).
+pub enum MailboxError {
+    /// No such actor (or its channel is closed).
+    Dead(Message),
+    /// The receiver's mailbox is full and its policy refused the message.
+    Full(Message),
+    /// The receiver's mailbox is full and its policy discarded the message.
+    Dropped(Message),
+}
+
 impl std::fmt::Display for Message {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(
@@ -217,8 +494,12 @@ pub struct Watchdog {
     /// Actor thread's switchL re-installs hook when trap != 0.
     /// signal_hook resets to 0 after raising the Lua error.
     pub trap: AtomicI32,
-    /// Number of consecutive timeout detections. Interrupt only fires after 3.
+    /// Number of consecutive timeout detections of the current dispatch.
     pub timeout_count: AtomicU32,
+    /// Start of the current dispatch; `heartbeat_ms` moves on with each
+    /// detection, this does not.
+    started_ms: AtomicU64,
+    config: WatchdogConfig,
 
     /// Per-actor statistics, published by the actor thread after each dispatch
     /// (`record_dispatch`) and read by `CONTEXT` (e.g. `server_stats`) through
@@ -227,14 +508,39 @@ pub struct Watchdog {
     ///
     /// Total messages this actor has finished dispatching.
     message_total: AtomicU64,
-    /// Cumulative time spent inside message dispatch, in milliseconds.
-    cpu_ms_total: AtomicU64,
+    /// Cumulative time spent inside message dispatch, in microseconds.
+    cpu_us_total: AtomicU64,
     /// Last observed Lua memory footprint of the actor, in bytes.
     memory: AtomicIsize,
+
+    /// Messages enqueued to this actor but not yet dispatched. Incremented by
+    /// the sender before the channel push, decremented by the actor thread as
+    /// it takes each message off the channel.
+    queue_depth: AtomicUsize,
+    /// Mailbox bound enforced by `send_bounded` (0 = unbounded). Fixed at spawn.
+    mailbox_capacity: usize,
+    mailbox_policy: MailboxPolicy,
+    /// Messages refused or discarded because the mailbox was full.
+    dropped_total: AtomicU64,
+    /// Senders parked in `wait_room`; `dequeue` wakes them through `room`.
+    room_waiters: AtomicUsize,
+    room_lock: Mutex<()>,
+    room: Condvar,
+    /// Queue wait and dispatch time histograms per ptype. Written by the actor
+    /// thread once per dispatch; the lock is only contended by stats readers.
+    latency: Mutex<LatencyTable>,
+    /// Lua stack samples, see `profiler.rs`.
+    pub profiler: Profiler,
+    /// Breakpoints and pause state of an attached debugger, see `debugger.rs`.
+    pub debugger: Debugger,
 }
 
 impl Watchdog {
     pub fn new() -> Self {
+        Self::with_mailbox(0, MailboxPolicy::Block)
+    }
+
+    pub fn with_mailbox(capacity: usize, policy: MailboxPolicy) -> Self {
         Watchdog {
             heartbeat_ms: AtomicU64::new(0),
             ptype: AtomicU8::new(0),
@@ -244,9 +550,21 @@ impl Watchdog {
             active_l: AtomicPtr::new(std::ptr::null_mut()),
             trap: AtomicI32::new(0),
             timeout_count: AtomicU32::new(0),
+            started_ms: AtomicU64::new(0),
+            config: WatchdogConfig::default(),
             message_total: AtomicU64::new(0),
-            cpu_ms_total: AtomicU64::new(0),
+            cpu_us_total: AtomicU64::new(0),
             memory: AtomicIsize::new(0),
+            queue_depth: AtomicUsize::new(0),
+            mailbox_capacity: capacity,
+            mailbox_policy: policy,
+            dropped_total: AtomicU64::new(0),
+            room_waiters: AtomicUsize::new(0),
+            room_lock: Mutex::new(()),
+            room: Condvar::new(),
+            latency: Mutex::new(LatencyTable::default()),
+            profiler: Profiler::default(),
+            debugger: Debugger::default(),
         }
     }
 
@@ -268,14 +586,98 @@ impl Watchdog {
         self.to.store(to, Ordering::Relaxed);
         self.session.store(session, Ordering::Relaxed);
         self.timeout_count.store(0, Ordering::Relaxed);
+        self.started_ms.store(clock_ms, Ordering::Relaxed);
         self.heartbeat_ms.store(clock_ms, Ordering::Release);
     }
 
+    pub fn with_config(mut self, config: WatchdogConfig) -> Self {
+        self.config = config;
+        self
+    }
+
+    pub fn config(&self) -> WatchdogConfig {
+        self.config
+    }
+
+    /// True once the monitor has decided to interrupt the current dispatch.
+    pub fn interrupted(&self) -> bool {
+        self.config.action != WatchdogAction::Log
+            && self.timeout_count.load(Ordering::Relaxed) >= self.config.strikes
+    }
+
+    /// The `watchdog` system report about the current dispatch. `event` is
+    /// `slow`, `interrupt` or `kill`.
+    pub fn report(&self, id: ActorId, event: &str, now_ms: u64) -> serde_json::Value {
+        serde_json::json!({
+            "event": event,
+            "id": id,
+            "blocked_ms": now_ms.saturating_sub(self.started_ms.load(Ordering::Relaxed)),
+            "strike": self.timeout_count.load(Ordering::Relaxed),
+            "strikes": self.config.strikes,
+            "timeout_ms": self.config.timeout_ms,
+            "action": self.config.action.as_str(),
+            "ptype": self.ptype.load(Ordering::Relaxed),
+            "from": self.from.load(Ordering::Relaxed),
+            "to": self.to.load(Ordering::Relaxed),
+            "session": self.session.load(Ordering::Relaxed),
+        })
+    }
+
     #[inline]
     pub fn end(&self) {
         self.heartbeat_ms.store(0, Ordering::Release);
     }
 
+    /// Ask for a profiler sample. Only a dispatch is sampled: the hook goes on
+    /// the running `lua_State`, the same way `check_watchdogs` interrupts it,
+    /// and records the stack at the next VM instruction.
+    pub fn request_sample(&self) {
+        if self.heartbeat_ms.load(Ordering::Acquire) == 0 {
+            return;
+        }
+        let active = self.active_l.load(Ordering::Acquire);
+        if active.is_null() {
+            return;
+        }
+        self.profiler.request();
+        unsafe {
+            lua_ffi::lua_sethook(
+                active as *mut lua_ffi::lua_State,
+                Some(moon_signal_hook),
+                lua_ffi::LUA_MASKCOUNT,
+                1,
+            );
+        }
+    }
+
+    /// Hook the running `lua_State` for the debugger, so that a pause or a
+    /// new breakpoint takes effect at the next line, even in a loop.
+    /// Coroutines get the hook as they are resumed.
+    pub fn request_debug_hook(&self) {
+        let active = self.active_l.load(Ordering::Acquire);
+        if active.is_null() {
+            return;
+        }
+        unsafe {
+            lua_ffi::lua_sethook(
+                active as *mut lua_ffi::lua_State,
+                Some(moon_signal_hook),
+                lua_ffi::LUA_MASKLINE | lua_ffi::LUA_MASKCOUNT,
+                1,
+            );
+        }
+    }
+
+    /// Start the current watchdog period again, after the debugger held the
+    /// dispatch.
+    pub fn restart_heartbeat(&self, clock_ms: u64) {
+        let _ = self
+            .heartbeat_ms
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |hb| {
+                (hb != 0).then_some(clock_ms)
+            });
+    }
+
     #[inline]
     pub fn set_active_l(&self, l: *mut c_void) {
         self.active_l.store(l, Ordering::Release);
@@ -283,13 +685,46 @@ impl Watchdog {
 
     /// Publish per-dispatch statistics. Called by the actor thread right after a
     /// message has been handled: bumps the message counter, accumulates the
-    /// elapsed dispatch time, and snapshots the actor's current memory usage.
+    /// elapsed dispatch time, records the queue wait and dispatch time under
+    /// the message's ptype, and snapshots the actor's current memory usage.
+    /// Times are `clock_us` readings; `enqueue_us == 0` means the message was
+    /// never stamped and counts as no wait.
     #[inline]
-    pub fn record_dispatch(&self, begin_ms: u64, end_ms: u64, memory: isize) {
+    pub fn record_dispatch(
+        &self,
+        ptype: u8,
+        enqueue_us: u64,
+        begin_us: u64,
+        end_us: u64,
+        memory: isize,
+    ) {
+        let dispatch_us = end_us.saturating_sub(begin_us);
+        let wait_us = if enqueue_us == 0 {
+            0
+        } else {
+            begin_us.saturating_sub(enqueue_us)
+        };
         self.message_total.fetch_add(1, Ordering::Relaxed);
-        self.cpu_ms_total
-            .fetch_add(end_ms.saturating_sub(begin_ms), Ordering::Relaxed);
+        self.cpu_us_total.fetch_add(dispatch_us, Ordering::Relaxed);
         self.memory.store(memory, Ordering::Relaxed);
+        if let Ok(mut latency) = self.latency.lock() {
+            latency.record(ptype, wait_us, dispatch_us);
+        }
+    }
+
+    /// Per-ptype latency summaries, ordered by ptype.
+    pub fn latency_stats(&self) -> Vec<LatencyStat> {
+        self.latency
+            .lock()
+            .map(|latency| latency.snapshot())
+            .unwrap_or_default()
+    }
+
+    /// Start the latency histograms over.
+    pub fn reset_latency(&self) {
+        if let Ok(mut latency) = self.latency.lock() {
+            latency.clear();
+        }
     }
 
     #[inline]
@@ -299,13 +734,79 @@ impl Watchdog {
 
     #[inline]
     pub fn cpu_ms_total(&self) -> u64 {
-        self.cpu_ms_total.load(Ordering::Relaxed)
+        self.cpu_us_total.load(Ordering::Relaxed) / 1000
     }
 
     #[inline]
     pub fn memory(&self) -> isize {
         self.memory.load(Ordering::Relaxed)
     }
+
+    /// Called by the actor thread for every message it takes off its channel.
+    #[inline]
+    pub fn dequeue(&self) {
+        // SeqCst pairs with `wait_room`: either the waiter sees the new depth
+        // or this sees the waiter.
+        self.queue_depth.fetch_sub(1, Ordering::SeqCst);
+        if self.room_waiters.load(Ordering::SeqCst) > 0 {
+            self.wake_senders();
+        }
+    }
+
+    #[inline]
+    pub fn queue_depth(&self) -> usize {
+        self.queue_depth.load(Ordering::Acquire)
+    }
+
+    #[inline]
+    pub fn mailbox_capacity(&self) -> usize {
+        self.mailbox_capacity
+    }
+
+    #[inline]
+    pub fn mailbox_policy(&self) -> MailboxPolicy {
+        self.mailbox_policy
+    }
+
+    #[inline]
+    pub fn dropped_total(&self) -> u64 {
+        self.dropped_total.load(Ordering::Relaxed)
+    }
+
+    #[inline]
+    fn is_full(&self) -> bool {
+        self.mailbox_capacity > 0 && self.queue_depth() >= self.mailbox_capacity
+    }
+
+    /// Park the calling thread until the mailbox has room. Returns `false` if
+    /// `deadline` passes or `stop` returns true first.
+    fn wait_room(&self, deadline: Instant, stop: impl Fn() -> bool) -> bool {
+        self.room_waiters.fetch_add(1, Ordering::SeqCst);
+        let mut guard = self.room_lock.lock().unwrap_or_else(|e| e.into_inner());
+        let has_room = loop {
+            if self.queue_depth.load(Ordering::SeqCst) < self.mailbox_capacity {
+                break true;
+            }
+            let now = Instant::now();
+            if now >= deadline || stop() {
+                break false;
+            }
+            guard = self
+                .room
+                .wait_timeout(guard, deadline - now)
+                .unwrap_or_else(|e| e.into_inner())
+                .0;
+        };
+        drop(guard);
+        self.room_waiters.fetch_sub(1, Ordering::SeqCst);
+        has_room
+    }
+
+    /// Wake the senders parked in `wait_room` to check their condition again.
+    fn wake_senders(&self) {
+        let _guard = self.room_lock.lock().unwrap_or_else(|e| e.into_inner());
+        self.room.notify_all();
+    }
 }
 
 impl Default for Watchdog {
@@ -319,26 +820,50 @@ struct ActorEntry {
     watchdog: Arc<Watchdog>,
 }
 
+impl ActorEntry {
+    /// Every enqueue goes through here so `queue_depth` stays balanced with the
+    /// `dequeue` calls made by the receiving actor. `now_us` stamps the
+    /// message's enqueue time.
+    fn push(&self, mut msg: Message, now_us: u64) -> Result<(), Message> {
+        msg.enqueue_us = now_us;
+        self.watchdog.queue_depth.fetch_add(1, Ordering::AcqRel);
+        self.tx.send(msg).map_err(|err| {
+            self.watchdog.queue_depth.fetch_sub(1, Ordering::AcqRel);
+            err.0
+        })
+    }
+}
+
 pub struct LuaActorServer {
     actor_uuid: AtomicU32,
     actor_counter: AtomicU32,
     exit_code: AtomicI32,
     error_count: AtomicUsize,
     /// Timers that have been scheduled (`insert_timer`) but not yet fired by the
-    /// `run_timer` task. The actual `BTreeSet` lives inside that task, so this
+    /// `run_timer` tasks. The timer stores live inside those tasks, so this
     /// atomic mirror is the only process-wide view of "alive" timers.
-    pending_timers: AtomicUsize,
+    pub(crate) pending_timers: AtomicUsize,
     actors: DashMap<ActorId, ActorEntry>,
     unique_actors: DashMap<String, ActorId>,
+    /// Topic subscriptions of `subscribe` / `publish`. Read on every publish,
+    /// written on (un)subscribe and actor removal.
+    topics: RwLock<TopicTable>,
+    /// Service groups (`group` in the service params), see `group.rs`.
+    groups: RwLock<GroupTable>,
     clock: Instant,
     env: DashMap<String, Arc<Vec<u8>>>,
-    /// The timer channel is created in `run_timer`, which keeps the receiver in
-    /// its task and publishes the sender here. Only the sender lives in the
-    /// global, and `send` needs just `&self`, so `OnceLock` fits exactly. The
-    /// receiver is never stored globally (no `Mutex`/`&mut` juggling needed).
-    timer_tx: OnceLock<mpsc::UnboundedSender<Timer>>,
+    /// One channel per timer shard, created in `run_timer`, which keeps each
+    /// receiver in its task and publishes the senders here. Only the senders
+    /// live in the global, and `send` needs just `&self`, so `OnceLock` fits
+    /// exactly. The receivers are never stored globally.
+    pub(crate) timer_tx: OnceLock<Vec<mpsc::UnboundedSender<TimerOp>>>,
     now: DateTime<Utc>,
     time_offset: AtomicU64,
+    /// Set by `--replay`: the clocks read the recorded values below, advanced
+    /// by the replay loop before each message, instead of the wall clock.
+    replaying: AtomicBool,
+    replay_now_ms: AtomicI64,
+    replay_clock_us: AtomicU64,
     io_runtime: tokio::runtime::Runtime,
     main_handle: std::sync::OnceLock<tokio::runtime::Handle>,
     /// Join handles for unique actors, which each run on a dedicated OS thread.
@@ -360,9 +885,16 @@ impl LuaActorServer {
         if actor.unique && self.unique_actors.contains_key(&actor.name) {
             return Err(format!("unique actor named {} already exists", actor.name));
         }
+        if !actor.group.is_empty() {
+            let mut groups = self.groups.write().map_err(|e| e.to_string())?;
+            groups.join(actor.id, &actor.group, actor.balance)?;
+        }
 
         self.actor_counter.fetch_add(1, Ordering::AcqRel);
-        let watchdog = Arc::new(Watchdog::new());
+        let watchdog = Arc::new(
+            Watchdog::with_mailbox(actor.mailbox_capacity, actor.mailbox_policy)
+                .with_config(actor.watchdog_config),
+        );
         self.actors.insert(
             actor.id,
             ActorEntry {
@@ -397,17 +929,24 @@ impl LuaActorServer {
         // we must NOT decrement the counter (it would underflow `AtomicU32` and
         // make `stopped()` never true, hanging shutdown) nor evict the *existing*
         // owner of `name` from `unique_actors`.
-        if self.actors.remove(&id).is_none() {
+        let Some((_, entry)) = self.actors.remove(&id) else {
             // A failed bootstrap must still bring the process down.
             if id == BOOTSTRAP_ACTOR_ADDR {
                 self.shutdown(-1);
             }
             return;
-        }
+        };
+        entry.watchdog.debugger.exited();
 
         if !name.is_empty() {
             self.unique_actors.remove(name);
         }
+        if let Ok(mut topics) = self.topics.write() {
+            topics.unsubscribe_all(id);
+        }
+        if let Ok(mut groups) = self.groups.write() {
+            groups.leave(id);
+        }
         self.actor_counter.fetch_sub(1, Ordering::AcqRel);
 
         if id == BOOTSTRAP_ACTOR_ADDR {
@@ -418,11 +957,11 @@ impl LuaActorServer {
         // `broadcast`: PTYPE_SYSTEM messages are delivered only to unique
         // services, whose Lua `_service_exit` handler releases watched calls.
         self.unique_actors.iter().for_each(|v| {
-            let _ = self.send(Message {
-                from: id,
-                to: *v.value(),
-                session: 0,
-                data: MessageBody::Buffer(
+            let _ = self.send(Message::new(
+                id,
+                *v.value(),
+                0,
+                MessageBody::Buffer(
                     PTYPE_SYSTEM,
                     Box::new(
                         format!("_service_exit,Actor id:{} quited", id)
@@ -430,23 +969,108 @@ impl LuaActorServer {
                             .into(),
                     ),
                 ),
-            });
+            ));
         });
     }
 
+    /// Subscribe `id` to a topic or pattern (see `pubsub.rs`). Returns false if
+    /// it was already subscribed.
+    pub fn subscribe(&self, id: ActorId, topic: &str) -> Result<bool, String> {
+        pubsub::check_topic(topic, true)?;
+        let mut topics = self.topics.write().map_err(|e| e.to_string())?;
+        Ok(topics.subscribe(id, topic))
+    }
+
+    /// Returns false if `id` was not subscribed to `topic`.
+    pub fn unsubscribe(&self, id: ActorId, topic: &str) -> bool {
+        self.topics
+            .write()
+            .is_ok_and(|mut topics| topics.unsubscribe(id, topic))
+    }
+
+    /// Deliver `data` as a `PTYPE_PUBSUB` message to every subscriber of
+    /// `topic`, see [`broadcast`](Self::broadcast). Returns the number of
+    /// subscribers reached.
+    pub fn publish(&self, from: ActorId, topic: &str, data: Box<Buffer>) -> Result<usize, String> {
+        pubsub::check_topic(topic, false)?;
+        let ids = self
+            .topics
+            .read()
+            .map_err(|e| e.to_string())?
+            .subscribers(topic);
+        Ok(self.broadcast(from, PTYPE_PUBSUB, &ids, data))
+    }
+
+    /// Send one notification to many actors. `data` is moved into an `Arc`
+    /// once and every receiver gets a `MessageBody::Shared` pointing at it,
+    /// so the cost does not grow with the payload size. A receiver whose
+    /// mailbox is full misses the message (counted in its `dropped`) instead
+    /// of stalling the sender, and unknown ids are skipped. Returns the
+    /// number of receivers reached.
+    pub fn broadcast(&self, from: ActorId, ptype: u8, ids: &[ActorId], data: Box<Buffer>) -> usize {
+        if ids.is_empty() {
+            return 0;
+        }
+
+        let data: Arc<Buffer> = Arc::from(data);
+        let now_us = self.clock_us();
+        let mut delivered = 0;
+        for &id in ids {
+            let Some(entry) = self.actors.get(&id) else {
+                continue;
+            };
+            let wd = &entry.value().watchdog;
+            if wd.is_full() {
+                wd.dropped_total.fetch_add(1, Ordering::Relaxed);
+                continue;
+            }
+            let msg = Message::new(from, id, 0, MessageBody::Shared(ptype, data.clone()));
+            if entry.value().push(msg, now_us).is_ok() {
+                delivered += 1;
+            }
+        }
+        delivered
+    }
+
+    /// Choose a member of group `name` by its balance; `key` is required for
+    /// `hash` groups. `Ok(None)` if the group has no member.
+    pub fn pick_group(&self, name: &str, key: Option<&[u8]>) -> Result<Option<ActorId>, String> {
+        let groups = self.groups.read().map_err(|e| e.to_string())?;
+        groups.pick(name, key, |id| {
+            self.actors
+                .get(&id)
+                .map_or(usize::MAX, |entry| entry.value().watchdog.queue_depth())
+        })
+    }
+
+    pub fn group_members(&self, name: &str) -> Vec<ActorId> {
+        self.groups
+            .read()
+            .map_or_else(|_| Vec::new(), |groups| groups.members(name))
+    }
+
+    pub fn group_count(&self) -> usize {
+        self.groups.read().map_or(0, |groups| groups.group_count())
+    }
+
+    /// Topics and patterns with at least one subscriber.
+    pub fn topic_count(&self) -> usize {
+        self.topics.read().map_or(0, |topics| topics.topic_count())
+    }
+
     /// Broadcast a PTYPE_SYSTEM message to all unique actors (same scope as
     /// `_service_exit`). Used by subsystems like cluster to deliver events.
     pub fn broadcast_system(&self, sender: ActorId, payload: &str) {
         self.unique_actors.iter().for_each(|v| {
-            let _ = self.send(Message {
-                from: sender,
-                to: *v.value(),
-                session: 0,
-                data: MessageBody::Buffer(
+            let _ = self.send(Message::new(
+                sender,
+                *v.value(),
+                0,
+                MessageBody::Buffer(
                     PTYPE_SYSTEM,
                     Box::new(payload.to_string().into_bytes().into()),
                 ),
-            });
+            ));
         });
     }
 
@@ -504,13 +1128,12 @@ impl LuaActorServer {
         }
 
         log::warn!("receive shutdown event, exit code: {}.", exit_code);
+        let now_us = self.clock_us();
         self.actors.iter().for_each(|v| {
-            let _ = v.value().tx.send(Message {
-                from: 0,
-                to: 0,
-                session: 0,
-                data: MessageBody::None(PTYPE_SHUTDOWN),
-            });
+            let _ = v.value().push(
+                Message::new(0, 0, 0, MessageBody::None(PTYPE_SHUTDOWN)),
+                now_us,
+            );
         });
 
         // When the exit code is negative the shutdown is triggered by an
@@ -522,28 +1145,93 @@ impl LuaActorServer {
         // so the process can terminate without external intervention.
         if exit_code < 0 {
             self.actors.iter().for_each(|v| {
-                let _ = v.value().tx.send(Message {
-                    from: 0,
-                    to: 0,
-                    session: 0,
-                    data: MessageBody::None(PTYPE_QUIT),
-                });
+                let _ = v
+                    .value()
+                    .push(Message::new(0, 0, 0, MessageBody::None(PTYPE_QUIT)), now_us);
             });
         }
+
+        // Senders parked on a full mailbox give up now.
+        self.actors
+            .iter()
+            .for_each(|v| v.value().watchdog.wake_senders());
     }
 
     #[must_use]
     pub fn send(&self, msg: Message) -> Option<Message> {
         if let Some(entry) = self.actors.get(&msg.to) {
-            if let Err(err) = entry.value().tx.send(msg) {
-                return Some(err.0);
-            } else {
-                return None;
-            }
+            return entry.value().push(msg, self.clock_us()).err();
         }
         Some(msg)
     }
 
+    /// Deliver an actor-originated message, honoring the receiver's mailbox
+    /// capacity and overflow policy.
+    ///
+    /// Only new requests and notifications (`session <= 0`) are subject to the
+    /// bound. Responses and runtime events keep using the unbounded `send`:
+    /// dropping or delaying them would strand a coroutine that is already
+    /// waiting on the receiver side.
+    pub fn send_bounded(&self, msg: Message) -> Result<(), MailboxError> {
+        let watchdog = match self.actors.get(&msg.to) {
+            Some(entry) => {
+                let wd = &entry.value().watchdog;
+                if msg.session > 0 || !wd.is_full() {
+                    return entry
+                        .value()
+                        .push(msg, self.clock_us())
+                        .map_err(MailboxError::Dead);
+                }
+                match wd.mailbox_policy() {
+                    MailboxPolicy::DropNewest => {
+                        wd.dropped_total.fetch_add(1, Ordering::Relaxed);
+                        return Err(MailboxError::Dropped(msg));
+                    }
+                    MailboxPolicy::Reject => {
+                        wd.dropped_total.fetch_add(1, Ordering::Relaxed);
+                        return Err(MailboxError::Full(msg));
+                    }
+                    // Nobody would ever make room in the sender's own mailbox.
+                    MailboxPolicy::Block if msg.from == msg.to => {
+                        wd.dropped_total.fetch_add(1, Ordering::Relaxed);
+                        return Err(MailboxError::Full(msg));
+                    }
+                    // Release the shard lock before parking: holding it would
+                    // stall `add_actor`/`remove_actor` on the same shard.
+                    MailboxPolicy::Block => wd.clone(),
+                }
+            }
+            None => return Err(MailboxError::Dead(msg)),
+        };
+
+        if !self.wait_mailbox(&watchdog) {
+            watchdog.dropped_total.fetch_add(1, Ordering::Relaxed);
+            return Err(MailboxError::Full(msg));
+        }
+        self.send(msg).map_or(Ok(()), |m| Err(MailboxError::Dead(m)))
+    }
+
+    /// Park the calling actor until `watchdog`'s mailbox has room. Gives up
+    /// (returns `false`) after `LIMITS.mailbox_block_timeout_ms` so two actors
+    /// blocked on each other's full mailboxes cannot deadlock forever, and
+    /// returns early once shutdown has been requested.
+    fn wait_mailbox(&self, watchdog: &Watchdog) -> bool {
+        let wait = || {
+            let deadline =
+                Instant::now() + Duration::from_millis(crate::LIMITS.mailbox_block_timeout_ms);
+            watchdog.wait_room(deadline, || self.exit_code() != i32::MAX)
+        };
+
+        // Non-unique actors run on a multi-thread runtime worker; hand the
+        // worker's other tasks off before sleeping on it.
+        match tokio::runtime::Handle::try_current() {
+            Ok(h) if h.runtime_flavor() == tokio::runtime::RuntimeFlavor::MultiThread => {
+                tokio::task::block_in_place(wait)
+            }
+            _ => wait(),
+        }
+    }
+
     #[must_use]
     pub fn send_value<T: Send>(
         &self,
@@ -552,12 +1240,12 @@ impl LuaActorServer {
         session: i64,
         res: T,
     ) -> Option<Message> {
-        self.send(Message {
-            from: 0,
-            to: owner,
+        self.send(Message::new(
+            0,
+            owner,
             session,
-            data: MessageBody::Boxed(protocol_type, Box::new(BoxedValue::new(res))),
-        })
+            MessageBody::Boxed(protocol_type, Box::new(BoxedValue::new(res))),
+        ))
     }
 
     pub fn next_actor_id(&self) -> ActorId {
@@ -583,14 +1271,21 @@ impl LuaActorServer {
     }
 
     pub fn clock(&self) -> f64 {
-        self.clock.elapsed().as_secs_f64()
+        self.now_clock().as_secs_f64()
     }
 
     pub fn now_clock(&self) -> Duration {
+        if self.is_replaying() {
+            return Duration::from_micros(self.replay_clock_us.load(Ordering::Acquire));
+        }
         self.clock.elapsed()
     }
 
     pub fn now(&self) -> DateTime<Utc> {
+        if self.is_replaying() {
+            let ms = self.replay_now_ms.load(Ordering::Acquire);
+            return DateTime::from_timestamp_millis(ms).unwrap_or(self.now);
+        }
         self.now + self.now_clock() + Duration::from_millis(self.time_offset.load(Ordering::Acquire))
     }
 
@@ -604,17 +1299,39 @@ impl LuaActorServer {
         if session >= 0 {
             log::error!("{}.", err);
         } else {
-            let _ = self.send(Message {
+            let _ = self.send(Message::new(
                 from,
                 to,
-                session: -session,
-                data: MessageBody::Buffer(PTYPE_ERROR, Box::new(err.into_bytes().into())),
-            });
+                -session,
+                MessageBody::Buffer(PTYPE_ERROR, Box::new(err.into_bytes().into())),
+            ));
         }
     }
 
     pub fn clock_ms(&self) -> u64 {
-        self.clock.elapsed().as_millis() as u64
+        self.now_clock().as_millis() as u64
+    }
+
+    /// Monotonic microseconds since startup; the time base of message latency
+    /// tracing.
+    pub fn clock_us(&self) -> u64 {
+        self.now_clock().as_micros() as u64
+    }
+
+    /// Switch the clocks to the virtual clock of a replay; see `record.rs`.
+    pub fn start_replay(&self, now_ms: i64, clock_us: u64) {
+        self.set_replay_clock(now_ms, clock_us);
+        self.replaying.store(true, Ordering::Release);
+    }
+
+    pub fn is_replaying(&self) -> bool {
+        self.replaying.load(Ordering::Acquire)
+    }
+
+    /// Move the virtual clock to the time a recorded message was handled.
+    pub fn set_replay_clock(&self, now_ms: i64, clock_us: u64) {
+        self.replay_now_ms.store(now_ms, Ordering::Release);
+        self.replay_clock_us.store(clock_us, Ordering::Release);
     }
 
     pub fn check_watchdogs(&self) {
@@ -622,59 +1339,156 @@ impl LuaActorServer {
         self.actors.iter().for_each(|entry| {
             let id = *entry.key();
             let wd = &entry.value().watchdog;
+            let config = wd.config();
             let hb = wd.heartbeat_ms.load(Ordering::Acquire);
-            if hb > 0 && now_ms.saturating_sub(hb) >= 10_000 {
-                let elapsed_s = (now_ms - hb) / 1000;
-                // Read only the published scalars (paired Acquire above via `hb`);
-                // never touch the actor's live Message.
-                let msg_info = format!(
-                    "Message {{ ptype: {}, from: 0x{:08x}, to: 0x{:08x}, session: {} }}",
-                    wd.ptype.load(Ordering::Relaxed),
-                    wd.from.load(Ordering::Relaxed),
-                    wd.to.load(Ordering::Relaxed),
-                    wd.session.load(Ordering::Relaxed),
-                );
-                let s = format!(
-                    "slow_message,Actor 0x{:08X} blocked for {}s, msg: {}",
-                    id, elapsed_s, msg_info
-                );
-                log::error!("{}", s);
-                let _ = self.send(Message {
-                    from: id,
-                    to: BOOTSTRAP_ACTOR_ADDR,
-                    session: 0,
-                    data: MessageBody::Buffer(PTYPE_SYSTEM, Box::new(s.into())),
-                });
-                wd.heartbeat_ms.store(now_ms, Ordering::Release);
-
-                // Only interrupt after 3 consecutive timeout detections (~30s).
-                let count = wd.timeout_count.fetch_add(1, Ordering::Relaxed) + 1;
-                if count >= 3 {
-                    // Interrupt: CAS(0→1), install hook on active_l, CAS(1→-1).
-                    // If trap is already non-zero a previous interrupt is in flight.
-                    if wd
-                        .trap
-                        .compare_exchange(0, 1, Ordering::AcqRel, Ordering::Acquire)
-                        .is_ok()
-                    {
-                        let active = wd.active_l.load(Ordering::Acquire);
-                        if !active.is_null() {
-                            unsafe {
-                                lua_ffi::lua_sethook(
-                                    active as *mut lua_ffi::lua_State,
-                                    Some(moon_signal_hook),
-                                    lua_ffi::LUA_MASKCOUNT,
-                                    1,
-                                );
-                            }
+            if hb == 0 || now_ms.saturating_sub(hb) < config.timeout_ms || wd.debugger.is_paused()
+            {
+                return;
+            }
+            // Start the next period, unless the dispatch ended meanwhile.
+            if wd
+                .heartbeat_ms
+                .compare_exchange(hb, now_ms, Ordering::AcqRel, Ordering::Acquire)
+                .is_err()
+            {
+                return;
+            }
+            let strike = wd.timeout_count.fetch_add(1, Ordering::Relaxed) + 1;
+
+            // Read only the published scalars (paired Acquire above via `hb`);
+            // never touch the actor's live Message.
+            let report = wd.report(id, "slow", now_ms);
+            log::error!(
+                "slow_message,Actor 0x{:08X} blocked for {}ms ({}/{}), msg: Message {{ ptype: {}, from: 0x{:08x}, to: 0x{:08x}, session: {} }}",
+                id,
+                report["blocked_ms"],
+                strike,
+                config.strikes,
+                report["ptype"],
+                report["from"].as_u64().unwrap_or_default(),
+                report["to"].as_u64().unwrap_or_default(),
+                report["session"],
+            );
+            self.send_watchdog_report(id, &report);
+
+            if strike >= config.strikes && config.action != WatchdogAction::Log {
+                // Interrupt: CAS(0→1), install hook on active_l, CAS(1→-1).
+                // If trap is already non-zero a previous interrupt is in flight.
+                if wd
+                    .trap
+                    .compare_exchange(0, 1, Ordering::AcqRel, Ordering::Acquire)
+                    .is_ok()
+                {
+                    let active = wd.active_l.load(Ordering::Acquire);
+                    if !active.is_null() {
+                        unsafe {
+                            lua_ffi::lua_sethook(
+                                active as *mut lua_ffi::lua_State,
+                                Some(moon_signal_hook),
+                                lua_ffi::LUA_MASKCOUNT,
+                                1,
+                            );
                         }
-                        wd.trap.store(-1, Ordering::Release);
                     }
+                    wd.trap.store(-1, Ordering::Release);
                 }
             }
         });
     }
 
+    /// Sample the Lua stacks of `id` `hz` times per second until
+    /// `stop_profile`. The sampler thread ends with the run, or with the
+    /// service.
+    pub fn start_profile(&self, id: ActorId, hz: u32) -> Result<(), String> {
+        let (watchdog, run) = {
+            let entry = self
+                .actors
+                .get(&id)
+                .ok_or_else(|| format!("service {:08x} not found", id))?;
+            let run = entry
+                .watchdog
+                .profiler
+                .start(hz)
+                .map_err(|err| format!("service {:08x}: {}", id, err))?;
+            (Arc::downgrade(&entry.watchdog), run)
+        };
+        let period = Duration::from_secs(1) / hz;
+        thread::Builder::new()
+            .name(format!("profiler-{:08x}", id))
+            .spawn(move || {
+                loop {
+                    thread::sleep(period);
+                    let Some(wd) = watchdog.upgrade() else {
+                        break;
+                    };
+                    if !wd.profiler.is_current(run) {
+                        break;
+                    }
+                    wd.request_sample();
+                }
+            })
+            .map_err(|err| err.to_string())?;
+        Ok(())
+    }
+
+    /// End the profiling run of `id` and return its samples.
+    pub fn stop_profile(&self, id: ActorId) -> Result<Profile, String> {
+        let entry = self
+            .actors
+            .get(&id)
+            .ok_or_else(|| format!("service {:08x} not found", id))?;
+        entry
+            .watchdog
+            .profiler
+            .stop()
+            .ok_or_else(|| format!("service {:08x} is not being profiled", id))
+    }
+
+    /// Look up a service given by id (decimal or `0x` hex) or by unique name.
+    /// An id is returned as is, whether or not the service is running.
+    pub fn resolve_service(&self, service: &str) -> Result<ActorId, String> {
+        let service = service.trim();
+        let id = match service
+            .strip_prefix("0x")
+            .or_else(|| service.strip_prefix("0X"))
+        {
+            Some(hex) => ActorId::from_str_radix(hex, 16).ok(),
+            None => service.parse::<ActorId>().ok(),
+        };
+        match id {
+            Some(id) => Ok(id),
+            None => self
+                .query(service)
+                .map(|id| *id)
+                .ok_or_else(|| format!("service '{}' not found", service)),
+        }
+    }
+
+    /// Attach a debugger to `service`, see [`resolve_service`](Self::resolve_service).
+    pub fn debug_attach(&self, service: &str) -> Result<DebugSession, String> {
+        let id = self.resolve_service(service)?;
+        let watchdog = self
+            .actors
+            .get(&id)
+            .map(|entry| entry.watchdog.clone())
+            .ok_or_else(|| format!("service {:08x} not found", id))?;
+        DebugSession::new(id, watchdog).map_err(|err| format!("service {:08x}: {}", id, err))
+    }
+
+    /// Deliver a watchdog report to the bootstrap service, where
+    /// `moon.system("watchdog", fn)` receives it as a table.
+    pub fn send_watchdog_report(&self, from: ActorId, report: &serde_json::Value) {
+        let _ = self.send(Message::new(
+            from,
+            BOOTSTRAP_ACTOR_ADDR,
+            0,
+            MessageBody::Buffer(
+                PTYPE_SYSTEM,
+                Box::new(format!("watchdog,{}", report).into_bytes().into()),
+            ),
+        ));
+    }
+
     pub fn io_runtime(&self) -> &tokio::runtime::Runtime {
         &self.io_runtime
     }
@@ -799,12 +1613,22 @@ impl LuaActorServer {
         // Collect watchdog data first (releasing the `actors` shard locks), then
         // look up unique names — avoids AB-BA deadlock with callers that hold
         // `unique_actors` → `actors` (e.g. broadcast_system / remove_actor).
-        let entries: Vec<(ActorId, u64, u64, u64)> = self
+        let mut stats: Vec<ActorStat> = self
             .actors
             .iter()
             .map(|e| {
                 let wd = &e.value().watchdog;
-                (*e.key(), wd.memory() as u64, wd.message_total(), wd.cpu_ms_total())
+                ActorStat {
+                    id: *e.key(),
+                    name: None,
+                    memory: wd.memory() as i64,
+                    messages: wd.message_total(),
+                    cpu_ms: wd.cpu_ms_total(),
+                    queue: wd.queue_depth(),
+                    capacity: wd.mailbox_capacity(),
+                    dropped: wd.dropped_total(),
+                    latency: wd.latency_stats(),
+                }
             })
             .collect();
         // O(1) reverse lookup instead of O(N×M) inner scan per actor.
@@ -813,16 +1637,10 @@ impl LuaActorServer {
             .iter()
             .map(|u| (*u.value(), u.key().clone()))
             .collect();
-        entries
-            .into_iter()
-            .map(|(id, memory, messages, cpu_ms)| ActorStat {
-                id,
-                name: id_to_name.get(&id).cloned(),
-                memory: memory as i64,
-                messages,
-                cpu_ms,
-            })
-            .collect()
+        for stat in stats.iter_mut() {
+            stat.name = id_to_name.get(&stat.id).cloned();
+        }
+        stats
     }
 
     pub fn error_count(&self) -> usize {
@@ -834,98 +1652,24 @@ impl LuaActorServer {
     }
 }
 
+/// Period of the watchdog monitor, and so the shortest `timeout_ms` it can
+/// enforce.
+pub const WATCHDOG_PERIOD_MS: u64 = 50;
+
 pub fn run_monitor() {
     thread::spawn(|| {
         loop {
             if CONTEXT.exit_code() != i32::MAX && CONTEXT.stopped() {
                 break;
             }
-            thread::sleep(Duration::from_secs(5));
+            // Fine enough for sub-second `timeout_ms`; a check only reads a
+            // few atomics per actor.
+            thread::sleep(Duration::from_millis(WATCHDOG_PERIOD_MS));
             CONTEXT.check_watchdogs();
         }
     });
 }
 
-#[derive(Ord, PartialOrd, Eq, PartialEq, Debug)]
-struct Timer {
-    expiry_clock: i64,
-    timer_id: i64,
-    owner: ActorId,
-}
-
-pub fn insert_timer(owner: ActorId, timer_id: i64, interval: u64) {
-    let expiry_clock = CONTEXT.now_clock() + Duration::from_millis(interval);
-    let Some(timer_tx) = CONTEXT.timer_tx.get() else {
-        log::error!("insert_timer called before run_timer started");
-        return;
-    };
-    if timer_tx
-        .send(Timer {
-            timer_id,
-            expiry_clock: expiry_clock.as_millis() as i64,
-            owner,
-        })
-        .is_ok()
-    {
-        CONTEXT.pending_timers.fetch_add(1, Ordering::Release);
-    }
-}
-
-pub fn run_timer() {
-    // Create the channel here so the receiver can be moved straight into the
-    // single timer task. Only the sender is published globally; this must be
-    // called exactly once and before any `insert_timer`.
-    let (timer_tx, mut rc) = mpsc::unbounded_channel();
-    CONTEXT
-        .timer_tx
-        .set(timer_tx)
-        .unwrap_or_else(|_| panic!("run_timer called more than once"));
-    tokio::spawn(async move {
-        let mut btree_map = BTreeSet::new();
-        let mut wait_time = 1000;
-        loop {
-            // // Terminate cooperatively once shutdown has been requested. The
-            // // global `timer_tx` sender lives in `CONTEXT` and never drops, so
-            // // `rc.recv()` alone would never return `None` and this task would
-            // // otherwise keep the IO runtime alive forever. The ≤1s `wait_time`
-            // // bounds how long after shutdown we take to notice.
-            // if CONTEXT.exit_code() != i32::MAX {
-            //     break;
-            // }
-            match timeout(Duration::from_millis(wait_time), rc.recv()).await {
-                Ok(Some(timer)) => {
-                    //println!("insert timer: {:?} {:?}", timer, CONTEXT.now_clock());
-                    btree_map.insert(timer);
-                }
-                Ok(None) => {
-                    break;
-                }
-                Err(_) => {} //timeout
-            }
-
-            wait_time = 1000;
-
-            while let Some(timer) = btree_map.first() {
-                let diff = timer.expiry_clock - CONTEXT.now_clock().as_millis() as i64;
-                if diff <= 0 {
-                    //println!("timer timeout: {:?} {:?}", timer, CONTEXT.now_clock());
-                    let _ = CONTEXT.send(Message {
-                        from: 0,
-                        to: timer.owner,
-                        session: 0,
-                        data: MessageBody::ISize(PTYPE_TIMER, timer.timer_id as isize),
-                    });
-                    btree_map.pop_first();
-                    CONTEXT.pending_timers.fetch_sub(1, Ordering::Release);
-                } else {
-                    wait_time = diff as u64;
-                    break;
-                }
-            }
-        }
-    });
-}
-
 /// Per-actor statistics snapshot produced by [`LuaActorServer::actor_stats`].
 pub struct ActorStat {
     pub id: ActorId,
@@ -937,6 +1681,14 @@ pub struct ActorStat {
     pub messages: u64,
     /// Cumulative dispatch time, in milliseconds.
     pub cpu_ms: u64,
+    /// Messages currently waiting in the mailbox.
+    pub queue: usize,
+    /// Mailbox capacity (0 = unbounded).
+    pub capacity: usize,
+    /// Messages refused or discarded because the mailbox was full.
+    pub dropped: u64,
+    /// Queue wait and dispatch latency per received ptype.
+    pub latency: Vec<LatencyStat>,
 }
 
 pub struct LuaActorParam {
@@ -945,10 +1697,39 @@ pub struct LuaActorParam {
     pub creator: ActorId,
     pub session: i64,
     pub memlimit: i64,
+    /// Memory above which the service is asked to shed load (0 = none).
+    pub mem_soft_limit: i64,
+    pub mem_action: MemoryAction,
     pub name: String,
     pub source: String,
     pub params: String,
     pub block: bool,
+    /// Maximum queued messages before `mailbox_policy` applies (0 = unbounded).
+    pub mailbox_capacity: usize,
+    pub mailbox_policy: MailboxPolicy,
+    /// Log level override for this actor (0 = follow the global level).
+    pub log_level: u8,
+    /// Write this actor's Lua logs to their own file instead of the main one.
+    pub log_file: Option<String>,
+    /// Restart policy. `transient` acts on the abnormal exits of
+    /// [`ExitReason::is_abnormal`] only: a Lua error in a dispatch is answered
+    /// with an error response and the service carries on, so it is no exit.
+    pub restart: RestartPolicy,
+    pub restart_log: RestartLog,
+    /// Service group to join (empty = none), see `group.rs`.
+    pub group: String,
+    /// Balance of the group; `None` follows the group's existing members.
+    pub balance: Option<Balance>,
+    /// Record every handled message to this file, see `record.rs`.
+    pub record: Option<String>,
+    /// Fixed seed for `math.random` (`None` = random).
+    pub seed: Option<u64>,
+    /// Work a shared actor does before yielding its worker thread.
+    pub budget: Budget,
+    /// Dispatch responses, timers and runtime messages ahead of bulk traffic.
+    pub priority_lanes: bool,
+    /// Blocked-dispatch thresholds, see `check_watchdogs`.
+    pub watchdog: WatchdogConfig,
 }
 
 #[cfg(test)]
@@ -962,10 +1743,25 @@ mod tests {
             creator: 0,
             session: 0,
             memlimit: 0,
+            mem_soft_limit: 0,
+            mem_action: MemoryAction::Error,
             name: name.to_string(),
             source: String::new(),
             params: String::new(),
             block: false,
+            mailbox_capacity: 0,
+            mailbox_policy: MailboxPolicy::Block,
+            log_level: 0,
+            log_file: None,
+            restart: RestartPolicy::default(),
+            restart_log: RestartLog::default(),
+            group: String::new(),
+            balance: None,
+            record: None,
+            seed: None,
+            budget: Budget::default(),
+            priority_lanes: false,
+            watchdog: WatchdogConfig::default(),
         }
     }
 
@@ -1038,4 +1834,289 @@ mod tests {
         CONTEXT.remove_actor(unique_watcher_id, &unique_watcher.name);
         CONTEXT.remove_actor(normal_watcher_id, &normal_watcher.name);
     }
+
+    fn text_msg(to: ActorId, session: i64) -> Message {
+        Message::new(
+            0,
+            to,
+            session,
+            MessageBody::Buffer(PTYPE_TEXT, Box::new(b"x".to_vec().into())),
+        )
+    }
+
+    #[test]
+    fn bounded_mailbox_applies_policy_only_to_requests() {
+        let reject_id = 0x7200_0001;
+        let drop_id = 0x7200_0002;
+
+        let (reject_tx, mut reject_rx) = mpsc::unbounded_channel();
+        let (drop_tx, _drop_rx) = mpsc::unbounded_channel();
+
+        let mut params = actor_param(reject_id, "mailbox-reject", false);
+        params.mailbox_capacity = 2;
+        params.mailbox_policy = MailboxPolicy::Reject;
+        let mut reject = LuaActor::new(&params);
+        let reject_wd = CONTEXT.add_actor(&mut reject, reject_tx).unwrap();
+
+        let mut params = actor_param(drop_id, "mailbox-drop", false);
+        params.mailbox_capacity = 1;
+        params.mailbox_policy = MailboxPolicy::DropNewest;
+        let mut dropper = LuaActor::new(&params);
+        let drop_wd = CONTEXT.add_actor(&mut dropper, drop_tx).unwrap();
+
+        assert!(CONTEXT.send_bounded(text_msg(reject_id, 0)).is_ok());
+        assert!(CONTEXT.send_bounded(text_msg(reject_id, -1)).is_ok());
+        match CONTEXT.send_bounded(text_msg(reject_id, -2)) {
+            Err(MailboxError::Full(m)) => assert_eq!(m.session, -2),
+            _ => panic!("third request should be rejected"),
+        }
+        // Responses bypass the bound.
+        assert!(CONTEXT.send_bounded(text_msg(reject_id, 3)).is_ok());
+        assert_eq!(reject_wd.queue_depth(), 3);
+        assert_eq!(reject_wd.dropped_total(), 1);
+
+        // Draining makes room again.
+        while reject_rx.try_recv().is_ok() {
+            reject_wd.dequeue();
+        }
+        assert_eq!(reject_wd.queue_depth(), 0);
+        assert!(CONTEXT.send_bounded(text_msg(reject_id, 0)).is_ok());
+
+        assert!(CONTEXT.send_bounded(text_msg(drop_id, 0)).is_ok());
+        match CONTEXT.send_bounded(text_msg(drop_id, -4)) {
+            Err(MailboxError::Dropped(m)) => assert_eq!(m.session, -4),
+            _ => panic!("second request should be dropped"),
+        }
+        assert_eq!(drop_wd.queue_depth(), 1);
+        assert_eq!(drop_wd.dropped_total(), 1);
+
+        let stat = CONTEXT
+            .actor_stats()
+            .into_iter()
+            .find(|s| s.id == drop_id)
+            .expect("actor stats should include the dropping actor");
+        assert_eq!(stat.queue, 1);
+        assert_eq!(stat.capacity, 1);
+        assert_eq!(stat.dropped, 1);
+
+        CONTEXT.remove_actor(reject_id, &reject.name);
+        CONTEXT.remove_actor(drop_id, &dropper.name);
+    }
+
+    #[test]
+    fn blocked_sender_wakes_when_the_receiver_dequeues() {
+        let id = 0x7200_0003;
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let mut params = actor_param(id, "mailbox-block", false);
+        params.mailbox_capacity = 1;
+        let mut actor = LuaActor::new(&params);
+        let wd = CONTEXT.add_actor(&mut actor, tx).unwrap();
+
+        assert!(CONTEXT.send_bounded(text_msg(id, 0)).is_ok());
+
+        // A service never drains its own mailbox while it is sending.
+        let mut own = text_msg(id, -1);
+        own.from = id;
+        let begin = Instant::now();
+        assert!(matches!(
+            CONTEXT.send_bounded(own),
+            Err(MailboxError::Full(_))
+        ));
+        assert!(begin.elapsed() < Duration::from_secs(1));
+
+        let receiver = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(50));
+            rx.try_recv().unwrap();
+            wd.dequeue();
+            rx
+        });
+        let begin = Instant::now();
+        assert!(CONTEXT.send_bounded(text_msg(id, 0)).is_ok());
+        let waited = begin.elapsed();
+        assert!(waited >= Duration::from_millis(40) && waited < Duration::from_secs(1));
+        let mut rx = receiver.join().unwrap();
+        assert!(rx.try_recv().is_ok());
+
+        CONTEXT.remove_actor(id, &actor.name);
+    }
+
+    #[test]
+    fn send_stamps_enqueue_time_for_latency() {
+        let id = 0x7300_0001;
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let mut actor = LuaActor::new(&actor_param(id, "latency", false));
+        let wd = CONTEXT.add_actor(&mut actor, tx).unwrap();
+
+        let before = CONTEXT.clock_us();
+        assert!(CONTEXT.send(text_msg(id, 0)).is_none());
+        let m = rx.try_recv().unwrap();
+        wd.dequeue();
+        assert!(m.enqueue_us >= before && m.enqueue_us <= CONTEXT.clock_us());
+
+        let begin = m.enqueue_us + 300;
+        wd.record_dispatch(m.ptype(), m.enqueue_us, begin, begin + 2000, 0);
+        wd.record_dispatch(PTYPE_TIMER, 0, begin, begin + 5, 0);
+        let stat = CONTEXT
+            .actor_stats()
+            .into_iter()
+            .find(|s| s.id == id)
+            .unwrap();
+        assert_eq!(stat.cpu_ms, 2);
+        assert_eq!(stat.latency.len(), 2);
+        let text = &stat.latency[0];
+        assert_eq!(text.ptype, PTYPE_TEXT);
+        assert_eq!((text.wait.max_us, text.dispatch.max_us), (300, 2000));
+        // Unstamped messages record no wait.
+        assert_eq!(stat.latency[1].wait.max_us, 0);
+
+        wd.reset_latency();
+        assert!(wd.latency_stats().is_empty());
+        CONTEXT.remove_actor(id, &actor.name);
+    }
+
+    #[test]
+    fn publish_shares_one_buffer_and_skips_full_mailboxes() {
+        let a = 0x7400_0001;
+        let b = 0x7400_0002;
+        let (a_tx, mut a_rx) = mpsc::unbounded_channel();
+        let (b_tx, _b_rx) = mpsc::unbounded_channel();
+        let mut actor_a = LuaActor::new(&actor_param(a, "pubsub-a", false));
+        let a_wd = CONTEXT.add_actor(&mut actor_a, a_tx).unwrap();
+        let mut params = actor_param(b, "pubsub-b", false);
+        params.mailbox_capacity = 1;
+        let mut actor_b = LuaActor::new(&params);
+        let b_wd = CONTEXT.add_actor(&mut actor_b, b_tx).unwrap();
+
+        assert!(CONTEXT.subscribe(a, "t.x").unwrap());
+        assert!(CONTEXT.subscribe(a, "t.#").unwrap());
+        assert!(CONTEXT.subscribe(b, "t.*").unwrap());
+        assert!(CONTEXT.subscribe(b, "t.").is_err());
+        assert!(
+            CONTEXT
+                .publish(a, "t.*", Box::new(Buffer::from("x")))
+                .is_err()
+        );
+
+        assert_eq!(
+            CONTEXT.publish(a, "t.x", Box::new(Buffer::from("one"))),
+            Ok(2)
+        );
+        // b's mailbox (capacity 1) is now full.
+        assert_eq!(
+            CONTEXT.publish(a, "t.x", Box::new(Buffer::from("two"))),
+            Ok(1)
+        );
+        assert_eq!(b_wd.dropped_total(), 1);
+
+        let first = a_rx.try_recv().unwrap();
+        assert_eq!(first.ptype(), PTYPE_PUBSUB);
+        let MessageBody::Shared(_, data) = &first.data else {
+            panic!("pubsub body should be shared");
+        };
+        assert_eq!(data.as_slice(), b"one");
+        // Shared with the message still queued for b.
+        assert_eq!(Arc::strong_count(data), 2);
+        a_wd.dequeue();
+
+        CONTEXT.remove_actor(a, &actor_a.name);
+        CONTEXT.remove_actor(b, &actor_b.name);
+        assert_eq!(
+            CONTEXT.publish(a, "t.x", Box::new(Buffer::from("x"))),
+            Ok(0)
+        );
+    }
+
+    #[test]
+    fn actors_join_and_leave_groups() {
+        let (a, b, c) = (0x7500_0001, 0x7500_0002, 0x7500_0003);
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let mut params = actor_param(a, "", false);
+        params.group = "ctx-group".to_string();
+        params.balance = Some(Balance::LeastQueue);
+        let mut actor_a = LuaActor::new(&params);
+        let a_wd = CONTEXT.add_actor(&mut actor_a, tx.clone()).unwrap();
+        params.id = b;
+        params.balance = None;
+        let mut actor_b = LuaActor::new(&params);
+        CONTEXT.add_actor(&mut actor_b, tx.clone()).unwrap();
+        params.id = c;
+        params.balance = Some(Balance::Hash);
+        let mut actor_c = LuaActor::new(&params);
+        assert!(CONTEXT.add_actor(&mut actor_c, tx).is_err());
+        assert_eq!(CONTEXT.group_members("ctx-group"), vec![a, b]);
+
+        assert!(CONTEXT.send(text_msg(a, 0)).is_none());
+        assert_eq!(CONTEXT.pick_group("ctx-group", None), Ok(Some(b)));
+        let _ = rx.try_recv();
+        a_wd.dequeue();
+
+        CONTEXT.remove_actor(b, "");
+        assert_eq!(CONTEXT.pick_group("ctx-group", None), Ok(Some(a)));
+        CONTEXT.remove_actor(a, "");
+        assert_eq!(CONTEXT.pick_group("ctx-group", None), Ok(None));
+    }
+
+    #[test]
+    fn restart_policy_modes_and_intensity() {
+        let mut policy = RestartPolicy::default();
+        assert!(!policy.wants_restart(ExitReason::InitFailed));
+
+        policy.mode = RestartMode::Transient;
+        assert!(policy.wants_restart(ExitReason::Timeout));
+        assert!(policy.wants_restart(ExitReason::OutOfMemory));
+        assert!(!policy.wants_restart(ExitReason::Normal));
+        assert!(!policy.wants_restart(ExitReason::Killed));
+
+        policy.mode = RestartMode::Permanent;
+        assert!(policy.wants_restart(ExitReason::Normal));
+        assert!(policy.wants_restart(ExitReason::InitFailed));
+        assert!(!policy.wants_restart(ExitReason::Killed));
+
+        policy.max_restarts = 2;
+        policy.within_secs = 1;
+        let mut log = RestartLog::default();
+        assert!(log.admit(&policy, 100));
+        assert!(log.admit(&policy, 500));
+        assert!(!log.admit(&policy, 900));
+        // The first restart leaves the window.
+        assert!(log.admit(&policy, 1100));
+        assert_eq!(log.history, vec![500, 1100]);
+
+        policy.max_restarts = 0;
+        assert!(!RestartLog::default().admit(&policy, 0));
+    }
+
+    #[test]
+    fn watchdog_config_decides_interrupt_and_report() {
+        let wd = Watchdog::new().with_config(WatchdogConfig {
+            timeout_ms: 200,
+            strikes: 2,
+            action: WatchdogAction::Kill,
+        });
+        wd.begin(1000, PTYPE_LUA, 7, 9, -3);
+        wd.timeout_count.store(1, Ordering::Relaxed);
+        assert!(!wd.interrupted());
+        wd.timeout_count.store(2, Ordering::Relaxed);
+        assert!(wd.interrupted());
+
+        let report = wd.report(9, "kill", 1450);
+        assert_eq!(report["event"], "kill");
+        assert_eq!(report["blocked_ms"], 450);
+        assert_eq!(report["strike"], 2);
+        assert_eq!(report["action"], "kill");
+        assert_eq!(report["from"], 7);
+        assert_eq!(report["session"], -3);
+
+        // A new dispatch starts over.
+        wd.begin(2000, PTYPE_LUA, 7, 9, -4);
+        assert!(!wd.interrupted());
+
+        let log_only = Watchdog::new().with_config(WatchdogConfig {
+            action: WatchdogAction::Log,
+            ..Default::default()
+        });
+        log_only.timeout_count.store(100, Ordering::Relaxed);
+        assert!(!log_only.interrupted());
+        assert_eq!(WatchdogAction::parse("nap"), None);
+    }
 }
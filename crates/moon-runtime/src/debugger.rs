@@ -0,0 +1,521 @@
+//! Remote Lua debugger for one service, driven by the DAP server in
+//! `moon-app`.
+//!
+//! A session attaches to a service through [`LuaActorServer::debug_attach`]. While
+//! attached, the service's `lua_State`s run with a line hook (the same
+//! `moon_signal_hook` the watchdog and the profilers use): at each line it
+//! checks the breakpoints, the step in progress and pause requests. When it
+//! stops, the hook holds the service's thread, so the dispatch in progress
+//! waits while every other service keeps running, and answers the session's
+//! requests (stack, variables) on that thread until it is told to resume. The
+//! watchdog leaves a paused service alone.
+//!
+//! Breakpoints are matched by line first and by source only on a line that
+//! has one, so an attached service without breakpoints pays for a lookup per
+//! line.
+//!
+//! [`LuaActorServer::debug_attach`]: crate::context::LuaActorServer::debug_attach
+
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    sync::{
+        Arc, Condvar, Mutex,
+        atomic::{AtomicBool, Ordering},
+    },
+    time::Duration,
+};
+
+use tokio::sync::{mpsc, oneshot};
+
+use crate::context::{ActorId, Watchdog};
+
+/// Frames reported in a stack trace.
+pub const MAX_FRAMES: usize = 200;
+
+/// Entries listed when a table is expanded.
+pub const MAX_FIELDS: usize = 1000;
+
+/// How long a session waits for a paused service to answer.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Variable references at and above this one name tables; below it, the
+/// locals and upvalues of a frame.
+const TABLE_REFERENCE: i64 = 1 << 24;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Resume {
+    Continue,
+    /// Stop at the next line of the same function or of a caller.
+    Next,
+    /// Stop at the next line run anywhere.
+    StepIn,
+    /// Stop at the next line of a caller.
+    StepOut,
+}
+
+/// What a paused service is asked by its session.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Command {
+    StackTrace,
+    Variables(Reference),
+    Resume(Resume),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Reply {
+    Frames(Vec<Frame>),
+    Variables(Vec<Variable>),
+    Resumed,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Event {
+    /// The service stopped; `reason` is `breakpoint`, `step` or `pause`.
+    Stopped { reason: &'static str },
+    /// The service exited and the session is over.
+    Exited,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Frame {
+    /// Stack level + 1, so that no frame has id 0.
+    pub id: i64,
+    pub name: String,
+    /// Chunk name of a Lua file, without the leading `@`.
+    pub source: Option<String>,
+    pub line: i32,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Variable {
+    pub name: String,
+    pub value: String,
+    /// Lua type name.
+    pub kind: String,
+    /// Reference to expand a table, 0 for other values.
+    pub reference: i64,
+}
+
+/// Something whose variables can be listed while a service is paused. Ids
+/// are only valid until the service resumes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Reference {
+    Locals(usize),
+    Upvalues(usize),
+    /// A table kept by the paused service, by index.
+    Table(usize),
+}
+
+impl Reference {
+    pub fn id(self) -> i64 {
+        match self {
+            Reference::Locals(level) => level as i64 * 2 + 1,
+            Reference::Upvalues(level) => level as i64 * 2 + 2,
+            Reference::Table(index) => TABLE_REFERENCE + index as i64,
+        }
+    }
+
+    pub fn from_id(id: i64) -> Option<Self> {
+        match id {
+            i64::MIN..=0 => None,
+            TABLE_REFERENCE.. => Some(Reference::Table((id - TABLE_REFERENCE) as usize)),
+            _ if id % 2 == 1 => Some(Reference::Locals((id as usize - 1) / 2)),
+            _ => Some(Reference::Upvalues((id as usize - 2) / 2)),
+        }
+    }
+}
+
+/// Whether the breakpoint file `path` is the Lua chunk `source`. Chunk names
+/// are often relative to the working directory or to a `package.path` entry,
+/// so a relative chunk matches any path ending with it.
+pub fn same_source(path: &str, source: &str) -> bool {
+    let path = normalize(path);
+    let source = normalize(source);
+    if path == source {
+        return true;
+    }
+    !source.starts_with('/')
+        && path.len() > source.len()
+        && path.ends_with(source.as_str())
+        && path.as_bytes()[path.len() - source.len() - 1] == b'/'
+}
+
+fn normalize(path: &str) -> String {
+    let path = path.strip_prefix('@').unwrap_or(path).replace('\\', "/");
+    let mut path = path.as_str();
+    while let Some(rest) = path.strip_prefix("./") {
+        path = rest;
+    }
+    path.replace("/./", "/")
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Step {
+    kind: Resume,
+    /// The `lua_State` the step started in; `Next` and `StepOut` only stop
+    /// there, so stepping over a call that waits stops after it returns.
+    thread: usize,
+    depth: usize,
+}
+
+#[derive(Default)]
+struct State {
+    events: Option<mpsc::UnboundedSender<Event>>,
+    breakpoints: HashMap<String, HashSet<i32>>,
+    /// Every line that has a breakpoint, in any file.
+    lines: HashSet<i32>,
+    step: Option<Step>,
+    commands: VecDeque<(Command, oneshot::Sender<Reply>)>,
+}
+
+/// Debugging state of one service, shared by its session and the hook.
+#[derive(Default)]
+pub struct Debugger {
+    attached: AtomicBool,
+    paused: AtomicBool,
+    pause_requested: AtomicBool,
+    state: Mutex<State>,
+    wake: Condvar,
+}
+
+impl Debugger {
+    pub fn is_attached(&self) -> bool {
+        self.attached.load(Ordering::Acquire)
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused.load(Ordering::Acquire)
+    }
+
+    /// Start a session. Its events are sent to the returned channel.
+    pub fn attach(&self) -> Result<mpsc::UnboundedReceiver<Event>, String> {
+        let mut state = self.state.lock().map_err(|err| err.to_string())?;
+        if self.attached.load(Ordering::Acquire) {
+            return Err("already being debugged".to_string());
+        }
+        let (tx, rx) = mpsc::unbounded_channel();
+        *state = State {
+            events: Some(tx),
+            ..Default::default()
+        };
+        self.pause_requested.store(false, Ordering::Release);
+        self.attached.store(true, Ordering::Release);
+        Ok(rx)
+    }
+
+    /// End the session: breakpoints are dropped and a paused service resumes.
+    pub fn detach(&self) {
+        if let Ok(mut state) = self.state.lock() {
+            self.attached.store(false, Ordering::Release);
+            self.pause_requested.store(false, Ordering::Release);
+            *state = State::default();
+        }
+        self.wake.notify_all();
+    }
+
+    /// The service is gone: tell the session, then detach.
+    pub fn exited(&self) {
+        if let Ok(state) = self.state.lock()
+            && let Some(events) = state.events.as_ref()
+        {
+            let _ = events.send(Event::Exited);
+        }
+        self.detach();
+    }
+
+    /// Replace the breakpoints of the file `path`.
+    pub fn set_breakpoints(&self, path: &str, lines: &[i32]) {
+        let Ok(mut state) = self.state.lock() else {
+            return;
+        };
+        if lines.is_empty() {
+            state.breakpoints.remove(path);
+        } else {
+            state
+                .breakpoints
+                .insert(path.to_string(), lines.iter().copied().collect());
+        }
+        state.lines = state.breakpoints.values().flatten().copied().collect();
+    }
+
+    /// Stop at the next line the service runs.
+    pub fn pause(&self) {
+        self.pause_requested.store(true, Ordering::Release);
+    }
+
+    /// Queue `command` for the paused service.
+    pub fn request(&self, command: Command) -> Result<oneshot::Receiver<Reply>, String> {
+        let mut state = self.state.lock().map_err(|err| err.to_string())?;
+        if !self.is_paused() {
+            return Err("the service is running".to_string());
+        }
+        let (tx, rx) = oneshot::channel();
+        state.commands.push_back((command, tx));
+        drop(state);
+        self.wake.notify_all();
+        Ok(rx)
+    }
+
+    /// Called by the hook at each line of `thread`: the reason to stop there,
+    /// if any. `depth` and `source` are only called when needed.
+    pub fn check_line(
+        &self,
+        thread: usize,
+        line: i32,
+        depth: impl FnOnce() -> usize,
+        source: impl FnOnce() -> Option<String>,
+    ) -> Option<&'static str> {
+        if self.pause_requested.swap(false, Ordering::AcqRel) {
+            return Some("pause");
+        }
+        let mut state = self.state.lock().ok()?;
+        if let Some(step) = state.step {
+            let stop = match step.kind {
+                Resume::Continue => false,
+                Resume::StepIn => true,
+                Resume::Next => step.thread == thread && depth() <= step.depth,
+                Resume::StepOut => step.thread == thread && depth() < step.depth,
+            };
+            if stop {
+                state.step = None;
+                return Some("step");
+            }
+        }
+        if !state.lines.contains(&line) {
+            return None;
+        }
+        let source = source()?;
+        state
+            .breakpoints
+            .iter()
+            .any(|(path, lines)| lines.contains(&line) && same_source(path, &source))
+            .then_some("breakpoint")
+    }
+
+    /// Called by the hook once it stopped: announce the stop and answer the
+    /// session's commands with `inspect` until it resumes the service.
+    /// Returns how to resume, or `None` when the session ended.
+    pub fn hold(
+        &self,
+        reason: &'static str,
+        mut inspect: impl FnMut(Command) -> Reply,
+    ) -> Option<Resume> {
+        // A service on a shared worker hands the worker's other tasks over
+        // before blocking it.
+        let blocking = tokio::runtime::Handle::try_current().is_ok_and(|handle| {
+            handle.runtime_flavor() == tokio::runtime::RuntimeFlavor::MultiThread
+        });
+        let mut hold = || {
+            let mut state = self.state.lock().ok()?;
+            self.paused.store(true, Ordering::Release);
+            if let Some(events) = state.events.as_ref() {
+                let _ = events.send(Event::Stopped { reason });
+            }
+            let resume = loop {
+                if !self.is_attached() {
+                    break None;
+                }
+                let Some((command, reply)) = state.commands.pop_front() else {
+                    state = self.wake.wait(state).ok()?;
+                    continue;
+                };
+                if let Command::Resume(resume) = command {
+                    let _ = reply.send(Reply::Resumed);
+                    break Some(resume);
+                }
+                drop(state);
+                let _ = reply.send(inspect(command));
+                state = self.state.lock().ok()?;
+            };
+            self.paused.store(false, Ordering::Release);
+            // Whatever was asked after the resume is answered by dropping it.
+            state.commands.clear();
+            resume
+        };
+        let resume = if blocking {
+            tokio::task::block_in_place(&mut hold)
+        } else {
+            hold()
+        };
+        self.paused.store(false, Ordering::Release);
+        resume
+    }
+
+    /// Start the step chosen at a stop in `thread`, `depth` frames deep.
+    pub fn step(&self, kind: Resume, thread: usize, depth: usize) {
+        if let Ok(mut state) = self.state.lock() {
+            state.step = (kind != Resume::Continue).then_some(Step {
+                kind,
+                thread,
+                depth,
+            });
+        }
+    }
+}
+
+/// A debugger session attached to one service. Dropping it detaches.
+pub struct DebugSession {
+    pub id: ActorId,
+    watchdog: Arc<Watchdog>,
+    events: mpsc::UnboundedReceiver<Event>,
+}
+
+impl DebugSession {
+    pub fn new(id: ActorId, watchdog: Arc<Watchdog>) -> Result<Self, String> {
+        let events = watchdog.debugger.attach()?;
+        watchdog.request_debug_hook();
+        Ok(DebugSession {
+            id,
+            watchdog,
+            events,
+        })
+    }
+
+    pub async fn next_event(&mut self) -> Option<Event> {
+        self.events.recv().await
+    }
+
+    pub fn set_breakpoints(&self, path: &str, lines: &[i32]) {
+        self.watchdog.debugger.set_breakpoints(path, lines);
+    }
+
+    pub fn pause(&self) {
+        self.watchdog.debugger.pause();
+        self.watchdog.request_debug_hook();
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.watchdog.debugger.is_paused()
+    }
+
+    /// Ask the paused service `command` and wait for its answer.
+    pub async fn request(&self, command: Command) -> Result<Reply, String> {
+        let reply = self.watchdog.debugger.request(command)?;
+        match tokio::time::timeout(REQUEST_TIMEOUT, reply).await {
+            Ok(Ok(reply)) => Ok(reply),
+            Ok(Err(_)) => Err("the service resumed".to_string()),
+            Err(_) => Err("the service did not answer".to_string()),
+        }
+    }
+}
+
+impl Drop for DebugSession {
+    fn drop(&mut self) {
+        self.watchdog.debugger.detach();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn matches_sources_and_references() {
+        assert!(same_source(
+            "/srv/game/service/battle.lua",
+            "@service/battle.lua"
+        ));
+        assert!(same_source(
+            "/srv/game/service/battle.lua",
+            "./service/battle.lua"
+        ));
+        assert!(same_source(r"C:\game\battle.lua", "@battle.lua"));
+        assert!(same_source("/srv/lualib/moon.lua", "@/srv/lualib/moon.lua"));
+        assert!(!same_source("/srv/game/xbattle.lua", "@battle.lua"));
+        assert!(!same_source("/srv/a/battle.lua", "@/srv/b/battle.lua"));
+
+        for r in [
+            Reference::Locals(0),
+            Reference::Upvalues(0),
+            Reference::Locals(7),
+            Reference::Upvalues(MAX_FRAMES),
+            Reference::Table(0),
+            Reference::Table(42),
+        ] {
+            assert!(r.id() > 0);
+            assert_eq!(Reference::from_id(r.id()), Some(r));
+        }
+        assert_eq!(Reference::from_id(0), None);
+    }
+
+    #[test]
+    fn stops_at_breakpoints_steps_and_pauses() {
+        let d = Debugger::default();
+        let _events = d.attach().unwrap();
+        assert!(d.attach().is_err(), "one session at a time");
+        d.set_breakpoints("/srv/battle.lua", &[10, 12]);
+
+        let no_depth = || -> usize { panic!("depth is only needed by a step") };
+        assert_eq!(
+            d.check_line(1, 11, no_depth, || panic!("no breakpoint on 11")),
+            None
+        );
+        assert_eq!(
+            d.check_line(1, 10, no_depth, || Some("battle.lua".into())),
+            Some("breakpoint")
+        );
+        assert_eq!(
+            d.check_line(1, 10, no_depth, || Some("moon.lua".into())),
+            None
+        );
+
+        d.step(Resume::Next, 1, 3);
+        assert_eq!(d.check_line(1, 20, || 4, || None), None, "inside a call");
+        assert_eq!(
+            d.check_line(2, 20, || 1, || None),
+            None,
+            "another coroutine"
+        );
+        assert_eq!(d.check_line(1, 20, || 3, || None), Some("step"));
+        assert_eq!(d.check_line(1, 21, || 3, || None), None, "the step is over");
+
+        d.step(Resume::StepOut, 1, 3);
+        assert_eq!(d.check_line(1, 20, || 3, || None), None);
+        assert_eq!(d.check_line(1, 5, || 2, || None), Some("step"));
+
+        d.pause();
+        assert_eq!(d.check_line(9, 1, no_depth, || None), Some("pause"));
+
+        d.set_breakpoints("/srv/battle.lua", &[]);
+        assert_eq!(
+            d.check_line(1, 10, no_depth, || panic!("no breakpoints")),
+            None
+        );
+    }
+
+    #[test]
+    fn holds_until_resumed() {
+        let d = Arc::new(Debugger::default());
+        let mut events = d.attach().unwrap();
+        assert!(d.request(Command::StackTrace).is_err(), "not paused");
+
+        let held = d.clone();
+        let service = std::thread::spawn(move || {
+            held.hold("breakpoint", |command| match command {
+                Command::StackTrace => Reply::Frames(vec![]),
+                _ => Reply::Variables(vec![]),
+            })
+        });
+        assert_eq!(
+            events.blocking_recv(),
+            Some(Event::Stopped {
+                reason: "breakpoint"
+            })
+        );
+        assert!(d.is_paused());
+        let frames = d.request(Command::StackTrace).unwrap();
+        assert_eq!(frames.blocking_recv(), Ok(Reply::Frames(vec![])));
+        let resumed = d.request(Command::Resume(Resume::Next)).unwrap();
+        assert_eq!(resumed.blocking_recv(), Ok(Reply::Resumed));
+        assert_eq!(service.join().unwrap(), Some(Resume::Next));
+        assert!(!d.is_paused());
+
+        let held = d.clone();
+        let service = std::thread::spawn(move || held.hold("pause", |_| Reply::Resumed));
+        assert!(events.blocking_recv().is_some());
+        d.exited();
+        assert_eq!(events.blocking_recv(), Some(Event::Exited));
+        assert_eq!(service.join().unwrap(), None, "detached");
+        assert!(!d.is_attached());
+    }
+}
@@ -0,0 +1,259 @@
+//! Named service groups.
+//!
+//! A service created with `group = "battle"` joins the group of that name; any
+//! number of services can share a group, unlike unique names. `moon.send_group`
+//! and `moon.call_group` address the group and the registry picks one member
+//! according to the group's [`Balance`], fixed by its first member:
+//!
+//! - `round_robin`: members in turn.
+//! - `least_queue`: the member with the fewest messages waiting in its mailbox.
+//! - `hash`: rendezvous (highest random weight) hashing on a key, so a key
+//!   keeps reaching the same member, and a member joining or leaving only moves
+//!   the keys that member gains or loses.
+//!
+//! Members leave their group when they are removed. An empty group is
+//! forgotten, so the next member to join chooses the balance again.
+
+use std::{
+    collections::HashMap,
+    hash::{DefaultHasher, Hash, Hasher},
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use crate::context::ActorId;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Balance {
+    #[default]
+    RoundRobin,
+    LeastQueue,
+    Hash,
+}
+
+impl Balance {
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "round_robin" => Some(Balance::RoundRobin),
+            "least_queue" => Some(Balance::LeastQueue),
+            "hash" => Some(Balance::Hash),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Balance::RoundRobin => "round_robin",
+            Balance::LeastQueue => "least_queue",
+            Balance::Hash => "hash",
+        }
+    }
+}
+
+struct ServiceGroup {
+    balance: Balance,
+    /// Kept sorted so a pick does not depend on join order.
+    members: Vec<ActorId>,
+    /// Round-robin cursor; atomic so picking only needs a shared lock.
+    next: AtomicUsize,
+}
+
+fn weight(key: &[u8], id: ActorId) -> u64 {
+    let mut h = DefaultHasher::new();
+    key.hash(&mut h);
+    id.hash(&mut h);
+    h.finish()
+}
+
+/// All groups, kept by `LuaActorServer` behind a lock.
+#[derive(Default)]
+pub struct GroupTable {
+    groups: HashMap<String, ServiceGroup>,
+    by_actor: HashMap<ActorId, String>,
+}
+
+impl GroupTable {
+    /// Add `id` to group `name`. `balance` of `None` follows the group; a
+    /// balance different from the one the group was created with is an error.
+    pub fn join(
+        &mut self,
+        id: ActorId,
+        name: &str,
+        balance: Option<Balance>,
+    ) -> Result<(), String> {
+        if let Some(current) = self.by_actor.get(&id) {
+            return Err(format!("service {:08x} already in group '{}'", id, current));
+        }
+        let group = self
+            .groups
+            .entry(name.to_string())
+            .or_insert_with(|| ServiceGroup {
+                balance: balance.unwrap_or_default(),
+                members: Vec::new(),
+                next: AtomicUsize::new(0),
+            });
+        if let Some(b) = balance
+            && b != group.balance
+        {
+            return Err(format!(
+                "group '{}' balances by {}, not {}",
+                name,
+                group.balance.as_str(),
+                b.as_str()
+            ));
+        }
+        if let Err(pos) = group.members.binary_search(&id) {
+            group.members.insert(pos, id);
+        }
+        self.by_actor.insert(id, name.to_string());
+        Ok(())
+    }
+
+    /// Remove `id` from its group, if any.
+    pub fn leave(&mut self, id: ActorId) {
+        let Some(name) = self.by_actor.remove(&id) else {
+            return;
+        };
+        if let Some(group) = self.groups.get_mut(&name) {
+            if let Ok(pos) = group.members.binary_search(&id) {
+                group.members.remove(pos);
+            }
+            if group.members.is_empty() {
+                self.groups.remove(&name);
+            }
+        }
+    }
+
+    /// Choose a member of `name`. `key` is required by `hash` groups and
+    /// ignored by the others; `queue_depth` reports a member's mailbox depth
+    /// for `least_queue`. `Ok(None)` when the group has no member.
+    pub fn pick(
+        &self,
+        name: &str,
+        key: Option<&[u8]>,
+        queue_depth: impl Fn(ActorId) -> usize,
+    ) -> Result<Option<ActorId>, String> {
+        let Some(group) = self.groups.get(name) else {
+            return Ok(None);
+        };
+        let members = &group.members;
+        let picked = match group.balance {
+            Balance::RoundRobin => {
+                let n = group.next.fetch_add(1, Ordering::Relaxed);
+                members.get(n % members.len().max(1)).copied()
+            }
+            Balance::LeastQueue => {
+                // Start the scan at a rotating offset so ties (e.g. all idle)
+                // still spread across members.
+                let start = group.next.fetch_add(1, Ordering::Relaxed);
+                (0..members.len())
+                    .map(|i| members[(start + i) % members.len()])
+                    .min_by_key(|&id| queue_depth(id))
+            }
+            Balance::Hash => {
+                let Some(key) = key else {
+                    return Err(format!("group '{}' balances by hash and needs a key", name));
+                };
+                members.iter().copied().max_by_key(|&id| weight(key, id))
+            }
+        };
+        Ok(picked)
+    }
+
+    /// Members of `name`, ascending.
+    pub fn members(&self, name: &str) -> Vec<ActorId> {
+        self.groups
+            .get(name)
+            .map(|g| g.members.clone())
+            .unwrap_or_default()
+    }
+
+    /// Number of groups with at least one member.
+    pub fn group_count(&self) -> usize {
+        self.groups.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_robin_and_least_queue() {
+        let mut t = GroupTable::default();
+        for id in [3, 1, 2] {
+            t.join(id, "rr", None).unwrap();
+        }
+        let picks: Vec<_> = (0..6)
+            .map(|_| t.pick("rr", None, |_| 0).unwrap().unwrap())
+            .collect();
+        assert_eq!(picks, vec![1, 2, 3, 1, 2, 3]);
+
+        for id in [10, 11, 12] {
+            t.join(id, "lq", Some(Balance::LeastQueue)).unwrap();
+        }
+        let depth = |id| match id {
+            10 => 5,
+            11 => 1,
+            _ => 3,
+        };
+        for _ in 0..3 {
+            assert_eq!(t.pick("lq", None, depth), Ok(Some(11)));
+        }
+        let mut idle: Vec<_> = (0..3)
+            .map(|_| t.pick("lq", None, |_| 0).unwrap().unwrap())
+            .collect();
+        idle.sort_unstable();
+        assert_eq!(idle, vec![10, 11, 12], "ties rotate");
+
+        assert_eq!(t.pick("missing", None, |_| 0), Ok(None));
+    }
+
+    #[test]
+    fn hash_is_stable_and_moves_few_keys() {
+        let mut t = GroupTable::default();
+        for id in 1..=4 {
+            t.join(id, "h", Some(Balance::Hash)).unwrap();
+        }
+        assert!(t.pick("h", None, |_| 0).is_err());
+
+        let keys: Vec<String> = (0..200).map(|i| format!("user{}", i)).collect();
+        let pick =
+            |t: &GroupTable, k: &str| t.pick("h", Some(k.as_bytes()), |_| 0).unwrap().unwrap();
+        let before: Vec<_> = keys.iter().map(|k| pick(&t, k)).collect();
+        assert_eq!(before, keys.iter().map(|k| pick(&t, k)).collect::<Vec<_>>());
+        for id in 1..=4 {
+            assert!(before.contains(&id), "every member gets keys");
+        }
+
+        t.leave(2);
+        for (k, old) in keys.iter().zip(&before) {
+            let new = pick(&t, k);
+            if *old != 2 {
+                assert_eq!(new, *old, "keys of remaining members stay put");
+            } else {
+                assert_ne!(new, 2);
+            }
+        }
+    }
+
+    #[test]
+    fn membership_and_balance_conflicts() {
+        let mut t = GroupTable::default();
+        t.join(1, "g", Some(Balance::Hash)).unwrap();
+        t.join(2, "g", None).unwrap();
+        assert!(t.join(3, "g", Some(Balance::RoundRobin)).is_err());
+        assert!(t.join(1, "other", None).is_err());
+        assert_eq!(t.members("g"), vec![1, 2]);
+
+        t.join(4, "fresh", Some(Balance::Hash)).unwrap();
+        t.leave(4);
+        assert_eq!(t.group_count(), 1);
+
+        t.leave(1);
+        t.leave(2);
+        assert_eq!(t.group_count(), 0);
+        // The next member chooses the balance again.
+        t.join(5, "g", Some(Balance::LeastQueue)).unwrap();
+        assert!(t.by_actor.contains_key(&5));
+    }
+}
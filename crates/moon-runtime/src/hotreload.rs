@@ -0,0 +1,331 @@
+//! Hot reload of Lua modules, enabled with `hot_reload = true` in the
+//! bootstrap `__init__` table.
+//!
+//! Every module a service loads with `require` from a file is reported here
+//! with its path (see the hot reload section of `moon.lua`). The directories
+//! holding those files are watched with inotify. When a file is written or
+//! replaced, the code cache is cleared, so the file is compiled again, and
+//! each service that loaded it gets a `_hotfix` system command naming the
+//! module; the service runs `hotfix.update` and reports the outcome to the
+//! bootstrap service as a `hotfix` system command. Services started later
+//! load the new code.
+//!
+//! Changes are collected until no file has changed for [`DEBOUNCE`], so an
+//! editor saving in several steps causes one update. Only Linux is supported.
+
+use std::{
+    collections::{BTreeSet, HashMap},
+    fs, io,
+    path::{Path, PathBuf},
+    sync::{Mutex, MutexGuard, OnceLock},
+    thread,
+    time::Duration,
+};
+
+use moon_base::ffi;
+
+use crate::context::{ActorId, CONTEXT, Message, MessageBody, PTYPE_SYSTEM};
+
+pub const DEBOUNCE: Duration = Duration::from_millis(100);
+
+/// Which services loaded which file, and as which module.
+#[derive(Default)]
+pub struct Modules {
+    files: HashMap<PathBuf, Vec<(ActorId, String)>>,
+}
+
+impl Modules {
+    /// Record that service `id` loaded `path` as module `name`.
+    pub fn add(&mut self, id: ActorId, name: &str, path: PathBuf) {
+        let users = self.files.entry(path).or_default();
+        if !users.iter().any(|(i, n)| *i == id && n == name) {
+            users.push((id, name.to_string()));
+        }
+    }
+
+    /// Services that loaded `path`, with the module name each used.
+    pub fn users(&self, path: &Path) -> Vec<(ActorId, String)> {
+        self.files.get(path).cloned().unwrap_or_default()
+    }
+
+    /// Forget service `id`, e.g. once it has exited.
+    pub fn remove(&mut self, id: ActorId) {
+        self.files.retain(|_, users| {
+            users.retain(|(i, _)| *i != id);
+            !users.is_empty()
+        });
+    }
+}
+
+struct HotReload {
+    watcher: Watcher,
+    modules: Mutex<Modules>,
+}
+
+impl HotReload {
+    fn modules(&self) -> MutexGuard<'_, Modules> {
+        self.modules.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+static HOT_RELOAD: OnceLock<HotReload> = OnceLock::new();
+
+/// Start the watcher thread. Services started afterwards report the modules
+/// they load.
+pub fn enable() -> io::Result<()> {
+    let hot_reload = HotReload {
+        watcher: Watcher::new()?,
+        modules: Mutex::default(),
+    };
+    if HOT_RELOAD.set(hot_reload).is_err() {
+        return Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            "hot reload already enabled",
+        ));
+    }
+    thread::Builder::new()
+        .name("hot-reload".to_string())
+        .spawn(|| {
+            if let Some(hot_reload) = HOT_RELOAD.get() {
+                run(hot_reload);
+            }
+        })?;
+    Ok(())
+}
+
+pub fn enabled() -> bool {
+    HOT_RELOAD.get().is_some()
+}
+
+/// Watch `path`, loaded by service `id` as module `name`. False when hot
+/// reload is disabled.
+pub fn watch_module(id: ActorId, name: &str, path: &Path) -> io::Result<bool> {
+    let Some(hot_reload) = HOT_RELOAD.get() else {
+        return Ok(false);
+    };
+    let path = fs::canonicalize(path)?;
+    if let Some(dir) = path.parent() {
+        hot_reload.watcher.add_dir(dir)?;
+    }
+    hot_reload.modules().add(id, name, path);
+    Ok(true)
+}
+
+fn run(hot_reload: &HotReload) {
+    loop {
+        let changed = match hot_reload.watcher.wait(DEBOUNCE) {
+            Ok(changed) => changed,
+            Err(err) => {
+                log::error!("hot reload watcher failed: {}", err);
+                return;
+            }
+        };
+        let updates: Vec<_> = changed
+            .into_iter()
+            .map(|path| {
+                let users = hot_reload.modules().users(&path);
+                (path, users)
+            })
+            .filter(|(_, users)| !users.is_empty())
+            .collect();
+        if updates.is_empty() {
+            continue;
+        }
+        unsafe { ffi::luaL_clearcodecache() };
+        for (path, users) in updates {
+            log::info!(
+                "hot reload: {} changed, updating {} service(s)",
+                path.display(),
+                users.len()
+            );
+            for (id, name) in users {
+                let msg = Message::new(
+                    0,
+                    id,
+                    0,
+                    MessageBody::Buffer(
+                        PTYPE_SYSTEM,
+                        Box::new(format!("_hotfix,{}", name).into_bytes().into()),
+                    ),
+                );
+                if CONTEXT.send(msg).is_some() {
+                    hot_reload.modules().remove(id);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+struct Watcher {
+    fd: std::os::fd::OwnedFd,
+    /// Watch descriptor to directory.
+    dirs: Mutex<HashMap<i32, PathBuf>>,
+}
+
+#[cfg(target_os = "linux")]
+impl Watcher {
+    fn new() -> io::Result<Watcher> {
+        use std::os::fd::FromRawFd;
+
+        let fd = unsafe { libc::inotify_init1(libc::IN_CLOEXEC) };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(Watcher {
+            fd: unsafe { std::os::fd::OwnedFd::from_raw_fd(fd) },
+            dirs: Mutex::default(),
+        })
+    }
+
+    /// Watch the files in `dir`. Watching a directory again is a no-op.
+    fn add_dir(&self, dir: &Path) -> io::Result<()> {
+        use std::os::{fd::AsRawFd, unix::ffi::OsStrExt};
+
+        let path = std::ffi::CString::new(dir.as_os_str().as_bytes())
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))?;
+        // Files are either written in place or replaced by a rename.
+        let mask = libc::IN_CLOSE_WRITE | libc::IN_MOVED_TO;
+        let wd = unsafe { libc::inotify_add_watch(self.fd.as_raw_fd(), path.as_ptr(), mask) };
+        if wd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        self.dirs
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .insert(wd, dir.to_path_buf());
+        Ok(())
+    }
+
+    /// Block until files change, then until `quiet` passes without another
+    /// change, and return the changed files.
+    fn wait(&self, quiet: Duration) -> io::Result<Vec<PathBuf>> {
+        use std::os::fd::AsRawFd;
+
+        let mut changed = BTreeSet::new();
+        loop {
+            let timeout = if changed.is_empty() {
+                -1
+            } else {
+                quiet.as_millis() as i32
+            };
+            let mut pfd = libc::pollfd {
+                fd: self.fd.as_raw_fd(),
+                events: libc::POLLIN,
+                revents: 0,
+            };
+            match unsafe { libc::poll(&mut pfd, 1, timeout) } {
+                0 => return Ok(changed.into_iter().collect()),
+                n if n < 0 => {
+                    let err = io::Error::last_os_error();
+                    if err.kind() != io::ErrorKind::Interrupted {
+                        return Err(err);
+                    }
+                }
+                _ => self.read_events(&mut changed)?,
+            }
+        }
+    }
+
+    fn read_events(&self, changed: &mut BTreeSet<PathBuf>) -> io::Result<()> {
+        use std::os::{fd::AsRawFd, unix::ffi::OsStrExt};
+
+        let mut buf = [0u8; 4096];
+        let n = unsafe {
+            libc::read(
+                self.fd.as_raw_fd(),
+                buf.as_mut_ptr() as *mut libc::c_void,
+                buf.len(),
+            )
+        };
+        if n < 0 {
+            let err = io::Error::last_os_error();
+            if err.kind() == io::ErrorKind::Interrupted {
+                return Ok(());
+            }
+            return Err(err);
+        }
+        let n = n as usize;
+        let header = std::mem::size_of::<libc::inotify_event>();
+        let dirs = self.dirs.lock().unwrap_or_else(|e| e.into_inner());
+        let mut offset = 0;
+        while offset + header <= n {
+            let event: libc::inotify_event =
+                unsafe { std::ptr::read_unaligned(buf.as_ptr().add(offset) as *const _) };
+            let start = offset + header;
+            let end = (start + event.len as usize).min(n);
+            // The name is padded with NULs.
+            let name = &buf[start..end];
+            let name = &name[..name.iter().position(|b| *b == 0).unwrap_or(name.len())];
+            if let Some(dir) = dirs.get(&event.wd) {
+                if !name.is_empty() {
+                    changed.insert(dir.join(std::ffi::OsStr::from_bytes(name)));
+                }
+            }
+            offset = end;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+struct Watcher;
+
+#[cfg(not(target_os = "linux"))]
+impl Watcher {
+    fn new() -> io::Result<Watcher> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "hot reload needs inotify (Linux)",
+        ))
+    }
+
+    fn add_dir(&self, _dir: &Path) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn wait(&self, _quiet: Duration) -> io::Result<Vec<PathBuf>> {
+        Ok(Vec::new())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn modules_track_users_per_file() {
+        let mut modules = Modules::default();
+        let a = PathBuf::from("/srv/lib/a.lua");
+        modules.add(5, "a", a.clone());
+        modules.add(5, "a", a.clone());
+        modules.add(6, "lib.a", a.clone());
+        modules.add(6, "b", PathBuf::from("/srv/lib/b.lua"));
+        assert_eq!(
+            modules.users(&a),
+            vec![(5, "a".to_string()), (6, "lib.a".to_string())]
+        );
+
+        modules.remove(6);
+        assert_eq!(modules.users(&a), vec![(5, "a".to_string())]);
+        assert!(modules.users(Path::new("/srv/lib/b.lua")).is_empty());
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn watcher_reports_writes_and_renames() {
+        let dir = std::env::temp_dir().join(format!("moon_hotreload_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let dir = fs::canonicalize(&dir).unwrap();
+        let watcher = Watcher::new().unwrap();
+        watcher.add_dir(&dir).unwrap();
+
+        fs::write(dir.join("a.lua"), "return 1").unwrap();
+        fs::write(dir.join("b.tmp"), "return 2").unwrap();
+        fs::rename(dir.join("b.tmp"), dir.join("b.lua")).unwrap();
+        let changed = watcher.wait(Duration::from_millis(20)).unwrap();
+        assert!(changed.contains(&dir.join("a.lua")));
+        assert!(changed.contains(&dir.join("b.lua")));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
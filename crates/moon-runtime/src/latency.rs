@@ -0,0 +1,232 @@
+//! Per-actor, per-ptype message latency tracing.
+//!
+//! Every message is stamped with the runtime clock (microseconds) when it is
+//! pushed into a mailbox (`Message::enqueue_us`). When the receiving actor
+//! dispatches it, [`Watchdog::record_dispatch`](crate::context::Watchdog)
+//! records two samples under the message's ptype: the queue wait (dispatch
+//! start minus enqueue time) and the dispatch time itself.
+//!
+//! Samples go into fixed-size log-linear histograms: exact below 4us, then
+//! four sub-buckets per power of two, so a reported percentile is at most 25%
+//! above the true value. `max` is tracked exactly. The histograms live behind
+//! the actor's `Watchdog` and are read by `server_stats` and the `latency`
+//! debug command.
+
+/// Sub-buckets per power of two (must be a power of two).
+const SUB_BUCKETS: u64 = 4;
+const SUB_BITS: u32 = SUB_BUCKETS.trailing_zeros();
+/// Largest exponent tracked; slower samples land in the last bucket, which
+/// still spans ~71 minutes.
+const MAX_EXP: u32 = 31;
+const BUCKETS: usize = (SUB_BUCKETS + (MAX_EXP - SUB_BITS + 1) as u64 * SUB_BUCKETS) as usize;
+
+fn bucket_index(us: u64) -> usize {
+    if us < SUB_BUCKETS {
+        return us as usize;
+    }
+    let exp = (63 - us.leading_zeros()).min(MAX_EXP);
+    let sub = (us >> (exp - SUB_BITS)) & (SUB_BUCKETS - 1);
+    let idx = SUB_BUCKETS + (exp - SUB_BITS) as u64 * SUB_BUCKETS + sub;
+    (idx as usize).min(BUCKETS - 1)
+}
+
+/// Largest value that falls into bucket `idx`.
+fn bucket_upper(idx: usize) -> u64 {
+    let idx = idx as u64;
+    if idx < SUB_BUCKETS {
+        return idx;
+    }
+    let exp = (idx - SUB_BUCKETS) / SUB_BUCKETS + SUB_BITS as u64;
+    let sub = (idx - SUB_BUCKETS) % SUB_BUCKETS;
+    let width = 1u64 << (exp - SUB_BITS as u64);
+    ((SUB_BUCKETS + sub) << (exp - SUB_BITS as u64)) + width - 1
+}
+
+/// Log-linear histogram of microsecond samples.
+pub struct Histogram {
+    counts: Box<[u32; BUCKETS]>,
+    count: u64,
+    sum: u64,
+    max: u64,
+}
+
+impl Default for Histogram {
+    fn default() -> Self {
+        Histogram {
+            counts: Box::new([0; BUCKETS]),
+            count: 0,
+            sum: 0,
+            max: 0,
+        }
+    }
+}
+
+impl Histogram {
+    pub fn record(&mut self, us: u64) {
+        let slot = &mut self.counts[bucket_index(us)];
+        *slot = slot.saturating_add(1);
+        self.count += 1;
+        self.sum = self.sum.saturating_add(us);
+        self.max = self.max.max(us);
+    }
+
+    /// Upper bound of the bucket holding the `q`-quantile (0.0..=1.0), capped
+    /// at the exact maximum. 0 when empty.
+    pub fn percentile(&self, q: f64) -> u64 {
+        if self.count == 0 {
+            return 0;
+        }
+        let rank = ((q * self.count as f64).ceil() as u64).clamp(1, self.count);
+        let mut seen = 0u64;
+        for (idx, &n) in self.counts.iter().enumerate() {
+            seen += n as u64;
+            if seen >= rank {
+                return bucket_upper(idx).min(self.max);
+            }
+        }
+        self.max
+    }
+
+    pub fn summary(&self) -> LatencySummary {
+        LatencySummary {
+            count: self.count,
+            mean_us: self.sum.checked_div(self.count).unwrap_or(0),
+            p50_us: self.percentile(0.5),
+            p99_us: self.percentile(0.99),
+            max_us: self.max,
+        }
+    }
+}
+
+/// Percentiles of one histogram, in microseconds.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct LatencySummary {
+    pub count: u64,
+    pub mean_us: u64,
+    pub p50_us: u64,
+    pub p99_us: u64,
+    pub max_us: u64,
+}
+
+/// Latency of one message type received by one actor.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LatencyStat {
+    pub ptype: u8,
+    /// Time between enqueue and the start of dispatch.
+    pub wait: LatencySummary,
+    /// Time spent in the Lua dispatch.
+    pub dispatch: LatencySummary,
+}
+
+struct PtypeHistograms {
+    ptype: u8,
+    wait: Histogram,
+    dispatch: Histogram,
+}
+
+/// The histograms of one actor, keyed by ptype. An actor receives only a
+/// handful of ptypes, so a vector with linear lookup beats a map.
+#[derive(Default)]
+pub struct LatencyTable {
+    entries: Vec<PtypeHistograms>,
+}
+
+impl LatencyTable {
+    pub fn record(&mut self, ptype: u8, wait_us: u64, dispatch_us: u64) {
+        let pos = match self.entries.iter().position(|e| e.ptype == ptype) {
+            Some(pos) => pos,
+            None => {
+                self.entries.push(PtypeHistograms {
+                    ptype,
+                    wait: Histogram::default(),
+                    dispatch: Histogram::default(),
+                });
+                self.entries.len() - 1
+            }
+        };
+        let entry = &mut self.entries[pos];
+        entry.wait.record(wait_us);
+        entry.dispatch.record(dispatch_us);
+    }
+
+    /// Summaries ordered by ptype.
+    pub fn snapshot(&self) -> Vec<LatencyStat> {
+        let mut stats: Vec<LatencyStat> = self
+            .entries
+            .iter()
+            .map(|e| LatencyStat {
+                ptype: e.ptype,
+                wait: e.wait.summary(),
+                dispatch: e.dispatch.summary(),
+            })
+            .collect();
+        stats.sort_by_key(|s| s.ptype);
+        stats
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn buckets_are_contiguous_and_cover_values() {
+        let mut prev = None;
+        for idx in 0..BUCKETS {
+            let upper = bucket_upper(idx);
+            if let Some(p) = prev {
+                assert!(upper > p, "bucket {} upper {} <= {}", idx, upper, p);
+            }
+            assert_eq!(bucket_index(upper), idx);
+            prev = Some(upper);
+        }
+        for us in [0, 1, 3, 4, 5, 7, 8, 100, 999, 1_000_000, 1 << 31] {
+            let idx = bucket_index(us);
+            assert!(us <= bucket_upper(idx));
+            if idx > 0 {
+                assert!(us > bucket_upper(idx - 1));
+            }
+        }
+        assert_eq!(bucket_index(u64::MAX), BUCKETS - 1);
+    }
+
+    #[test]
+    fn percentiles_stay_within_bucket_error() {
+        let mut h = Histogram::default();
+        for us in 1..=1000 {
+            h.record(us);
+        }
+        let s = h.summary();
+        assert_eq!(s.count, 1000);
+        assert_eq!(s.max_us, 1000);
+        assert_eq!(s.mean_us, 500);
+        assert!((500..=625).contains(&s.p50_us), "p50 {}", s.p50_us);
+        assert!((990..=1000).contains(&s.p99_us), "p99 {}", s.p99_us);
+
+        let mut one = Histogram::default();
+        one.record(12345);
+        assert_eq!(one.percentile(0.5), 12345);
+        assert_eq!(Histogram::default().summary(), LatencySummary::default());
+    }
+
+    #[test]
+    fn table_keys_by_ptype() {
+        let mut t = LatencyTable::default();
+        t.record(7, 10, 100);
+        t.record(3, 1, 2);
+        t.record(7, 30, 300);
+        let snap = t.snapshot();
+        assert_eq!(snap.len(), 2);
+        assert_eq!(snap[0].ptype, 3);
+        assert_eq!(snap[1].ptype, 7);
+        assert_eq!(snap[1].dispatch.count, 2);
+        assert_eq!(snap[1].dispatch.max_us, 300);
+        assert_eq!(snap[1].wait.max_us, 30);
+        t.clear();
+        assert!(t.snapshot().is_empty());
+    }
+}
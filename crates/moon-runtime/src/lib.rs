@@ -16,13 +16,26 @@ use std::sync::atomic::{AtomicI64, Ordering};
 
 // ---- Actor server runtime (formerly the `moon-runtime` crate) ----
 pub mod actor;
+pub mod admin;
+pub mod bundle;
 // `Buffer` lives in the shared `moon-base` crate; re-export it so the
 // long-standing `moon_runtime::buffer` path keeps working.
 pub use moon_base::buffer;
 use buffer::Buffer;
 pub mod context;
+pub mod debugger;
 pub mod error;
+pub mod group;
+pub mod hotreload;
+pub mod latency;
 pub mod log;
+pub mod memprofile;
+pub mod metrics;
+pub mod profiler;
+pub mod pubsub;
+pub mod record;
+pub mod schedule;
+pub mod timer;
 
 /// Stack-allocated byte buffer. `data[0]` stores the length, `data[1..]` stores
 /// the content (string or binary). Max capacity is N-1 bytes. No heap allocation.
@@ -366,6 +379,14 @@ pub(crate) struct Limits {
     /// Default read/query timeout for DB protocols, in milliseconds. Used where
     /// the module has a protocol-level read timeout separate from connect time.
     pub db_read_timeout_ms: u64,
+    /// Longest a sender may be parked by a full `block`-policy mailbox, in
+    /// milliseconds. Past this the message is rejected instead, so two actors
+    /// blocked on each other's full mailboxes cannot deadlock forever.
+    pub mailbox_block_timeout_ms: u64,
+    /// Longest a raw TCP socket may spend in the TLS handshake, in
+    /// milliseconds. Stops a peer that opens a connection and stalls from
+    /// holding a listener slot indefinitely.
+    pub tls_handshake_timeout_ms: u64,
 }
 
 impl Limits {
@@ -388,6 +409,8 @@ impl Limits {
             zset_range_len: 1_000_000,
             db_pool_size: 5,
             db_read_timeout_ms: 10_000,
+            mailbox_block_timeout_ms: 5_000,
+            tls_handshake_timeout_ms: 10_000,
         }
     }
 }
@@ -487,6 +510,8 @@ pub fn luaopen_custom_libs(state: LuaState) {
         ffi::luaL_requiref(state.as_ptr(), cstr!("clonefunc"), luaopen_clonefunc, 0);
         ffi::lua_pop(state.as_ptr(), 1);
     }
+
+    bundle::open_searcher(state);
 }
 
 /// Eagerly build the process-wide message-decoder table.
@@ -510,8 +535,8 @@ pub(crate) static DECODERS: LazyLock<[message_decode::MessageDecodeFn; 256]> =
 
 fn build_decoders() -> [message_decode::MessageDecodeFn; 256] {
     use moon_runtime::context::{
-        PTYPE_DEBUG, PTYPE_ERROR, PTYPE_INTEGER, PTYPE_LUA, PTYPE_SOCKET_EVENT, PTYPE_SOCKET_TCP,
-        PTYPE_TEXT, PTYPE_TIMER,
+        PTYPE_DEBUG, PTYPE_ERROR, PTYPE_INTEGER, PTYPE_LUA, PTYPE_PUBSUB, PTYPE_SOCKET_EVENT,
+        PTYPE_SOCKET_TCP, PTYPE_SOCKET_UDP, PTYPE_TEXT, PTYPE_TIMER,
     };
     #[cfg(feature = "httpc")]
     use moon_runtime::context::PTYPE_HTTPC;
@@ -540,7 +565,9 @@ fn build_decoders() -> [message_decode::MessageDecodeFn; 256] {
     decoders[PTYPE_SOCKET_TCP as usize] = message_decode::decode_buffer_as_string_message;
     decoders[PTYPE_LUA as usize] = lua_seri::decode_buffer_message;
     decoders[PTYPE_DEBUG as usize] = lua_seri::decode_buffer_message;
+    decoders[PTYPE_PUBSUB as usize] = lua_seri::decode_buffer_message;
     decoders[PTYPE_SOCKET_EVENT as usize] = lua_socket::decode_socket_event_message;
+    decoders[PTYPE_SOCKET_UDP as usize] = lua_socket::decode_udp_message;
     #[cfg(feature = "httpc")]
     {
         decoders[PTYPE_HTTPC as usize] = lua_httpc::decode_httpc_message;
@@ -1119,21 +1146,16 @@ mod tests {
     // `lua_decode_message_payload` does, then inspecting the resulting Lua stack.
 
     fn buffer_msg(ptype: u8, data: &[u8]) -> Message {
-        Message {
-            from: 0,
-            to: 0,
-            session: 0,
-            data: MessageBody::Buffer(ptype, Box::new(Buffer::from_slice(data))),
-        }
+        Message::new(
+            0,
+            0,
+            0,
+            MessageBody::Buffer(ptype, Box::new(Buffer::from_slice(data))),
+        )
     }
 
     fn isize_msg(ptype: u8, v: isize) -> Message {
-        Message {
-            from: 0,
-            to: 0,
-            session: 0,
-            data: MessageBody::ISize(ptype, v),
-        }
+        Message::new(0, 0, 0, MessageBody::ISize(ptype, v))
     }
 
     /// Dispatch a message through the real `DECODERS` table (covering the
@@ -1241,6 +1263,39 @@ mod tests {
         }
     }
 
+    #[test]
+    fn decode_shared_body_like_buffer() {
+        let (state, _guard) = new_lua_vm();
+        unsafe {
+            run_lua(state, r#"_packed = require("seri").packstring("all", 7)"#)
+                .expect("seri pack failed");
+            let name = CString::new("_packed").unwrap();
+            ffi::lua_getglobal(state.as_ptr(), name.as_ptr());
+            let packed = Arc::new(Buffer::from_slice(&stack_bytes(state, -1)));
+            ffi::lua_settop(state.as_ptr(), 0);
+
+            // Every receiver of a broadcast decodes the same allocation.
+            for ptype in [context::PTYPE_LUA, context::PTYPE_PUBSUB] {
+                let msg = Message::new(0, 0, 0, MessageBody::Shared(ptype, packed.clone()));
+                let n = decode_via_table(state, msg);
+                assert_eq!(n, 2);
+                assert_eq!(stack_bytes(state, 1), b"all");
+                assert_eq!(ffi::lua_tointeger(state.as_ptr(), 2), 7);
+                ffi::lua_settop(state.as_ptr(), 0);
+            }
+            assert_eq!(Arc::strong_count(&packed), 1, "decoding must not leak a reference");
+
+            let text = Message::new(
+                0,
+                0,
+                0,
+                MessageBody::Shared(context::PTYPE_TEXT, Arc::new(Buffer::from("hi"))),
+            );
+            assert_eq!(decode_via_table(state, text), 1);
+            assert_eq!(stack_bytes(state, 1), b"hi");
+        }
+    }
+
     #[test]
     fn decode_lua_message_empty_buffer_yields_nothing() {
         let (state, _guard) = new_lua_vm();
@@ -1297,4 +1352,14 @@ mod tests {
             assert_eq!(ffi::lua_toboolean(state.as_ptr(), 1), 0);
         }
     }
+
+    #[test]
+    fn decode_udp_rejects_wrong_body() {
+        let (state, _guard) = new_lua_vm();
+        unsafe {
+            let n = decode_via_table(state, isize_msg(context::PTYPE_SOCKET_UDP, 1));
+            assert_eq!(n, 2);
+            assert_eq!(ffi::lua_toboolean(state.as_ptr(), 1), 0);
+        }
+    }
 }
@@ -1,20 +1,26 @@
-use chrono::Local;
+use chrono::{DateTime, Local, NaiveDate};
 use colored::*;
 use log::{Level, Metadata, Record};
 use std::{
+    collections::HashMap,
     error::Error,
+    ffi::OsString,
     fs::{self, File, OpenOptions},
-    io::prelude::*,
-    path::Path,
+    io::{self, prelude::*},
+    path::{Path, PathBuf},
     sync::{
-        Arc,
-        atomic::{AtomicBool, AtomicU8, AtomicUsize, Ordering},
+        Arc, Mutex,
+        atomic::{AtomicBool, AtomicU8, AtomicU32, AtomicUsize, Ordering},
         mpsc::{self, Sender},
     },
     thread,
 };
 
-use crate::{buffer::Buffer, context::CONTEXT};
+use crate::{
+    buffer::Buffer,
+    context::{ActorId, CONTEXT, LOGGER},
+    lua_json::write_json_string,
+};
 
 const STATE_INIT: u8 = 0;
 const STATE_RUNNING: u8 = 1;
@@ -25,21 +31,216 @@ struct LoggerConext {
     enable_stdout: AtomicBool,
     state: Arc<AtomicU8>,
     level: AtomicU8,
+    format: AtomicU8,
+}
+
+/// Layout of each log line, set by `log_format` in the bootstrap `__init__`
+/// table.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LogFormat {
+    /// `timestamp LEVL|actor_id| msg    (file:line)`.
+    Text = 0,
+    /// One JSON object per line, see [`JsonRecord`].
+    Json = 1,
+}
+
+impl LogFormat {
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "text" => Some(Self::Text),
+            "json" => Some(Self::Json),
+            _ => None,
+        }
+    }
 }
 
+/// Fields of one `LogFormat::Json` line. `ts` and `level` are filled in by
+/// [`Logger::make_json_line`].
+pub struct JsonRecord<'a> {
+    /// 0 for logs emitted from Rust.
+    pub actor_id: ActorId,
+    pub actor_name: &'a str,
+    pub msg: &'a [u8],
+    pub file: &'a [u8],
+    pub line: u32,
+    /// Extra members, already encoded as `"key":value,...` (may be empty).
+    pub fields: &'a [u8],
+}
+
+/// Keys written for every JSON line; extra fields must not reuse them.
+pub const JSON_RECORD_KEYS: [&str; 7] = [
+    "ts",
+    "level",
+    "actor_id",
+    "actor_name",
+    "msg",
+    "file",
+    "line",
+];
+
 pub struct Logger {
     sender: Sender<LogMessage>,
     state: Arc<LoggerConext>,
     // Number of log lines enqueued but not yet written by the consumer thread.
     pending: Arc<AtomicUsize>,
+    /// Open per-actor files: path -> (route id, handles).
+    routes: Mutex<HashMap<String, (u32, usize)>>,
+    next_route: AtomicU32,
+    rotation: Mutex<LogRotation>,
 }
 
+/// Route id of the main log file.
+const MAIN_ROUTE: u32 = 0;
+
 enum LogMessage {
-    Line(Buffer),
-    File(File),
+    /// A line for the main file (`MAIN_ROUTE`) or a per-actor file.
+    Line(u32, Buffer),
+    File(LogFile),
+    OpenRoute(u32, LogFile),
+    CloseRoute(u32),
+    /// Reopen the log file by path, after an external tool moved it away.
+    Reopen,
     Stop,
 }
 
+/// Rotation policy for the log file, read from the bootstrap `__init__` table.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct LogRotation {
+    /// Rotate once the file would grow past this many bytes (0 = no limit).
+    pub max_size: u64,
+    /// Rotate on the first line written after local midnight.
+    pub daily: bool,
+    /// Rotated files to keep (`game.log.1` is the newest); 0 keeps them all.
+    pub max_files: usize,
+    /// Append to an existing file at startup instead of truncating it.
+    pub append: bool,
+}
+
+/// The open log file plus the bookkeeping rotation needs. Owned by the logger
+/// thread, so none of this is shared.
+struct LogFile {
+    path: PathBuf,
+    file: File,
+    size: u64,
+    /// Local date of the first line in the current file.
+    day: NaiveDate,
+    rotation: LogRotation,
+}
+
+impl LogFile {
+    fn open(path: PathBuf, rotation: LogRotation, today: NaiveDate) -> io::Result<LogFile> {
+        let file = OpenOptions::new()
+            .create(true)
+            .write(true)
+            .append(rotation.append)
+            .truncate(!rotation.append)
+            .open(&path)?;
+        let meta = file.metadata()?;
+        // An appended file last written on an earlier day rotates on the
+        // first new line rather than mixing two days.
+        let day = meta
+            .modified()
+            .ok()
+            .filter(|_| rotation.append)
+            .map_or(today, |t| {
+                DateTime::<Local>::from(t).date_naive().min(today)
+            });
+        Ok(LogFile {
+            path,
+            file,
+            size: meta.len(),
+            day,
+            rotation,
+        })
+    }
+
+    fn write_line(&mut self, line: &[u8], today: NaiveDate) -> io::Result<()> {
+        let len = line.len() as u64 + 1;
+        let full =
+            self.rotation.max_size > 0 && self.size > 0 && self.size + len > self.rotation.max_size;
+        if full || (self.rotation.daily && today != self.day) {
+            if let Err(err) = self.rotate() {
+                // Keep logging into the current file rather than losing lines.
+                eprintln!("rotate log file {} failed: {}", self.path.display(), err);
+            }
+            self.day = today;
+        }
+        self.file.write_all(line)?;
+        self.file.write_all(b"\n")?;
+        self.size += len;
+        Ok(())
+    }
+
+    fn rotated_path(&self, n: usize) -> PathBuf {
+        let mut name = OsString::from(self.path.as_os_str());
+        name.push(format!(".{}", n));
+        name.into()
+    }
+
+    /// Shift `path.N` to `path.N+1` (dropping those beyond `max_files`), move
+    /// the current file to `path.1` and start a fresh one.
+    fn rotate(&mut self) -> io::Result<()> {
+        let mut free = 1;
+        while self.rotated_path(free).exists() {
+            free += 1;
+        }
+        let keep = self.rotation.max_files;
+        let top = if keep > 0 {
+            for n in keep..free {
+                fs::remove_file(self.rotated_path(n))?;
+            }
+            free.min(keep)
+        } else {
+            free
+        };
+        for n in (1..top).rev() {
+            fs::rename(self.rotated_path(n), self.rotated_path(n + 1))?;
+        }
+        fs::rename(&self.path, self.rotated_path(1))?;
+        self.file = OpenOptions::new()
+            .create(true)
+            .write(true)
+            .truncate(true)
+            .open(&self.path)?;
+        self.size = 0;
+        Ok(())
+    }
+
+    /// Reopen `path` in append mode. Used after logrotate-style tools renamed
+    /// the file, so new lines land in a file at the configured path again.
+    fn reopen(&mut self) -> io::Result<()> {
+        self.file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        self.size = self.file.metadata()?.len();
+        Ok(())
+    }
+}
+
+/// Handle to a per-actor log file opened by [`Logger::open_route`] on the
+/// global `LOGGER`. The file is closed when the last handle drops.
+pub struct LogRoute {
+    id: u32,
+    path: String,
+}
+
+impl LogRoute {
+    pub fn id(&self) -> u32 {
+        self.id
+    }
+}
+
+impl Drop for LogRoute {
+    fn drop(&mut self) {
+        LOGGER.close_route(self);
+    }
+}
+
+fn local_today() -> NaiveDate {
+    CONTEXT.now().with_timezone(&Local).date_naive()
+}
+
 impl log::Log for Logger {
     fn enabled(&self, metadata: &Metadata) -> bool {
         metadata.level() <= Logger::u8_to_level(self.state.level.load(Ordering::Acquire))
@@ -47,6 +248,23 @@ impl log::Log for Logger {
 
     fn log(&self, record: &Record) {
         if self.enabled(record.metadata()) {
+            if self.log_format() == LogFormat::Json {
+                let msg = record.args().to_string();
+                let line = self.make_json_line(
+                    true,
+                    record.level(),
+                    &JsonRecord {
+                        actor_id: 0,
+                        actor_name: "",
+                        msg: msg.as_bytes(),
+                        file: record.file().unwrap_or("<unknown>").as_bytes(),
+                        line: record.line().unwrap_or(0),
+                        fields: &[],
+                    },
+                );
+                self.write(line);
+                return;
+            }
             // Append the source location (file:line) captured by the `log` macros so
             // every Rust log carries it automatically, matching the manual
             // `({file}:{line})` convention used elsewhere.
@@ -93,6 +311,7 @@ impl Logger {
             enable_stdout: AtomicBool::new(true),
             state: Arc::new(AtomicU8::new(STATE_INIT)),
             level: AtomicU8::new(Logger::level_to_u8(Level::Debug)),
+            format: AtomicU8::new(LogFormat::Text as u8),
         });
 
         let clone_state = state.clone();
@@ -101,14 +320,15 @@ impl Logger {
         let clone_pending = pending.clone();
 
         thread::spawn(move || {
-            let mut file: Option<File> = None;
+            let mut file: Option<LogFile> = None;
+            let mut routes: HashMap<u32, LogFile> = HashMap::new();
 
             clone_state.state.store(STATE_RUNNING, Ordering::Release);
 
             loop {
                 match receiver.recv() {
                     Ok(message) => match message {
-                        LogMessage::Line(mut line) => {
+                        LogMessage::Line(route, mut line) => {
                             clone_pending.fetch_sub(1, Ordering::Relaxed);
                             let enable_stdout = line.read_u8(0) != 0;
                             let level = line.read_u8(1);
@@ -125,14 +345,34 @@ impl Logger {
                                 println!("{}", console_str);
                             }
 
-                            if let Some(ref mut file) = file {
-                                file.write_all(line.as_slice()).unwrap();
-                                file.write_all(b"\n").unwrap();
+                            let target = match routes.get_mut(&route) {
+                                Some(routed) => Some(routed),
+                                None => file.as_mut(),
+                            };
+                            if let Some(file) = target {
+                                file.write_line(line.as_slice(), local_today()).unwrap();
                             }
                         }
                         LogMessage::File(new_file) => {
                             file = Some(new_file);
                         }
+                        LogMessage::OpenRoute(route, routed) => {
+                            routes.insert(route, routed);
+                        }
+                        LogMessage::CloseRoute(route) => {
+                            routes.remove(&route);
+                        }
+                        LogMessage::Reopen => {
+                            for file in file.iter_mut().chain(routes.values_mut()) {
+                                if let Err(err) = file.reopen() {
+                                    eprintln!(
+                                        "reopen log file {} failed: {}",
+                                        file.path.display(),
+                                        err
+                                    );
+                                }
+                            }
+                        }
                         LogMessage::Stop => {
                             break;
                         }
@@ -151,6 +391,9 @@ impl Logger {
             sender,
             state,
             pending,
+            routes: Mutex::new(HashMap::new()),
+            next_route: AtomicU32::new(MAIN_ROUTE + 1),
+            rotation: Mutex::new(LogRotation::default()),
         }
     }
 
@@ -196,7 +439,9 @@ impl Logger {
         }
     }
 
-    pub fn make_line(&self, mut enable_stdout: bool, level: Level, data_size: usize) -> Buffer {
+    /// Start a line buffer with the two header bytes (stdout flag, level)
+    /// consumed by the logger thread.
+    fn line_header(&self, mut enable_stdout: bool, level: Level, data_size: usize) -> Buffer {
         if level == Level::Error {
             CONTEXT.increment_error_count();
         }
@@ -212,6 +457,11 @@ impl Logger {
         let mut line = Buffer::with_capacity(if data_size > 0 { 64 + data_size } else { 256 });
         line.write(if enable_stdout { 1 } else { 0 });
         line.write(Logger::level_to_u8(level));
+        line
+    }
+
+    pub fn make_line(&self, enable_stdout: bool, level: Level, data_size: usize) -> Buffer {
+        let mut line = self.line_header(enable_stdout, level, data_size);
 
         line.write_str(
             CONTEXT
@@ -227,9 +477,49 @@ impl Logger {
         line
     }
 
+    /// Build a complete `LogFormat::Json` line, ready for [`Logger::write`].
+    pub fn make_json_line(&self, enable_stdout: bool, level: Level, record: &JsonRecord) -> Buffer {
+        let mut json = Vec::with_capacity(128 + record.msg.len() + record.fields.len());
+        json.extend_from_slice(b"{\"ts\":\"");
+        json.extend_from_slice(
+            CONTEXT
+                .now()
+                .with_timezone(&Local)
+                .format("%Y-%m-%dT%H:%M:%S%.3f%:z")
+                .to_string()
+                .as_bytes(),
+        );
+        json.extend_from_slice(b"\",\"level\":\"");
+        json.extend_from_slice(level.as_str().as_bytes());
+        json.extend_from_slice(
+            format!("\",\"actor_id\":{},\"actor_name\":", record.actor_id).as_bytes(),
+        );
+        write_json_string(&mut json, record.actor_name.as_bytes());
+        json.extend_from_slice(b",\"msg\":");
+        write_json_string(&mut json, record.msg);
+        json.extend_from_slice(b",\"file\":");
+        write_json_string(&mut json, record.file);
+        json.extend_from_slice(format!(",\"line\":{}", record.line).as_bytes());
+        if !record.fields.is_empty() {
+            json.push(b',');
+            json.extend_from_slice(record.fields);
+        }
+        json.push(b'}');
+
+        let mut line = self.line_header(enable_stdout, level, json.len());
+        line.write_slice(&json);
+        line
+    }
+
     pub fn write(&self, data: Buffer) {
+        self.write_route(MAIN_ROUTE, data);
+    }
+
+    /// Write a line to the file behind `route` (see [`LogRoute::id`]); lines
+    /// for a closed route fall back to the main file.
+    pub fn write_route(&self, route: u32, data: Buffer) {
         self.pending.fetch_add(1, Ordering::Relaxed);
-        if self.sender.send(LogMessage::Line(data)).is_err() {
+        if self.sender.send(LogMessage::Line(route, data)).is_err() {
             self.pending.fetch_sub(1, Ordering::Relaxed);
         }
     }
@@ -239,6 +529,49 @@ impl Logger {
         self.pending.load(Ordering::Acquire)
     }
 
+    /// Open (or share) a dedicated log file for one actor. Actors routed to
+    /// the same path share one handle, so rotation sees a single writer. The
+    /// file uses the process-wide rotation policy.
+    pub fn open_route(&self, path: &str) -> io::Result<LogRoute> {
+        let mut routes = self.routes.lock().unwrap();
+        if let Some((id, handles)) = routes.get_mut(path) {
+            *handles += 1;
+            return Ok(LogRoute {
+                id: *id,
+                path: path.to_string(),
+            });
+        }
+        if let Some(parent) = Path::new(path).parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let rotation = *self.rotation.lock().unwrap();
+        let file = LogFile::open(PathBuf::from(path), rotation, local_today())?;
+        let id = self.next_route.fetch_add(1, Ordering::Relaxed);
+        let _ = self.sender.send(LogMessage::OpenRoute(id, file));
+        routes.insert(path.to_string(), (id, 1));
+        Ok(LogRoute {
+            id,
+            path: path.to_string(),
+        })
+    }
+
+    fn close_route(&self, route: &LogRoute) {
+        let mut routes = self.routes.lock().unwrap();
+        if let Some((id, handles)) = routes.get_mut(&route.path) {
+            *handles -= 1;
+            if *handles == 0 {
+                let _ = self.sender.send(LogMessage::CloseRoute(*id));
+                routes.remove(&route.path);
+            }
+        }
+    }
+
+    /// Ask the logger thread to reopen the log file (`moon.log_reopen()`,
+    /// SIGHUP). No-op when logging to stdout only.
+    pub fn reopen(&self) {
+        let _ = self.sender.send(LogMessage::Reopen);
+    }
+
     pub fn stop(&self) {
         let _ = self.sender.send(LogMessage::Stop);
     }
@@ -257,23 +590,33 @@ impl Logger {
         Logger::u8_to_level(self.state.level.load(Ordering::Acquire))
     }
 
+    pub fn set_log_format(&self, format: LogFormat) {
+        self.state.format.store(format as u8, Ordering::Release);
+    }
+
+    pub fn log_format(&self) -> LogFormat {
+        match self.state.format.load(Ordering::Acquire) {
+            1 => LogFormat::Json,
+            _ => LogFormat::Text,
+        }
+    }
+
     pub fn setup_logger(
         &'static self,
         enable_stdout: bool,
         log_file: Option<String>,
         log_level: String,
+        rotation: LogRotation,
+        format: LogFormat,
     ) -> Result<(), Box<dyn Error>> {
+        self.set_log_format(format);
+        *self.rotation.lock().unwrap() = rotation;
         if let Some(file) = log_file {
             let path = Path::new(&file);
             if let Some(parent) = path.parent() {
                 fs::create_dir_all(parent)?;
             }
-            if let Ok(file) = OpenOptions::new()
-                .create(true)
-                .write(true)
-                .truncate(true)
-                .open(file.clone())
-            {
+            if let Ok(file) = LogFile::open(PathBuf::from(file.clone()), rotation, local_today()) {
                 let _ = self.sender.send(LogMessage::File(file));
                 self.state
                     .enable_stdout
@@ -292,3 +635,128 @@ impl Logger {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_log(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("moon_log_{}_{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir.join("game.log")
+    }
+
+    fn read(path: PathBuf) -> String {
+        fs::read_to_string(path).unwrap_or_default()
+    }
+
+    #[test]
+    fn size_rotation_keeps_max_files() {
+        let path = temp_log("size");
+        let today = NaiveDate::from_ymd_opt(2024, 1, 1).unwrap();
+        let rotation = LogRotation {
+            max_size: 8,
+            max_files: 2,
+            ..Default::default()
+        };
+        let mut file = LogFile::open(path.clone(), rotation, today).unwrap();
+        for line in ["line1", "line2", "line3", "line4"] {
+            file.write_line(line.as_bytes(), today).unwrap();
+        }
+        assert_eq!(read(path.clone()), "line4\n");
+        assert_eq!(read(file.rotated_path(1)), "line3\n");
+        assert_eq!(read(file.rotated_path(2)), "line2\n");
+        assert!(!file.rotated_path(3).exists(), "oldest file dropped");
+        let _ = fs::remove_dir_all(path.parent().unwrap());
+    }
+
+    #[test]
+    fn daily_rotation_and_append() {
+        let path = temp_log("daily");
+        fs::write(&path, "old\n").unwrap();
+        let day1 = NaiveDate::from_ymd_opt(2024, 1, 1).unwrap();
+        let day2 = day1.succ_opt().unwrap();
+        let rotation = LogRotation {
+            daily: true,
+            append: true,
+            ..Default::default()
+        };
+        let mut file = LogFile::open(path.clone(), rotation, day1).unwrap();
+        file.write_line(b"a", day1).unwrap();
+        assert_eq!(read(path.clone()), "old\na\n", "append keeps previous run");
+        file.write_line(b"b", day2).unwrap();
+        assert_eq!(read(path.clone()), "b\n");
+        assert_eq!(read(file.rotated_path(1)), "old\na\n");
+        let _ = fs::remove_dir_all(path.parent().unwrap());
+    }
+
+    #[test]
+    fn reopen_follows_external_rename() {
+        let path = temp_log("reopen");
+        let today = NaiveDate::from_ymd_opt(2024, 1, 1).unwrap();
+        let mut file = LogFile::open(path.clone(), LogRotation::default(), today).unwrap();
+        file.write_line(b"before", today).unwrap();
+        let moved = path.with_extension("log.moved");
+        fs::rename(&path, &moved).unwrap();
+        file.reopen().unwrap();
+        file.write_line(b"after", today).unwrap();
+        assert_eq!(read(moved), "before\n");
+        assert_eq!(read(path.clone()), "after\n");
+        let _ = fs::remove_dir_all(path.parent().unwrap());
+    }
+
+    #[test]
+    fn json_line_is_valid_json_with_extra_fields() {
+        let logger = Logger::new();
+        let mut line = logger.make_json_line(
+            false,
+            Level::Warn,
+            &JsonRecord {
+                actor_id: 7,
+                actor_name: "ga\"me",
+                msg: b"a\nb",
+                file: b"main.lua",
+                line: 12,
+                fields: b"\"uid\":42",
+            },
+        );
+        line.consume(2);
+        let value: serde_json::Value = serde_json::from_slice(line.as_slice()).unwrap();
+        assert_eq!(value["level"], "WARN");
+        assert_eq!(value["actor_id"], 7);
+        assert_eq!(value["actor_name"], "ga\"me");
+        assert_eq!(value["msg"], "a\nb");
+        assert_eq!(value["file"], "main.lua");
+        assert_eq!(value["line"], 12);
+        assert_eq!(value["uid"], 42);
+        assert!(value["ts"].as_str().unwrap().contains('T'));
+    }
+
+    #[test]
+    fn routes_share_a_file_and_close_with_last_handle() {
+        let path = temp_log("route");
+        let key = path.to_str().unwrap();
+        let a = LOGGER.open_route(key).unwrap();
+        let b = LOGGER.open_route(key).unwrap();
+        assert_eq!(a.id(), b.id());
+        assert_ne!(a.id(), MAIN_ROUTE);
+
+        let mut line = LOGGER.make_line(false, Level::Info, 0);
+        line.write_str("routed");
+        LOGGER.write_route(a.id(), line);
+        for _ in 0..100 {
+            if read(path.clone()).contains("routed") {
+                break;
+            }
+            thread::sleep(std::time::Duration::from_millis(10));
+        }
+        assert!(read(path.clone()).ends_with("INFO|routed\n"));
+
+        drop(a);
+        assert!(LOGGER.routes.lock().unwrap().contains_key(key));
+        drop(b);
+        assert!(!LOGGER.routes.lock().unwrap().contains_key(key));
+        let _ = fs::remove_dir_all(path.parent().unwrap());
+    }
+}
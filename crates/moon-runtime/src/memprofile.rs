@@ -0,0 +1,202 @@
+//! Allocation profiler for one Lua service.
+//!
+//! Started with `moon.memprofile.start(interval)`, the service's allocator
+//! samples about one allocation per `interval` bytes allocated (every
+//! allocation with an interval of 1). A sample stands for `max(size,
+//! interval)` bytes, so totals are estimates of what the service allocated.
+//!
+//! The allocator can run in the middle of a VM operation (a stack reallocation,
+//! a GC step), where walking the Lua stack is unsafe, so it does not look up the
+//! source location itself: it keeps the sample unresolved and installs the
+//! count hook, and the hook attributes it to the running function and line at
+//! the next instruction. Allocations made inside a C function are attributed
+//! to the Lua line that called it.
+//!
+//! Sampled allocations are tracked until they are freed, so a report gives
+//! both the bytes allocated by a site and the bytes it still holds: a site
+//! whose live bytes keep growing is a leak candidate.
+
+use std::collections::HashMap;
+
+pub const DEFAULT_INTERVAL: usize = 64 * 1024;
+
+/// Site of allocations made while no Lua function was running, e.g. by the
+/// runtime before or after a dispatch.
+pub const NATIVE_SITE: &str = "[native]";
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SiteStats {
+    /// `source:line`, or [`NATIVE_SITE`].
+    pub site: String,
+    /// Estimated bytes allocated since the profile started.
+    pub allocated: u64,
+    /// Sampled allocations.
+    pub count: u64,
+    /// Estimated bytes still allocated.
+    pub live: u64,
+    pub live_count: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SortBy {
+    Allocated,
+    Live,
+}
+
+impl SortBy {
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "allocated" => Some(SortBy::Allocated),
+            "live" => Some(SortBy::Live),
+            _ => None,
+        }
+    }
+}
+
+/// Owned by the actor and only touched on its thread, by the allocator and
+/// the hook.
+pub struct AllocProfile {
+    interval: usize,
+    until_sample: isize,
+    /// Sampled allocations waiting for the hook: address and weight.
+    unresolved: Vec<(usize, u64)>,
+    /// Sampled allocations not freed yet: address to site index and weight.
+    live: HashMap<usize, (usize, u64)>,
+    sites: Vec<SiteStats>,
+    site_index: HashMap<String, usize>,
+}
+
+impl AllocProfile {
+    pub fn new(interval: usize) -> Self {
+        let interval = interval.max(1);
+        AllocProfile {
+            interval,
+            until_sample: interval as isize,
+            unresolved: Vec::new(),
+            live: HashMap::new(),
+            sites: Vec::new(),
+            site_index: HashMap::new(),
+        }
+    }
+
+    pub fn interval(&self) -> usize {
+        self.interval
+    }
+
+    /// Count an allocation of `size` bytes at `ptr`. True when it was sampled
+    /// and waits for [`resolve`](Self::resolve).
+    pub fn on_alloc(&mut self, ptr: usize, size: usize) -> bool {
+        self.until_sample -= size as isize;
+        if self.until_sample > 0 {
+            return false;
+        }
+        self.until_sample = self.interval as isize;
+        self.unresolved.push((ptr, size.max(self.interval) as u64));
+        true
+    }
+
+    /// Forget the allocation at `ptr`, if it was sampled.
+    pub fn on_free(&mut self, ptr: usize) {
+        if let Some(pos) = self.unresolved.iter().position(|(p, _)| *p == ptr) {
+            self.unresolved.swap_remove(pos);
+            return;
+        }
+        if let Some((index, weight)) = self.live.remove(&ptr) {
+            let stats = &mut self.sites[index];
+            stats.live -= weight;
+            stats.live_count -= 1;
+        }
+    }
+
+    pub fn has_unresolved(&self) -> bool {
+        !self.unresolved.is_empty()
+    }
+
+    /// Attribute the unresolved samples to `site`.
+    pub fn resolve(&mut self, site: &str) {
+        if self.unresolved.is_empty() {
+            return;
+        }
+        let index = match self.site_index.get(site) {
+            Some(index) => *index,
+            None => {
+                self.sites.push(SiteStats {
+                    site: site.to_string(),
+                    ..Default::default()
+                });
+                self.site_index
+                    .insert(site.to_string(), self.sites.len() - 1);
+                self.sites.len() - 1
+            }
+        };
+        let stats = &mut self.sites[index];
+        for (ptr, weight) in self.unresolved.drain(..) {
+            stats.allocated += weight;
+            stats.count += 1;
+            stats.live += weight;
+            stats.live_count += 1;
+            self.live.insert(ptr, (index, weight));
+        }
+    }
+
+    /// The `limit` top sites (0 = all), largest first.
+    pub fn report(&self, by: SortBy, limit: usize) -> Vec<SiteStats> {
+        let mut sites: Vec<SiteStats> = self
+            .sites
+            .iter()
+            .filter(|s| by == SortBy::Allocated || s.live > 0)
+            .cloned()
+            .collect();
+        sites.sort_by(|a, b| {
+            let (x, y) = match by {
+                SortBy::Allocated => (a.allocated, b.allocated),
+                SortBy::Live => (a.live, b.live),
+            };
+            y.cmp(&x).then_with(|| a.site.cmp(&b.site))
+        });
+        if limit > 0 {
+            sites.truncate(limit);
+        }
+        sites
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn samples_by_interval_and_tracks_live_bytes() {
+        let mut p = AllocProfile::new(100);
+        assert!(!p.on_alloc(1, 60));
+        assert!(p.on_alloc(2, 60), "120 bytes since the start");
+        assert!(p.has_unresolved());
+        p.resolve("a.lua:3");
+        assert!(!p.has_unresolved());
+        assert!(p.on_alloc(3, 500), "a large allocation counts its size");
+        p.resolve("b.lua:7");
+        assert!(!p.on_alloc(4, 10));
+        p.on_free(1);
+
+        let top = p.report(SortBy::Allocated, 0);
+        assert_eq!(top[0].site, "b.lua:7");
+        assert_eq!((top[0].allocated, top[0].live), (500, 500));
+        assert_eq!((top[1].allocated, top[1].count), (100, 1));
+
+        p.on_free(3);
+        let live = p.report(SortBy::Live, 0);
+        assert_eq!(live.len(), 1, "b.lua:7 holds nothing anymore");
+        assert_eq!((live[0].site.as_str(), live[0].live), ("a.lua:3", 100));
+        assert_eq!(p.report(SortBy::Allocated, 1).len(), 1);
+    }
+
+    #[test]
+    fn freed_before_resolved() {
+        let mut p = AllocProfile::new(1);
+        assert!(p.on_alloc(1, 8));
+        p.on_free(1);
+        assert!(!p.has_unresolved());
+        p.resolve(NATIVE_SITE);
+        assert!(p.report(SortBy::Allocated, 0).is_empty());
+    }
+}
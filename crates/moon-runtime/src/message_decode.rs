@@ -48,7 +48,8 @@ pub unsafe fn take_boxed<T: Send>(m: *mut Message) -> Result<T, String> {
     }
 }
 
-/// Borrow the `Buffer` payload **without** taking ownership of it.
+/// Borrow the `Buffer` payload **without** taking ownership of it. A
+/// `Shared` payload is borrowed the same way.
 ///
 /// The body is left inside the `Message`, so the `Message`'s own `Drop` still
 /// frees the buffer. Use this for any decoder whose body-reading path can
@@ -65,6 +66,7 @@ pub unsafe fn borrow_buffer<'a>(m: *mut Message) -> Result<&'a Buffer, String> {
     unsafe {
         match &(*m).data {
             MessageBody::Buffer(_, buf) => Ok(&**buf),
+            MessageBody::Shared(_, buf) => Ok(&**buf),
             other => Err(format!(
                 "expected Buffer message body for ptype {}, got {}",
                 (*m).ptype(),
@@ -79,6 +81,7 @@ fn body_discriminant(body: &MessageBody) -> &'static str {
         MessageBody::ISize(..) => "ISize",
         MessageBody::Buffer(..) => "Buffer",
         MessageBody::Boxed(..) => "Boxed",
+        MessageBody::Shared(..) => "Shared",
         MessageBody::None(..) => "None",
     }
 }
@@ -0,0 +1,294 @@
+//! Prometheus text exposition (format 0.0.4) of the runtime statistics.
+//!
+//! [`render`] snapshots the same counters `server_stats` reports, plus one
+//! series per actor and per DB connection pool. It is served at `/metrics` on
+//! the admin listener (`admin_addr` in the bootstrap `__init__` table, see
+//! `admin.rs`) and returned by `moon.metrics()` for services that would rather
+//! mount it on their own HTTP server.
+
+use std::fmt::{Display, Write};
+
+use crate::{
+    context::{ActorStat, CLUSTER_ACTOR_ADDR, CONTEXT, LOGGER},
+    request_pool::PoolStats,
+};
+
+/// Content type Prometheus expects for the text format.
+pub const CONTENT_TYPE: &str = "text/plain; version=0.0.4; charset=utf-8";
+
+/// `(name, type, help, value)` of a family with one sample per item.
+type Series<T, V> = (&'static str, &'static str, &'static str, fn(&T) -> V);
+
+struct Exposition {
+    out: String,
+}
+
+impl Exposition {
+    fn family(&mut self, name: &str, kind: &str, help: &str) {
+        let _ = writeln!(self.out, "# HELP {} {}", name, help);
+        let _ = writeln!(self.out, "# TYPE {} {}", name, kind);
+    }
+
+    fn sample(&mut self, name: &str, labels: &[(&str, &str)], value: impl Display) {
+        self.out.push_str(name);
+        if !labels.is_empty() {
+            self.out.push('{');
+            for (i, (key, val)) in labels.iter().enumerate() {
+                if i > 0 {
+                    self.out.push(',');
+                }
+                self.out.push_str(key);
+                self.out.push_str("=\"");
+                escape_label(&mut self.out, val);
+                self.out.push('"');
+            }
+            self.out.push('}');
+        }
+        let _ = writeln!(self.out, " {}", value);
+    }
+
+    /// A family with a single unlabeled sample.
+    fn single(&mut self, name: &str, kind: &str, help: &str, value: impl Display) {
+        self.family(name, kind, help);
+        self.sample(name, &[], value);
+    }
+}
+
+fn escape_label(out: &mut String, val: &str) {
+    for ch in val.chars() {
+        match ch {
+            '\\' => out.push_str("\\\\"),
+            '"' => out.push_str("\\\""),
+            '\n' => out.push_str("\\n"),
+            _ => out.push(ch),
+        }
+    }
+}
+
+/// `(driver, name, stats)` for every open DB connection in the enabled
+/// modules.
+fn pool_stats() -> Vec<(&'static str, String, PoolStats)> {
+    #[allow(unused_mut)]
+    let mut pools = Vec::new();
+    #[cfg(feature = "redis")]
+    pools.extend(
+        crate::lua_redis::pool_stats()
+            .into_iter()
+            .map(|(name, s)| ("redis", name, s)),
+    );
+    #[cfg(feature = "pg")]
+    pools.extend(
+        crate::lua_pg::pool_stats()
+            .into_iter()
+            .map(|(name, s)| ("pg", name, s)),
+    );
+    #[cfg(feature = "sqlx")]
+    pools.extend(
+        crate::lua_sqlx::pool_stats()
+            .into_iter()
+            .map(|(name, s)| ("sqlx", name, s)),
+    );
+    #[cfg(feature = "mongodb")]
+    pools.extend(
+        crate::lua_mongodb::pool_stats()
+            .into_iter()
+            .map(|(name, s)| ("mongodb", name, s)),
+    );
+    pools
+}
+
+/// Render every runtime metric in Prometheus text format.
+pub fn render() -> String {
+    let mut e = Exposition {
+        out: String::with_capacity(4096),
+    };
+
+    e.single(
+        "moon_uptime_seconds",
+        "gauge",
+        "Process uptime.",
+        CONTEXT.uptime_secs(),
+    );
+    e.single(
+        "moon_services",
+        "gauge",
+        "Lua services currently alive.",
+        CONTEXT.actor_count(),
+    );
+    e.single(
+        "moon_services_unique",
+        "gauge",
+        "Unique (named) services currently alive.",
+        CONTEXT.unique_actor_count(),
+    );
+    e.single(
+        "moon_services_created_total",
+        "counter",
+        "Services created since startup.",
+        CONTEXT.total_actor_created(),
+    );
+    e.single(
+        "moon_timers",
+        "gauge",
+        "Timers scheduled but not yet fired or cancelled.",
+        CONTEXT.timer_count(),
+    );
+    e.single(
+        "moon_log_errors_total",
+        "counter",
+        "Error-level log lines.",
+        CONTEXT.error_count(),
+    );
+    e.single(
+        "moon_log_queue",
+        "gauge",
+        "Log lines enqueued but not yet written.",
+        LOGGER.pending_count(),
+    );
+
+    let actors: Vec<_> = CONTEXT
+        .actor_stats()
+        .into_iter()
+        .filter(|s| s.id != CLUSTER_ACTOR_ADDR)
+        .collect();
+    let (memory, messages, cpu_ms) = actors.iter().fold((0, 0, 0), |(m, n, c), s| {
+        (m + s.memory, n + s.messages, c + s.cpu_ms)
+    });
+    e.single(
+        "moon_memory_bytes",
+        "gauge",
+        "Lua memory across all services.",
+        memory,
+    );
+    e.single(
+        "moon_messages_total",
+        "counter",
+        "Messages dispatched across all services.",
+        messages,
+    );
+    e.single(
+        "moon_dispatch_seconds_total",
+        "counter",
+        "Time spent dispatching messages across all services.",
+        cpu_ms as f64 / 1000.0,
+    );
+
+    let labels: Vec<(String, &str)> = actors
+        .iter()
+        .map(|s| (s.id.to_string(), s.name.as_deref().unwrap_or("")))
+        .collect();
+    let per_actor: [Series<ActorStat, f64>; 6] = [
+        (
+            "moon_service_memory_bytes",
+            "gauge",
+            "Lua memory of the service.",
+            |s| s.memory as f64,
+        ),
+        (
+            "moon_service_messages_total",
+            "counter",
+            "Messages dispatched by the service.",
+            |s| s.messages as f64,
+        ),
+        (
+            "moon_service_dispatch_seconds_total",
+            "counter",
+            "Time the service spent dispatching messages.",
+            |s| s.cpu_ms as f64 / 1000.0,
+        ),
+        (
+            "moon_service_queue",
+            "gauge",
+            "Messages waiting in the service mailbox.",
+            |s| s.queue as f64,
+        ),
+        (
+            "moon_service_mailbox_capacity",
+            "gauge",
+            "Mailbox capacity of the service (0 = unbounded).",
+            |s| s.capacity as f64,
+        ),
+        (
+            "moon_service_dropped_total",
+            "counter",
+            "Messages refused or discarded because the mailbox was full.",
+            |s| s.dropped as f64,
+        ),
+    ];
+    for (name, kind, help, value) in per_actor {
+        e.family(name, kind, help);
+        for (stat, (id, service)) in actors.iter().zip(&labels) {
+            e.sample(name, &[("id", id), ("name", service)], value(stat));
+        }
+    }
+
+    let pools = pool_stats();
+    let per_pool: [Series<PoolStats, i64>; 4] = [
+        (
+            "moon_pool_pending",
+            "gauge",
+            "Requests dispatched to the pool and not yet answered.",
+            |s| s.pending,
+        ),
+        (
+            "moon_pool_pending_peak",
+            "gauge",
+            "Highest pending count observed (summed across workers).",
+            |s| s.peak,
+        ),
+        (
+            "moon_pool_requests_total",
+            "counter",
+            "Requests dispatched to the pool.",
+            |s| s.total,
+        ),
+        (
+            "moon_pool_workers",
+            "gauge",
+            "Worker connections in the pool.",
+            |s| s.workers,
+        ),
+    ];
+    for (name, kind, help, value) in per_pool {
+        e.family(name, kind, help);
+        for (driver, pool, stats) in &pools {
+            e.sample(name, &[("driver", driver), ("name", pool)], value(stats));
+        }
+    }
+
+    e.out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn samples_escape_label_values() {
+        let mut e = Exposition { out: String::new() };
+        e.family("moon_x", "gauge", "Test.");
+        e.sample("moon_x", &[("name", "a\"b\\c\nd"), ("id", "1")], 2.5);
+        assert_eq!(
+            e.out,
+            "# HELP moon_x Test.\n# TYPE moon_x gauge\nmoon_x{name=\"a\\\"b\\\\c\\nd\",id=\"1\"} 2.5\n"
+        );
+    }
+
+    #[test]
+    fn render_declares_every_family_once() {
+        let text = render();
+        let mut families = std::collections::HashSet::new();
+        for line in text.lines() {
+            if let Some(rest) = line.strip_prefix("# TYPE ") {
+                let name = rest.split(' ').next().unwrap();
+                assert!(families.insert(name.to_string()), "{} declared twice", name);
+            } else if !line.starts_with('#') {
+                let name = line.split(['{', ' ']).next().unwrap();
+                assert!(families.contains(name), "{} sampled before its TYPE", name);
+            }
+        }
+        assert!(families.contains("moon_services"));
+        assert!(families.contains("moon_service_queue"));
+        assert!(families.contains("moon_pool_pending"));
+    }
+}
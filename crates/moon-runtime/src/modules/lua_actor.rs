@@ -3,14 +3,29 @@ use crate::{lua_require, luaopen_custom_libs, not_null_wrapper};
 use moon_base::{
     self, cstr,
     ffi::{self, luaL_Reg},
-    laux::{self, LuaState, LuaThread, LuaType},
+    laux::{self, LuaState, LuaTable, LuaThread, LuaType, LuaValue},
     lreg, lreg_null,
 };
 use moon_runtime::{
     actor::LuaActor,
+    buffer::Buffer,
+    bundle,
     check_buffer,
-    context::{self, CONTEXT, LOGGER, LuaActorParam, Message, MessageBody, Watchdog},
-    log::Logger,
+    context::{
+        self, CONTEXT, ExitReason, LOGGER, LuaActorParam, MailboxError, MailboxPolicy,
+        MemoryAction, Message, MessageBody, RestartMode, RestartPolicy, Watchdog, WatchdogAction,
+        WatchdogConfig,
+    },
+    group::Balance,
+    hotreload,
+    latency::LatencySummary,
+    log::{JSON_RECORD_KEYS, JsonRecord, LogFormat, LogRoute, Logger},
+    lua_json::{JsonOptions, encode_one, write_json_string},
+    memprofile::{self, AllocProfile, SortBy},
+    metrics, profiler, pubsub,
+    record::{RecordHeader, Recorder, Replay},
+    schedule::{Budget, BudgetMeter, Lanes},
+    timer,
 };
 use tokio::sync::mpsc;
 
@@ -25,8 +40,11 @@ use std::{
     },
 };
 
+/// String hash seed of all Lua states. Must be the same for all of them:
+/// functions loaded through the code cache share their string constants.
+static SEED: AtomicU32 = AtomicU32::new(0);
+
 fn global_seed() -> std::ffi::c_uint {
-    static SEED: AtomicU32 = AtomicU32::new(0);
     let mut ret = SEED.load(Ordering::Acquire);
     while ret == 0 {
         let mut t = unsafe { ffi::luaL_makeseed(std::ptr::null_mut()) };
@@ -48,6 +66,14 @@ extern "C-unwind" fn lua_actor_protect_init(state: LuaState) -> c_int {
 
         ffi::luaL_openlibs(state.as_ptr());
 
+        if let Some(seed) = (*param).seed {
+            ffi::lua_getglobal(state.as_ptr(), cstr!("math"));
+            ffi::lua_getfield(state.as_ptr(), -1, cstr!("randomseed"));
+            ffi::lua_pushinteger(state.as_ptr(), seed as ffi::lua_Integer);
+            ffi::lua_call(state.as_ptr(), 1, 0);
+            ffi::lua_pop(state.as_ptr(), 1);
+        }
+
         lua_require!(state, "moon.core", luaopen_core);
         lua_require!(
             state,
@@ -57,8 +83,21 @@ extern "C-unwind" fn lua_actor_protect_init(state: LuaState) -> c_int {
 
         luaopen_custom_libs(state);
 
-        let source = CString::new((*param).source.as_str()).unwrap();
-        if ffi::luaL_loadfile(state.as_ptr(), source.as_ptr()) != ffi::LUA_OK {
+        let source = (*param).source.as_str();
+        let bundle = bundle::get();
+        let status = match bundle.and_then(|bundle| bundle.get(source)) {
+            Some(data) => bundle::load_chunk(state, source, data),
+            // Code from disk would bypass the signature and encryption.
+            None if bundle.is_some_and(|bundle| bundle.is_sealed()) => {
+                laux::lua_push(state, format!("source '{}' is not in the bundle", source));
+                ffi::LUA_ERRFILE
+            }
+            None => {
+                let source = CString::new(source).unwrap();
+                ffi::luaL_loadfile(state.as_ptr(), source.as_ptr())
+            }
+        };
+        if status != ffi::LUA_OK {
             return 1;
         }
 
@@ -87,6 +126,9 @@ unsafe extern "C" fn allocator(
                 let layout = Layout::from_size_align_unchecked(osize, moon_base::SYS_MIN_ALIGN);
                 alloc::dealloc(ptr as *mut u8, layout);
                 actor.mem -= osize as isize;
+                if let Some(profile) = actor.memprofile.as_mut() {
+                    profile.on_free(ptr as usize);
+                }
             }
             return std::ptr::null_mut();
         }
@@ -117,6 +159,20 @@ unsafe extern "C" fn allocator(
 
         actor.mem += mem_diff;
 
+        let soft_limit = actor.mem_soft_limit;
+        if soft_limit > 0 {
+            if actor.mem > soft_limit {
+                if !actor.mem_above_soft {
+                    actor.mem_above_soft = true;
+                    actor.mem_pressure = true;
+                }
+            } else if actor.mem < soft_limit - soft_limit / 8 {
+                // Some slack, so hovering at the limit does not collect
+                // after every dispatch.
+                actor.mem_above_soft = false;
+            }
+        }
+
         if actor.mem > actor.mem_warning {
             actor.mem_warning *= 2;
             log::warn!(
@@ -137,6 +193,7 @@ unsafe extern "C" fn allocator(
             if new_ptr.is_null() {
                 alloc::handle_alloc_error(new_layout);
             }
+            profile_alloc(actor, ptr, new_ptr, nsize);
             return new_ptr;
         }
 
@@ -146,24 +203,67 @@ unsafe extern "C" fn allocator(
         if new_ptr.is_null() {
             alloc::handle_alloc_error(old_layout);
         }
+        profile_alloc(actor, ptr, new_ptr, nsize);
         new_ptr
     }
 }
 
+/// Feed an allocation (or a reallocation from `old`) to the allocation
+/// profiler. A sampled allocation gets its site from `moon_signal_hook` at the
+/// next instruction of the running `lua_State`, see `memprofile.rs`.
+#[inline]
+fn profile_alloc(actor: &mut LuaActor, old: *mut c_void, new: *mut c_void, size: usize) {
+    let Some(profile) = actor.memprofile.as_mut() else {
+        return;
+    };
+    if !old.is_null() {
+        profile.on_free(old as usize);
+    }
+    if !profile.on_alloc(new as usize, size) || actor.watchdog.is_null() {
+        return;
+    }
+    let active = unsafe { (*actor.watchdog).active_l.load(Ordering::Acquire) };
+    if active.is_null() {
+        profile.resolve(memprofile::NATIVE_SITE);
+        return;
+    }
+    unsafe {
+        ffi::lua_sethook(
+            active as *mut ffi::lua_State,
+            Some(context::moon_signal_hook),
+            ffi::LUA_MASKCOUNT,
+            1,
+        );
+    }
+}
+
 fn handle_message(
     actor: &mut LuaActor,
     m: &mut Message,
     rx: &mut mpsc::UnboundedReceiver<Message>,
+    lanes: &mut Lanes,
     watchdog: &Watchdog,
 ) -> bool {
+    watchdog.dequeue();
     if m.ptype() == context::PTYPE_QUIT {
         if actor.id == context::BOOTSTRAP_ACTOR_ADDR {
             CONTEXT.shutdown(0);
         }
         actor.ok = false;
+        let reason = if m.from == actor.id {
+            ExitReason::Normal
+        } else {
+            ExitReason::Killed
+        };
+        actor.exit_reason.get_or_insert(reason);
 
         let err = "actor quited";
-        while let Ok(m) = rx.try_recv() {
+        let pending: Vec<Message> = lanes.drain().collect();
+        for m in pending
+            .into_iter()
+            .chain(std::iter::from_fn(|| rx.try_recv().ok()))
+        {
+            watchdog.dequeue();
             // Only fail messages that carry a pending request session; fire-and-forget
             // notifications (session == 0, e.g. the PTYPE_SHUTDOWN this actor enqueues
             // to itself while quitting) have no caller waiting and must not be logged.
@@ -176,97 +276,257 @@ fn handle_message(
         return false;
     }
 
-    let begin_ms = CONTEXT.clock_ms();
-    watchdog.begin(begin_ms, m.ptype(), m.from, m.to, m.session);
+    let ptype = m.ptype();
+    if ptype == context::PTYPE_SHUTDOWN {
+        // A service sitting at its hard limit must still be able to run its
+        // shutdown handler and quit.
+        actor.mem_limit = 0;
+    }
+    let begin_us = CONTEXT.clock_us();
+    watchdog.begin(begin_us / 1000, ptype, m.from, m.to, m.session);
     handle(actor, m);
+    if let Some(profile) = actor.memprofile.as_mut() {
+        // Allocated after the last Lua instruction of the dispatch.
+        profile.resolve(memprofile::NATIVE_SITE);
+    }
+    check_memory(actor);
     watchdog.end();
-    watchdog.record_dispatch(begin_ms, CONTEXT.clock_ms(), actor.mem);
+    watchdog.record_dispatch(ptype, m.enqueue_us, begin_us, CONTEXT.clock_us(), actor.mem);
     true
 }
 
-fn actor_started(actor: &LuaActor, params: &LuaActorParam) {
+/// Act on the memory events of the last dispatch. Runs between
+/// dispatches, where collecting garbage and sending messages is safe.
+fn check_memory(actor: &mut LuaActor) {
+    if actor.mem_failed {
+        actor.mem_failed = false;
+        let report = serde_json::json!({
+            "event": "hard_limit",
+            "id": actor.id,
+            "name": actor.name,
+            "memory": actor.mem,
+            "limit": actor.mem_limit,
+            "action": actor.mem_action.as_str(),
+        });
+        let _ = CONTEXT.send(Message::new(
+            actor.id,
+            context::BOOTSTRAP_ACTOR_ADDR,
+            0,
+            MessageBody::Buffer(
+                context::PTYPE_SYSTEM,
+                Box::new(format!("memory,{}", report).into_bytes().into()),
+            ),
+        ));
+        if actor.mem_action == MemoryAction::Kill && actor.exit_reason.is_none() {
+            actor.exit_reason = Some(ExitReason::OutOfMemory);
+            let _ = remove_actor(actor.id, actor.id);
+        }
+    }
+
+    if actor.mem_pressure {
+        actor.mem_pressure = false;
+        unsafe {
+            ffi::lua_gc(actor.callback_state.0, ffi::LUA_GCCOLLECT, 0);
+        }
+        if actor.mem > actor.mem_soft_limit {
+            log::warn!(
+                "Actor id:0x{:08X} name:{:?} memory pressure: {} bytes, soft limit {}.",
+                actor.id,
+                actor.name,
+                actor.mem,
+                actor.mem_soft_limit
+            );
+            let _ = CONTEXT.send(Message::new(
+                actor.id,
+                actor.id,
+                0,
+                MessageBody::Buffer(
+                    context::PTYPE_SYSTEM,
+                    Box::new(
+                        format!("_memory_pressure,{},{}", actor.mem, actor.mem_soft_limit)
+                            .into_bytes()
+                            .into(),
+                    ),
+                ),
+            ));
+        } else {
+            actor.mem_above_soft = false;
+        }
+    }
+}
+
+fn actor_started(actor: &LuaActor, params: &mut LuaActorParam) {
     log::info!("Actor id:0x{:08X} name:{:?} started.", actor.id, actor.name);
 
-    if params.creator != 0 {
-        let _ = CONTEXT.send(Message {
-            from: actor.id,
-            to: params.creator,
-            session: params.session,
-            data: MessageBody::ISize(context::PTYPE_INTEGER, actor.id as isize),
-        });
+    if params.creator == 0 {
+        return;
+    }
+    if params.session != 0 {
+        let _ = CONTEXT.send(Message::new(
+            actor.id,
+            params.creator,
+            params.session,
+            MessageBody::ISize(context::PTYPE_INTEGER, actor.id as isize),
+        ));
+        // Answered once; later incarnations notify through `service_restart`.
+        params.session = 0;
+    } else if let Some((old_id, reason)) = params.restart_log.previous {
+        notify_restart(params.creator, old_id, actor.id, reason);
     }
 }
 
-fn actor_init_failed(params: &LuaActorParam, err: String) {
-    if params.creator != 0 {
-        let _ = CONTEXT.send(Message {
-            from: params.id,
-            to: params.creator,
-            session: params.session,
-            data: MessageBody::ISize(context::PTYPE_INTEGER, 0),
-        });
+/// Answer a pending `new_service` call with 0.
+fn actor_init_failed(params: &LuaActorParam) {
+    if params.creator != 0 && params.session != 0 {
+        let _ = CONTEXT.send(Message::new(
+            params.id,
+            params.creator,
+            params.session,
+            MessageBody::ISize(context::PTYPE_INTEGER, 0),
+        ));
+    }
+}
+
+/// `service_restart,<old id>,<new id>,<reason>` system message to the creator
+/// of a supervised service; `new id` is 0 when the service was given up on.
+fn notify_restart(
+    creator: context::ActorId,
+    old_id: context::ActorId,
+    new_id: context::ActorId,
+    reason: ExitReason,
+) {
+    let from = if new_id != 0 { new_id } else { old_id };
+    let _ = CONTEXT.send(Message::new(
+        from,
+        creator,
+        0,
+        MessageBody::Buffer(
+            context::PTYPE_SYSTEM,
+            Box::new(
+                format!("service_restart,{},{},{}", old_id, new_id, reason.as_str())
+                    .into_bytes()
+                    .into(),
+            ),
+        ),
+    ));
+}
+
+/// Called after an actor has been torn down: start a new incarnation with the
+/// same name and params when its restart policy asks for one, else answer a
+/// creator still waiting on `new_service`.
+fn supervise(mut params: LuaActorParam, reason: ExitReason) {
+    let policy = params.restart;
+    let shutting_down = CONTEXT.exit_code() != i32::MAX;
+    if shutting_down || !policy.wants_restart(reason) {
+        if reason == ExitReason::InitFailed {
+            actor_init_failed(&params);
+        }
+        return;
+    }
+
+    let old_id = params.id;
+    if !params.restart_log.admit(&policy, CONTEXT.clock_ms()) {
+        log::error!(
+            "Actor id:0x{:08X} name:{:?} stopped ({}), giving up after {} restarts in {}s.",
+            old_id,
+            params.name,
+            reason.as_str(),
+            policy.max_restarts,
+            policy.within_secs
+        );
+        if params.session != 0 {
+            actor_init_failed(&params);
+        } else if params.creator != 0 {
+            notify_restart(params.creator, old_id, 0, reason);
+        }
+        return;
     }
-    log::error!("Create actor failed: {}.", err);
+
+    params.id = CONTEXT.next_actor_id();
+    params.restart_log.previous = Some((old_id, reason));
+    log::warn!(
+        "Actor id:0x{:08X} name:{:?} stopped ({}), restarting as 0x{:08X}.",
+        old_id,
+        params.name,
+        reason.as_str(),
+        params.id
+    );
+    new_actor(params);
 }
 
 fn run_actor_blocking(
-    params: LuaActorParam,
+    mut params: LuaActorParam,
     tx: mpsc::UnboundedSender<Message>,
     mut rx: mpsc::UnboundedReceiver<Message>,
 ) {
-    match init(&params, tx) {
+    let reason = match init(&params, tx) {
         Ok((mut actor, watchdog)) => {
             watchdog.set_active_l(actor.callback_state.0 as *mut std::ffi::c_void);
-            actor_started(&actor, &params);
+            actor_started(&actor, &mut params);
+            let mut lanes = Lanes::new(params.priority_lanes);
             let mut buffer = Vec::new();
             loop {
-                buffer.clear();
-                let n = rx.blocking_recv_many(&mut buffer, 16);
-                if n == 0 {
-                    break; // channel closed
-                }
-                if !dispatch_batch(&mut actor, &mut buffer, &mut rx, &watchdog) {
+                let Some(mut m) = lanes.next(&mut rx) else {
+                    buffer.clear();
+                    let n = rx.blocking_recv_many(&mut buffer, 16);
+                    if n == 0 {
+                        break; // channel closed
+                    }
+                    buffer.drain(..).for_each(|m| lanes.push(m));
+                    continue;
+                };
+                if !handle_message(&mut actor, &mut m, &mut rx, &mut lanes, &watchdog) {
                     break;
                 }
             }
+            actor.exit_reason.unwrap_or(ExitReason::Normal)
         }
-        Err(err) => actor_init_failed(&params, err),
-    }
+        Err(err) => {
+            log::error!("Create actor failed: {}.", err);
+            ExitReason::InitFailed
+        }
+    };
     CONTEXT.remove_actor(params.id, &params.name);
+    supervise(params, reason);
 }
 
 async fn run_actor_async(
-    params: LuaActorParam,
+    mut params: LuaActorParam,
     tx: mpsc::UnboundedSender<Message>,
     mut rx: mpsc::UnboundedReceiver<Message>,
 ) {
-    match init(&params, tx) {
+    let reason = match init(&params, tx) {
         Ok((mut actor, watchdog)) => {
             watchdog.set_active_l(actor.callback_state.0 as *mut std::ffi::c_void);
-            actor_started(&actor, &params);
-            while let Some(mut m) = rx.recv().await {
-                if !handle_message(&mut actor, &mut m, &mut rx, &watchdog) {
+            actor_started(&actor, &mut params);
+            let mut lanes = Lanes::new(params.priority_lanes);
+            let mut meter = BudgetMeter::new(params.budget, CONTEXT.clock_ms());
+            loop {
+                let mut m = match lanes.next(&mut rx) {
+                    Some(m) => m,
+                    None => match rx.recv().await {
+                        Some(m) => m,
+                        None => break,
+                    },
+                };
+                if !handle_message(&mut actor, &mut m, &mut rx, &mut lanes, &watchdog) {
                     break;
                 }
+                // Give the worker thread to other actors before draining a
+                // long mailbox any further.
+                if meter.spend(CONTEXT.clock_ms()) {
+                    tokio::task::yield_now().await;
+                }
             }
+            actor.exit_reason.unwrap_or(ExitReason::Normal)
         }
-        Err(err) => actor_init_failed(&params, err),
-    }
-    CONTEXT.remove_actor(params.id, &params.name);
-}
-
-fn dispatch_batch(
-    actor: &mut LuaActor,
-    buffer: &mut [Message],
-    rx: &mut mpsc::UnboundedReceiver<Message>,
-    watchdog: &Watchdog,
-) -> bool {
-    for m in buffer.iter_mut() {
-        if !handle_message(actor, m, rx, watchdog) {
-            return false;
+        Err(err) => {
+            log::error!("Create actor failed: {}.", err);
+            ExitReason::InitFailed
         }
-    }
-    true
+    };
+    CONTEXT.remove_actor(params.id, &params.name);
+    supervise(params, reason);
 }
 
 pub fn new_actor(params: LuaActorParam) {
@@ -287,13 +547,155 @@ pub fn new_actor(params: LuaActorParam) {
     }
 }
 
+/// Run the service `source` alone on the messages recorded in `path`, with
+/// the clocks following the recording; see `record.rs`. The service gets the
+/// recorded id, name, params and seed. Shuts the system down when the
+/// recording is exhausted.
+pub fn replay_actor(path: &str, source: String) -> Result<(), String> {
+    let replay =
+        Replay::open(path).map_err(|err| format!("open replay '{}' failed: {}", path, err))?;
+    let header = replay.header.clone();
+    CONTEXT.start_replay(header.now_ms, header.clock_us);
+    // The replayed service is the first Lua state, so nothing hashed with
+    // another seed yet.
+    SEED.store(header.hash_seed.max(1), Ordering::Release);
+
+    let prefix = CONTEXT.get_env("PATH").unwrap_or_default();
+    let params = LuaActorParam {
+        id: header.id,
+        unique: header.unique,
+        creator: 0,
+        session: 0,
+        memlimit: 0,
+        mem_soft_limit: 0,
+        mem_action: MemoryAction::Error,
+        name: header.name,
+        source,
+        params: String::from_utf8_lossy(&prefix).into_owned() + header.params.as_str(),
+        block: true,
+        mailbox_capacity: 0,
+        mailbox_policy: MailboxPolicy::Block,
+        log_level: 0,
+        log_file: None,
+        restart: Default::default(),
+        restart_log: Default::default(),
+        group: String::new(),
+        balance: None,
+        record: None,
+        seed: Some(header.seed),
+        budget: Budget::default(),
+        priority_lanes: false,
+        watchdog: WatchdogConfig::default(),
+    };
+
+    let (tx, rx) = mpsc::unbounded_channel();
+    let handle = std::thread::Builder::new()
+        .name(format!("replay-{}", params.name))
+        .spawn(move || run_replay(params, replay, tx, rx))
+        .map_err(|err| err.to_string())?;
+    CONTEXT.register_unique_thread(handle);
+    Ok(())
+}
+
+fn run_replay(
+    params: LuaActorParam,
+    replay: Replay,
+    tx: mpsc::UnboundedSender<Message>,
+    mut rx: mpsc::UnboundedReceiver<Message>,
+) {
+    match init(&params, tx) {
+        Ok((mut actor, watchdog)) => {
+            watchdog.set_active_l(actor.callback_state.0 as *mut std::ffi::c_void);
+            log::info!(
+                "Actor id:0x{:08X} name:{:?} replaying.",
+                actor.id,
+                actor.name
+            );
+            let mut count = 0;
+            for record in replay {
+                // Whatever reached the mailbox live (timers, self sends, errors
+                // for unreachable receivers) is in the recording already.
+                while rx.try_recv().is_ok() {
+                    watchdog.dequeue();
+                }
+                let record = match record {
+                    Ok(record) => record,
+                    Err(err) => {
+                        log::error!("replay read failed: {}.", err);
+                        break;
+                    }
+                };
+                CONTEXT.set_replay_clock(record.now_ms, record.clock_us);
+                let Some(mut m) = record.to_message() else {
+                    log::warn!(
+                        "replay skips message ptype:{} from 0x{:08X} session:{}: no recorded body.",
+                        record.ptype,
+                        record.from,
+                        record.session
+                    );
+                    continue;
+                };
+                if m.ptype() == context::PTYPE_QUIT {
+                    break;
+                }
+                log::debug!(
+                    "replay #{} ptype:{} from 0x{:08X} session:{}",
+                    count,
+                    record.ptype,
+                    record.from,
+                    record.session
+                );
+                handle(&mut actor, &mut m);
+                count += 1;
+            }
+            log::info!("replay finished: {} messages.", count);
+        }
+        Err(err) => log::error!("Create actor failed: {}.", err),
+    }
+    CONTEXT.shutdown(0);
+    CONTEXT.remove_actor(params.id, &params.name);
+}
+
 pub fn init(
     params: &LuaActorParam,
     tx: mpsc::UnboundedSender<Message>,
 ) -> Result<(Box<LuaActor>, Arc<Watchdog>), String> {
     let mut actor = Box::new(LuaActor::new(params));
+    if let Some(path) = &params.log_file {
+        actor.log_route = Some(
+            LOGGER
+                .open_route(path)
+                .map_err(|err| format!("open log file '{}' failed: {}", path, err))?,
+        );
+    }
+    if let Some(path) = &params.record {
+        let prefix = CONTEXT.get_env("PATH").unwrap_or_default();
+        let header = RecordHeader {
+            id: params.id,
+            name: params.name.clone(),
+            unique: params.unique,
+            params: params
+                .params
+                .strip_prefix(String::from_utf8_lossy(&prefix).as_ref())
+                .unwrap_or(&params.params)
+                .to_string(),
+            seed: params.seed.unwrap_or_default(),
+            hash_seed: global_seed(),
+            now_ms: CONTEXT.now().timestamp_millis(),
+            clock_us: CONTEXT.clock_us(),
+        };
+        actor.recorder = Some(
+            Recorder::create(path, &header)
+                .map_err(|err| format!("open record file '{}' failed: {}", path, err))?,
+        );
+    }
     let watchdog = CONTEXT.add_actor(&mut actor, tx)?;
     actor.watchdog = Arc::as_ptr(&watchdog);
+    // A restart that races with shutdown would miss the PTYPE_SHUTDOWN
+    // broadcast and keep the process alive.
+    if params.restart_log.previous.is_some() && CONTEXT.exit_code() != i32::MAX {
+        return Err("restart cancelled by shutdown".to_string());
+    }
 
     //log::info!("init actor id: {} name: {}", id, params.name);
     unsafe {
@@ -345,6 +747,18 @@ fn handle(actor: &mut LuaActor, m: &mut Message) {
         return;
     }
 
+    if let Some(recorder) = actor.recorder.as_mut()
+        && let Err(err) = recorder.write(m, CONTEXT.now().timestamp_millis(), CONTEXT.clock_us())
+    {
+        log::error!(
+            "Actor id:0x{:08X} name:{:?} recording stopped: {}.",
+            actor.id,
+            actor.name,
+            err
+        );
+        actor.recorder = None;
+    }
+
     debug_assert!(!actor.callback_state.0.is_null(), "moon_rs not initialized");
     let callback_state = actor.callback_state.0;
 
@@ -362,8 +776,13 @@ fn handle(actor: &mut LuaActor, m: &mut Message) {
 
         let r = ffi::lua_pcall(callback_state, 4, 0, trace);
         if r == ffi::LUA_OK {
+            // A memory error caught by the service itself is no hard-limit event.
+            actor.mem_failed = false;
             return;
         }
+        // Errors inside a coroutine reach here rethrown as runtime errors;
+        // `coroutine.resume` flags the memory errors among them.
+        actor.mem_failed |= r == ffi::LUA_ERRMEM;
 
         let err = match r {
             ffi::LUA_ERRRUN => {
@@ -399,24 +818,37 @@ fn handle(actor: &mut LuaActor, m: &mut Message) {
         // If watchdog interrupt just fired (trap was reset to 0 by signal_hook),
         // send the error with traceback to bootstrap as a system notification.
         let wd = actor.watchdog;
-        if !wd.is_null() && (*wd).timeout_count.load(Ordering::Relaxed) >= 3 {
-            let notify = format!("timeout_kill,From 0x{:08X} {}", actor.id, err);
-            let _ = CONTEXT.send(Message {
-                from: actor.id,
-                to: context::BOOTSTRAP_ACTOR_ADDR,
-                session: 0,
-                data: MessageBody::Buffer(
-                    context::PTYPE_SYSTEM,
-                    Box::new(notify.into_bytes().into()),
-                ),
-            });
+        let timed_out = !wd.is_null() && (*wd).interrupted();
+        let kill = timed_out && (*wd).config().action == WatchdogAction::Kill;
+        if timed_out {
+            let event = if kill { "kill" } else { "interrupt" };
+            let mut report = (*wd).report(actor.id, event, CONTEXT.clock_ms());
+            report["error"] = err.as_str().into();
+            CONTEXT.send_watchdog_report(actor.id, &report);
         }
 
         CONTEXT.response_error(msg_to, msg_from, msg_session, err.to_string());
+
+        // A supervised service does not carry on after an interrupted or
+        // out-of-memory dispatch; it quits and `supervise` starts it afresh.
+        // The `kill` watchdog action stops any service.
+        if (kill || actor.restart != RestartMode::Temporary) && actor.exit_reason.is_none() {
+            let reason = if timed_out {
+                Some(ExitReason::Timeout)
+            } else if actor.mem_failed {
+                Some(ExitReason::OutOfMemory)
+            } else {
+                None
+            };
+            if let Some(reason) = reason {
+                actor.exit_reason = Some(reason);
+                let _ = remove_actor(actor.id, actor.id);
+            }
+        }
     }
 }
 
-pub fn remove_actor(id: context::ActorId) -> Result<(), String> {
+pub fn remove_actor(id: context::ActorId, from: context::ActorId) -> Result<(), String> {
     // Only deliver PTYPE_QUIT; do NOT remove the registry entry here. The actor's
     // run loop processes the quit message, exits, and then calls
     // `CONTEXT.remove_actor`, which is the single owner of teardown (map removal,
@@ -424,12 +856,12 @@ pub fn remove_actor(id: context::ActorId) -> Result<(), String> {
     // Removing the entry here would race with that teardown: the method would see
     // the entry already gone and skip the counter decrement, leaving `stopped()`
     // permanently false and hanging shutdown.
-    match CONTEXT.send(Message {
-        from: 0,
-        to: id,
-        session: 0,
-        data: MessageBody::None(context::PTYPE_QUIT),
-    }) {
+    match CONTEXT.send(Message::new(
+        from,
+        id,
+        0,
+        MessageBody::None(context::PTYPE_QUIT),
+    )) {
         None => Ok(()),
         Some(_) => Err(format!("not found actor id= {}", id)),
     }
@@ -470,21 +902,50 @@ extern "C-unwind" fn lua_actor_send(state: LuaState) -> c_int {
 
     let from: context::ActorId = laux::lua_opt(state, 5).unwrap_or(unsafe { (*actor).id });
 
-    if let Some(m) = CONTEXT.send(Message {
+    match CONTEXT.send_bounded(Message::new(
         from,
         to,
-        session: -session,
-        data: MessageBody::Buffer(ptype, data),
-    }) {
-        CONTEXT.response_error(
-            m.to,
-            m.from,
-            m.session,
-            format!(
-                "Dead service 0x{:08x} recv message from 0x{:08x}: {}.",
-                to, from, m.data
-            ),
-        );
+        -session,
+        MessageBody::Buffer(ptype, data),
+    )) {
+        Ok(()) => {}
+        // Fire-and-forget messages are dropped silently.
+        Err(MailboxError::Dropped(m)) if m.session == 0 => {}
+        // A replayed service runs alone; what it sends goes nowhere.
+        Err(MailboxError::Dead(_)) if CONTEXT.is_replaying() => {}
+        Err(MailboxError::Dead(m)) => {
+            CONTEXT.response_error(
+                m.to,
+                m.from,
+                m.session,
+                format!(
+                    "Dead service 0x{:08x} recv message from 0x{:08x}: {}.",
+                    to, from, m.data
+                ),
+            );
+        }
+        Err(MailboxError::Full(m)) => {
+            CONTEXT.response_error(
+                m.to,
+                m.from,
+                m.session,
+                format!(
+                    "Service 0x{:08x} mailbox full, reject message from 0x{:08x}.",
+                    to, from
+                ),
+            );
+        }
+        Err(MailboxError::Dropped(m)) => {
+            CONTEXT.response_error(
+                m.to,
+                m.from,
+                m.session,
+                format!(
+                    "Service 0x{:08x} mailbox full, drop message from 0x{:08x}.",
+                    to, from
+                ),
+            );
+        }
     }
 
     laux::lua_push(state, session);
@@ -493,9 +954,71 @@ extern "C-unwind" fn lua_actor_send(state: LuaState) -> c_int {
     2
 }
 
+extern "C-unwind" fn lua_actor_broadcast(state: LuaState) -> c_int {
+    let ptype = laux::lua_get(state, 1);
+    if ptype == 0 {
+        laux::lua_arg_error(state, 1, cstr!("PTYPE must > 0"));
+    }
+    laux::lua_checktype(state, 2, ffi::LUA_TTABLE);
+    let ids: Option<Vec<context::ActorId>> = LuaTable::from_stack(state, 2)
+        .array_iter()
+        .map(|v| match v {
+            LuaValue::Integer(id) if id > 0 => context::ActorId::try_from(id).ok(),
+            _ => None,
+        })
+        .collect();
+    let ids = match ids {
+        Some(ids) => ids,
+        None => {
+            laux::lua_arg_error(state, 2, cstr!("receivers must be service ids"));
+            return 0;
+        }
+    };
+    let data = check_buffer(state, 3);
+    let actor = LuaActor::from_lua_state(state);
+    let delivered = CONTEXT.broadcast(unsafe { (*actor).id }, ptype, &ids, data);
+    laux::lua_push(state, delivered as i64);
+    1
+}
+
+/// `group_pick(name [, key])`: the member of group `name` the next message
+/// should go to, or 0 if the group is empty. Integer keys hash like their
+/// decimal string, so `42` and `"42"` reach the same member; other values
+/// count as no key.
+extern "C-unwind" fn lua_group_pick(state: LuaState) -> c_int {
+    let name = unsafe { laux::lua_check_str(state, 1) };
+    let int_key;
+    let key: Option<&[u8]> = match LuaValue::from_stack(state, 2) {
+        LuaValue::String(s) => Some(s),
+        LuaValue::Integer(i) => {
+            int_key = i.to_string();
+            Some(int_key.as_bytes())
+        }
+        _ => None,
+    };
+    match CONTEXT.pick_group(name, key) {
+        Ok(id) => {
+            laux::lua_push(state, id.unwrap_or(0));
+            1
+        }
+        Err(err) => laux::lua_error(state, err),
+    }
+}
+
+extern "C-unwind" fn lua_group_members(state: LuaState) -> c_int {
+    let name = unsafe { laux::lua_check_str(state, 1) };
+    let members = CONTEXT.group_members(name);
+    let table = LuaTable::new(state, members.len(), 0);
+    for id in members {
+        table.push(id);
+    }
+    1
+}
+
 extern "C-unwind" fn lua_kill_actor(state: LuaState) -> c_int {
     let who: context::ActorId = laux::lua_get(state, 1);
-    let res = remove_actor(who);
+    let actor = LuaActor::from_lua_state(state);
+    let res = remove_actor(who, unsafe { (*actor).id });
     match res {
         Ok(_) => {
             laux::lua_push(state, true);
@@ -505,6 +1028,45 @@ extern "C-unwind" fn lua_kill_actor(state: LuaState) -> c_int {
     }
 }
 
+extern "C-unwind" fn lua_subscribe(state: LuaState) -> c_int {
+    let topic = unsafe { laux::lua_check_str(state, 1) };
+    let actor = LuaActor::from_lua_state(state);
+    match CONTEXT.subscribe(unsafe { (*actor).id }, topic) {
+        Ok(added) => {
+            laux::lua_push(state, added);
+            1
+        }
+        Err(err) => laux::lua_error(state, err),
+    }
+}
+
+extern "C-unwind" fn lua_unsubscribe(state: LuaState) -> c_int {
+    let topic = unsafe { laux::lua_check_str(state, 1) };
+    let actor = LuaActor::from_lua_state(state);
+    laux::lua_push(state, CONTEXT.unsubscribe(unsafe { (*actor).id }, topic));
+    1
+}
+
+extern "C-unwind" fn lua_publish(state: LuaState) -> c_int {
+    let topic = unsafe { laux::lua_check_str(state, 1) };
+    let data = check_buffer(state, 2);
+    let actor = LuaActor::from_lua_state(state);
+    match CONTEXT.publish(unsafe { (*actor).id }, topic, data) {
+        Ok(n) => {
+            laux::lua_push(state, n as i64);
+            1
+        }
+        Err(err) => laux::lua_error(state, err),
+    }
+}
+
+extern "C-unwind" fn lua_topic_match(state: LuaState) -> c_int {
+    let pattern = unsafe { laux::lua_check_str(state, 1) };
+    let topic = unsafe { laux::lua_check_str(state, 2) };
+    laux::lua_push(state, pubsub::topic_match(pattern, topic));
+    1
+}
+
 extern "C-unwind" fn lua_new_actor(state: LuaState) -> c_int {
     laux::lua_checktype(state, 1, ffi::LUA_TTABLE);
 
@@ -515,7 +1077,131 @@ extern "C-unwind" fn lua_new_actor(state: LuaState) -> c_int {
     let name: String = laux::opt_field(state, 1, "name").unwrap_or_default();
     let source = laux::opt_field(state, 1, "source").unwrap_or_default();
     let memlimit: i64 = laux::opt_field(state, 1, "memlimit").unwrap_or_default();
+    let mem_soft_limit: i64 = laux::opt_field(state, 1, "mem_soft_limit").unwrap_or_default();
+    let mem_action = match laux::opt_field::<String>(state, 1, "mem_action") {
+        Some(action) => match MemoryAction::parse(&action) {
+            Some(a) => a,
+            None => laux::lua_error(
+                state,
+                format!("invalid mem_action '{}' (expected error or kill)", action),
+            ),
+        },
+        None => MemoryAction::default(),
+    };
     let unique: bool = laux::opt_field(state, 1, "unique").unwrap_or_default();
+    let mailbox_capacity: usize =
+        laux::opt_field(state, 1, "mailbox_capacity").unwrap_or_default();
+    let policy: String = laux::opt_field(state, 1, "mailbox_policy").unwrap_or_default();
+    let mailbox_policy = if policy.is_empty() {
+        MailboxPolicy::default()
+    } else {
+        match MailboxPolicy::parse(&policy) {
+            Some(p) => p,
+            None => laux::lua_error(
+                state,
+                format!(
+                    "invalid mailbox_policy '{}' (expected block, drop_newest or reject)",
+                    policy
+                ),
+            ),
+        }
+    };
+
+    let log_level = match laux::opt_field::<String>(state, 1, "loglevel") {
+        Some(lv) => Logger::level_to_u8(Logger::string_to_level(lv)),
+        None => 0,
+    };
+    let log_file: Option<String> = laux::opt_field(state, 1, "logfile");
+
+    let mut restart = RestartPolicy::default();
+    if let Some(mode) = laux::opt_field::<String>(state, 1, "restart") {
+        restart.mode = match RestartMode::parse(&mode) {
+            Some(m) => m,
+            None => laux::lua_error(
+                state,
+                format!(
+                    "invalid restart '{}' (expected temporary, permanent or transient)",
+                    mode
+                ),
+            ),
+        };
+    }
+    if let Some(n) = laux::opt_field(state, 1, "max_restarts") {
+        restart.max_restarts = n;
+    }
+    if let Some(secs) = laux::opt_field(state, 1, "within") {
+        restart.within_secs = secs;
+    }
+
+    let group: String = laux::opt_field(state, 1, "group").unwrap_or_default();
+    let balance = match laux::opt_field::<String>(state, 1, "balance") {
+        Some(b) => match Balance::parse(&b) {
+            Some(b) => Some(b),
+            None => laux::lua_error(
+                state,
+                format!(
+                    "invalid balance '{}' (expected round_robin, least_queue or hash)",
+                    b
+                ),
+            ),
+        },
+        None => None,
+    };
+
+    let mut watchdog = WatchdogConfig::default();
+    if unsafe { ffi::lua_getfield(state.as_ptr(), 1, cstr!("watchdog")) } == ffi::LUA_TTABLE {
+        if let Some(ms) = laux::opt_field::<u64>(state, -1, "timeout_ms") {
+            if ms < context::WATCHDOG_PERIOD_MS {
+                laux::lua_error(
+                    state,
+                    format!(
+                        "invalid watchdog timeout_ms {} (expected at least {})",
+                        ms,
+                        context::WATCHDOG_PERIOD_MS
+                    ),
+                );
+            }
+            watchdog.timeout_ms = ms;
+        }
+        if let Some(n) = laux::opt_field::<u32>(state, -1, "strikes") {
+            watchdog.strikes = n.max(1);
+        }
+        if let Some(action) = laux::opt_field::<String>(state, -1, "action") {
+            watchdog.action = match WatchdogAction::parse(&action) {
+                Some(a) => a,
+                None => laux::lua_error(
+                    state,
+                    format!(
+                        "invalid watchdog action '{}' (expected interrupt, log or kill)",
+                        action
+                    ),
+                ),
+            };
+        }
+    }
+    laux::lua_pop(state, 1);
+
+    let mut budget = Budget::default();
+    if let Some(n) = laux::opt_field(state, 1, "budget") {
+        budget.messages = n;
+    }
+    if let Some(ms) = laux::opt_field(state, 1, "budget_ms") {
+        budget.ms = ms;
+    }
+    let priority_lanes: bool = laux::opt_field(state, 1, "priority_lanes").unwrap_or_default();
+
+    let record: Option<String> = laux::opt_field(state, 1, "record");
+    // The seed goes into the recording, so a replay draws the same random
+    // numbers as the recorded run.
+    let seed = record
+        .as_ref()
+        .map(|_| unsafe { ffi::luaL_makeseed(state.as_ptr()) } as u64);
+
+    // The recording holds the answer to this call; there is nothing to start.
+    if CONTEXT.is_replaying() {
+        laux::lua_push(state, session);
+        return 1;
+    }
 
     let mut params: String = laux::lua_get(state, 2);
     if let Some(p) = CONTEXT.get_env("PATH") {
@@ -528,10 +1214,25 @@ extern "C-unwind" fn lua_new_actor(state: LuaState) -> c_int {
         creator,
         session,
         memlimit,
+        mem_soft_limit,
+        mem_action,
         name,
         source,
         params,
         block: false,
+        mailbox_capacity,
+        mailbox_policy,
+        log_level,
+        log_file,
+        restart,
+        restart_log: Default::default(),
+        group,
+        balance,
+        record,
+        seed,
+        budget,
+        priority_lanes,
+        watchdog,
     });
 
     laux::lua_push(state, session);
@@ -561,25 +1262,46 @@ extern "C-unwind" fn lua_actor_callback(state: LuaState) -> c_int {
 
 extern "C-unwind" fn lua_timeout(state: LuaState) -> c_int {
     let interval: i64 = laux::lua_get(state, 1);
+    // Number of fires: 1 (default) for a one-shot, `-1` to repeat until removed.
+    let count: i64 = laux::lua_opt(state, 2).unwrap_or(1);
+    if count == 0 || count < timer::TIMER_REPEAT_FOREVER {
+        laux::lua_error(state, format!("timeout: invalid count {}", count));
+    }
+    if count != 1 && interval <= 0 {
+        laux::lua_error(
+            state,
+            format!(
+                "timeout: repeating timer needs interval > 0, got {}",
+                interval
+            ),
+        );
+    }
     let actor = LuaActor::from_lua_state(state);
     let owner = unsafe { (*actor).id };
     let timer_id = unsafe { (*actor).next_session() };
 
     if interval <= 0 {
-        let _ = CONTEXT.send(Message {
-            from: 0,
-            to: owner,
-            session: 0,
-            data: MessageBody::ISize(context::PTYPE_TIMER, timer_id as isize),
-        });
+        let _ = CONTEXT.send(Message::new(
+            0,
+            owner,
+            0,
+            MessageBody::ISize(context::PTYPE_TIMER, timer_id as isize),
+        ));
     } else {
-        context::insert_timer(owner, timer_id, interval as u64);
+        timer::insert_timer(owner, timer_id, interval as u64, count);
     }
 
     laux::lua_push(state, timer_id);
     1
 }
 
+extern "C-unwind" fn lua_remove_timer(state: LuaState) -> c_int {
+    let timer_id: i64 = laux::lua_get(state, 1);
+    let actor = LuaActor::from_lua_state(state);
+    timer::remove_timer(unsafe { (*actor).id }, timer_id);
+    0
+}
+
 extern "C-unwind" fn lua_loglevel(state: LuaState) -> c_int {
     if laux::lua_top(state) == 0 {
         laux::lua_push(state, LOGGER.get_log_level() as u8);
@@ -591,16 +1313,233 @@ extern "C-unwind" fn lua_loglevel(state: LuaState) -> c_int {
     0
 }
 
+/// The actor's own level when overridden, else the global one.
+fn effective_log_level(actor: &LuaActor) -> u8 {
+    if actor.log_level != 0 {
+        actor.log_level
+    } else {
+        Logger::level_to_u8(LOGGER.get_log_level())
+    }
+}
+
+fn log_route(actor: &LuaActor) -> u32 {
+    actor.log_route.as_ref().map_or(0, LogRoute::id)
+}
+
+/// `set_memlimit(hard [, soft])`: change this actor's memory limits in bytes;
+/// 0 removes a limit. Returns the previous hard and soft limits.
+extern "C-unwind" fn lua_set_memlimit(state: LuaState) -> c_int {
+    let actor = unsafe { &mut *LuaActor::from_lua_state(state) };
+    let (hard, soft) = (actor.mem_limit, actor.mem_soft_limit);
+    let new_hard: i64 = laux::lua_get(state, 1);
+    actor.mem_limit = new_hard.max(0) as isize;
+    if let Some(new_soft) = laux::lua_opt::<i64>(state, 2) {
+        actor.mem_soft_limit = new_soft.max(0) as isize;
+        actor.mem_above_soft = false;
+    }
+    laux::lua_push(state, hard as i64);
+    laux::lua_push(state, soft as i64);
+    2
+}
+
+/// `watch_module()` tells whether hot reload is enabled;
+/// `watch_module(name, path)` watches the file this actor loaded module `name`
+/// from, and returns false when hot reload is disabled.
+extern "C-unwind" fn lua_watch_module(state: LuaState) -> c_int {
+    if laux::lua_top(state) == 0 {
+        laux::lua_push(state, hotreload::enabled());
+        return 1;
+    }
+
+    let actor = unsafe { &*LuaActor::from_lua_state(state) };
+    let name: &str = laux::lua_get(state, 1);
+    let path: &str = laux::lua_get(state, 2);
+    match hotreload::watch_module(actor.id, name, std::path::Path::new(path)) {
+        Ok(watched) => laux::lua_push(state, watched),
+        Err(err) => {
+            log::warn!("hot reload cannot watch '{}': {}", path, err);
+            laux::lua_push(state, false);
+        }
+    }
+    1
+}
+
+/// `actor_loglevel()` returns the level in effect for this actor;
+/// `actor_loglevel(lv)` overrides it, and `actor_loglevel("")` goes back to
+/// following the global level.
+extern "C-unwind" fn lua_actor_loglevel(state: LuaState) -> c_int {
+    let actor = unsafe { &mut *LuaActor::from_lua_state(state) };
+    if laux::lua_top(state) == 0 {
+        laux::lua_push(state, effective_log_level(actor));
+        return 1;
+    }
+
+    let level: String = laux::lua_get(state, 1);
+    actor.log_level = if level.is_empty() {
+        0
+    } else {
+        Logger::level_to_u8(Logger::string_to_level(level))
+    };
+    0
+}
+
+fn push_latency_summary(state: LuaState, summary: &LatencySummary) {
+    let t = LuaTable::new(state, 0, 5);
+    t.insert("count", summary.count);
+    t.insert("mean_us", summary.mean_us);
+    t.insert("p50_us", summary.p50_us);
+    t.insert("p99_us", summary.p99_us);
+    t.insert("max_us", summary.max_us);
+}
+
+/// `latency([reset])`: this actor's queue wait and dispatch latency as
+/// `{ [ptype] = { wait = summary, dispatch = summary } }`, times in
+/// microseconds. With `reset = true` the histograms start over afterwards.
+extern "C-unwind" fn lua_actor_latency(state: LuaState) -> c_int {
+    let actor = unsafe { &*LuaActor::from_lua_state(state) };
+    let reset: bool = laux::lua_opt(state, 1).unwrap_or(false);
+    let watchdog = unsafe { &*actor.watchdog };
+    let stats = watchdog.latency_stats();
+    if reset {
+        watchdog.reset_latency();
+    }
+    let table = LuaTable::new(state, 0, stats.len());
+    for stat in &stats {
+        table.rawset_x(stat.ptype, || {
+            let t = LuaTable::new(state, 0, 2);
+            t.rawset_x("wait", || push_latency_summary(state, &stat.wait));
+            t.rawset_x("dispatch", || push_latency_summary(state, &stat.dispatch));
+        });
+    }
+    1
+}
+
+/// `profile_start(id [, hz])`: sample the Lua stacks of service `id`, see
+/// `profiler.rs`.
+extern "C-unwind" fn lua_profile_start(state: LuaState) -> c_int {
+    let id: context::ActorId = laux::lua_get(state, 1);
+    let hz: u32 = laux::lua_opt(state, 2).unwrap_or(profiler::DEFAULT_HZ);
+    if let Err(err) = CONTEXT.start_profile(id, hz) {
+        laux::lua_error(state, err);
+    }
+    0
+}
+
+/// `profile_stop(id [, path])`: end the profiling run of service `id` and
+/// return its folded stacks and number of samples. With `path`, the profile is
+/// also written there: an SVG flamegraph when `path` ends in `.svg`, folded
+/// stacks otherwise.
+extern "C-unwind" fn lua_profile_stop(state: LuaState) -> c_int {
+    let id: context::ActorId = laux::lua_get(state, 1);
+    let path: Option<String> = laux::lua_opt(state, 2);
+    let profile = match CONTEXT.stop_profile(id) {
+        Ok(profile) => profile,
+        Err(err) => laux::lua_error(state, err),
+    };
+    let folded = profile.folded();
+    if let Some(path) = path {
+        let data = if path.ends_with(".svg") {
+            profile.flamegraph(&format!("service {:08x}", id))
+        } else {
+            Ok(folded.clone().into_bytes())
+        };
+        if let Err(err) =
+            data.and_then(|data| std::fs::write(&path, data).map_err(|e| e.to_string()))
+        {
+            laux::lua_error(state, format!("profile '{}': {}", path, err));
+        }
+    }
+    laux::lua_push(state, folded.as_str());
+    laux::lua_push(state, profile.samples);
+    2
+}
+
+/// `memprofile_start([interval])`: start sampling this actor's allocations,
+/// about one per `interval` bytes; see `memprofile.rs`.
+extern "C-unwind" fn lua_memprofile_start(state: LuaState) -> c_int {
+    let actor = unsafe { &mut *LuaActor::from_lua_state(state) };
+    let interval: usize = laux::lua_opt(state, 1).unwrap_or(memprofile::DEFAULT_INTERVAL);
+    if actor.memprofile.is_some() {
+        laux::lua_error(state, "memprofile already running".to_string());
+    }
+    actor.memprofile = Some(Box::new(AllocProfile::new(interval)));
+    0
+}
+
+/// `memprofile_stop()`: stop sampling and drop the profile. Returns whether a
+/// profile was running.
+extern "C-unwind" fn lua_memprofile_stop(state: LuaState) -> c_int {
+    let actor = unsafe { &mut *LuaActor::from_lua_state(state) };
+    laux::lua_push(state, actor.memprofile.take().is_some());
+    1
+}
+
+/// `memprofile_report([sort [, limit]])`: the top allocation sites as an array
+/// of `{ site, allocated, count, live, live_count }`, largest first by `sort`
+/// (`"allocated"` or `"live"`). Sites holding nothing are left out of a
+/// `"live"` report.
+extern "C-unwind" fn lua_memprofile_report(state: LuaState) -> c_int {
+    let actor = unsafe { &*LuaActor::from_lua_state(state) };
+    let sort: &str = laux::lua_opt(state, 1).unwrap_or("allocated");
+    let Some(sort) = SortBy::parse(sort) else {
+        laux::lua_error(state, format!("unknown memprofile sort '{}'", sort));
+    };
+    let limit: usize = laux::lua_opt(state, 2).unwrap_or(0);
+    let Some(profile) = actor.memprofile.as_ref() else {
+        laux::lua_error(state, "memprofile not running".to_string());
+    };
+    let sites = profile.report(sort, limit);
+    let table = LuaTable::new(state, sites.len(), 0);
+    for stats in &sites {
+        let t = LuaTable::new(state, 0, 5);
+        t.insert("site", stats.site.as_str());
+        t.insert("allocated", stats.allocated);
+        t.insert("count", stats.count);
+        t.insert("live", stats.live);
+        t.insert("live_count", stats.live_count);
+        table.push_table(t);
+    }
+    1
+}
+
+extern "C-unwind" fn lua_log_reopen(_state: LuaState) -> c_int {
+    LOGGER.reopen();
+    0
+}
+
+/// Source file and line of the Lua caller `stack_level` frames up.
+fn log_source(state: LuaState, stack_level: i32) -> Option<(&'static [u8], i32)> {
+    let mut debug: ffi::lua_Debug = unsafe { std::mem::zeroed() };
+    if unsafe {
+        ffi::lua_getstack(state.as_ptr(), stack_level as c_int, &mut debug) == 0
+            || ffi::lua_getinfo(state.as_ptr(), cstr!("Sl"), &mut debug) == 0
+    } {
+        return None;
+    }
+    let mut file_name: &[u8] = &[];
+    if debug.srclen > 1 {
+        file_name = unsafe { slice::from_raw_parts(debug.source as *mut u8, debug.srclen) };
+        if file_name[0] == b'@' {
+            file_name = &file_name[1..];
+        }
+    }
+    Some((file_name, debug.currentline))
+}
+
 extern "C-unwind" fn lua_actor_log(state: LuaState) -> c_int {
     let log_level: u8 = laux::lua_get(state, 1);
+    let actor = LuaActor::from_lua_state(state);
     // Honor the configured log level. The u8 scheme is severity-ordered
     // (Error=1 .. Trace=5), so a message is emitted only when its level is at
     // or above the threshold (e.g. an INFO=3 threshold drops DEBUG=4/TRACE=5).
-    if log_level > Logger::level_to_u8(LOGGER.get_log_level()) {
+    if log_level > effective_log_level(unsafe { &*actor }) {
         return 0;
     }
     let stack_level: i32 = laux::lua_get(state, 2);
-    let actor = LuaActor::from_lua_state(state);
+
+    if LOGGER.log_format() == LogFormat::Json {
+        return actor_log_json(state, Logger::u8_to_level(log_level), stack_level, actor);
+    }
 
     let mut content = LOGGER.make_line(true, Logger::u8_to_level(log_level), 256);
     content.write_str(format!("{:08X}| ", unsafe { (*actor).id }).as_str());
@@ -615,27 +1554,86 @@ extern "C-unwind" fn lua_actor_log(state: LuaState) -> c_int {
         laux::lua_pop(state, 1);
     }
 
-    let mut debug: ffi::lua_Debug = unsafe { std::mem::zeroed() };
-    if unsafe {
-        ffi::lua_getstack(state.as_ptr(), stack_level as c_int, &mut debug) != 0
-            && ffi::lua_getinfo(state.as_ptr(), cstr!("Sl"), &mut debug) != 0
-    } {
+    if let Some((file_name, line)) = log_source(state, stack_level) {
         content.write_str("    ");
         content.write(b'(');
-        if debug.srclen > 1 {
-            let file_name = unsafe { slice::from_raw_parts(debug.source as *mut u8, debug.srclen) };
-            if file_name[0] == b'@' {
-                content.write_slice(&file_name[1..]);
-            } else {
-                content.write_slice(file_name);
-            }
-        }
+        content.write_slice(file_name);
         content.write(b':');
-        content.write_str(debug.currentline.to_string().as_str());
+        content.write_str(line.to_string().as_str());
         content.write(b')');
     }
 
-    LOGGER.write(content);
+    LOGGER.write_route(log_route(unsafe { &*actor }), content);
+
+    0
+}
+
+/// `LogFormat::Json` variant of `lua_actor_log`. Table arguments (as in
+/// `moon.info{uid = 1, "text"}`) contribute their string-keyed entries as
+/// extra fields; everything else, positional entries included, is joined
+/// into `msg`.
+fn actor_log_json(
+    state: LuaState,
+    level: log::Level,
+    stack_level: i32,
+    actor: *mut LuaActor,
+) -> c_int {
+    let options = JsonOptions::default();
+    let mut msg = Vec::new();
+    let mut fields = Vec::new();
+    let top = laux::lua_top(state);
+    for i in 3..=top {
+        if laux::lua_type(state, i) != LuaType::Table {
+            if !msg.is_empty() {
+                msg.extend_from_slice(b"    ");
+            }
+            msg.extend_from_slice(unsafe { laux::lua_as_slice(state, i) });
+            laux::lua_pop(state, 1);
+            continue;
+        }
+        let table = LuaTable::from_stack(state, i);
+        for (key, value) in table.iter() {
+            let LuaValue::String(key) = key else {
+                if !msg.is_empty() {
+                    msg.extend_from_slice(b"    ");
+                }
+                msg.extend_from_slice(unsafe { laux::lua_as_slice(state, -1) });
+                laux::lua_pop(state, 1);
+                continue;
+            };
+            if JSON_RECORD_KEYS.iter().any(|k| k.as_bytes() == key) {
+                continue;
+            }
+            let mark = fields.len();
+            if mark > 0 {
+                fields.push(b',');
+            }
+            write_json_string(&mut fields, key);
+            fields.push(b':');
+            let value_start = fields.len();
+            if encode_one(&mut fields, value, 0, false, &options).is_err() {
+                // Functions, userdata, cyclic tables: log their tostring form.
+                fields.truncate(value_start);
+                write_json_string(&mut fields, unsafe { laux::lua_as_slice(state, -1) });
+                laux::lua_pop(state, 1);
+            }
+        }
+    }
+
+    let (file, line) = log_source(state, stack_level).unwrap_or((&[], 0));
+    let content = LOGGER.make_json_line(
+        true,
+        level,
+        &JsonRecord {
+            actor_id: unsafe { (*actor).id },
+            actor_name: unsafe { &(*actor).name },
+            msg: &msg,
+            file,
+            line: line.max(0) as u32,
+            fields: &fields,
+        },
+    );
+    LOGGER.write_route(log_route(unsafe { &*actor }), content);
 
     0
 }
@@ -683,6 +1681,15 @@ fn get_message_pointer(state: LuaState) -> *mut Message {
     m
 }
 
+/// Payload bytes of a `Buffer` or `Shared` body.
+fn message_bytes<'a>(m: *mut Message) -> Option<&'a [u8]> {
+    match unsafe { &(*m).data } {
+        MessageBody::Buffer(_, data) => Some(data.as_slice()),
+        MessageBody::Shared(_, data) => Some(data.as_slice()),
+        _ => None,
+    }
+}
+
 extern "C-unwind" fn lua_message_decode(state: LuaState) -> c_int {
     let m = get_message_pointer(state);
     let opt = unsafe { laux::lua_check_str(state, 2) };
@@ -704,20 +1711,17 @@ extern "C-unwind" fn lua_message_decode(state: LuaState) -> c_int {
                 laux::lua_push(state, unsafe { (*m).session });
             }
             'Z' => {
-                if let MessageBody::Buffer(_, data) = unsafe { &(*m).data } {
-                    laux::lua_push(state, data.as_slice());
+                if let Some(data) = message_bytes(m) {
+                    laux::lua_push(state, data);
                 } else {
                     laux::lua_pushnil(state);
                 }
             }
             'N' => {
-                if let MessageBody::Buffer(_, data) = unsafe { &(*m).data } {
-                    laux::lua_push(state, data.len());
-                } else {
-                    laux::lua_push(state, 0);
-                }
+                laux::lua_push(state, message_bytes(m).map_or(0, |data| data.len()));
             }
             'B' => {
+                // A `Shared` body is read-only, so it has no writable pointer.
                 if let MessageBody::Buffer(_, data) = unsafe { &mut (*m).data } {
                     laux::lua_pushlightuserdata(state, data.as_mut().as_pointer() as *mut c_void);
                 } else {
@@ -725,7 +1729,7 @@ extern "C-unwind" fn lua_message_decode(state: LuaState) -> c_int {
                 }
             }
             'C' => {
-                if let MessageBody::Buffer(_, data) = unsafe { &(*m).data } {
+                if let Some(data) = message_bytes(m) {
                     laux::lua_pushlightuserdata(state, data.as_ptr() as *mut c_void);
                     laux::lua_push(state, data.len());
                 } else {
@@ -738,11 +1742,16 @@ extern "C-unwind" fn lua_message_decode(state: LuaState) -> c_int {
                 // the message (leaving `None` so it is not freed again) and hand
                 // Lua the raw pointer. The receiver now owns it and must release
                 // it via `buffer.drop` / `buffer.into_arc_buffer` (or forward it
-                // with `moon.send`, transferring ownership again).
+                // with `moon.send`, transferring ownership again). A `Shared`
+                // body is still referenced by other messages; Lua gets a copy.
                 let ptype = unsafe { (*m).data.ptype() };
                 let body = std::mem::replace(unsafe { &mut (*m).data }, MessageBody::None(ptype));
                 if let MessageBody::Buffer(_, data) = body {
                     laux::lua_pushlightuserdata(state, Box::into_raw(data) as *mut c_void);
+                } else if let MessageBody::Shared(_, data) = &body {
+                    let copy = Box::new(Buffer::from_slice(data.as_slice()));
+                    unsafe { (*m).data = body };
+                    laux::lua_pushlightuserdata(state, Box::into_raw(copy) as *mut c_void);
                 } else {
                     unsafe { (*m).data = body };
                     laux::lua_pushnil(state);
@@ -773,6 +1782,23 @@ extern "C-unwind" fn next_session(state: LuaState) -> c_int {
     1
 }
 
+/// `moon.metrics()`: the `/metrics` exposition as a string, for services that
+/// serve it from their own HTTP handler.
+extern "C-unwind" fn lua_metrics(state: LuaState) -> c_int {
+    laux::lua_push(state, metrics::render().as_str());
+    1
+}
+
+fn latency_json(summary: &LatencySummary) -> serde_json::Value {
+    serde_json::json!({
+        "count": summary.count,
+        "mean_us": summary.mean_us,
+        "p50_us": summary.p50_us,
+        "p99_us": summary.p99_us,
+        "max_us": summary.max_us,
+    })
+}
+
 extern "C-unwind" fn server_stats(state: LuaState) -> c_int {
     // Backward-compatible scalar lookup: `server_stats("service.count")` keeps
     // returning a single integer. With no argument, return the full snapshot as
@@ -787,6 +1813,8 @@ extern "C-unwind" fn server_stats(state: LuaState) -> c_int {
             "log.error_count" => CONTEXT.error_count() as i64,
             "log.queue" => LOGGER.pending_count() as i64,
             "timer.count" => CONTEXT.timer_count() as i64,
+            "topic.count" => CONTEXT.topic_count() as i64,
+            "group.count" => CONTEXT.group_count() as i64,
             "env.count" => CONTEXT.env_count() as i64,
             "time.offset" => CONTEXT.time_offset() as i64,
             "time.now" => CONTEXT.now().timestamp_millis(),
@@ -811,6 +1839,14 @@ extern "C-unwind" fn server_stats(state: LuaState) -> c_int {
                 "memory": s.memory,
                 "messages": s.messages,
                 "cpu_ms": s.cpu_ms,
+                "queue": s.queue,
+                "capacity": s.capacity,
+                "dropped": s.dropped,
+                "latency": s.latency.iter().map(|l| serde_json::json!({
+                    "ptype": l.ptype,
+                    "wait": latency_json(&l.wait),
+                    "dispatch": latency_json(&l.dispatch),
+                })).collect::<Vec<_>>(),
             })
         })
         .collect();
@@ -823,6 +1859,8 @@ extern "C-unwind" fn server_stats(state: LuaState) -> c_int {
         "log.error_count": CONTEXT.error_count(),
         "log.queue": LOGGER.pending_count(),
         "timer.count": CONTEXT.timer_count(),
+        "topic.count": CONTEXT.topic_count(),
+        "group.count": CONTEXT.group_count(),
         "env.count": CONTEXT.env_count(),
         "time.offset": CONTEXT.time_offset(),
         "time.now": CONTEXT.now().timestamp_millis(),
@@ -843,11 +1881,29 @@ unsafe extern "C-unwind" fn luaopen_core(state: LuaState) -> c_int {
         lreg!("query", lua_actor_query),
         lreg!("kill", lua_kill_actor),
         lreg!("send", lua_actor_send),
+        lreg!("broadcast", lua_actor_broadcast),
+        lreg!("group_pick", lua_group_pick),
+        lreg!("group_members", lua_group_members),
+        lreg!("subscribe", lua_subscribe),
+        lreg!("unsubscribe", lua_unsubscribe),
+        lreg!("publish", lua_publish),
+        lreg!("topic_match", lua_topic_match),
         lreg!("log", lua_actor_log),
         lreg!("loglevel", lua_loglevel),
+        lreg!("log_reopen", lua_log_reopen),
+        lreg!("actor_loglevel", lua_actor_loglevel),
+        lreg!("set_memlimit", lua_set_memlimit),
+        lreg!("latency", lua_actor_latency),
+        lreg!("watch_module", lua_watch_module),
+        lreg!("profile_start", lua_profile_start),
+        lreg!("profile_stop", lua_profile_stop),
+        lreg!("memprofile_start", lua_memprofile_start),
+        lreg!("memprofile_stop", lua_memprofile_stop),
+        lreg!("memprofile_report", lua_memprofile_report),
         lreg!("callback", lua_actor_callback),
         lreg!("exit", lua_actor_exit),
         lreg!("timeout", lua_timeout),
+        lreg!("remove_timer", lua_remove_timer),
         lreg!("decode", lua_message_decode),
         lreg!("decode_message", lua_decode_message_payload),
         lreg!("env", env),
@@ -855,6 +1911,7 @@ unsafe extern "C-unwind" fn luaopen_core(state: LuaState) -> c_int {
         lreg!("now", now),
         lreg!("next_session", next_session),
         lreg!("server_stats", server_stats),
+        lreg!("metrics", lua_metrics),
         lreg_null!(),
     ];
 
@@ -345,12 +345,12 @@ fn dispatch_frame(mut frame: Box<Buffer>, remote_node_id: u32) {
                 let actor_id = *actor_ref;
                 drop(actor_ref);
                 frame.consume(nl + 1);
-                let _ = CONTEXT.send(Message {
-                    from: from_addr,
-                    to: actor_id,
-                    session: 0,
-                    data: MessageBody::Buffer(context::PTYPE_LUA, frame),
-                });
+                let _ = CONTEXT.send(Message::new(
+                    from_addr,
+                    actor_id,
+                    0,
+                    MessageBody::Buffer(context::PTYPE_LUA, frame),
+                ));
             } else {
                 log::error!(
                     "cluster SEND: service '{}' not found on node {}",
@@ -402,12 +402,12 @@ fn dispatch_frame(mut frame: Box<Buffer>, remote_node_id: u32) {
                 );
 
                 frame.consume(nl + 1);
-                let _ = CONTEXT.send(Message {
-                    from: context::CLUSTER_ACTOR_ADDR,
-                    to: actor_id,
-                    session: -local_session,
-                    data: MessageBody::Buffer(context::PTYPE_LUA, frame),
-                });
+                let _ = CONTEXT.send(Message::new(
+                    context::CLUSTER_ACTOR_ADDR,
+                    actor_id,
+                    -local_session,
+                    MessageBody::Buffer(context::PTYPE_LUA, frame),
+                ));
             } else {
                 log::error!(
                     "cluster CALL: service '{}' not found on node {}",
@@ -452,12 +452,12 @@ fn dispatch_frame(mut frame: Box<Buffer>, remote_node_id: u32) {
                 return;
             }
             frame.consume(nl + 1);
-            let _ = CONTEXT.send(Message {
-                from: 0,
-                to: from_addr,
+            let _ = CONTEXT.send(Message::new(
+                0,
+                from_addr,
                 session,
-                data: MessageBody::Buffer(context::PTYPE_LUA, frame),
-            });
+                MessageBody::Buffer(context::PTYPE_LUA, frame),
+            ));
         }
         _ => {
             log::error!(
@@ -940,6 +940,7 @@ fn spawn_response_reader(mut rx: mpsc::UnboundedReceiver<Message>) {
             if let Some((_, info)) = CLUSTER.pending_calls.remove(&local_session) {
                 let body = match msg.data {
                     MessageBody::Buffer(_, buf) => buf,
+                    MessageBody::Shared(_, buf) => Box::new(Buffer::from_slice(buf.as_slice())),
                     _ => Box::new(Buffer::new()),
                 };
                 let header = make_resp_header(info.from_addr, info.session);
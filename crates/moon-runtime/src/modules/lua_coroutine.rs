@@ -2,28 +2,52 @@ use moon_base::{cstr, ffi, laux::LuaState};
 use moon_runtime::{
     actor::LuaActor,
     context::{CONTEXT, Watchdog},
+    debugger::{Command, Debugger, Frame, MAX_FIELDS, MAX_FRAMES, Reference, Reply, Variable},
+    memprofile,
+    profiler::{MAX_DEPTH, Profiler},
 };
-use std::ffi::c_int;
+use std::ffi::{CStr, c_int};
 use std::sync::atomic::Ordering;
 
-/// Lua count-hook callback installed by the monitor (via `check_watchdogs`) or
-/// by `switch_l` when a trap is pending. Clears the hook, resets the trap flag,
-/// and raises a Lua error to unwind the stuck coroutine.
+/// Lua hook callback installed by the monitor (via `check_watchdogs`), by the
+/// profilers (via `Watchdog::request_sample` and the allocator), by the
+/// debugger or by `switch_l` when a trap or sample is pending. Clears the
+/// count hook (keeping the line hook while a debugger is attached), takes a
+/// pending profiler sample and gives pending allocation samples their site.
+/// On a line event it lets the debugger stop there.
+/// If a trap is pending, it resets the trap flag and raises a Lua error to
+/// unwind the stuck coroutine.
 /// Traceback is captured HERE (before lua_error unwinds the stack).
 ///
 /// # Safety
 /// `l` must be a valid `lua_State` pointer whose actor was created by this
 /// runtime (so `LuaActor::from_lua_state` yields a live actor). Intended to be
-/// invoked only by the Lua VM as a debug hook; `_ar` is unused. May raise a Lua
-/// error via `longjmp`/unwind, so the caller must be inside the Lua VM.
+/// invoked only by the Lua VM as a debug hook with a valid `ar`. May raise a
+/// Lua error via `longjmp`/unwind, so the caller must be inside the Lua VM.
 #[unsafe(no_mangle)]
-pub unsafe extern "C-unwind" fn moon_signal_hook(l: *mut ffi::lua_State, _ar: *mut ffi::lua_Debug) {
+pub unsafe extern "C-unwind" fn moon_signal_hook(l: *mut ffi::lua_State, ar: *mut ffi::lua_Debug) {
     unsafe {
-        ffi::lua_sethook(l, None, 0, 0);
         let state = LuaState::new(l).unwrap();
         let actor = LuaActor::from_lua_state(state);
         let wd = (*actor).watchdog;
+        let debugging = !wd.is_null() && (*wd).debugger.is_attached();
+        if debugging {
+            ffi::lua_sethook(l, Some(moon_signal_hook), ffi::LUA_MASKLINE, 0);
+        } else {
+            ffi::lua_sethook(l, None, 0, 0);
+        }
+        if let Some(profile) = (*actor).memprofile.as_mut()
+            && profile.has_unresolved()
+        {
+            profile.resolve(&current_site(l));
+        }
         if !wd.is_null() {
+            if (*wd).profiler.take_request() {
+                sample_stack(l, &(*wd).profiler);
+            }
+            if debugging && (*ar).event == ffi::LUA_HOOKLINE {
+                debug_line(l, (*ar).currentline, &*wd);
+            }
             let trap = (*wd).trap.load(Ordering::Acquire);
             if trap != 0 {
                 (*wd).trap.store(0, Ordering::Release);
@@ -39,16 +63,310 @@ pub unsafe extern "C-unwind" fn moon_signal_hook(l: *mut ffi::lua_State, _ar: *m
     }
 }
 
-/// Update `active_l` to `l` and, if a trap is pending, install the count-hook
-/// on this state so it fires on the very next VM instruction.
+/// Name of a stack frame in a profile: `name (source:line)`, `<source:line>`
+/// for an anonymous function, `name [C]` for a C function.
+unsafe fn frame_name(ar: &ffi::lua_Debug) -> String {
+    unsafe {
+        let name = (!ar.name.is_null()).then(|| CStr::from_ptr(ar.name).to_string_lossy());
+        let src = CStr::from_ptr(ar.short_src.as_ptr()).to_string_lossy();
+        match CStr::from_ptr(ar.what).to_bytes() {
+            b"C" => format!("{} [C]", name.as_deref().unwrap_or("?")),
+            b"main" => format!("main chunk ({})", src),
+            _ => match name {
+                Some(name) => format!("{} ({}:{})", name, src, ar.linedefined),
+                None => format!("<{}:{}>", src, ar.linedefined),
+            },
+        }
+    }
+}
+
+/// Record the stack of `l` as one profiler sample.
+unsafe fn sample_stack(l: *mut ffi::lua_State, profiler: &Profiler) {
+    unsafe {
+        let mut frames = Vec::new();
+        let mut ar: ffi::lua_Debug = std::mem::zeroed();
+        let mut level = 0;
+        while frames.len() < MAX_DEPTH && ffi::lua_getstack(l, level, &mut ar) != 0 {
+            if ffi::lua_getinfo(l, cstr!("Sn"), &mut ar) != 0 {
+                frames.push(frame_name(&ar));
+            }
+            level += 1;
+        }
+        profiler.record(frames.iter().rev());
+    }
+}
+
+/// `source:line` of the running Lua function, for the allocation profiler.
+unsafe fn current_site(l: *mut ffi::lua_State) -> String {
+    unsafe {
+        let mut ar: ffi::lua_Debug = std::mem::zeroed();
+        if ffi::lua_getstack(l, 0, &mut ar) == 0 || ffi::lua_getinfo(l, cstr!("Sl"), &mut ar) == 0 {
+            return memprofile::NATIVE_SITE.to_string();
+        }
+        let src = CStr::from_ptr(ar.short_src.as_ptr()).to_string_lossy();
+        format!("{}:{}", src, ar.currentline)
+    }
+}
+
+/// Number of frames on the stack of `l`.
+unsafe fn stack_depth(l: *mut ffi::lua_State) -> usize {
+    unsafe {
+        let mut ar: ffi::lua_Debug = std::mem::zeroed();
+        let mut depth = 0;
+        while ffi::lua_getstack(l, depth, &mut ar) != 0 {
+            depth += 1;
+        }
+        depth as usize
+    }
+}
+
+/// File chunk name (without the `@`) of the frame `ar` was filled for by
+/// `lua_getstack`.
+unsafe fn frame_source(l: *mut ffi::lua_State, ar: &mut ffi::lua_Debug) -> Option<String> {
+    unsafe {
+        if ffi::lua_getinfo(l, cstr!("S"), ar) == 0 || ar.source.is_null() {
+            return None;
+        }
+        let source = std::slice::from_raw_parts(ar.source as *const u8, ar.srclen);
+        let path = source.strip_prefix(b"@")?;
+        Some(String::from_utf8_lossy(path).into_owned())
+    }
+}
+
+/// Line hook of an attached debugger: stop at `line` if the debugger says
+/// so, and hold the dispatch until the session resumes it.
+unsafe fn debug_line(l: *mut ffi::lua_State, line: c_int, wd: &Watchdog) {
+    unsafe {
+        let debugger = &wd.debugger;
+        let reason = debugger.check_line(
+            l as usize,
+            line,
+            || stack_depth(l),
+            || {
+                let mut ar: ffi::lua_Debug = std::mem::zeroed();
+                if ffi::lua_getstack(l, 0, &mut ar) == 0 {
+                    return None;
+                }
+                frame_source(l, &mut ar)
+            },
+        );
+        let Some(reason) = reason else {
+            return;
+        };
+        let resume = debug_hold(l, debugger, reason);
+        wd.restart_heartbeat(CONTEXT.clock_ms());
+        if let Some(resume) = resume {
+            debugger.step(resume, l as usize, stack_depth(l));
+        }
+    }
+}
+
+/// Answer the session's requests about the stopped `l` until it resumes. The
+/// tables it expands are kept in a registry table, so their references stay
+/// valid for the whole stop.
+unsafe fn debug_hold(
+    l: *mut ffi::lua_State,
+    debugger: &Debugger,
+    reason: &'static str,
+) -> Option<moon_runtime::debugger::Resume> {
+    unsafe {
+        let top = ffi::lua_gettop(l);
+        ffi::lua_createtable(l, 0, 0);
+        let tables = ffi::luaL_ref(l, ffi::LUA_REGISTRYINDEX);
+        let resume = debugger.hold(reason, |command| {
+            let reply = if ffi::lua_checkstack(l, 8) == 0 {
+                Reply::Variables(Vec::new())
+            } else {
+                match command {
+                    Command::StackTrace => Reply::Frames(debug_frames(l)),
+                    Command::Variables(reference) => {
+                        Reply::Variables(debug_variables(l, tables, reference))
+                    }
+                    Command::Resume(_) => Reply::Resumed,
+                }
+            };
+            ffi::lua_settop(l, top);
+            reply
+        });
+        ffi::luaL_unref(l, ffi::LUA_REGISTRYINDEX, tables);
+        resume
+    }
+}
+
+/// The stack of the stopped `l`, innermost first.
+unsafe fn debug_frames(l: *mut ffi::lua_State) -> Vec<Frame> {
+    unsafe {
+        let mut frames = Vec::new();
+        let mut ar: ffi::lua_Debug = std::mem::zeroed();
+        let mut level = 0;
+        while frames.len() < MAX_FRAMES && ffi::lua_getstack(l, level, &mut ar) != 0 {
+            level += 1;
+            if ffi::lua_getinfo(l, cstr!("nSl"), &mut ar) == 0 {
+                continue;
+            }
+            let name = match CStr::from_ptr(ar.what).to_bytes() {
+                b"main" => "main chunk".to_string(),
+                _ if !ar.name.is_null() => CStr::from_ptr(ar.name).to_string_lossy().into_owned(),
+                b"C" => "?".to_string(),
+                _ => format!(
+                    "<{}:{}>",
+                    CStr::from_ptr(ar.short_src.as_ptr()).to_string_lossy(),
+                    ar.linedefined
+                ),
+            };
+            frames.push(Frame {
+                id: level as i64,
+                name,
+                source: frame_source(l, &mut ar),
+                line: ar.currentline,
+            });
+        }
+        frames
+    }
+}
+
+/// The variables of `reference`. `tables` is the registry reference of the
+/// table keeping the expandable values of this stop.
+unsafe fn debug_variables(
+    l: *mut ffi::lua_State,
+    tables: c_int,
+    reference: Reference,
+) -> Vec<Variable> {
+    unsafe {
+        let mut vars = Vec::new();
+        let mut ar: ffi::lua_Debug = std::mem::zeroed();
+        match reference {
+            Reference::Locals(level) => {
+                if ffi::lua_getstack(l, level as c_int, &mut ar) == 0 {
+                    return vars;
+                }
+                let mut n = 1;
+                while let Some(name) = non_null(ffi::lua_getlocal(l, &ar, n)) {
+                    // `(temporary)`, `(for state)` and the like are internal.
+                    if !name.starts_with('(') {
+                        vars.push(debug_variable(l, tables, name));
+                    }
+                    ffi::lua_pop(l, 1);
+                    n += 1;
+                }
+            }
+            Reference::Upvalues(level) => {
+                if ffi::lua_getstack(l, level as c_int, &mut ar) == 0
+                    || ffi::lua_getinfo(l, cstr!("f"), &mut ar) == 0
+                {
+                    return vars;
+                }
+                let mut n = 1;
+                while let Some(name) = non_null(ffi::lua_getupvalue(l, -1, n)) {
+                    let name = if name.is_empty() {
+                        "?".to_string()
+                    } else {
+                        name
+                    };
+                    vars.push(debug_variable(l, tables, name));
+                    ffi::lua_pop(l, 1);
+                    n += 1;
+                }
+            }
+            Reference::Table(index) => {
+                ffi::lua_rawgeti(l, ffi::LUA_REGISTRYINDEX, tables as ffi::lua_Integer);
+                if ffi::lua_rawgeti(l, -1, index as ffi::lua_Integer + 1) != ffi::LUA_TTABLE {
+                    return vars;
+                }
+                let t = ffi::lua_gettop(l);
+                ffi::lua_pushnil(l);
+                while vars.len() < MAX_FIELDS && ffi::lua_next(l, t) != 0 {
+                    let name = match ffi::lua_type(l, -2) {
+                        ffi::LUA_TSTRING => debug_value(l, -2).trim_matches('"').to_string(),
+                        _ => format!("[{}]", debug_value(l, -2)),
+                    };
+                    vars.push(debug_variable(l, tables, name));
+                    ffi::lua_pop(l, 1);
+                }
+            }
+        }
+        vars
+    }
+}
+
+unsafe fn non_null(name: *const std::ffi::c_char) -> Option<String> {
+    unsafe { (!name.is_null()).then(|| CStr::from_ptr(name).to_string_lossy().into_owned()) }
+}
+
+/// Describe the value on top of `l`. A table is kept in `tables` so that it
+/// can be expanded.
+unsafe fn debug_variable(l: *mut ffi::lua_State, tables: c_int, name: String) -> Variable {
+    unsafe {
+        let tp = ffi::lua_type(l, -1);
+        let mut reference = 0;
+        if tp == ffi::LUA_TTABLE {
+            ffi::lua_rawgeti(l, ffi::LUA_REGISTRYINDEX, tables as ffi::lua_Integer);
+            let index = ffi::lua_rawlen(l, -1);
+            ffi::lua_pushvalue(l, -2);
+            ffi::lua_rawseti(l, -2, index as ffi::lua_Integer + 1);
+            ffi::lua_pop(l, 1);
+            reference = Reference::Table(index as usize).id();
+        }
+        Variable {
+            name,
+            value: debug_value(l, -1),
+            kind: CStr::from_ptr(ffi::lua_typename(l, tp))
+                .to_string_lossy()
+                .into_owned(),
+            reference,
+        }
+    }
+}
+
+/// Display a value without running any metamethod.
+unsafe fn debug_value(l: *mut ffi::lua_State, idx: c_int) -> String {
+    unsafe {
+        match ffi::lua_type(l, idx) {
+            ffi::LUA_TNIL => "nil".to_string(),
+            ffi::LUA_TBOOLEAN => (ffi::lua_toboolean(l, idx) != 0).to_string(),
+            ffi::LUA_TNUMBER if ffi::lua_isinteger(l, idx) != 0 => {
+                ffi::lua_tointegerx(l, idx, std::ptr::null_mut()).to_string()
+            }
+            ffi::LUA_TNUMBER => format!("{:?}", ffi::lua_tonumberx(l, idx, std::ptr::null_mut())),
+            ffi::LUA_TSTRING => {
+                let mut len = 0;
+                let s = ffi::lua_tolstring(l, idx, &mut len);
+                let s = String::from_utf8_lossy(std::slice::from_raw_parts(s as *const u8, len));
+                let s: String = s.chars().take(256).collect();
+                format!("{:?}", s)
+            }
+            tp => format!(
+                "{}: {:p}",
+                CStr::from_ptr(ffi::lua_typename(l, tp)).to_string_lossy(),
+                ffi::lua_topointer(l, idx)
+            ),
+        }
+    }
+}
+
+/// Update `active_l` to `l` and, if a trap or a sample of either profiler is
+/// pending, install the count-hook on this state so it fires on the very next
+/// VM instruction. With a debugger attached, `l` also gets the line hook.
 #[inline]
 unsafe fn switch_l(l: *mut ffi::lua_State, wd: *const Watchdog) {
     unsafe {
         (*wd)
             .active_l
             .store(l as *mut std::ffi::c_void, Ordering::Release);
-        if (*wd).trap.load(Ordering::Acquire) != 0 {
-            ffi::lua_sethook(l, Some(moon_signal_hook), ffi::LUA_MASKCOUNT, 1);
+        let actor = LuaActor::from_lua_state(LuaState::new(l).unwrap());
+        let unresolved = (*actor)
+            .memprofile
+            .as_ref()
+            .is_some_and(|profile| profile.has_unresolved());
+        let mut mask = 0;
+        if (*wd).trap.load(Ordering::Acquire) != 0 || (*wd).profiler.is_pending() || unresolved {
+            mask |= ffi::LUA_MASKCOUNT;
+        }
+        if (*wd).debugger.is_attached() {
+            mask |= ffi::LUA_MASKLINE;
+        }
+        if mask != 0 {
+            ffi::lua_sethook(l, Some(moon_signal_hook), mask, 1);
         }
     }
 }
@@ -118,6 +436,12 @@ unsafe fn core_resume(
             ffi::lua_xmove(co, l, nres);
             nres
         } else {
+            if status == ffi::LUA_ERRMEM && !wd.is_null() {
+                // `coresume` rethrows this as a runtime error; let the
+                // dispatch know it ran out of memory.
+                let actor = LuaActor::from_lua_state(LuaState::new(l).unwrap());
+                (*actor).mem_failed = true;
+            }
             ffi::lua_xmove(co, l, 1);
             -1
         }
@@ -1341,20 +1341,16 @@ extern "C-unwind" fn find_connection(state: LuaState) -> c_int {
     1
 }
 
+/// Per-connection request counters, for `stats()` and the `/metrics` export.
+pub(crate) fn pool_stats() -> Vec<(String, crate::request_pool::PoolStats)> {
+    DATABASE_CONNECTIONSS
+        .iter()
+        .map(|pair| (pair.key().clone(), crate::request_pool::PoolStats::from_counter(&pair.value().counter)))
+        .collect()
+}
+
 extern "C-unwind" fn stats(state: LuaState) -> c_int {
-    let table = LuaTable::new(state, 0, DATABASE_CONNECTIONSS.len());
-    DATABASE_CONNECTIONSS.iter().for_each(|pair| {
-        let counter = &pair.value().counter;
-        table.rawset_x(pair.key().as_str(), || {
-            crate::request_pool::push_pool_stats(
-                state,
-                counter.load(),
-                counter.total(),
-                counter.peak(),
-                1,
-            );
-        });
-    });
+    crate::request_pool::push_pool_stats(state, pool_stats());
     1
 }
 
@@ -1158,20 +1158,16 @@ extern "C-unwind" fn close(state: LuaState) -> c_int {
     1
 }
 
+/// Per-connection request counters, for `stats()` and the `/metrics` export.
+pub(crate) fn pool_stats() -> Vec<(String, crate::request_pool::PoolStats)> {
+    REDIS_CONNECTIONS
+        .iter()
+        .map(|pair| (pair.key().clone(), crate::request_pool::PoolStats::from_workers(&pair.value().inner)))
+        .collect()
+}
+
 extern "C-unwind" fn stats(state: LuaState) -> c_int {
-    let table = LuaTable::new(state, 0, REDIS_CONNECTIONS.len());
-    REDIS_CONNECTIONS.iter().for_each(|pair| {
-        let pool = &pair.value().inner;
-        table.rawset_x(pair.key().as_str(), || {
-            crate::request_pool::push_pool_stats(
-                state,
-                pool.pending(),
-                pool.total(),
-                pool.peak(),
-                pool.worker_count() as i64,
-            );
-        });
-    });
+    crate::request_pool::push_pool_stats(state, pool_stats());
     1
 }
 
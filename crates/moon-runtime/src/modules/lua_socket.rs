@@ -10,18 +10,20 @@ use moon_runtime::{
     check_arc_buffer,
     context::MessageBody,
 };
+use rustls::pki_types::{CertificateDer, PrivateKeyDer, ServerName, pem::PemObject};
 use std::{
     ffi::{c_int, c_void},
     io::{Error, ErrorKind, IoSlice},
-    net::TcpStream,
+    net::{SocketAddr, TcpStream},
     sync::Arc,
     time::Duration,
 };
 use tokio::io::AsyncReadExt;
+use tokio_rustls::{TlsAcceptor, TlsConnector};
 
 use tokio::{
-    io::{AsyncBufReadExt, AsyncWrite, AsyncWriteExt, BufReader, Result},
-    net::{TcpListener, tcp::OwnedReadHalf},
+    io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader, Result},
+    net::{TcpListener, UdpSocket},
     sync::{Semaphore, mpsc},
     time::{sleep, timeout},
 };
@@ -49,6 +51,8 @@ pub enum NetOp {
     ReadFrame(ActorId, i64, u64),                   //owner,session,read_timeout
     Write(ActorId, Arc<Buffer>, bool),              //owner,data,close
     WriteFrame(ActorId, Arc<Buffer>, bool),         //owner,data,close
+    SendTo(Arc<Buffer>, Option<SocketAddr>),        //data,peer (None = connected peer)
+    UdpConnect(ActorId, i64, String),               //owner,session,peer
     Close(),
 }
 
@@ -60,6 +64,9 @@ const SOCKET_DATA_CLOSE: u8 = 4;
 
 const MESSAGE_CONTINUED_FLAG: u16 = u16::MAX;
 
+/// Largest payload a single UDP datagram can carry (IPv4 65535 - 8 UDP - 20 IP).
+const MAX_UDP_DATAGRAM_SIZE: usize = 65507;
+
 /// Hard upper bound on the bytes a single `socket.read` may request
 /// (`read_bytes`) or accumulate (`read_until`). See `crate::LIMITS.max_network_read_bytes`.
 const MAX_READ_SIZE: usize = crate::LIMITS.max_network_read_bytes;
@@ -76,8 +83,15 @@ pub enum SocketEvent {
     Close(i64, String, String),
 }
 
-async fn read_until(
-    reader: &mut BufReader<OwnedReadHalf>,
+/// Inbound datagram delivered to the owner as a `PTYPE_SOCKET_UDP` message.
+pub struct UdpDatagram {
+    pub fd: i64,
+    pub data: Vec<u8>,
+    pub from: SocketAddr,
+}
+
+async fn read_until<R: AsyncRead + Unpin>(
+    reader: &mut BufReader<R>,
     owner: ActorId,
     session: i64,
     max_size: usize,
@@ -156,12 +170,12 @@ async fn read_until(
                     buffer.revert(delim_len);
                 }
                 if CONTEXT
-                    .send(Message {
-                        from: 0,
-                        to: owner,
+                    .send(Message::new(
+                        0,
+                        owner,
                         session,
-                        data: MessageBody::Buffer(context::PTYPE_SOCKET_TCP, buffer),
-                    })
+                        MessageBody::Buffer(context::PTYPE_SOCKET_TCP, buffer),
+                    ))
                     .is_some()
                 {
                     return false;
@@ -175,8 +189,8 @@ async fn read_until(
     true
 }
 
-async fn read_bytes(
-    reader: &mut BufReader<OwnedReadHalf>,
+async fn read_bytes<R: AsyncRead + Unpin>(
+    reader: &mut BufReader<R>,
     owner: ActorId,
     session: i64,
     size: usize,
@@ -228,12 +242,12 @@ async fn read_bytes(
         Ok(_) => {
             let _ = buffer.commit(size);
             if CONTEXT
-                .send(Message {
-                    from: 0,
-                    to: owner,
+                .send(Message::new(
+                    0,
+                    owner,
                     session,
-                    data: MessageBody::Buffer(context::PTYPE_SOCKET_TCP, buffer),
-                })
+                    MessageBody::Buffer(context::PTYPE_SOCKET_TCP, buffer),
+                ))
                 .is_some()
             {
                 return false;
@@ -258,10 +272,10 @@ impl Drop for ReadOpGuard {
             match op {
                 NetOp::ReadUntil(owner, session, ..)
                 | NetOp::ReadBytes(owner, session, ..)
-                | NetOp::ReadFrame(owner, session, ..) => {
-                    if session > 0 {
-                        CONTEXT.response_error(0, owner, -session, "closed".to_string());
-                    }
+                | NetOp::ReadFrame(owner, session, ..)
+                    if session > 0 =>
+                {
+                    CONTEXT.response_error(0, owner, -session, "closed".to_string());
                 }
                 _ => {}
             }
@@ -273,8 +287,10 @@ impl Drop for ReadOpGuard {
 /// Returns `Some(reason)` when the connection ended due to an I/O error (the reason
 /// should appear in the close event). Returns `None` for clean exits (explicit close,
 /// owner dead, channel closed).
-async fn handle_read(
-    reader: OwnedReadHalf,
+// The inner `if`s await, so they cannot become match guards.
+#[allow(clippy::collapsible_match)]
+async fn handle_read<R: AsyncRead + Unpin>(
+    reader: R,
     fd: i64,
     _addr: String,
     rx: mpsc::Receiver<NetOp>,
@@ -298,12 +314,12 @@ async fn handle_read(
                     match read_one_frame(&mut reader, read_timeout).await {
                         Ok(buf) => {
                             if CONTEXT
-                                .send(Message {
-                                    from: 0,
-                                    to: owner,
+                                .send(Message::new(
+                                    0,
+                                    owner,
                                     session,
-                                    data: MessageBody::Buffer(context::PTYPE_SOCKET_EVENT, buf),
-                                })
+                                    MessageBody::Buffer(context::PTYPE_SOCKET_EVENT, buf),
+                                ))
                                 .is_some()
                             {
                                 return None;
@@ -526,31 +542,30 @@ where
     W: AsyncWrite + Unpin,
 {
     while let Some(op) = rx.recv().await {
-        match op {
-            NetOp::Write(_owner, data, close) => {
-                let (batch, close_after_batch) =
-                    drain_socket_write_batch(SocketWriteItem::Raw(data), close, &mut rx);
-                if let Err(err) = write_socket_batch(&mut writer, batch).await {
-                    return Some(format!("write: {}", err));
-                }
-                if close_after_batch {
-                    return None;
-                }
-            }
-            NetOp::WriteFrame(_owner, data, close) => {
-                let (batch, close_after_batch) =
-                    drain_socket_write_batch(SocketWriteItem::Frame(data), close, &mut rx);
-                if let Err(err) = write_socket_batch(&mut writer, batch).await {
-                    return Some(format!("write: {}", err));
-                }
-                if close_after_batch {
-                    return None;
-                }
-            }
+        let (item, close) = match op {
+            NetOp::Write(_owner, data, close) => (SocketWriteItem::Raw(data), close),
+            NetOp::WriteFrame(_owner, data, close) => (SocketWriteItem::Frame(data), close),
             NetOp::Close() => {
+                // Sends TLS close_notify before the FIN; plain TCP only shuts
+                // down the write side, same as dropping the half.
+                let _ = writer.shutdown().await;
                 return None;
             }
-            _ => {}
+            _ => continue,
+        };
+        let (batch, close_after_batch) = drain_socket_write_batch(item, close, &mut rx);
+        // TLS streams buffer records internally; flush so every batch
+        // reaches the socket. A no-op for plain TCP.
+        let res = match write_socket_batch(&mut writer, batch).await {
+            Ok(_) => writer.flush().await,
+            Err(err) => Err(err),
+        };
+        if let Err(err) = res {
+            return Some(format!("write: {}", err));
+        }
+        if close_after_batch {
+            let _ = writer.shutdown().await;
+            return None;
         }
     }
     None
@@ -558,8 +573,8 @@ where
 
 /// Read one complete framed message from the reader.
 /// Returns Ok(Some(buf)) on success, Ok(None) if owner is dead, Err(msg) on I/O error.
-async fn read_one_frame(
-    reader: &mut BufReader<OwnedReadHalf>,
+async fn read_one_frame<R: AsyncRead + Unpin>(
+    reader: &mut BufReader<R>,
     read_timeout: u64,
 ) -> std::result::Result<Box<Buffer>, String> {
     let mut data: Option<Box<Buffer>> = None;
@@ -662,8 +677,8 @@ async fn read_one_frame(
 
 /// Auto-read loop: continuously reads framed messages and dispatches to owner via callback.
 /// Returns `Some(reason)` on I/O error, `None` if the owner is dead (send failed).
-async fn frame_read_loop(
-    reader: &mut BufReader<OwnedReadHalf>,
+async fn frame_read_loop<R: AsyncRead + Unpin>(
+    reader: &mut BufReader<R>,
     owner: ActorId,
     fd: i64,
     read_timeout: u64,
@@ -690,6 +705,24 @@ async fn frame_read_loop(
     }
 }
 
+/// Tell `owner` about a connection accepted on `listen_fd`. Returns `false`,
+/// and forgets the connection, when the owner is gone.
+fn announce_accept(owner: ActorId, listen_fd: i64, fd: i64, addr: String) -> bool {
+    if CONTEXT
+        .send_value(
+            context::PTYPE_SOCKET_EVENT,
+            owner,
+            0,
+            SocketEvent::Accept(listen_fd, fd, addr),
+        )
+        .is_some()
+    {
+        NET.remove(&fd);
+        return false;
+    }
+    true
+}
+
 /// Set up a connection: start read/write tasks. Assumes NET entry is already inserted.
 async fn run_connection(
     socket: tokio::net::TcpStream,
@@ -705,7 +738,69 @@ async fn run_connection(
 
     socket.set_nodelay(true).unwrap_or_default();
     let (reader, writer) = socket.into_split();
+    run_stream(reader, writer, addr, owner, fd, rx_reader, rx_writer).await;
+}
+
+/// Handshake a connection accepted on the TLS listener `listen_fd`, then
+/// announce it to `owner` and run it. A failed or stalled handshake is only
+/// logged: the owner never hears of the connection. Returns `false` when the
+/// owner is gone, as `announce_accept` does.
+async fn accept_tls(
+    socket: tokio::net::TcpStream,
+    acceptor: TlsAcceptor,
+    owner: ActorId,
+    listen_fd: i64,
+    fd: i64,
+    rx_reader: mpsc::Receiver<NetOp>,
+    rx_writer: mpsc::Receiver<NetOp>,
+) -> bool {
+    let addr = socket
+        .peer_addr()
+        .map(|a| a.to_string())
+        .unwrap_or_default();
+
+    socket.set_nodelay(true).unwrap_or_default();
+    let handshake = timeout(
+        Duration::from_millis(crate::LIMITS.tls_handshake_timeout_ms),
+        acceptor.accept(socket),
+    )
+    .await;
+    let err = match handshake {
+        Ok(Ok(stream)) => {
+            if !announce_accept(owner, listen_fd, fd, addr.clone()) {
+                return false;
+            }
+            let (reader, writer) = tokio::io::split(stream);
+            run_stream(reader, writer, addr, owner, fd, rx_reader, rx_writer).await;
+            return true;
+        }
+        Ok(Err(err)) => err.to_string(),
+        Err(_) => "timeout".to_string(),
+    };
+    NET.remove(&fd);
+    log::debug!(
+        "socket.listen fd={}: tls handshake with {}: {}",
+        listen_fd,
+        addr,
+        err
+    );
+    true
+}
 
+/// Drive an established (plain or TLS) stream until either half finishes,
+/// then emit the close event.
+async fn run_stream<R, W>(
+    reader: R,
+    writer: W,
+    addr: String,
+    owner: ActorId,
+    fd: i64,
+    rx_reader: mpsc::Receiver<NetOp>,
+    rx_writer: mpsc::Receiver<NetOp>,
+) where
+    R: AsyncRead + Unpin + Send + 'static,
+    W: AsyncWrite + Unpin + Send + 'static,
+{
     let addr_clone = addr.clone();
     let mut read_task = CONTEXT
         .io_runtime()
@@ -732,6 +827,86 @@ async fn run_connection(
     NET.remove(&fd);
 }
 
+/// PEM material from a Lua `tls` table: inline PEM text, or a path to a PEM file.
+fn load_pem(source: &[u8]) -> std::result::Result<Vec<u8>, String> {
+    if source.starts_with(b"-----BEGIN") {
+        return Ok(source.to_vec());
+    }
+    let path = String::from_utf8_lossy(source);
+    std::fs::read(path.as_ref()).map_err(|e| format!("read '{}': {}", path, e))
+}
+
+fn tls_provider() -> Arc<rustls::crypto::CryptoProvider> {
+    Arc::new(rustls::crypto::aws_lc_rs::default_provider())
+}
+
+fn tls_acceptor(cert: &[u8], key: &[u8]) -> std::result::Result<TlsAcceptor, String> {
+    let certs = CertificateDer::pem_slice_iter(&load_pem(cert)?)
+        .collect::<std::result::Result<Vec<_>, _>>()
+        .map_err(|e| format!("tls cert: {}", e))?;
+    if certs.is_empty() {
+        return Err("tls cert: no certificate found".to_string());
+    }
+    let key =
+        PrivateKeyDer::from_pem_slice(&load_pem(key)?).map_err(|e| format!("tls key: {}", e))?;
+    let config = rustls::ServerConfig::builder_with_provider(tls_provider())
+        .with_safe_default_protocol_versions()
+        .map_err(|e| e.to_string())?
+        .with_no_client_auth()
+        .with_single_cert(certs, key)
+        .map_err(|e| format!("tls: {}", e))?;
+    Ok(TlsAcceptor::from(Arc::new(config)))
+}
+
+/// Client config trusting `ca` when given, otherwise the bundled webpki roots.
+fn tls_connector(ca: Option<&[u8]>) -> std::result::Result<TlsConnector, String> {
+    let mut roots = rustls::RootCertStore::empty();
+    match ca {
+        Some(ca) => {
+            for cert in CertificateDer::pem_slice_iter(&load_pem(ca)?) {
+                let cert = cert.map_err(|e| format!("tls ca: {}", e))?;
+                roots.add(cert).map_err(|e| format!("tls ca: {}", e))?;
+            }
+        }
+        None => roots.extend(webpki_roots::TLS_SERVER_ROOTS.iter().cloned()),
+    }
+    let config = rustls::ClientConfig::builder_with_provider(tls_provider())
+        .with_safe_default_protocol_versions()
+        .map_err(|e| e.to_string())?
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+    Ok(TlsConnector::from(Arc::new(config)))
+}
+
+/// Host part of a `host:port` / `[v6]:port` address, used as the default SNI.
+fn host_of(addr: &str) -> &str {
+    let host = addr.rsplit_once(':').map_or(addr, |(host, _)| host);
+    host.trim_start_matches('[').trim_end_matches(']')
+}
+
+/// Parse the optional `tls` sub-table of the opts table at `index`.
+/// Returns `Ok(None)` when no `tls` field is present.
+fn opt_tls_table<T>(
+    state: LuaState,
+    index: i32,
+    build: impl FnOnce(LuaState, i32) -> std::result::Result<T, String>,
+) -> std::result::Result<Option<T>, String> {
+    if laux::lua_type(state, index) != LuaType::Table {
+        return Ok(None);
+    }
+    unsafe {
+        ffi::lua_getfield(state.as_ptr(), index, cstr!("tls"));
+    }
+    let top = laux::lua_top(state);
+    let res = match laux::lua_type(state, top) {
+        LuaType::Table => build(state, top).map(Some),
+        LuaType::Nil => Ok(None),
+        _ => Err("tls: expected a table".to_string()),
+    };
+    laux::lua_pop(state, 1);
+    res
+}
+
 fn setup_net_channel(fd: i64) -> (mpsc::Receiver<NetOp>, mpsc::Receiver<NetOp>) {
     let (tx_reader, rx_reader) = mpsc::channel::<NetOp>(1);
     let (tx_writer, rx_writer) = mpsc::channel::<NetOp>(crate::LIMITS.network_write_queue_capacity);
@@ -739,7 +914,12 @@ fn setup_net_channel(fd: i64) -> (mpsc::Receiver<NetOp>, mpsc::Receiver<NetOp>)
     (rx_reader, rx_writer)
 }
 
-fn listen(addr: &str, owner: ActorId, max_connections: usize) -> Result<i64> {
+fn listen(
+    addr: &str,
+    owner: ActorId,
+    max_connections: usize,
+    tls: Option<TlsAcceptor>,
+) -> Result<i64> {
     let listener = std::net::TcpListener::bind(addr)?;
     listener.set_nonblocking(true)?;
     let listener = TcpListener::from_std(listener)?;
@@ -752,6 +932,9 @@ fn listen(addr: &str, owner: ActorId, max_connections: usize) -> Result<i64> {
     // inbound peers cannot exhaust fds / spawn unbounded tasks. The permit is
     // held for the whole connection lifetime (moved into `run_connection`).
     let semaphore = Arc::new(Semaphore::new(max_connections));
+    // TLS connections are announced after their handshake, so their tasks
+    // report a dead owner back to the loop.
+    let (owner_gone, mut owner_gone_rx) = mpsc::unbounded_channel::<()>();
 
     CONTEXT.io_runtime().spawn(async move {
         loop {
@@ -778,19 +961,30 @@ fn listen(addr: &str, owner: ActorId, max_connections: usize) -> Result<i64> {
 
                             let (rx_reader, rx_writer) = setup_net_channel(conn_fd);
 
-                            if CONTEXT.send_value(
-                                context::PTYPE_SOCKET_EVENT,
-                                owner,
-                                0,
-                                SocketEvent::Accept(fd, conn_fd, remote_addr),
-                            ).is_some() {
-                                NET.remove(&conn_fd);
+                            if tls.is_none() && !announce_accept(owner, fd, conn_fd, remote_addr) {
                                 break;
                             }
 
+                            let tls = tls.clone();
+                            let owner_gone = owner_gone.clone();
                             CONTEXT.io_runtime().spawn(async move {
                                 let _permit = permit;
-                                run_connection(socket, owner, conn_fd, rx_reader, rx_writer).await;
+                                match tls {
+                                    Some(acceptor) => {
+                                        if !accept_tls(
+                                            socket, acceptor, owner, fd, conn_fd, rx_reader,
+                                            rx_writer,
+                                        )
+                                        .await
+                                        {
+                                            let _ = owner_gone.send(());
+                                        }
+                                    }
+                                    None => {
+                                        run_connection(socket, owner, conn_fd, rx_reader, rx_writer)
+                                            .await
+                                    }
+                                }
                             });
                         }
                         Err(err) => {
@@ -805,6 +999,7 @@ fn listen(addr: &str, owner: ActorId, max_connections: usize) -> Result<i64> {
                         _ => {}
                     }
                 }
+                _ = owner_gone_rx.recv() => break,
             }
         }
         NET.remove(&fd);
@@ -820,14 +1015,25 @@ extern "C-unwind" fn lua_socket_listen(state: LuaState) -> c_int {
     let actor = LuaActor::from_lua_state(state);
     let owner = unsafe { (*actor).id };
 
-    // Optional opts table at arg 2: { max_connections = N }.
+    // Optional opts table at arg 2: { max_connections = N, tls = { cert, key } }.
     let max_connections: usize = if laux::lua_type(state, 2) == LuaType::Table {
         laux::opt_field(state, 2, "max_connections").unwrap_or(crate::LIMITS.listener_connections)
     } else {
         crate::LIMITS.listener_connections
     };
+    let tls = opt_tls_table(state, 2, |state, index| {
+        let cert: &[u8] = laux::opt_field(state, index, "cert").ok_or("tls: 'cert' is required")?;
+        let key: &[u8] = laux::opt_field(state, index, "key").ok_or("tls: 'key' is required")?;
+        tls_acceptor(cert, key)
+    });
+    let tls = match tls {
+        Ok(tls) => tls,
+        Err(err) => {
+            return crate::lua_push_error(state, &format!("Listen '{}' failed: {}", addr, err));
+        }
+    };
 
-    match listen(addr, owner, max_connections) {
+    match listen(addr, owner, max_connections, tls) {
         Ok(fd) => {
             laux::lua_push(state, fd);
             1
@@ -936,39 +1142,80 @@ extern "C-unwind" fn lua_socket_write(state: LuaState) -> c_int {
     }
 }
 
+/// Outcome of `lua_socket_connect` before the IO tasks start.
+enum Connected {
+    Plain(tokio::net::TcpStream),
+    Tls(Box<tokio_rustls::client::TlsStream<tokio::net::TcpStream>>),
+}
+
 extern "C-unwind" fn lua_socket_connect(state: LuaState) -> c_int {
     let addr = unsafe { laux::lua_check_str(state, 1) }.to_string();
     let connect_timeout: u64 = laux::lua_opt(state, 2).unwrap_or(5000);
 
+    // Optional opts table at arg 3: { tls = { sni?, ca? } }.
+    let tls = opt_tls_table(state, 3, |state, index| {
+        let sni: String =
+            laux::opt_field(state, index, "sni").unwrap_or_else(|| host_of(&addr).to_string());
+        let sni = ServerName::try_from(sni).map_err(|e| format!("tls sni: {}", e))?;
+        let ca: Option<&[u8]> = laux::opt_field(state, index, "ca");
+        Ok((tls_connector(ca)?, sni))
+    });
+    let tls = match tls {
+        Ok(tls) => tls,
+        Err(err) => return crate::lua_push_error(state, &format!("connect '{}': {}", addr, err)),
+    };
+
     let actor = LuaActor::from_lua_state(state);
     let owner = unsafe { (*actor).id };
     let session = unsafe { (*actor).next_session() };
 
     CONTEXT.io_runtime().spawn(async move {
-        match timeout(
-            Duration::from_millis(connect_timeout),
-            tokio::net::TcpStream::connect(addr.as_str()),
-        )
-        .await
-        {
-            Ok(Ok(socket)) => {
+        // The TLS handshake shares the connect timeout, so a returned fd is
+        // always ready for reads and writes.
+        let connect = async {
+            let socket = tokio::net::TcpStream::connect(addr.as_str()).await?;
+            socket.set_nodelay(true).unwrap_or_default();
+            let peer = socket
+                .peer_addr()
+                .map(|a| a.to_string())
+                .unwrap_or_default();
+            match tls {
+                Some((connector, sni)) => {
+                    let stream = connector.connect(sni, socket).await?;
+                    Ok::<_, Error>((Connected::Tls(Box::new(stream)), peer))
+                }
+                None => Ok((Connected::Plain(socket), peer)),
+            }
+        };
+        match timeout(Duration::from_millis(connect_timeout), connect).await {
+            Ok(Ok((stream, peer))) => {
                 let fd = next_net_fd();
                 let (rx_reader, rx_writer) = setup_net_channel(fd);
                 if CONTEXT
-                    .send(Message {
-                        from: 0,
-                        to: owner,
+                    .send(Message::new(
+                        0,
+                        owner,
                         session,
-                        data: MessageBody::ISize(context::PTYPE_INTEGER, fd as isize),
-                    })
+                        MessageBody::ISize(context::PTYPE_INTEGER, fd as isize),
+                    ))
                     .is_some()
                 {
                     NET.remove(&fd);
                     return;
                 }
-                CONTEXT
-                    .io_runtime()
-                    .spawn(run_connection(socket, owner, fd, rx_reader, rx_writer));
+                match stream {
+                    Connected::Plain(socket) => {
+                        CONTEXT
+                            .io_runtime()
+                            .spawn(run_connection(socket, owner, fd, rx_reader, rx_writer));
+                    }
+                    Connected::Tls(stream) => {
+                        let (reader, writer) = tokio::io::split(*stream);
+                        CONTEXT.io_runtime().spawn(run_stream(
+                            reader, writer, peer, owner, fd, rx_reader, rx_writer,
+                        ));
+                    }
+                }
             }
             Ok(Err(err)) => {
                 CONTEXT.response_error(0, owner, -session, format!("connect '{}': {}", addr, err));
@@ -1062,6 +1309,174 @@ extern "C-unwind" fn lua_socket_close(state: LuaState) -> c_int {
     0
 }
 
+/// Parse a literal `ip:port` peer; host names are rejected. `udp_sendto` runs
+/// on the actor thread, where a host name would need a blocking lookup.
+fn parse_peer(addr: &str) -> Result<SocketAddr> {
+    addr.parse().map_err(|_| {
+        Error::new(
+            ErrorKind::InvalidInput,
+            "expected a literal ip:port address",
+        )
+    })
+}
+
+/// Drive one UDP socket: forward inbound datagrams to `owner` and serve
+/// send/connect/close ops from the actor until closed or the owner is gone.
+async fn run_udp(socket: UdpSocket, owner: ActorId, fd: i64, mut rx: mpsc::Receiver<NetOp>) {
+    let mut buf = vec![0u8; MAX_UDP_DATAGRAM_SIZE];
+    loop {
+        tokio::select! {
+            result = socket.recv_from(&mut buf) => {
+                match result {
+                    Ok((n, from)) => {
+                        let datagram = UdpDatagram { fd, data: buf[..n].to_vec(), from };
+                        if CONTEXT
+                            .send_value(context::PTYPE_SOCKET_UDP, owner, 0, datagram)
+                            .is_some()
+                        {
+                            break;
+                        }
+                    }
+                    Err(err) => {
+                        // ICMP port-unreachable surfaces here on connected
+                        // sockets; it does not invalidate the socket.
+                        log::debug!("udp fd={} recv: {}", fd, err);
+                    }
+                }
+            }
+            op = rx.recv() => {
+                match op {
+                    Some(NetOp::SendTo(data, peer)) => {
+                        let res = match peer {
+                            Some(peer) => socket.send_to(data.as_slice(), peer).await,
+                            None => socket.send(data.as_slice()).await,
+                        };
+                        if let Err(err) = res {
+                            log::warn!("udp fd={} send: {}", fd, err);
+                        }
+                    }
+                    Some(NetOp::UdpConnect(owner, session, peer)) => {
+                        // Host names are resolved here, on the io runtime.
+                        match socket.connect(peer.as_str()).await {
+                            Ok(_) => {
+                                let _ = CONTEXT.send(Message::new(0, owner, session, MessageBody::ISize(context::PTYPE_INTEGER, fd as isize)));
+                            }
+                            Err(err) => {
+                                CONTEXT.response_error(
+                                    0,
+                                    owner,
+                                    -session,
+                                    format!("udp_connect '{}': {}", peer, err),
+                                );
+                            }
+                        }
+                    }
+                    Some(NetOp::Close()) | None => break,
+                    _ => {}
+                }
+            }
+        }
+    }
+    NET.remove(&fd);
+}
+
+fn udp_bind(addr: &str, owner: ActorId) -> Result<i64> {
+    let socket = std::net::UdpSocket::bind(addr)?;
+    socket.set_nonblocking(true)?;
+    let socket = UdpSocket::from_std(socket)?;
+
+    let fd = next_net_fd();
+    let (tx, rx) = mpsc::channel::<NetOp>(crate::LIMITS.network_write_queue_capacity);
+    NET.insert(fd, NetChannel(tx.clone(), tx));
+    CONTEXT.io_runtime().spawn(run_udp(socket, owner, fd, rx));
+    Ok(fd)
+}
+
+extern "C-unwind" fn lua_udp_bind(state: LuaState) -> c_int {
+    let _guard = CONTEXT.io_runtime().enter();
+
+    let addr = laux::lua_opt(state, 1).unwrap_or("0.0.0.0:0");
+    let actor = LuaActor::from_lua_state(state);
+    let owner = unsafe { (*actor).id };
+
+    match udp_bind(addr, owner) {
+        Ok(fd) => {
+            laux::lua_push(state, fd);
+            1
+        }
+        Err(err) => crate::lua_push_error(state, &format!("udp_bind '{}' failed: {}", addr, err)),
+    }
+}
+
+extern "C-unwind" fn lua_udp_sendto(state: LuaState) -> c_int {
+    let fd: i64 = laux::lua_get(state, 1);
+    let peer = if matches!(laux::lua_type(state, 2), LuaType::None | LuaType::Nil) {
+        None
+    } else {
+        let addr = unsafe { laux::lua_check_str(state, 2) };
+        match parse_peer(addr) {
+            Ok(peer) => Some(peer),
+            Err(err) => {
+                return crate::lua_push_error(state, &format!("udp_sendto '{}': {}", addr, err));
+            }
+        }
+    };
+    let data = check_arc_buffer(state, 3);
+
+    if data.len() > MAX_UDP_DATAGRAM_SIZE {
+        return crate::lua_push_error(
+            state,
+            &format!(
+                "udp_sendto: datagram of {} bytes exceeds limit of {} bytes",
+                data.len(),
+                MAX_UDP_DATAGRAM_SIZE
+            ),
+        );
+    }
+
+    if let Some(channel) = NET.get(&fd) {
+        match channel.value().1.try_send(NetOp::SendTo(data, peer)) {
+            Ok(_) => {
+                laux::lua_push(state, true);
+                1
+            }
+            Err(err) => crate::lua_push_error(
+                state,
+                &format!("udp_sendto: channel full (fd={}): {}", fd, err),
+            ),
+        }
+    } else {
+        crate::lua_push_error(state, &format!("udp_sendto: fd {} not found", fd))
+    }
+}
+
+extern "C-unwind" fn lua_udp_connect(state: LuaState) -> c_int {
+    let fd: i64 = laux::lua_get(state, 1);
+    let peer: String = laux::lua_get(state, 2);
+
+    if let Some(channel) = NET.get(&fd) {
+        let actor = LuaActor::from_lua_state(state);
+        let owner = unsafe { (*actor).id };
+        let session = unsafe { (*actor).next_session() };
+        if let Err(err) = channel
+            .value()
+            .1
+            .try_send(NetOp::UdpConnect(owner, session, peer))
+        {
+            CONTEXT.response_error(
+                0,
+                owner,
+                -session,
+                format!("udp_connect: channel full (fd={}): {}", fd, err),
+            );
+        }
+        laux::lua_push(state, session);
+        1
+    } else {
+        crate::lua_push_error(state, &format!("udp_connect: fd {} not found", fd))
+    }
+}
+
 extern "C-unwind" fn lua_host(state: LuaState) -> c_int {
     if let Ok(addr) = laux::lua_opt(state, 1).unwrap_or("1.1.1.1:80").parse()
         && let Ok(socket) = TcpStream::connect_timeout(&addr, Duration::from_millis(1000))
@@ -1174,6 +1589,32 @@ pub unsafe extern "C-unwind" fn decode_socket_event_message(
     }
 }
 
+pub unsafe extern "C-unwind" fn decode_udp_message(state: LuaState, m: *mut Message) -> c_int {
+    unsafe {
+        let body = (*m).take_body();
+        match body {
+            MessageBody::Boxed(_, mut boxed) => {
+                let ptr = boxed.into_raw();
+                if ptr.is_null() {
+                    return crate::lua_push_error(state, "boxed message payload already consumed");
+                }
+                let datagram = *Box::from_raw(ptr as *mut UdpDatagram);
+                laux::lua_push(state, datagram.fd);
+                laux::lua_push(state, datagram.data.as_slice());
+                laux::lua_push(state, datagram.from.to_string().as_str());
+                3
+            }
+            other => {
+                (*m).data = other;
+                crate::lua_push_error(
+                    state,
+                    &format!("unexpected udp message body for ptype {}", (*m).ptype()),
+                )
+            }
+        }
+    }
+}
+
 pub extern "C-unwind" fn luaopen_socket(state: LuaState) -> c_int {
     let l = [
         lreg!("listen", lua_socket_listen),
@@ -1185,6 +1626,10 @@ pub extern "C-unwind" fn luaopen_socket(state: LuaState) -> c_int {
         lreg!("connect", lua_socket_connect),
         lreg!("close", lua_socket_close),
         lreg!("host", lua_host),
+        lreg!("udp_bind", lua_udp_bind),
+        lreg!("udp_sendto", lua_udp_sendto),
+        lreg!("udp_connect", lua_udp_connect),
+        lreg!("udp_close", lua_socket_close),
         lreg_null!(),
     ];
 
@@ -1196,7 +1641,7 @@ pub extern "C-unwind" fn luaopen_socket(state: LuaState) -> c_int {
 mod tests {
     use super::*;
     use tokio::io::duplex;
-    use tokio::net::{TcpListener, TcpStream};
+    use tokio::net::{TcpListener, TcpStream, tcp::OwnedReadHalf};
 
     // -----------------------------------------------------------------------
     // read_one_frame tests
@@ -1618,4 +2063,194 @@ mod tests {
         assert_eq!(end_marker, 0u16.to_be_bytes());
         assert_eq!(writer.await.unwrap(), None);
     }
+
+    #[tokio::test]
+    async fn udp_delivers_datagrams_and_sends_replies() {
+        let owner = 0x8001_0100u32;
+        let (tx, mut inbox) = tokio::sync::mpsc::unbounded_channel();
+        CONTEXT.register_pseudo_actor(owner, tx);
+
+        let server = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let server_addr = server.local_addr().unwrap();
+        let client = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let client_addr = client.local_addr().unwrap();
+
+        let fd = next_net_fd();
+        let (op_tx, op_rx) = mpsc::channel::<NetOp>(8);
+        NET.insert(fd, NetChannel(op_tx.clone(), op_tx.clone()));
+        let handle = tokio::spawn(run_udp(server, owner, fd, op_rx));
+
+        client.send_to(b"ping", server_addr).await.unwrap();
+        let mut msg = inbox.recv().await.unwrap();
+        assert_eq!(msg.ptype(), context::PTYPE_SOCKET_UDP);
+        match msg.take_body() {
+            MessageBody::Boxed(_, mut boxed) => {
+                let datagram = unsafe { *Box::from_raw(boxed.into_raw() as *mut UdpDatagram) };
+                assert_eq!(datagram.fd, fd);
+                assert_eq!(datagram.data, b"ping");
+                assert_eq!(datagram.from, client_addr);
+            }
+            _ => panic!("expected boxed udp datagram"),
+        }
+
+        let reply = Arc::new(Buffer::from(&b"pong"[..]));
+        op_tx
+            .send(NetOp::SendTo(reply, Some(client_addr)))
+            .await
+            .unwrap();
+        let mut buf = [0u8; 16];
+        let (n, from) = client.recv_from(&mut buf).await.unwrap();
+        assert_eq!(&buf[..n], b"pong");
+        assert_eq!(from, server_addr);
+
+        op_tx.send(NetOp::Close()).await.unwrap();
+        handle.await.unwrap();
+        assert!(NET.get(&fd).is_none());
+    }
+
+    #[test]
+    fn parse_peer_accepts_only_literal_addresses() {
+        let addr = parse_peer("127.0.0.1:9000").unwrap();
+        assert_eq!(addr.port(), 9000);
+        assert!(parse_peer("[::1]:9000").is_ok());
+        assert!(parse_peer("localhost:9000").is_err());
+        assert!(parse_peer("not an address").is_err());
+    }
+
+    const TLS_FIXTURES: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/../../assets/test/tls/");
+
+    fn tls_fixture(name: &str) -> String {
+        format!("{}{}", TLS_FIXTURES, name)
+    }
+
+    #[tokio::test]
+    async fn tls_connection_reads_and_writes_through_net_ops() {
+        let owner = 0x8001_0200u32;
+        let (tx, mut inbox) = tokio::sync::mpsc::unbounded_channel();
+        CONTEXT.register_pseudo_actor(owner, tx);
+
+        let acceptor = tls_acceptor(
+            tls_fixture("server.pem").as_bytes(),
+            tls_fixture("server.key").as_bytes(),
+        )
+        .unwrap();
+        let ca = std::fs::read(tls_fixture("ca.pem")).unwrap();
+        let connector = tls_connector(Some(&ca)).unwrap();
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let client = TcpStream::connect(addr).await.unwrap();
+        let (server, _) = listener.accept().await.unwrap();
+
+        let fd = next_net_fd();
+        let (rx_reader, rx_writer) = setup_net_channel(fd);
+        let handle = tokio::spawn(accept_tls(
+            server, acceptor, owner, 7, fd, rx_reader, rx_writer,
+        ));
+
+        let sni = ServerName::try_from("localhost").unwrap();
+        let mut client = connector.connect(sni, client).await.unwrap();
+
+        // Announced once the handshake is done.
+        let mut msg = inbox.recv().await.unwrap();
+        match msg.take_body() {
+            MessageBody::Boxed(_, mut boxed) => {
+                let event = unsafe { *Box::from_raw(boxed.into_raw() as *mut SocketEvent) };
+                assert!(matches!(event, SocketEvent::Accept(7, accepted, _) if accepted == fd));
+            }
+            _ => panic!("expected boxed socket event"),
+        }
+
+        client.write_all(b"hello").await.unwrap();
+        let reader = NET.get(&fd).unwrap().0.clone();
+        reader.send(NetOp::ReadBytes(owner, 1, 5, 0)).await.unwrap();
+        let msg = inbox.recv().await.unwrap();
+        match msg.data {
+            MessageBody::Buffer(ptype, buf) => {
+                assert_eq!(ptype, context::PTYPE_SOCKET_TCP);
+                assert_eq!(buf.as_slice(), b"hello");
+            }
+            _ => panic!("expected buffer body"),
+        }
+
+        let writer = NET.get(&fd).unwrap().1.clone();
+        let data = Arc::new(Buffer::from(&b"world"[..]));
+        writer.send(NetOp::Write(owner, data, true)).await.unwrap();
+        let mut reply = Vec::new();
+        client.read_to_end(&mut reply).await.unwrap();
+        assert_eq!(reply, b"world");
+
+        assert!(handle.await.unwrap());
+        assert!(NET.get(&fd).is_none());
+    }
+
+    #[tokio::test]
+    async fn tls_handshake_failure_is_not_announced() {
+        let owner = 0x8001_0201u32;
+        let (tx, mut inbox) = tokio::sync::mpsc::unbounded_channel();
+        CONTEXT.register_pseudo_actor(owner, tx);
+
+        let acceptor = tls_acceptor(
+            tls_fixture("server.pem").as_bytes(),
+            tls_fixture("server.key").as_bytes(),
+        )
+        .unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let mut client = TcpStream::connect(addr).await.unwrap();
+        let (server, _) = listener.accept().await.unwrap();
+
+        let fd = next_net_fd();
+        let (rx_reader, rx_writer) = setup_net_channel(fd);
+        let handle = tokio::spawn(accept_tls(
+            server, acceptor, owner, 7, fd, rx_reader, rx_writer,
+        ));
+        client.write_all(b"GET / HTTP/1.1\r\n\r\n").await.unwrap();
+        assert!(handle.await.unwrap());
+
+        assert!(inbox.try_recv().is_err());
+        assert!(NET.get(&fd).is_none());
+    }
+
+    #[tokio::test]
+    async fn tls_accept_reports_a_dead_owner() {
+        let owner = 0x8001_0202u32;
+        let acceptor = tls_acceptor(
+            tls_fixture("server.pem").as_bytes(),
+            tls_fixture("server.key").as_bytes(),
+        )
+        .unwrap();
+        let ca = std::fs::read(tls_fixture("ca.pem")).unwrap();
+        let connector = tls_connector(Some(&ca)).unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let client = TcpStream::connect(addr).await.unwrap();
+        let (server, _) = listener.accept().await.unwrap();
+
+        let fd = next_net_fd();
+        let (rx_reader, rx_writer) = setup_net_channel(fd);
+        let handle = tokio::spawn(accept_tls(
+            server, acceptor, owner, 7, fd, rx_reader, rx_writer,
+        ));
+        let sni = ServerName::try_from("localhost").unwrap();
+        let _client = connector.connect(sni, client).await.unwrap();
+
+        assert!(!handle.await.unwrap());
+        assert!(NET.get(&fd).is_none());
+    }
+
+    #[test]
+    fn tls_config_rejects_missing_or_invalid_pem() {
+        assert!(tls_acceptor(b"/nonexistent/cert.pem", b"/nonexistent/key.pem").is_err());
+        assert!(
+            tls_acceptor(
+                b"-----BEGIN CERTIFICATE-----\n-----END CERTIFICATE-----\n",
+                b""
+            )
+            .is_err()
+        );
+        assert!(tls_connector(None).is_ok());
+        assert_eq!(host_of("example.com:443"), "example.com");
+        assert_eq!(host_of("[::1]:443"), "::1");
+    }
 }
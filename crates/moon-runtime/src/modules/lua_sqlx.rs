@@ -1160,20 +1160,16 @@ fn push_sqlx_response(state: LuaState, result: DatabaseResponse) -> c_int {
     }
 }
 
+/// Per-connection request counters, for `stats()` and the `/metrics` export.
+pub(crate) fn pool_stats() -> Vec<(String, crate::request_pool::PoolStats)> {
+    DATABASE_CONNECTIONS
+        .iter()
+        .map(|pair| (pair.key().clone(), crate::request_pool::PoolStats::from_counter(&pair.value().counter)))
+        .collect()
+}
+
 extern "C-unwind" fn stats(state: LuaState) -> c_int {
-    let table = LuaTable::new(state, 0, DATABASE_CONNECTIONS.len());
-    DATABASE_CONNECTIONS.iter().for_each(|pair| {
-        let counter = &pair.value().counter;
-        table.rawset_x(pair.key().as_str(), || {
-            crate::request_pool::push_pool_stats(
-                state,
-                counter.load(),
-                counter.total(),
-                counter.peak(),
-                1,
-            );
-        });
-    });
+    crate::request_pool::push_pool_stats(state, pool_stats());
     1
 }
 
@@ -0,0 +1,225 @@
+//! Sampling CPU profiler for Lua services.
+//!
+//! `moon.profile.start(id, hz)` starts a sampler thread that, `hz` times per
+//! second, asks the service for a sample while it is inside a dispatch. Like
+//! the watchdog's interrupt, the request installs a count hook on the running
+//! `lua_State`; the hook then walks the Lua stack on the service's own thread
+//! and records it here. An idle service is not sampled, so the profile shows
+//! where dispatch time goes.
+//!
+//! The hook runs at the next VM instruction, so time spent in a C function is
+//! counted against the Lua function that called it, once the C function
+//! returns.
+//!
+//! Stacks are kept in the folded format (`outer;inner;leaf count`) read by
+//! flamegraph tools, and [`Profile::flamegraph`] renders an SVG.
+
+use std::{
+    collections::HashMap,
+    sync::{
+        Mutex,
+        atomic::{AtomicBool, AtomicU64, Ordering},
+    },
+};
+
+pub const DEFAULT_HZ: u32 = 100;
+pub const MAX_HZ: u32 = 1000;
+
+/// Frames kept per sample; deeper stacks lose their outermost frames.
+pub const MAX_DEPTH: usize = 128;
+
+/// Profiling state of one service, shared by the sampler thread and the hook.
+#[derive(Default)]
+pub struct Profiler {
+    /// Current profiling run, 0 when stopped. A sampler thread exits once the
+    /// run it was started for is over.
+    run: AtomicU64,
+    next_run: AtomicU64,
+    /// A sample was requested and the hook has not taken it yet.
+    pending: AtomicBool,
+    stacks: Mutex<Stacks>,
+}
+
+#[derive(Default)]
+struct Stacks {
+    hz: u32,
+    samples: u64,
+    folded: HashMap<String, u64>,
+}
+
+impl Profiler {
+    /// Start a run sampling at `hz`. Returns the run, for the sampler thread.
+    pub fn start(&self, hz: u32) -> Result<u64, String> {
+        if hz == 0 || hz > MAX_HZ {
+            return Err(format!("profile hz must be 1..={}, got {}", MAX_HZ, hz));
+        }
+        let run = self.next_run.fetch_add(1, Ordering::Relaxed) + 1;
+        if self
+            .run
+            .compare_exchange(0, run, Ordering::AcqRel, Ordering::Acquire)
+            .is_err()
+        {
+            return Err("already being profiled".to_string());
+        }
+        if let Ok(mut stacks) = self.stacks.lock() {
+            *stacks = Stacks {
+                hz,
+                ..Default::default()
+            };
+        }
+        Ok(run)
+    }
+
+    /// Whether `run` is still the current run.
+    pub fn is_current(&self, run: u64) -> bool {
+        self.run.load(Ordering::Acquire) == run
+    }
+
+    /// End the current run and hand over what it sampled.
+    pub fn stop(&self) -> Option<Profile> {
+        if self.run.swap(0, Ordering::AcqRel) == 0 {
+            return None;
+        }
+        self.pending.store(false, Ordering::Release);
+        let stacks = std::mem::take(&mut *self.stacks.lock().ok()?);
+        let mut folded: Vec<(String, u64)> = stacks.folded.into_iter().collect();
+        folded.sort_unstable();
+        Some(Profile {
+            hz: stacks.hz,
+            samples: stacks.samples,
+            stacks: folded,
+        })
+    }
+
+    pub fn request(&self) {
+        self.pending.store(true, Ordering::Release);
+    }
+
+    pub fn is_pending(&self) -> bool {
+        self.pending.load(Ordering::Acquire)
+    }
+
+    /// Claim the pending sample, if any. Called by the hook.
+    pub fn take_request(&self) -> bool {
+        self.pending.swap(false, Ordering::AcqRel)
+    }
+
+    /// Count one sample of `frames`, outermost first.
+    pub fn record<I>(&self, frames: I)
+    where
+        I: IntoIterator,
+        I::Item: AsRef<str>,
+    {
+        if self.run.load(Ordering::Acquire) == 0 {
+            return;
+        }
+        let mut stack = String::new();
+        for frame in frames {
+            if !stack.is_empty() {
+                stack.push(';');
+            }
+            // `;` separates frames and a line holds one stack.
+            stack.extend(frame.as_ref().chars().map(|c| match c {
+                ';' => ':',
+                '\n' | '\r' => ' ',
+                c => c,
+            }));
+        }
+        if stack.is_empty() {
+            return;
+        }
+        if let Ok(mut stacks) = self.stacks.lock() {
+            stacks.samples += 1;
+            *stacks.folded.entry(stack).or_insert(0) += 1;
+        }
+    }
+}
+
+/// The result of one profiling run.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Profile {
+    pub hz: u32,
+    pub samples: u64,
+    /// Folded stacks and their sample counts, sorted by stack.
+    pub stacks: Vec<(String, u64)>,
+}
+
+impl Profile {
+    /// One `stack count` line per stack.
+    pub fn folded(&self) -> String {
+        let mut out = String::new();
+        for (stack, count) in &self.stacks {
+            out.push_str(stack);
+            out.push(' ');
+            out.push_str(&count.to_string());
+            out.push('\n');
+        }
+        out
+    }
+
+    /// Render the stacks as an SVG flamegraph.
+    pub fn flamegraph(&self, title: &str) -> Result<Vec<u8>, String> {
+        let mut options = inferno::flamegraph::Options::default();
+        options.title = title.to_string();
+        options.subtitle = Some(format!("{} samples at {} Hz", self.samples, self.hz));
+        options.count_name = "samples".to_string();
+        let folded = self.folded();
+        let mut svg = Vec::new();
+        inferno::flamegraph::from_lines(&mut options, folded.lines(), &mut svg)
+            .map_err(|err| err.to_string())?;
+        Ok(svg)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn records_folded_stacks_of_one_run() {
+        let p = Profiler::default();
+        p.record(["main"]);
+        assert!(p.stop().is_none(), "nothing recorded while stopped");
+
+        assert!(p.start(0).is_err());
+        let run = p.start(100).unwrap();
+        assert!(p.start(100).is_err(), "one run at a time");
+        for _ in 0..3 {
+            p.record(["dispatch (moon.lua:10)", "hit (battle.lua:5)"]);
+        }
+        p.record(["dispatch (moon.lua:10)", "a;b\nc"]);
+        p.record(Vec::<String>::new());
+
+        p.request();
+        assert!(p.take_request());
+        assert!(!p.take_request());
+
+        let profile = p.stop().unwrap();
+        assert!(!p.is_current(run));
+        assert_eq!(profile.samples, 4);
+        assert_eq!(
+            profile.folded(),
+            "dispatch (moon.lua:10);a:b c 1\ndispatch (moon.lua:10);hit (battle.lua:5) 3\n"
+        );
+
+        let next = p.start(10).unwrap();
+        assert_ne!(next, run);
+        assert_eq!(p.stop().unwrap().samples, 0, "a new run starts empty");
+    }
+
+    #[test]
+    fn renders_flamegraph() {
+        let profile = Profile {
+            hz: 100,
+            samples: 5,
+            stacks: vec![
+                ("main;update".to_string(), 3),
+                ("main;update;move".to_string(), 2),
+            ],
+        };
+        let svg = String::from_utf8(profile.flamegraph("battle").unwrap()).unwrap();
+        assert!(svg.starts_with("<?xml"));
+        assert!(svg.contains("battle"));
+        assert!(svg.contains("move"));
+    }
+}
@@ -0,0 +1,204 @@
+//! In-process topic bus.
+//!
+//! Topics are dot-separated segments, e.g. `room.12.chat`. A subscription can
+//! be an exact topic or a pattern where `*` matches one segment and `#` (last
+//! segment only) matches zero or more trailing segments: `room.*.chat`,
+//! `room.#`. `LuaActorServer::publish` looks up every subscriber once and
+//! delivers the same packed payload to each; an actor whose subscriptions
+//! overlap still receives one copy and sorts out its handlers in Lua.
+
+use std::collections::{BTreeSet, HashMap, HashSet};
+
+use crate::context::ActorId;
+
+/// Why a topic or pattern was rejected.
+pub fn check_topic(topic: &str, allow_wildcard: bool) -> Result<(), String> {
+    if topic.is_empty() {
+        return Err("empty topic".to_string());
+    }
+    let mut segments = topic.split('.').peekable();
+    while let Some(seg) = segments.next() {
+        if seg.is_empty() {
+            return Err(format!("topic '{}' has an empty segment", topic));
+        }
+        let wildcard = seg == "*" || seg == "#";
+        if !wildcard && (seg.contains('*') || seg.contains('#')) {
+            return Err(format!(
+                "topic '{}': wildcards must be whole segments",
+                topic
+            ));
+        }
+        if wildcard && !allow_wildcard {
+            return Err(format!("cannot publish to pattern '{}'", topic));
+        }
+        if seg == "#" && segments.peek().is_some() {
+            return Err(format!("topic '{}': '#' must be the last segment", topic));
+        }
+    }
+    Ok(())
+}
+
+fn is_pattern(topic: &str) -> bool {
+    topic.split('.').any(|seg| seg == "*" || seg == "#")
+}
+
+/// Whether `topic` matches the subscription `pattern`.
+pub fn topic_match(pattern: &str, topic: &str) -> bool {
+    let mut p = pattern.split('.');
+    let mut t = topic.split('.');
+    loop {
+        match (p.next(), t.next()) {
+            (Some("#"), _) => return true,
+            (Some("*"), Some(_)) => {}
+            (Some(a), Some(b)) if a == b => {}
+            (None, None) => return true,
+            _ => return false,
+        }
+    }
+}
+
+/// Subscriptions of all actors, kept by `LuaActorServer` behind a lock.
+#[derive(Default)]
+pub struct TopicTable {
+    exact: HashMap<String, BTreeSet<ActorId>>,
+    patterns: HashMap<String, BTreeSet<ActorId>>,
+    by_actor: HashMap<ActorId, HashSet<String>>,
+}
+
+impl TopicTable {
+    /// Returns false if `id` was already subscribed to `topic`.
+    pub fn subscribe(&mut self, id: ActorId, topic: &str) -> bool {
+        let map = if is_pattern(topic) {
+            &mut self.patterns
+        } else {
+            &mut self.exact
+        };
+        if !map.entry(topic.to_string()).or_default().insert(id) {
+            return false;
+        }
+        self.by_actor
+            .entry(id)
+            .or_default()
+            .insert(topic.to_string());
+        true
+    }
+
+    /// Returns false if `id` was not subscribed to `topic`.
+    pub fn unsubscribe(&mut self, id: ActorId, topic: &str) -> bool {
+        let map = if is_pattern(topic) {
+            &mut self.patterns
+        } else {
+            &mut self.exact
+        };
+        let Some(ids) = map.get_mut(topic) else {
+            return false;
+        };
+        if !ids.remove(&id) {
+            return false;
+        }
+        if ids.is_empty() {
+            map.remove(topic);
+        }
+        if let Some(topics) = self.by_actor.get_mut(&id) {
+            topics.remove(topic);
+            if topics.is_empty() {
+                self.by_actor.remove(&id);
+            }
+        }
+        true
+    }
+
+    /// Drop every subscription of `id`, when the actor is removed.
+    pub fn unsubscribe_all(&mut self, id: ActorId) {
+        for topic in self.by_actor.remove(&id).unwrap_or_default() {
+            let map = if is_pattern(&topic) {
+                &mut self.patterns
+            } else {
+                &mut self.exact
+            };
+            if let Some(ids) = map.get_mut(&topic) {
+                ids.remove(&id);
+                if ids.is_empty() {
+                    map.remove(&topic);
+                }
+            }
+        }
+    }
+
+    /// Every actor subscribed to `topic` directly or through a pattern, once.
+    pub fn subscribers(&self, topic: &str) -> Vec<ActorId> {
+        let mut ids: Vec<ActorId> = self
+            .exact
+            .get(topic)
+            .map(|ids| ids.iter().copied().collect())
+            .unwrap_or_default();
+        let direct = ids.len();
+        for (pattern, subs) in &self.patterns {
+            if topic_match(pattern, topic) {
+                ids.extend(subs.iter().copied());
+            }
+        }
+        if ids.len() > direct {
+            ids.sort_unstable();
+            ids.dedup();
+        }
+        ids
+    }
+
+    /// Number of distinct topics and patterns with at least one subscriber.
+    pub fn topic_count(&self) -> usize {
+        self.exact.len() + self.patterns.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn wildcard_matching() {
+        assert!(topic_match("room.1.chat", "room.1.chat"));
+        assert!(!topic_match("room.1.chat", "room.2.chat"));
+        assert!(topic_match("room.*.chat", "room.2.chat"));
+        assert!(!topic_match("room.*.chat", "room.chat"));
+        assert!(!topic_match("room.*", "room.2.chat"));
+        assert!(topic_match("room.#", "room"));
+        assert!(topic_match("room.#", "room.2.chat"));
+        assert!(topic_match("#", "anything.at.all"));
+        assert!(!topic_match("room.#", "lobby.1"));
+    }
+
+    #[test]
+    fn topics_are_validated() {
+        assert!(check_topic("room.1", false).is_ok());
+        assert!(check_topic("room.*.chat", true).is_ok());
+        assert!(check_topic("room.#", true).is_ok());
+        assert!(check_topic("", true).is_err());
+        assert!(check_topic("room..chat", true).is_err());
+        assert!(check_topic("room.a*", true).is_err());
+        assert!(check_topic("room.#.chat", true).is_err());
+        assert!(check_topic("room.*", false).is_err());
+    }
+
+    #[test]
+    fn subscribers_are_deduplicated_and_cleaned_up() {
+        let mut t = TopicTable::default();
+        assert!(t.subscribe(1, "room.1.chat"));
+        assert!(!t.subscribe(1, "room.1.chat"));
+        assert!(t.subscribe(1, "room.#"));
+        assert!(t.subscribe(2, "room.*.chat"));
+        assert!(t.subscribe(3, "lobby"));
+        assert_eq!(t.subscribers("room.1.chat"), vec![1, 2]);
+        assert_eq!(t.subscribers("room.9"), vec![1]);
+        assert_eq!(t.topic_count(), 4);
+
+        assert!(t.unsubscribe(2, "room.*.chat"));
+        assert!(!t.unsubscribe(2, "room.*.chat"));
+        assert_eq!(t.subscribers("room.1.chat"), vec![1]);
+
+        t.unsubscribe_all(1);
+        assert!(t.subscribers("room.1.chat").is_empty());
+        assert_eq!(t.topic_count(), 1);
+        assert!(!t.by_actor.contains_key(&1));
+    }
+}
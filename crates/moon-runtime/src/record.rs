@@ -0,0 +1,323 @@
+//! Message recording and replay.
+//!
+//! A service created with `record = "path"` writes every message it handles
+//! to a binary file: a header describing the service, then one record per
+//! message with the sender, session, body and both runtime clocks at the
+//! moment of dispatch. `moon_rs --replay path service.lua` starts the service
+//! alone with the recorded id, params and random seeds, and feeds the records
+//! back while the clocks read the recorded values, so the dispatch sequence
+//! can be reproduced (and stepped through) offline.
+//!
+//! Bodies that are Rust objects (`MessageBody::Boxed`, e.g. database and HTTP
+//! client responses) have no byte form; they are recorded as opaque and
+//! skipped on replay.
+//!
+//! Layout, all integers little-endian, strings as `u32` length + bytes:
+//!
+//! ```text
+//! header:  "MOONREC1" id:u32 name:str unique:u8 params:str seed:u64 hash_seed:u32
+//!          now_ms:i64 clock_us:u64
+//! message: kind:u8 ptype:u8 from:u32 to:u32 session:i64 now_ms:i64 clock_us:u64 body
+//! body:    kind 0 (none) | 1 (integer) i64 | 2 (bytes) bytes:str | 3 (opaque)
+//! ```
+
+use std::{
+    fs::File,
+    io::{self, BufReader, BufWriter, ErrorKind, Read, Write},
+    path::Path,
+};
+
+use crate::{
+    buffer::Buffer,
+    context::{ActorId, Message, MessageBody},
+};
+
+const MAGIC: &[u8; 8] = b"MOONREC1";
+
+const BODY_NONE: u8 = 0;
+const BODY_ISIZE: u8 = 1;
+const BODY_BYTES: u8 = 2;
+const BODY_OPAQUE: u8 = 3;
+
+/// The recorded service and the clocks when it started.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RecordHeader {
+    pub id: ActorId,
+    pub name: String,
+    pub unique: bool,
+    /// Service params without the `PATH` prefix, which belongs to the host.
+    pub params: String,
+    /// Seeds `math.random`.
+    pub seed: u64,
+    /// The process-wide string hash seed of every Lua state, which decides
+    /// the iteration order of string keyed tables.
+    pub hash_seed: u32,
+    pub now_ms: i64,
+    pub clock_us: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RecordedBody {
+    None,
+    ISize(isize),
+    Bytes(Vec<u8>),
+    /// A `Boxed` body; not replayable.
+    Opaque,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RecordedMessage {
+    pub ptype: u8,
+    pub from: ActorId,
+    pub to: ActorId,
+    pub session: i64,
+    pub now_ms: i64,
+    pub clock_us: u64,
+    pub body: RecordedBody,
+}
+
+impl RecordedMessage {
+    /// The message to dispatch, `None` for an opaque body.
+    pub fn to_message(&self) -> Option<Message> {
+        let data = match &self.body {
+            RecordedBody::None => MessageBody::None(self.ptype),
+            RecordedBody::ISize(v) => MessageBody::ISize(self.ptype, *v),
+            RecordedBody::Bytes(bytes) => {
+                MessageBody::Buffer(self.ptype, Box::new(Buffer::from_slice(bytes)))
+            }
+            RecordedBody::Opaque => return None,
+        };
+        Some(Message::new(self.from, self.to, self.session, data))
+    }
+}
+
+fn write_str(out: &mut impl Write, s: &[u8]) -> io::Result<()> {
+    let len = u32::try_from(s.len()).map_err(|_| io::Error::other("record field too large"))?;
+    out.write_all(&len.to_le_bytes())?;
+    out.write_all(s)
+}
+
+fn read_array<const N: usize>(input: &mut impl Read) -> io::Result<[u8; N]> {
+    let mut buf = [0u8; N];
+    input.read_exact(&mut buf)?;
+    Ok(buf)
+}
+
+fn read_u32(input: &mut impl Read) -> io::Result<u32> {
+    read_array(input).map(u32::from_le_bytes)
+}
+
+fn read_u64(input: &mut impl Read) -> io::Result<u64> {
+    read_array(input).map(u64::from_le_bytes)
+}
+
+fn read_i64(input: &mut impl Read) -> io::Result<i64> {
+    read_array(input).map(i64::from_le_bytes)
+}
+
+fn read_bytes(input: &mut impl Read) -> io::Result<Vec<u8>> {
+    let len = read_u32(input)? as usize;
+    let mut buf = vec![0u8; len];
+    input.read_exact(&mut buf)?;
+    Ok(buf)
+}
+
+fn read_string(input: &mut impl Read) -> io::Result<String> {
+    String::from_utf8(read_bytes(input)?).map_err(|e| io::Error::new(ErrorKind::InvalidData, e))
+}
+
+/// Appends the messages of one service to a recording.
+pub struct Recorder {
+    out: BufWriter<File>,
+}
+
+impl Recorder {
+    pub fn create(path: impl AsRef<Path>, header: &RecordHeader) -> io::Result<Self> {
+        let mut out = BufWriter::new(File::create(path)?);
+        out.write_all(MAGIC)?;
+        out.write_all(&header.id.to_le_bytes())?;
+        write_str(&mut out, header.name.as_bytes())?;
+        out.write_all(&[header.unique as u8])?;
+        write_str(&mut out, header.params.as_bytes())?;
+        out.write_all(&header.seed.to_le_bytes())?;
+        out.write_all(&header.hash_seed.to_le_bytes())?;
+        out.write_all(&header.now_ms.to_le_bytes())?;
+        out.write_all(&header.clock_us.to_le_bytes())?;
+        out.flush()?;
+        Ok(Recorder { out })
+    }
+
+    /// Append `m` as dispatched at `now_ms` / `clock_us`. Flushed right away
+    /// so the recording survives a crash of the process.
+    pub fn write(&mut self, m: &Message, now_ms: i64, clock_us: u64) -> io::Result<()> {
+        let (kind, bytes): (u8, Option<&[u8]>) = match &m.data {
+            MessageBody::None(_) => (BODY_NONE, None),
+            MessageBody::ISize(..) => (BODY_ISIZE, None),
+            MessageBody::Buffer(_, buf) => (BODY_BYTES, Some(buf.as_slice())),
+            MessageBody::Shared(_, buf) => (BODY_BYTES, Some(buf.as_slice())),
+            MessageBody::Boxed(..) => (BODY_OPAQUE, None),
+        };
+        let out = &mut self.out;
+        out.write_all(&[kind, m.ptype()])?;
+        out.write_all(&m.from.to_le_bytes())?;
+        out.write_all(&m.to.to_le_bytes())?;
+        out.write_all(&m.session.to_le_bytes())?;
+        out.write_all(&now_ms.to_le_bytes())?;
+        out.write_all(&clock_us.to_le_bytes())?;
+        if let MessageBody::ISize(_, v) = &m.data {
+            out.write_all(&(*v as i64).to_le_bytes())?;
+        }
+        if let Some(bytes) = bytes {
+            write_str(out, bytes)?;
+        }
+        out.flush()
+    }
+}
+
+/// Reads a recording back: the header, then the messages in order.
+pub struct Replay {
+    input: BufReader<File>,
+    pub header: RecordHeader,
+}
+
+impl Replay {
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        let mut input = BufReader::new(File::open(path)?);
+        if &read_array::<8>(&mut input)? != MAGIC {
+            return Err(io::Error::new(
+                ErrorKind::InvalidData,
+                "not a moon_rs recording",
+            ));
+        }
+        let header = RecordHeader {
+            id: read_u32(&mut input)?,
+            name: read_string(&mut input)?,
+            unique: read_array::<1>(&mut input)?[0] != 0,
+            params: read_string(&mut input)?,
+            seed: read_u64(&mut input)?,
+            hash_seed: read_u32(&mut input)?,
+            now_ms: read_i64(&mut input)?,
+            clock_us: read_u64(&mut input)?,
+        };
+        Ok(Replay { input, header })
+    }
+
+    fn read_message(&mut self, kind: u8) -> io::Result<RecordedMessage> {
+        let input = &mut self.input;
+        let ptype = read_array::<1>(input)?[0];
+        let from = read_u32(input)?;
+        let to = read_u32(input)?;
+        let session = read_i64(input)?;
+        let now_ms = read_i64(input)?;
+        let clock_us = read_u64(input)?;
+        let body = match kind {
+            BODY_NONE => RecordedBody::None,
+            BODY_ISIZE => RecordedBody::ISize(read_i64(input)? as isize),
+            BODY_BYTES => RecordedBody::Bytes(read_bytes(input)?),
+            BODY_OPAQUE => RecordedBody::Opaque,
+            _ => {
+                return Err(io::Error::new(
+                    ErrorKind::InvalidData,
+                    format!("unknown record kind {}", kind),
+                ));
+            }
+        };
+        Ok(RecordedMessage {
+            ptype,
+            from,
+            to,
+            session,
+            now_ms,
+            clock_us,
+            body,
+        })
+    }
+}
+
+impl Iterator for Replay {
+    type Item = io::Result<RecordedMessage>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut kind = [0u8; 1];
+        match self.input.read(&mut kind) {
+            Ok(0) => None,
+            Ok(_) => Some(self.read_message(kind[0])),
+            Err(err) => Some(Err(err)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::context::{BoxedValue, PTYPE_HTTPC, PTYPE_LUA, PTYPE_QUIT, PTYPE_TIMER};
+    use std::sync::Arc;
+
+    fn message(data: MessageBody) -> Message {
+        Message {
+            from: 7,
+            to: 9,
+            session: -3,
+            data,
+            enqueue_us: 123,
+        }
+    }
+
+    #[test]
+    fn recording_round_trips() {
+        let path = std::env::temp_dir().join(format!("moon_record_{}.rec", std::process::id()));
+        let header = RecordHeader {
+            id: 9,
+            name: "battle".to_string(),
+            unique: true,
+            params: "return {role='x'}".to_string(),
+            seed: 0xdead_beef,
+            hash_seed: 77,
+            now_ms: 1_700_000_000_000,
+            clock_us: 42,
+        };
+        let mut rec = Recorder::create(&path, &header).unwrap();
+        let shared = Arc::new(Buffer::from("shared"));
+        let bodies = [
+            MessageBody::Buffer(PTYPE_LUA, Box::new(Buffer::from("hello"))),
+            MessageBody::Shared(PTYPE_LUA, shared),
+            MessageBody::ISize(PTYPE_TIMER, -5),
+            MessageBody::None(PTYPE_QUIT),
+            MessageBody::Boxed(PTYPE_HTTPC, Box::new(BoxedValue::new(1u8))),
+        ];
+        for (i, body) in bodies.into_iter().enumerate() {
+            rec.write(&message(body), 1000 + i as i64, 50 + i as u64)
+                .unwrap();
+        }
+        drop(rec);
+
+        let mut replay = Replay::open(&path).unwrap();
+        assert_eq!(replay.header, header);
+        let msgs: Vec<RecordedMessage> = replay.by_ref().map(|r| r.unwrap()).collect();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(msgs.len(), 5);
+        assert_eq!(msgs[0].body, RecordedBody::Bytes(b"hello".to_vec()));
+        assert_eq!((msgs[0].from, msgs[0].to, msgs[0].session), (7, 9, -3));
+        assert_eq!((msgs[0].now_ms, msgs[0].clock_us), (1000, 50));
+        assert_eq!(msgs[1].body, RecordedBody::Bytes(b"shared".to_vec()));
+        assert_eq!(msgs[2].body, RecordedBody::ISize(-5));
+        assert_eq!(msgs[3].body, RecordedBody::None);
+        assert_eq!(msgs[3].ptype, PTYPE_QUIT);
+        assert_eq!(msgs[4].body, RecordedBody::Opaque);
+        assert!(msgs[4].to_message().is_none());
+
+        let m = msgs[0].to_message().unwrap();
+        assert_eq!(m.ptype(), PTYPE_LUA);
+        assert!(matches!(&m.data, MessageBody::Buffer(_, b) if b.as_slice() == b"hello"));
+    }
+
+    #[test]
+    fn rejects_foreign_files() {
+        let path = std::env::temp_dir().join(format!("moon_record_bad_{}.rec", std::process::id()));
+        std::fs::write(&path, b"not a recording").unwrap();
+        let err = Replay::open(&path).err().unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+    }
+}
@@ -92,24 +92,52 @@ impl PendingCounter {
     }
 }
 
-/// Build a per-connection stats table and leave it on top of the Lua stack.
-///
-/// Shared by every DB-backed module's `stats()` so the shape stays consistent:
-/// `{ pending, total, peak, workers }`. For pooled drivers (redis/pg) the
-/// values are summed across workers, so `peak` is the sum of per-worker
-/// high-water marks (an upper bound on true simultaneous peak).
-pub(crate) fn push_pool_stats(
-    state: LuaState,
-    pending: i64,
-    total: i64,
-    peak: i64,
-    workers: i64,
-) {
-    let t = LuaTable::new(state, 0, 4);
-    t.insert("pending", pending);
-    t.insert("total", total);
-    t.insert("peak", peak);
-    t.insert("workers", workers);
+/// Snapshot of one named connection/pool, as reported by each DB-backed
+/// module's `pool_stats()`. For pooled drivers (redis/pg) the values are
+/// summed across workers, so `peak` is the sum of per-worker high-water marks
+/// (an upper bound on true simultaneous peak).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub(crate) struct PoolStats {
+    pub(crate) pending: i64,
+    pub(crate) total: i64,
+    pub(crate) peak: i64,
+    pub(crate) workers: i64,
+}
+
+impl PoolStats {
+    pub(crate) fn from_counter(counter: &PendingCounter) -> Self {
+        Self {
+            pending: counter.load(),
+            total: counter.total(),
+            peak: counter.peak(),
+            workers: 1,
+        }
+    }
+
+    pub(crate) fn from_workers<M>(pool: &WorkerSet<M>) -> Self {
+        Self {
+            pending: pool.pending(),
+            total: pool.total(),
+            peak: pool.peak(),
+            workers: pool.worker_count() as i64,
+        }
+    }
+}
+
+/// Build the `stats()` table `{ [name] = { pending, total, peak, workers } }`
+/// and leave it on top of the Lua stack. Shared by every DB-backed module so
+/// the shape stays consistent.
+pub(crate) fn push_pool_stats(state: LuaState, pools: Vec<(String, PoolStats)>) {
+    let table = LuaTable::new(state, 0, pools.len());
+    for (name, stats) in pools {
+        table.rawset_x(name.as_str(), || {
+            let t = LuaTable::new(state, 0, 4);
+            t.insert("pending", stats.pending);
+            t.insert("total", stats.total);
+            t.insert("peak", stats.peak);
+            t.insert("workers", stats.workers);
+        });
+    }
 }
 
 impl Default for PendingCounter {
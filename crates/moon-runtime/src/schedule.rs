@@ -0,0 +1,196 @@
+//! Fair scheduling of actor mailboxes.
+//!
+//! Shared (non-unique) actors are tokio tasks on a few worker threads. A task
+//! that keeps finding messages in its mailbox would never give its thread
+//! back, so each dispatch loop spends a [`Budget`]: after that many messages,
+//! or that much time, the task yields and the other tasks on the worker run.
+//!
+//! Within one mailbox, [`Lanes`] let runtime traffic overtake bulk traffic:
+//! with priority lanes enabled, responses, timers and system, debug and error
+//! messages are dispatched before messages already queued from clients and
+//! sockets. Each lane stays in arrival order, so a connection's packets, or a
+//! sender's requests, are never reordered among themselves. `PTYPE_QUIT` is
+//! bulk: a service stops after the work queued before the quit.
+
+use std::collections::VecDeque;
+
+use tokio::sync::mpsc;
+
+use crate::context::{
+    Message, PTYPE_DEBUG, PTYPE_ERROR, PTYPE_SHUTDOWN, PTYPE_SYSTEM, PTYPE_TIMER,
+};
+
+/// How long an actor task may run before yielding; 0 disables a limit.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Budget {
+    pub messages: u32,
+    pub ms: u64,
+}
+
+impl Default for Budget {
+    fn default() -> Self {
+        Budget {
+            messages: 128,
+            ms: 10,
+        }
+    }
+}
+
+/// Tracks what a dispatch loop spent since it last yielded.
+pub struct BudgetMeter {
+    budget: Budget,
+    used: u32,
+    since_ms: u64,
+}
+
+impl BudgetMeter {
+    pub fn new(budget: Budget, now_ms: u64) -> Self {
+        BudgetMeter {
+            budget,
+            used: 0,
+            since_ms: now_ms,
+        }
+    }
+
+    /// Count one dispatched message; true when the loop should yield now.
+    /// Starts the next budget when it returns true.
+    pub fn spend(&mut self, now_ms: u64) -> bool {
+        self.used += 1;
+        let Budget { messages, ms } = self.budget;
+        let exhausted = (messages > 0 && self.used >= messages)
+            || (ms > 0 && now_ms.saturating_sub(self.since_ms) >= ms);
+        if exhausted {
+            self.used = 0;
+            self.since_ms = now_ms;
+        }
+        exhausted
+    }
+}
+
+/// Messages that go ahead of bulk traffic in a mailbox with priority lanes.
+pub fn is_priority(m: &Message) -> bool {
+    m.session > 0
+        || matches!(
+            m.ptype(),
+            PTYPE_SYSTEM | PTYPE_DEBUG | PTYPE_ERROR | PTYPE_SHUTDOWN | PTYPE_TIMER
+        )
+}
+
+/// Messages taken from a mailbox and not dispatched yet.
+#[derive(Default)]
+pub struct Lanes {
+    priority_enabled: bool,
+    priority: VecDeque<Message>,
+    bulk: VecDeque<Message>,
+}
+
+impl Lanes {
+    pub fn new(priority_enabled: bool) -> Self {
+        Lanes {
+            priority_enabled,
+            ..Default::default()
+        }
+    }
+
+    pub fn push(&mut self, m: Message) {
+        if self.priority_enabled && is_priority(&m) {
+            self.priority.push_back(m);
+        } else {
+            self.bulk.push_back(m);
+        }
+    }
+
+    /// The next message to dispatch. With priority lanes, everything already
+    /// in `rx` is sorted into the lanes first so it can be overtaken.
+    pub fn next(&mut self, rx: &mut mpsc::UnboundedReceiver<Message>) -> Option<Message> {
+        if self.priority_enabled {
+            while let Ok(m) = rx.try_recv() {
+                self.push(m);
+            }
+        }
+        self.priority.pop_front().or_else(|| self.bulk.pop_front())
+    }
+
+    /// Remove every pending message, in dispatch order.
+    pub fn drain(&mut self) -> impl Iterator<Item = Message> + '_ {
+        self.priority.drain(..).chain(self.bulk.drain(..))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::context::{MessageBody, PTYPE_LUA, PTYPE_QUIT, PTYPE_SOCKET_TCP};
+
+    fn message(ptype: u8, session: i64) -> Message {
+        Message::new(1, 2, session, MessageBody::None(ptype))
+    }
+
+    #[test]
+    fn budget_counts_messages_and_time() {
+        let mut meter = BudgetMeter::new(
+            Budget {
+                messages: 3,
+                ms: 10,
+            },
+            100,
+        );
+        assert!(!meter.spend(100));
+        assert!(!meter.spend(101));
+        assert!(meter.spend(102), "third message");
+        assert!(!meter.spend(103), "a new budget started");
+        assert!(meter.spend(112), "10ms since the last yield");
+
+        let mut unlimited = BudgetMeter::new(Budget { messages: 0, ms: 0 }, 0);
+        assert!((0..1000).all(|i| !unlimited.spend(i * 1000)));
+    }
+
+    #[test]
+    fn priority_lanes_overtake_bulk_in_order() {
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let sent = [
+            message(PTYPE_SOCKET_TCP, 0),
+            message(PTYPE_LUA, -1),
+            message(PTYPE_LUA, 5),
+            message(PTYPE_SOCKET_TCP, 0),
+            message(PTYPE_TIMER, 0),
+            message(PTYPE_QUIT, 0),
+        ];
+        for m in sent.iter() {
+            tx.send(message(m.ptype(), m.session)).unwrap();
+        }
+
+        let mut lanes = Lanes::new(true);
+        let order: Vec<(u8, i64)> = std::iter::from_fn(|| lanes.next(&mut rx))
+            .map(|m| (m.ptype(), m.session))
+            .collect();
+        assert_eq!(
+            order,
+            vec![
+                (PTYPE_LUA, 5),
+                (PTYPE_TIMER, 0),
+                (PTYPE_SOCKET_TCP, 0),
+                (PTYPE_LUA, -1),
+                (PTYPE_SOCKET_TCP, 0),
+                (PTYPE_QUIT, 0),
+            ]
+        );
+
+        let mut fifo = Lanes::new(false);
+        for m in sent {
+            fifo.push(m);
+        }
+        let order: Vec<u8> = fifo.drain().map(|m| m.ptype()).collect();
+        assert_eq!(
+            order,
+            vec![
+                PTYPE_SOCKET_TCP,
+                PTYPE_LUA,
+                PTYPE_LUA,
+                PTYPE_SOCKET_TCP,
+                PTYPE_TIMER,
+                PTYPE_QUIT
+            ]
+        );
+    }
+}
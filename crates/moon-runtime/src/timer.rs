@@ -0,0 +1,665 @@
+//! Process-wide timer service.
+//!
+//! Actors schedule timers through [`insert_timer`] / [`remove_timer`]; the ops
+//! travel over an unbounded channel to a timer task that owns the timer set and
+//! delivers `PTYPE_TIMER` messages when they expire. Timers are sharded by
+//! owner across `TimerConfig::shards` tasks, so one owner's insert and cancel
+//! always reach the same shard and stay ordered.
+//!
+//! The default store is a hierarchical timing wheel ([`TimerWheel`]) with
+//! millisecond ticks and O(1) insert/cancel. An ordered-map store
+//! ([`BTreeTimers`]) is selectable (`timer = "btree"` in the bootstrap
+//! `__init__` table) as a reference for `assets/benchmark/benchmark_timer.lua`.
+//! It runs in the same sharded tasks and cancels the same way, so the
+//! benchmark compares the two stores only. The timer task this module
+//! replaced kept a `BTreeSet` without cancel and ran in one task.
+
+use std::{
+    collections::{BTreeMap, HashMap, VecDeque},
+    sync::atomic::Ordering,
+    time::Duration,
+};
+use tokio::{sync::mpsc, time::timeout};
+
+use crate::context::{ActorId, CONTEXT, Message, MessageBody, PTYPE_TIMER};
+
+/// Sentinel `count` for a timer that repeats until removed.
+pub const TIMER_REPEAT_FOREVER: i64 = -1;
+
+/// Storage strategy of the timer tasks.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TimerBackend {
+    /// Hierarchical timing wheel, O(1) insert/cancel.
+    #[default]
+    Wheel,
+    /// Ordered map keyed by expiry, O(log n) insert/cancel.
+    BTree,
+}
+
+impl TimerBackend {
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "wheel" => Some(Self::Wheel),
+            "btree" => Some(Self::BTree),
+            _ => None,
+        }
+    }
+}
+
+/// Startup options for [`run_timer`], read from the bootstrap `__init__` table.
+#[derive(Debug, Clone, Copy)]
+pub struct TimerConfig {
+    pub backend: TimerBackend,
+    /// Number of independent timer tasks. Clamped to at least 1.
+    pub shards: usize,
+}
+
+impl Default for TimerConfig {
+    fn default() -> Self {
+        Self {
+            backend: TimerBackend::Wheel,
+            shards: 1,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub(crate) enum TimerOp {
+    Insert(Timer),
+    Remove(ActorId, i64),
+}
+
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Timer {
+    /// Absolute expiry on `CONTEXT.now_clock()`, in milliseconds.
+    expiry: u64,
+    timer_id: i64,
+    owner: ActorId,
+    /// Repeat period in milliseconds.
+    interval: u64,
+    /// Fires left, or `TIMER_REPEAT_FOREVER`.
+    remaining: i64,
+}
+
+/// A set of pending timers owned by one timer task.
+trait TimerStore: Send + 'static {
+    fn insert(&mut self, timer: Timer);
+    /// Cancel `(owner, timer_id)`. Returns whether it was pending.
+    fn remove(&mut self, owner: ActorId, timer_id: i64) -> bool;
+    /// Earliest point at which `pop_expired` can return a timer, if any.
+    fn next_expiry(&self) -> Option<u64>;
+    /// Pop one timer due at `now`, earliest first.
+    fn pop_expired(&mut self, now: u64) -> Option<Timer>;
+}
+
+/// Schedule the next tick of a repeating timer that just fired. Missed ticks
+/// are not replayed: if the actor fell behind, the next tick is one `interval`
+/// from `now`. Returns `false` once the timer has no fires left.
+fn rearm<S: TimerStore>(store: &mut S, mut timer: Timer, now: u64) -> bool {
+    if timer.remaining == 1 || timer.remaining == 0 {
+        return false;
+    }
+    if timer.remaining > 0 {
+        timer.remaining -= 1;
+    }
+    timer.expiry += timer.interval;
+    if timer.expiry <= now {
+        timer.expiry = now + timer.interval;
+    }
+    store.insert(timer);
+    true
+}
+
+/// Ordered-map store, ordered like the former timer task's `BTreeSet` but with
+/// cancel support. The `index` maps `(owner, timer_id)` to its expiry so a
+/// cancel can find the entry without a scan.
+#[derive(Default)]
+pub(crate) struct BTreeTimers {
+    queue: BTreeMap<(u64, ActorId, i64), (u64, i64)>,
+    index: HashMap<(ActorId, i64), u64>,
+}
+
+impl TimerStore for BTreeTimers {
+    fn insert(&mut self, timer: Timer) {
+        let key = (timer.owner, timer.timer_id);
+        if let Some(expiry) = self.index.insert(key, timer.expiry) {
+            self.queue.remove(&(expiry, timer.owner, timer.timer_id));
+        }
+        self.queue.insert(
+            (timer.expiry, timer.owner, timer.timer_id),
+            (timer.interval, timer.remaining),
+        );
+    }
+
+    fn remove(&mut self, owner: ActorId, timer_id: i64) -> bool {
+        match self.index.remove(&(owner, timer_id)) {
+            Some(expiry) => {
+                self.queue.remove(&(expiry, owner, timer_id));
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn next_expiry(&self) -> Option<u64> {
+        self.queue.first_key_value().map(|(key, _)| key.0)
+    }
+
+    fn pop_expired(&mut self, now: u64) -> Option<Timer> {
+        let (&(expiry, owner, timer_id), _) = self.queue.first_key_value()?;
+        if expiry > now {
+            return None;
+        }
+        let (interval, remaining) = self.queue.pop_first()?.1;
+        self.index.remove(&(owner, timer_id));
+        Some(Timer {
+            expiry,
+            timer_id,
+            owner,
+            interval,
+            remaining,
+        })
+    }
+}
+
+const SLOT_BITS: usize = 6;
+const SLOTS: usize = 1 << SLOT_BITS;
+const SLOT_MASK: u64 = SLOTS as u64 - 1;
+const LEVELS: usize = 6;
+/// Longest delay the wheel represents (2^36 ms, about 795 days). Longer timers
+/// are clamped to it.
+const MAX_SPAN: u64 = 1 << (SLOT_BITS * LEVELS);
+
+/// `WheelEntry::bucket` sentinels; real buckets are `level * SLOTS + slot`.
+const VACANT: u32 = u32::MAX;
+const DUE: u32 = u32::MAX - 1;
+const DUE_CANCELLED: u32 = u32::MAX - 2;
+const NIL: u32 = u32::MAX;
+
+struct WheelEntry {
+    timer: Timer,
+    bucket: u32,
+    prev: u32,
+    next: u32,
+}
+
+/// Hierarchical timing wheel with 1 ms ticks: `LEVELS` levels of 64 slots,
+/// level `n` slots spanning 64^n ms. A timer is filed at the level of the most
+/// significant 6-bit digit where its expiry differs from `elapsed`, and
+/// cascades down a level each time its slot comes up. Each slot is an
+/// intrusive doubly linked list over a slab, so insert and cancel are O(1);
+/// finding the next expiry is a bitmap scan per level.
+pub(crate) struct TimerWheel {
+    entries: Vec<WheelEntry>,
+    free: Vec<u32>,
+    heads: [u32; LEVELS * SLOTS],
+    occupied: [u64; LEVELS],
+    index: HashMap<(ActorId, i64), u32>,
+    /// Entries whose expiry has been reached, in firing order.
+    due: VecDeque<u32>,
+    /// Wheel time: every slot before it has been processed.
+    elapsed: u64,
+    scratch: Vec<u32>,
+}
+
+impl TimerWheel {
+    pub(crate) fn new(now: u64) -> Self {
+        Self {
+            entries: Vec::new(),
+            free: Vec::new(),
+            heads: [NIL; LEVELS * SLOTS],
+            occupied: [0; LEVELS],
+            index: HashMap::new(),
+            due: VecDeque::new(),
+            elapsed: now,
+            scratch: Vec::new(),
+        }
+    }
+
+    fn level_for(elapsed: u64, when: u64) -> usize {
+        let masked = ((elapsed ^ when) | SLOT_MASK).min(MAX_SPAN - 1);
+        let significant = 63 - masked.leading_zeros() as usize;
+        significant / SLOT_BITS
+    }
+
+    fn link(&mut self, idx: u32, bucket: usize) {
+        let head = self.heads[bucket];
+        let entry = &mut self.entries[idx as usize];
+        entry.bucket = bucket as u32;
+        entry.prev = NIL;
+        entry.next = head;
+        if head != NIL {
+            self.entries[head as usize].prev = idx;
+        }
+        self.heads[bucket] = idx;
+        self.occupied[bucket / SLOTS] |= 1 << (bucket % SLOTS);
+    }
+
+    fn unlink(&mut self, idx: u32) {
+        let (bucket, prev, next) = {
+            let entry = &self.entries[idx as usize];
+            (entry.bucket as usize, entry.prev, entry.next)
+        };
+        if prev != NIL {
+            self.entries[prev as usize].next = next;
+        } else {
+            self.heads[bucket] = next;
+        }
+        if next != NIL {
+            self.entries[next as usize].prev = prev;
+        }
+        if self.heads[bucket] == NIL {
+            self.occupied[bucket / SLOTS] &= !(1 << (bucket % SLOTS));
+        }
+    }
+
+    /// File entry `idx` relative to `elapsed`, or queue it as due.
+    fn place(&mut self, idx: u32) {
+        let when = self.entries[idx as usize].timer.expiry;
+        if when <= self.elapsed {
+            self.entries[idx as usize].bucket = DUE;
+            self.due.push_back(idx);
+            return;
+        }
+        let level = Self::level_for(self.elapsed, when);
+        let slot = ((when >> (level * SLOT_BITS)) & SLOT_MASK) as usize;
+        self.link(idx, level * SLOTS + slot);
+    }
+
+    fn release(&mut self, idx: u32) -> Timer {
+        let entry = &mut self.entries[idx as usize];
+        entry.bucket = VACANT;
+        self.free.push(idx);
+        entry.timer
+    }
+
+    /// Earliest occupied slot as `(level, slot, deadline)`. Lower levels
+    /// always expire before higher ones, so the first non-empty level wins.
+    fn next_expiration(&self) -> Option<(usize, usize, u64)> {
+        for level in 0..LEVELS {
+            let occupied = self.occupied[level];
+            if occupied == 0 {
+                continue;
+            }
+            let slot_range = 1u64 << (level * SLOT_BITS);
+            let level_range = slot_range << SLOT_BITS;
+            let now_slot = ((self.elapsed >> (level * SLOT_BITS)) & SLOT_MASK) as u32;
+            let zeros = occupied.rotate_right(now_slot).trailing_zeros();
+            let slot = (zeros + now_slot) as usize % SLOTS;
+            let level_start = self.elapsed & !(level_range - 1);
+            let mut deadline = level_start + slot as u64 * slot_range;
+            if deadline <= self.elapsed {
+                // Only the top level wraps: a clamped far timer can sit in a
+                // slot "behind" the cursor, meaning the next lap.
+                deadline += level_range;
+            }
+            return Some((level, slot, deadline));
+        }
+        None
+    }
+
+    /// Advance to `deadline` and cascade the slot: due entries are queued,
+    /// the rest are refiled at a lower level.
+    fn process_slot(&mut self, level: usize, slot: usize, deadline: u64) {
+        self.elapsed = deadline;
+        let bucket = level * SLOTS + slot;
+        let mut idx = std::mem::replace(&mut self.heads[bucket], NIL);
+        self.occupied[level] &= !(1 << slot);
+        let mut batch = std::mem::take(&mut self.scratch);
+        while idx != NIL {
+            batch.push(idx);
+            idx = self.entries[idx as usize].next;
+        }
+        for &idx in &batch {
+            self.place(idx);
+        }
+        batch.clear();
+        self.scratch = batch;
+    }
+
+    #[cfg(test)]
+    fn len(&self) -> usize {
+        self.index.len()
+    }
+}
+
+impl TimerStore for TimerWheel {
+    fn insert(&mut self, mut timer: Timer) {
+        self.remove(timer.owner, timer.timer_id);
+        timer.expiry = timer
+            .expiry
+            .clamp(self.elapsed, self.elapsed + MAX_SPAN - 1);
+        let entry = WheelEntry {
+            timer,
+            bucket: VACANT,
+            prev: NIL,
+            next: NIL,
+        };
+        let idx = match self.free.pop() {
+            Some(idx) => {
+                self.entries[idx as usize] = entry;
+                idx
+            }
+            None => {
+                self.entries.push(entry);
+                (self.entries.len() - 1) as u32
+            }
+        };
+        self.index.insert((timer.owner, timer.timer_id), idx);
+        self.place(idx);
+    }
+
+    fn remove(&mut self, owner: ActorId, timer_id: i64) -> bool {
+        let Some(idx) = self.index.remove(&(owner, timer_id)) else {
+            return false;
+        };
+        match self.entries[idx as usize].bucket {
+            // Still referenced by `due`; freed when popped.
+            DUE => self.entries[idx as usize].bucket = DUE_CANCELLED,
+            _ => {
+                self.unlink(idx);
+                self.release(idx);
+            }
+        }
+        true
+    }
+
+    fn next_expiry(&self) -> Option<u64> {
+        if !self.due.is_empty() {
+            return Some(self.elapsed);
+        }
+        self.next_expiration().map(|(_, _, deadline)| deadline)
+    }
+
+    fn pop_expired(&mut self, now: u64) -> Option<Timer> {
+        loop {
+            if let Some(idx) = self.due.pop_front() {
+                let cancelled = self.entries[idx as usize].bucket == DUE_CANCELLED;
+                let timer = self.release(idx);
+                if cancelled {
+                    continue;
+                }
+                self.index.remove(&(timer.owner, timer.timer_id));
+                return Some(timer);
+            }
+            match self.next_expiration() {
+                Some((level, slot, deadline)) if deadline <= now => {
+                    self.process_slot(level, slot, deadline);
+                }
+                _ => {
+                    self.elapsed = self.elapsed.max(now);
+                    return None;
+                }
+            }
+        }
+    }
+}
+
+fn now_ms() -> u64 {
+    CONTEXT.now_clock().as_millis() as u64
+}
+
+fn shard_of(owner: ActorId, shards: usize) -> usize {
+    owner as usize % shards
+}
+
+/// Schedule `timer_id` for `owner` after `interval` ms. `count` is the number
+/// of fires (`1` for a one-shot, `TIMER_REPEAT_FOREVER` to repeat until
+/// removed); repeats are re-armed inside the timer task.
+pub fn insert_timer(owner: ActorId, timer_id: i64, interval: u64, count: i64) {
+    let Some(shards) = CONTEXT.timer_tx.get() else {
+        log::error!("insert_timer called before run_timer started");
+        return;
+    };
+    let timer = Timer {
+        expiry: now_ms() + interval,
+        timer_id,
+        owner,
+        interval,
+        remaining: count,
+    };
+    if shards[shard_of(owner, shards.len())]
+        .send(TimerOp::Insert(timer))
+        .is_ok()
+    {
+        CONTEXT.pending_timers.fetch_add(1, Ordering::Release);
+    }
+}
+
+/// Cancel a timer previously scheduled by `owner`. A fire already queued in
+/// the owner's mailbox is not recalled.
+pub fn remove_timer(owner: ActorId, timer_id: i64) {
+    if let Some(shards) = CONTEXT.timer_tx.get() {
+        let _ = shards[shard_of(owner, shards.len())].send(TimerOp::Remove(owner, timer_id));
+    }
+}
+
+async fn run_shard<S: TimerStore>(mut timers: S, mut rc: mpsc::UnboundedReceiver<TimerOp>) {
+    let apply = |timers: &mut S, op: TimerOp| match op {
+        TimerOp::Insert(timer) => timers.insert(timer),
+        TimerOp::Remove(owner, timer_id) => {
+            if timers.remove(owner, timer_id) {
+                CONTEXT.pending_timers.fetch_sub(1, Ordering::Release);
+            }
+        }
+    };
+    let mut wait_time = 1000;
+    loop {
+        match timeout(Duration::from_millis(wait_time), rc.recv()).await {
+            Ok(Some(op)) => {
+                apply(&mut timers, op);
+                // Drain the burst so a flood of inserts costs one wakeup.
+                while let Ok(op) = rc.try_recv() {
+                    apply(&mut timers, op);
+                }
+            }
+            Ok(None) => {
+                break;
+            }
+            Err(_) => {} //timeout
+        }
+
+        let now = now_ms();
+        while let Some(timer) = timers.pop_expired(now) {
+            let dead = CONTEXT
+                .send(Message::new(
+                    0,
+                    timer.owner,
+                    0,
+                    MessageBody::ISize(PTYPE_TIMER, timer.timer_id as isize),
+                ))
+                .is_some();
+            // A repeating timer of an exited actor would otherwise tick forever.
+            if dead || !rearm(&mut timers, timer, now) {
+                CONTEXT.pending_timers.fetch_sub(1, Ordering::Release);
+            }
+        }
+        wait_time = timers
+            .next_expiry()
+            .map_or(1000, |expiry| expiry.saturating_sub(now).clamp(1, 1000));
+    }
+}
+
+pub fn run_timer(config: TimerConfig) {
+    // Create the channels here so each receiver can be moved straight into its
+    // shard task. Only the senders are published globally; this must be called
+    // exactly once and before any `insert_timer`.
+    let shards = config.shards.max(1);
+    let mut senders = Vec::with_capacity(shards);
+    for _ in 0..shards {
+        let (timer_tx, rc) = mpsc::unbounded_channel();
+        senders.push(timer_tx);
+        match config.backend {
+            TimerBackend::Wheel => tokio::spawn(run_shard(TimerWheel::new(now_ms()), rc)),
+            TimerBackend::BTree => tokio::spawn(run_shard(BTreeTimers::default(), rc)),
+        };
+    }
+    CONTEXT
+        .timer_tx
+        .set(senders)
+        .unwrap_or_else(|_| panic!("run_timer called more than once"));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn timer(owner: ActorId, timer_id: i64, expiry: u64, interval: u64, remaining: i64) -> Timer {
+        Timer {
+            expiry,
+            timer_id,
+            owner,
+            interval,
+            remaining,
+        }
+    }
+
+    fn drain<S: TimerStore>(timers: &mut S, now: u64) -> Vec<(ActorId, i64)> {
+        std::iter::from_fn(|| timers.pop_expired(now))
+            .map(|t| (t.owner, t.timer_id))
+            .collect()
+    }
+
+    #[test]
+    fn cancel_removes_entry() {
+        let mut btree = BTreeTimers::default();
+        let mut wheel = TimerWheel::new(0);
+        for timers in [&mut btree as &mut dyn StoreObj, &mut wheel] {
+            timers.insert_obj(timer(1, 1, 100, 100, 1));
+            timers.insert_obj(timer(1, 2, 50, 50, 1));
+            timers.insert_obj(timer(2, 1, 75, 75, 1));
+
+            assert!(timers.remove_obj(1, 2));
+            assert!(!timers.remove_obj(1, 2), "second cancel is a no-op");
+            assert!(
+                !timers.remove_obj(3, 1),
+                "other owners' ids are not matched"
+            );
+            assert_eq!(timers.drain_obj(74), vec![]);
+            assert_eq!(timers.drain_obj(1000), vec![(2, 1), (1, 1)]);
+        }
+        assert!(btree.index.is_empty());
+        assert_eq!(wheel.len(), 0);
+    }
+
+    /// Object-safe shim so one test body can run against both stores.
+    trait StoreObj {
+        fn insert_obj(&mut self, timer: Timer);
+        fn remove_obj(&mut self, owner: ActorId, timer_id: i64) -> bool;
+        fn drain_obj(&mut self, now: u64) -> Vec<(ActorId, i64)>;
+    }
+
+    impl<S: TimerStore> StoreObj for S {
+        fn insert_obj(&mut self, timer: Timer) {
+            self.insert(timer);
+        }
+        fn remove_obj(&mut self, owner: ActorId, timer_id: i64) -> bool {
+            self.remove(owner, timer_id)
+        }
+        fn drain_obj(&mut self, now: u64) -> Vec<(ActorId, i64)> {
+            drain(self, now)
+        }
+    }
+
+    #[test]
+    fn rearms_repeating_timers() {
+        let mut timers = TimerWheel::new(0);
+        timers.insert(timer(1, 1, 10, 10, 3));
+        timers.insert(timer(1, 2, 10, 10, TIMER_REPEAT_FOREVER));
+
+        let mut fires = 0;
+        let mut now = 10;
+        while now <= 100 {
+            while let Some(t) = timers.pop_expired(now) {
+                if t.timer_id == 1 {
+                    fires += 1;
+                }
+                rearm(&mut timers, t, now);
+            }
+            now += 10;
+        }
+        assert_eq!(fires, 3, "count-limited timer fires exactly count times");
+        assert_eq!(timers.len(), 1, "forever timer stays armed");
+
+        assert!(timers.remove(1, 2));
+        assert!(timers.next_expiry().is_none());
+    }
+
+    #[test]
+    fn skips_missed_ticks() {
+        let mut timers = TimerWheel::new(0);
+        timers.insert(timer(1, 1, 10, 10, TIMER_REPEAT_FOREVER));
+        let t = timers.pop_expired(55).unwrap();
+        assert!(rearm(&mut timers, t, 55));
+        assert!(timers.pop_expired(64).is_none());
+        assert_eq!(timers.pop_expired(65).map(|t| t.expiry), Some(65));
+    }
+
+    #[test]
+    fn wheel_cancels_entry_already_due() {
+        let mut timers = TimerWheel::new(0);
+        timers.insert(timer(1, 1, 64, 64, 1));
+        timers.insert(timer(1, 2, 64, 64, 1));
+        // Cascading the level-1 slot queues both as due; pop one, then cancel
+        // the other while it sits in the due queue.
+        assert_eq!(timers.pop_expired(64).map(|t| t.timer_id), Some(2));
+        assert!(timers.remove(1, 1));
+        assert!(timers.pop_expired(64).is_none());
+        assert_eq!(timers.len(), 0);
+        assert_eq!(timers.free.len(), timers.entries.len());
+    }
+
+    #[test]
+    fn wheel_matches_btree_across_all_levels() {
+        // xorshift: deterministic spread of delays from 1 ms to ~40 days.
+        let mut seed = 0x9E37_79B9_7F4A_7C15u64;
+        let mut next = move || {
+            seed ^= seed << 13;
+            seed ^= seed >> 7;
+            seed ^= seed << 17;
+            seed
+        };
+
+        let start = 1_000_003;
+        let mut wheel = TimerWheel::new(start);
+        let mut btree = BTreeTimers::default();
+        for id in 0..20_000i64 {
+            let bits = next() % 32;
+            let delay = 1 + next() % (1u64 << bits);
+            let t = timer((id % 7) as ActorId, id, start + delay, delay, 1);
+            wheel.insert(t);
+            btree.insert(t);
+            if next() % 4 == 0 {
+                let victim = (next() % (id as u64 + 1)) as i64;
+                let owner = (victim % 7) as ActorId;
+                assert_eq!(wheel.remove(owner, victim), btree.remove(owner, victim));
+            }
+        }
+
+        let mut now = start;
+        let mut fired = 0;
+        while btree.next_expiry().is_some() {
+            now += 1 + next() % (1 << (next() % 28));
+            let mut a = drain(&mut wheel, now);
+            let mut b = drain(&mut btree, now);
+            a.sort_unstable();
+            b.sort_unstable();
+            assert_eq!(a, b, "fired set differs at {}", now);
+            fired += a.len();
+        }
+        assert!(fired > 10_000);
+        assert!(wheel.next_expiry().is_none());
+    }
+
+    #[test]
+    fn wheel_clamps_timers_beyond_span() {
+        let mut timers = TimerWheel::new(5);
+        timers.insert(timer(1, 1, u64::MAX / 2, 0, 1));
+        assert_eq!(timers.next_expiry().map(|d| d <= 5 + MAX_SPAN), Some(true));
+        assert!(timers.pop_expired(5 + MAX_SPAN - 2).is_none());
+        assert_eq!(
+            timers.pop_expired(5 + MAX_SPAN).map(|t| t.timer_id),
+            Some(1)
+        );
+    }
+}
@@ -273,6 +273,15 @@ local function hotfix_(root, proto_map, short_source)
 	end
 end
 
+---track a module loaded without hotfix.require, so that hotfix.update can patch it
+function hotfix.track(name, loader)
+	assert(type(name) == "string")
+	if loaders[name] == nil then
+		origin[name] = loader
+	end
+	loaders[name] = loader
+end
+
 ---only hotfix functions defined in current source file
 function hotfix.update(name, updatename)
 	assert(type(name) == "string")
@@ -300,7 +309,9 @@ function hotfix.update(name, updatename)
 		end
 	end
 
-	hotfix_(_LOADED[name], diff, name)
+	-- functions of the module share the source of its loader: its name for
+	-- registered loaders, its file for modules loaded from a file
+	hotfix_(_LOADED[name], diff, debug.getinfo(loaders[name], "S").short_src)
 	loaders[name] = loader
 	return true, _LOADED[name]
 end
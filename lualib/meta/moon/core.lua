@@ -33,7 +33,7 @@ function core.env(key, value) end
 --- Print a log message.
 ---@param loglv integer @ Log level (`LOG_DEBUG`/`LOG_INFO`/`LOG_WARN`/`LOG_ERROR`)
 ---@param stack_level integer @ `lua_getstack` level used to attach the source location
----@vararg any @ Values to log; each is serialized and joined with spaces
+---@vararg any @ Values to log; each is serialized and joined with spaces. With `log_format = "json"`, table arguments add their string-keyed entries as extra fields and their positional entries to `msg`
 function core.log(loglv, stack_level, ...) end
 
 --- Get or set the global log level.
@@ -41,8 +41,26 @@ function core.log(loglv, stack_level, ...) end
 ---@return integer
 function core.loglevel(lv) end
 
+--- Get the log level in effect for the current service, or override it (`""`
+--- follows the global level again).
+---@param lv? string @ `DBUG`, `INFO`, `WARN`, `EROR`, or `""`
+---@return integer?
+function core.actor_loglevel(lv) end
+
+--- Change this actor's memory limits in bytes; 0 removes a limit, a nil
+--- `soft` keeps the soft limit.
+---@param hard integer
+---@param soft? integer
+---@return integer hard @ previous hard limit
+---@return integer soft @ previous soft limit
+function core.set_memlimit(hard, soft) end
+
+--- Reopen the log file at its configured path, e.g. after logrotate moved it.
+--- `SIGHUP` does the same.
+function core.log_reopen() end
+
 --- Create a new Lua service (actor).
----@param opts table @ `{ name?, source, unique?, memlimit? }`
+---@param opts table @ `{ name?, source, unique?, memlimit?, mem_soft_limit?, mem_action?, mailbox_capacity?, mailbox_policy?, loglevel?, logfile?, restart?, max_restarts?, within?, group?, balance?, record?, budget?, budget_ms?, priority_lanes?, watchdog? }`
 ---@param params string @ Bootstrap params (PATH env is prepended)
 ---@return integer session @ Session for the create response
 function core.new_service(opts, params) end
@@ -69,14 +87,65 @@ function core.kill(addr) end
 ---@return integer to
 function core.send(ptype, to, data, session, from) end
 
+--- Send one message to many services. `data` is stored once and shared by
+--- every receiver. Unknown ids and receivers with a full mailbox are skipped.
+---@param ptype integer @ Message type (`PTYPE_*`, must be > 0)
+---@param receivers integer[] @ Receiver actor ids
+---@param data buffer_ptr|string @ Message body
+---@return integer delivered
+function core.broadcast(ptype, receivers, data) end
+
+--- Choose the member of a service group the next message should go to, by
+--- the group's balance. `hash` groups need a key; integer keys hash like their
+--- decimal string.
+---@param group string
+---@param key? string|integer
+---@return integer id @ `0` if the group has no member
+function core.group_pick(group, key) end
+
+--- Members of a service group, ascending.
+---@param group string
+---@return integer[]
+function core.group_members(group) end
+
+--- Subscribe this service to a topic or pattern (`*` matches one segment, a
+--- trailing `#` any remaining segments). Raises an error for a malformed topic.
+--- Subscriptions are dropped when the service exits.
+---@param topic string
+---@return boolean added @ `false` if already subscribed
+function core.subscribe(topic) end
+
+---@param topic string
+---@return boolean removed @ `false` if not subscribed
+function core.unsubscribe(topic) end
+
+--- Deliver `data` as a `PTYPE_PUBSUB` message to every subscriber of `topic`.
+--- The buffer is shared, not copied. Subscribers with a full mailbox are skipped.
+---@param topic string @ Must not contain wildcards
+---@param data buffer_ptr|string
+---@return integer delivered
+function core.publish(topic, data) end
+
+--- Whether `topic` matches the subscription `pattern`.
+---@param pattern string
+---@param topic string
+---@return boolean
+function core.topic_match(pattern, topic) end
+
 --- Register the message dispatch callback for this service.
 ---@param fn fun(msg: message_ptr, ptype: integer)
 function core.callback(fn) end
 
 --- Schedule a timer. `interval <= 0` fires immediately.
 ---@param interval integer @ Milliseconds; `<= 0` for immediate
+---@param count? integer @ Number of fires (default 1); `-1` repeats until `core.remove_timer`. Repeating needs `interval > 0`
 ---@return integer timer_id
-function core.timeout(interval) end
+function core.timeout(interval, count) end
+
+--- Cancel a timer of this service in the timer task. A fire already queued in
+--- the mailbox is still delivered.
+---@param timer_id integer
+function core.remove_timer(timer_id) end
 
 --- Allocate the next session id for outbound requests.
 ---@return integer
@@ -103,6 +172,8 @@ function core.now(unit) end
 --- - `"log.error_count"` total error-level logs
 --- - `"log.queue"` log lines enqueued but not yet flushed by the logger thread
 --- - `"timer.count"` scheduled-but-unfired timers
+--- - `"topic.count"` topics and patterns with at least one subscriber
+--- - `"group.count"` service groups with at least one member
 --- - `"env.count"` runtime env vars
 --- - `"time.offset"` simulated-clock offset (ms)
 --- - `"time.now"` server timestamp (ms)
@@ -112,12 +183,68 @@ function core.now(unit) end
 --- - `"cpu.total_ms"` total dispatch time across all actors (ms)
 ---
 --- The JSON snapshot additionally contains a `services` array with one entry per
---- actor: `{ id, name, memory, messages, cpu_ms }` (per-actor stats tracked on
---- each actor's watchdog).
+--- actor: `{ id, name, memory, messages, cpu_ms, queue, capacity, dropped, latency }`
+--- (per-actor stats tracked on each actor's watchdog). `latency` is an array of
+--- `{ ptype, wait = summary, dispatch = summary }`, one per received message
+--- type, where a summary is `{ count, mean_us, p50_us, p99_us, max_us }`.
 ---@param key? string @ Counter name; omit to get the full JSON snapshot
 ---@return string|integer @ JSON string when `key` is omitted; an integer (`0` for unknown keys) otherwise
 function core.server_stats(key) end
 
+--- Queue wait (enqueue to dispatch start) and dispatch time of the messages
+--- this service has handled, per ptype. Percentiles come from log-linear
+--- histograms and are at most 25% above the true value; `max_us` is exact.
+--- Other services can read it through the debug protocol:
+--- `moon.call("debug", id, "latency")`.
+---@param reset? boolean @ Clear the histograms after reading
+---@return table<integer, { wait: table, dispatch: table }> @ Summaries `{ count, mean_us, p50_us, p99_us, max_us }` keyed by ptype
+function core.latency(reset) end
+
+--- Hot reload support (`hot_reload = true` in the bootstrap options). With no
+--- arguments, reports whether hot reload is enabled. With `name` and `path`,
+--- watches the file `path`, loaded as module `name`; when it changes, this
+--- service gets a `_hotfix` system command for the module.
+---@param name? string
+---@param path? string
+---@return boolean @ False when hot reload is disabled or the file cannot be watched
+function core.watch_module(name, path) end
+
+--- Start sampling the Lua stacks of service `id` `hz` times per second while
+--- it is handling a message. Raises an error if the service does not exist or
+--- is already being profiled.
+---@param id integer
+---@param hz? integer @ 1 to 1000, default 100
+function core.profile_start(id, hz) end
+
+--- Stop profiling service `id`. With `path`, also writes the profile there: an
+--- SVG flamegraph when `path` ends in `.svg`, folded stacks otherwise.
+---@param id integer
+---@param path? string
+---@return string folded @ One `outer;inner;leaf count` line per stack
+---@return integer samples
+function core.profile_stop(id, path) end
+
+--- Start sampling the current service's allocations, about one per `interval`
+--- bytes. Raises an error if a profile is already running.
+---@param interval? integer @ default 65536
+function core.memprofile_start(interval) end
+
+--- Stop the allocation profile and discard it.
+---@return boolean @ false if no profile was running
+function core.memprofile_stop() end
+
+--- Top allocation sites, largest first. Raises an error if no profile is
+--- running.
+---@param sort? string @ `"allocated"` (default) or `"live"`
+---@param limit? integer @ default all
+---@return {site: string, allocated: integer, count: integer, live: integer, live_count: integer}[]
+function core.memprofile_report(sort, limit) end
+
+--- Runtime statistics in Prometheus text format, the same body the admin
+--- listener (`admin_addr` bootstrap option) serves at `/metrics`.
+---@return string
+function core.metrics() end
+
 --- Decode fields from a runtime message.
 ---
 --- Pattern characters (one return value each, except `'C'` which returns two):
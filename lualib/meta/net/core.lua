@@ -1,13 +1,13 @@
 ---@meta
 -- IDE annotation file only. Do not require this file at runtime.
 
---- Native TCP socket module (`require("net.core")`).
+--- Native TCP/UDP socket module (`require("net.core")`).
 ---@class net.core
 local net = {}
 
 --- Listen on an address. Returns a listener fd.
 ---@param addr string @ e.g. `"127.0.0.1:8080"`
----@param opts? table @ `{ max_connections? }` cap on concurrently accepted connections (default 100000)
+---@param opts? table @ `{ max_connections?, tls? }`: connection cap (default 100000) and `tls = { cert, key }` (PEM text or file paths)
 ---@return integer fd
 ---@return false? err
 ---@return string? errmsg
@@ -15,9 +15,12 @@ function net.listen(addr, opts) end
 
 --- Connect to a remote address asynchronously.
 ---@param addr string
----@param timeout? integer @ Connect timeout in ms (default 5000)
+---@param timeout? integer @ Connect timeout in ms (default 5000), includes the TLS handshake
+---@param opts? table @ `{ tls = { sni?, ca? } }`; `sni` defaults to the host of `addr`, `ca` (PEM text or file path) defaults to webpki roots
 ---@return integer session
-function net.connect(addr, timeout) end
+---@return false? err
+---@return string? errmsg
+function net.connect(addr, timeout, opts) end
 
 --- Read bytes or until delimiter asynchronously.
 ---@param fd integer
@@ -66,6 +69,34 @@ function net.write_frame(fd, data, max_write_capacity, close) end
 ---@return boolean
 function net.close(fd) end
 
+--- Bind a UDP socket. Inbound datagrams are delivered to the caller as
+--- `PTYPE_SOCKET_UDP` messages decoded as `(fd, data, from_addr)`.
+---@param addr? string @ Local address (default `"0.0.0.0:0"`)
+---@return integer fd
+---@return false? err
+---@return string? errmsg
+function net.udp_bind(addr) end
+
+--- Send one datagram. `addr = nil` sends to the peer set by `udp_connect`.
+---@param fd integer
+---@param addr string?
+---@param data buffer_arc_ptr|string|buffer_ptr @ At most 65507 bytes
+---@return boolean success
+---@return false? err
+---@return string? errmsg
+function net.udp_sendto(fd, addr, data) end
+
+--- Set the default peer of a UDP socket asynchronously. Responds with the fd.
+---@param fd integer
+---@param addr string
+---@return integer session
+function net.udp_connect(fd, addr) end
+
+--- Close a UDP socket fd.
+---@param fd integer
+---@return boolean
+function net.udp_close(fd) end
+
 --- Resolve local IP by connecting to a remote address (default `"1.1.1.1:80"`).
 ---@param addr? string
 ---@return string? ip
@@ -8,6 +8,7 @@ require("base.class")
 
 local core             = require("moon.core")
 local seri             = require("seri")
+local json             = require("json")
 
 local pairs            = pairs
 local type             = type
@@ -22,11 +23,26 @@ local co_resume        = coroutine.resume
 local co_close         = coroutine.close
 
 local _send            = core.send
+local _broadcast       = core.broadcast
+local _group_pick      = core.group_pick
 local _now             = core.now
 local _addr            = core.id
 local _timeout         = core.timeout
+local _remove_timer    = core.remove_timer
 local _newservice      = core.new_service
 local _decode          = core.decode
+local _actor_loglevel  = core.actor_loglevel
+local _set_memlimit    = core.set_memlimit
+local _profile_start   = core.profile_start
+local _profile_stop    = core.profile_stop
+local _memprofile_start  = core.memprofile_start
+local _memprofile_stop   = core.memprofile_stop
+local _memprofile_report = core.memprofile_report
+local _watch_module    = core.watch_module
+local _subscribe       = core.subscribe
+local _unsubscribe     = core.unsubscribe
+local _publish         = core.publish
+local _topic_match     = core.topic_match
 
 ---@alias buffer_ptr lightuserdata
 ---@alias message_ptr lightuserdata
@@ -71,11 +87,12 @@ moon.PTYPE_HTTPD       = 18
 moon.PTYPE_PG          = 19
 moon.PTYPE_REDIS       = 20
 moon.PTYPE_GRPC        = 21
+moon.PTYPE_PUBSUB      = 22
 
---- Checks if debug logging is enabled
+--- Checks if debug logging is enabled for the current service
 --- @return boolean
 moon.DEBUG             = function()
-    return core.loglevel() == 4
+    return _actor_loglevel() == 4
 end
 
 local LOG_ERROR = 1
@@ -145,6 +162,8 @@ local protocol = {}
 local session_watcher = {}
 local timer_routine = {}
 local timer_profile_trace = {}
+---@type table<integer, integer> @ Fires left for repeating timers (-1 = forever).
+local timer_repeat = {}
 
 --- Safely resumes a coroutine with error handling
 --- @param co thread
@@ -187,6 +206,22 @@ function moon.raw_send(PTYPE, receiver, data, session, sender)
     return _send(p.PTYPE, receiver, data, session, sender)
 end
 
+--- Sends the same message to many services. The message is packed once and
+--- every receiver shares the packed buffer, so the cost does not grow with the
+--- number of receivers. Unlike `moon.send`, a receiver whose mailbox is full
+--- misses the message instead of blocking the sender.
+--- @param PTYPE PTYPE @ The protocol type, e.g., "lua".
+--- @param receivers integer[] @ The service IDs of the receivers.
+--- @param ... any @ The message content.
+--- @return integer @ Number of services the message was delivered to.
+function moon.broadcast(PTYPE, receivers, ...)
+    local p = protocol[PTYPE]
+    if not p then
+        error(string.format("moon broadcast unknown PTYPE[%s] message", PTYPE))
+    end
+    return _broadcast(p.PTYPE, receivers, p.pack(...))
+end
+
 ---@class protocol_config
 ---@field name string The protocol name
 ---@field PTYPE integer The protocol type constant
@@ -199,6 +234,23 @@ end
 ---@field name string The name of the service.
 ---@field source string The path to the startup script file for the service.
 ---@field unique? boolean Whether the service is unique. Default is `false`. If `true`, use `moon.query(name)` to query the service ID.
+---@field memlimit? integer Hard memory limit in bytes. An allocation past it fails with a memory error, and the bootstrap service gets a `memory` system command. Default is `0` (none).
+---@field mem_soft_limit? integer Soft memory limit in bytes. Crossing it runs a full garbage collection after the dispatch, then `moon.on_memory_pressure` if memory is still above it. Default is `0` (none).
+---@field mem_action? "error"|"kill" At the hard limit, `"error"` (default) only fails the allocation; `"kill"` also stops the service, which a `restart` policy can start again.
+---@field mailbox_capacity? integer Maximum queued messages before `mailbox_policy` applies. Default is `0` (unbounded).
+---@field mailbox_policy? "block"|"drop_newest"|"reject" What happens to `moon.send`/`moon.call` when the mailbox is full. Default is `"block"`.
+---@field loglevel? string Log level for this service only (`DBUG`, `INFO`, `WARN`, `EROR`). Default follows the global level.
+---@field logfile? string Write this service's logs to their own file instead of the main log file.
+---@field restart? "temporary"|"permanent"|"transient" Restart policy. `"permanent"` restarts the service whenever it stops, `"transient"` only after an abnormal exit (init failure, watchdog timeout, memory error); any other Lua error in a dispatch does not stop the service, and a later `moon.quit()` is a normal exit. Being killed by another service is never restarted. Default is `"temporary"` (never).
+---@field max_restarts? integer Give up after this many restarts within `within` seconds. Default is `3`.
+---@field within? integer Restart intensity window in seconds. Default is `5`.
+---@field group? string Join the named service group, addressed with `moon.send_group` / `moon.call_group`. Any number of services can share a group.
+---@field balance? "round_robin"|"least_queue"|"hash" How the group picks a member. Set by the group's first member; later members may omit it. Default is `"round_robin"`.
+---@field record? string Write every message the service handles to this file, to be replayed with `moon_rs --replay file service.lua`.
+---@field budget? integer Messages a shared service handles before it yields its worker thread to other services. `0` means no limit. Default is `128`.
+---@field budget_ms? integer Milliseconds a shared service runs before it yields its worker thread. `0` means no limit. Default is `10`.
+---@field watchdog? { timeout_ms?: integer, strikes?: integer, action?: "interrupt"|"log"|"kill" } Blocked-dispatch thresholds. A dispatch running `timeout_ms` (default 10000, at least 50) is reported to the bootstrap service as a `watchdog` system command once per period; after `strikes` (default 3) reports, `action` applies: `"interrupt"` (default) raises an error in the dispatch, `"kill"` also stops the service, `"log"` only reports.
+---@field priority_lanes? boolean Handle responses, timers and system messages before queued client and socket traffic. Default is `false`.
 
 --- Creates a new service.
 --- @async
@@ -398,6 +450,38 @@ function moon.call(PTYPE, receiver, ...)
     return moon.wait(_send(p.PTYPE, receiver, p.pack(...)), receiver)
 end
 
+--- Sends a message to one member of a service group (see `group` in `service_params`).
+--- The member is chosen by the group's `balance`; for `"hash"` groups the first
+--- message argument is the routing key, so messages about the same key reach the
+--- same member.
+--- @param PTYPE PTYPE @ The protocol type, e.g., "lua".
+--- @param group string @ The group name.
+--- @param ... any @ The message content.
+--- @return integer @ The service ID the message was sent to, `0` if the group has no member.
+function moon.send_group(PTYPE, group, ...)
+    local receiver = _group_pick(group, (...))
+    if receiver == 0 then
+        return 0
+    end
+    moon.send(PTYPE, receiver, ...)
+    return receiver
+end
+
+--- Sends a request to one member of a service group and waits for the response.
+--- The member is chosen as in `moon.send_group`.
+--- @async
+--- @param PTYPE PTYPE @ The protocol type.
+--- @param group string @ The group name.
+--- @param ... any @ The request content.
+--- @return ... @ The response, or `false, err` if the group has no member.
+function moon.call_group(PTYPE, group, ...)
+    local receiver = _group_pick(group, (...))
+    if receiver == 0 then
+        return false, string.format("service group '%s' has no member", group)
+    end
+    return moon.call(PTYPE, receiver, ...)
+end
+
 --- Responds to a request from `moon.call`.
 --- @param PTYPE string @ The protocol type.
 --- @param receiver integer @ The service ID of the receiver.
@@ -553,7 +637,138 @@ system_command._service_exit = function(sender, what)
     end
 end
 
+system_command._loglevel = function(_, lv)
+    _actor_loglevel(lv or "")
+end
+
+--- Get or set a service's log level override.
+--- - `moon.actor_loglevel()` returns the level in effect for the current service.
+--- - `moon.actor_loglevel(lv)` overrides it for the current service only; `""` follows the global level again.
+--- - `moon.actor_loglevel(lv, id)` asks service `id` to apply the override.
+--- @param lv? string @ `DBUG`, `INFO`, `WARN`, `EROR`, or `""`
+--- @param id? integer
+--- @return integer?
+function moon.actor_loglevel(lv, id)
+    if id and id ~= moon.id then
+        moon.send("system", id, "_loglevel", lv)
+        return
+    end
+    if lv == nil then
+        return _actor_loglevel()
+    end
+    _actor_loglevel(lv)
+end
+
+local memory_pressure_handler
+
+system_command._memory_pressure = function(_, memory, limit)
+    if memory_pressure_handler then
+        memory_pressure_handler(tonumber(memory), tonumber(limit))
+    end
+end
+
+system_command._memlimit = function(_, hard, soft)
+    _set_memlimit(tonumber(hard), tonumber(soft))
+end
+
+--- Sets the handler called when the service stays above its `mem_soft_limit`
+--- after a full garbage collection, e.g. to drop caches. It runs as its own
+--- dispatch, once per crossing of the soft limit.
+--- @param fn fun(memory: integer, soft_limit: integer)
+function moon.on_memory_pressure(fn)
+    memory_pressure_handler = fn
+end
+
+--- Changes a service's memory limits at runtime. `0` removes a limit; a nil
+--- `soft` keeps the soft limit.
+--- - `moon.set_memlimit(hard, soft)` applies to the current service and returns the previous hard and soft limits.
+--- - `moon.set_memlimit(hard, soft, id)` asks service `id` to apply them.
+--- @param hard integer @ bytes
+--- @param soft? integer @ bytes
+--- @param id? integer
+--- @return integer?, integer?
+function moon.set_memlimit(hard, soft, id)
+    if id and id ~= moon.id then
+        if soft then
+            moon.send("system", id, "_memlimit", hard, soft)
+        else
+            moon.send("system", id, "_memlimit", hard)
+        end
+        return
+    end
+    return _set_memlimit(hard, soft)
+end
+
+moon.profile = {}
+
+local profiled
+
+--- Starts a sampling CPU profile of a service: `hz` times per second, while the
+--- service is handling a message, its Lua stack is recorded. Works on a running
+--- service, so hot functions can be found in production.
+--- @param id? integer @ Service to profile, default the current service.
+--- @param hz? integer @ Samples per second, 1 to 1000, default 100.
+function moon.profile.start(id, hz)
+    id = id or moon.id
+    _profile_start(id, hz)
+    profiled = id
+end
+
+--- Stops a profile and returns its folded stacks (`outer;inner;leaf count`
+--- lines, as read by flamegraph tools) and the number of samples.
+--- With `path`, also writes the profile there: an SVG flamegraph when `path`
+--- ends in `.svg`, folded stacks otherwise.
+--- @param id? integer @ Default the service last passed to `moon.profile.start`.
+--- @param path? string
+--- @return string, integer
+function moon.profile.stop(id, path)
+    if type(id) == "string" then
+        id, path = nil, id
+    end
+    id = id or profiled or moon.id
+    if id == profiled then
+        profiled = nil
+    end
+    return _profile_stop(id, path)
+end
+
+moon.memprofile = {}
+
+--- Starts profiling the current service's allocations: about one allocation
+--- per `interval` bytes is sampled and attributed to the Lua line that made
+--- it. Use `moon.call("debug", id, "memprofile", "start", interval)` for
+--- another service.
+--- @param interval? integer @ Bytes between samples, default 65536; 1 samples every allocation.
+function moon.memprofile.start(interval)
+    _memprofile_start(interval)
+end
+
+--- Stops the allocation profile and discards it.
+--- @return boolean @ false if no profile was running.
+function moon.memprofile.stop()
+    return _memprofile_stop()
+end
+
+--- Top allocation sites since `moon.memprofile.start`, largest first. Each
+--- entry is `{ site = "source:line", allocated, count, live, live_count }`:
+--- estimated bytes allocated and still held by the site, and the sampled
+--- allocations behind them. A site whose `live` bytes keep growing is a leak
+--- candidate.
+--- @param sort? string @ `"allocated"` (default) or `"live"`; a `"live"` report leaves out sites holding nothing.
+--- @param limit? integer @ Number of sites, default all.
+--- @return table[]
+function moon.memprofile.report(sort, limit)
+    return _memprofile_report(sort, limit)
+end
+
+--- Structured system commands, whose handler receives one table.
+local structured_command = { watchdog = true, memory = true, admin = true, hotfix = true }
+
 --- Registers a system command handler.
+--- The `watchdog` command receives one table describing a blocked dispatch:
+--- `{ event = "slow"|"interrupt"|"kill", id, blocked_ms, strike, strikes, timeout_ms, action, ptype, from, to, session, error? }`.
+--- The `memory` command receives `{ event = "hard_limit", id, name, memory, limit, action }` when a service hit its `memlimit`.
+--- With `hot_reload`, the `hotfix` command receives `{ id, name, module, ok, error? }` from each service that updated a changed module.
 --- @param cmd string @ The command name.
 --- @param fn fun(sender: integer, ...: any) @ The handler function.
 moon.system = function(cmd, fn)
@@ -569,6 +784,14 @@ reg_protocol {
     end,
     dispatch = function(msg)
         local sender, data = _decode(msg, "SZ")
+        local cmd, payload = data:match("^(%w+),(.*)$")
+        if structured_command[cmd] then
+            local func = system_command[cmd]
+            if func then
+                func(sender, json.decode(payload))
+            end
+            return
+        end
         local params = string.split(data, ',')
         local func = system_command[params[1]]
         if func then
@@ -577,12 +800,70 @@ reg_protocol {
     end
 }
 
+--------------------------PubSub-------------
+
+---@type table<string, fun(sender: integer, topic: string, ...: any)>
+local topic_handlers = {}
+
+--- Subscribes the current service to a topic. Topics are dot-separated
+--- segments (`room.12.chat`); a subscription may use `*` to match one segment
+--- and a trailing `#` to match any remaining segments (`room.*.chat`, `room.#`).
+--- Subscribing again to the same topic replaces the handler.
+--- A service whose subscriptions overlap receives each message once, and every
+--- matching handler is called.
+--- @param topic string @ Topic or pattern.
+--- @param fn fun(sender: integer, topic: string, ...: any) @ The message handler.
+function moon.subscribe(topic, fn)
+    _subscribe(topic)
+    topic_handlers[topic] = fn
+end
+
+--- Removes a subscription made with `moon.subscribe`.
+--- @param topic string @ The same topic or pattern passed to `moon.subscribe`.
+--- @return boolean @ false if the service was not subscribed.
+function moon.unsubscribe(topic)
+    topic_handlers[topic] = nil
+    return _unsubscribe(topic)
+end
+
+--- Publishes a message to every service subscribed to `topic`. The message is
+--- packed once and shared by all subscribers. A subscriber whose mailbox is full
+--- misses the message.
+--- @param topic string @ A topic without wildcards.
+--- @param ... any @ The message content.
+--- @return integer @ Number of subscribers the message was delivered to.
+function moon.publish(topic, ...)
+    return _publish(topic, moon.pack(topic, ...))
+end
+
+reg_protocol {
+    name = "pubsub",
+    PTYPE = moon.PTYPE_PUBSUB,
+    pack = moon.pack,
+    dispatch = function(sender, _, topic, ...)
+        -- Handlers may subscribe or unsubscribe, so match before calling any.
+        local handlers = {}
+        local fn = topic_handlers[topic]
+        if fn then
+            handlers[1] = fn
+        end
+        for pattern, handler in pairs(topic_handlers) do
+            if pattern ~= topic and _topic_match(pattern, topic) then
+                handlers[#handlers + 1] = handler
+            end
+        end
+        for i = 1, #handlers do
+            handlers[i](sender, topic, ...)
+        end
+    end
+}
+
 reg_protocol {
     name = "udp",
     PTYPE = moon.PTYPE_SOCKET_UDP,
     pack = function(...) return ... end,
     dispatch = function(_)
-        error("PTYPE_SOCKET_UDP dispatch not implemented")
+        error("PTYPE_SOCKET_UDP dispatch not set, require 'moon.socket' to handle udp datagrams")
     end
 }
 
@@ -618,9 +899,17 @@ reg_protocol {
     PTYPE = moon.PTYPE_TIMER,
     dispatch = function(sender, session, timerid)
         local v = timer_routine[timerid]
-        timer_routine[timerid] = nil
         local trace = timer_profile_trace[timerid]
-        timer_profile_trace[timerid] = nil
+        local left = timer_repeat[timerid]
+        if left and left ~= 1 then
+            if left > 1 then
+                timer_repeat[timerid] = left - 1
+            end
+        else
+            timer_routine[timerid] = nil
+            timer_profile_trace[timerid] = nil
+            timer_repeat[timerid] = nil
+        end
         if not v then
             return
         end
@@ -637,10 +926,14 @@ reg_protocol {
     end
 }
 
---- Removes a timer.
+--- Removes a timer. The timer is cancelled in the timer task, so it no longer
+--- counts as pending and will not fire again.
 --- @param timerid integer @ The ID of the timer to be removed.
 function moon.remove_timer(timerid)
-    timer_routine[timerid] = false
+    timer_routine[timerid] = nil
+    timer_profile_trace[timerid] = nil
+    timer_repeat[timerid] = nil
+    _remove_timer(timerid)
 end
 
 --- Creates a timer that triggers a callback function after waiting for a specified number of milliseconds.
@@ -656,6 +949,23 @@ function moon.timeout(mills, fn, profile_trace)
     return timerid
 end
 
+--- Creates a repeating timer that calls `fn` every `mills` milliseconds, `times` times in total.
+--- The timer is re-armed natively; missed ticks are not replayed when the service falls behind.
+--- @param mills integer @ The interval in milliseconds, must be greater than 0.
+--- @param times integer @ The number of fires, or `-1` to repeat until `moon.remove_timer`.
+--- @param fn fun(timerid: integer) @ The callback function to be triggered on each fire.
+--- @param profile_trace? string @ Trace info for timer profiling.
+--- @return integer @ Returns the timer ID. You can use `moon.remove_timer` to remove the timer.
+function moon.repeated(mills, times, fn, profile_trace)
+    local timerid = _timeout(mills, times)
+    timer_routine[timerid] = fn
+    timer_profile_trace[timerid] = profile_trace
+    if times ~= 1 then
+        timer_repeat[timerid] = times
+    end
+    return timerid
+end
+
 --- Suspends the current coroutine for at least `mills` milliseconds.
 --- @async
 --- @param mills integer @ The number of milliseconds to suspend.
@@ -667,7 +977,9 @@ function moon.sleep(mills, profile_trace)
     timer_profile_trace[timerid] = profile_trace
     local id, reason = co_yield()
     if id ~= timerid then
-        timer_routine[timerid] = false
+        timer_routine[timerid] = nil
+        timer_profile_trace[timerid] = nil
+        _remove_timer(timerid)
         return false, reason
     end
     return true
@@ -696,6 +1008,28 @@ debug_command.state = function()
     return string.format("coroutine: running %d free %d", running_num, free_num)
 end
 
+--- Queue wait and dispatch latency per protocol, keyed by protocol name.
+--- `moon.call("debug", id, "latency", true)` also resets the histograms.
+debug_command.latency = function(reset)
+    local res = {}
+    for ptype, v in pairs(moon.latency(reset)) do
+        local p = protocol[ptype]
+        v.ptype = ptype
+        res[p and p.name or tostring(ptype)] = v
+    end
+    return res
+end
+
+--- Allocation profile of the service: `moon.call("debug", id, "memprofile", "start", interval)`,
+--- `"report", sort, limit` and `"stop"`.
+debug_command.memprofile = function(cmd, ...)
+    local fn = moon.memprofile[cmd]
+    if not fn then
+        return "unknown memprofile cmd " .. tostring(cmd)
+    end
+    return fn(...)
+end
+
 reg_protocol {
     name = "debug",
     PTYPE = moon.PTYPE_DEBUG,
@@ -711,4 +1045,115 @@ reg_protocol {
     end
 }
 
+--------------------------ADMIN----------------------------
+
+---@type table<string, fun(...: any): any ...>
+local admin_command = {}
+
+admin_command.debug = function(cmd, ...)
+    local func = debug_command[cmd]
+    if not func then
+        error("unknown debug cmd " .. tostring(cmd), 0)
+    end
+    return func(...)
+end
+
+--- Reads globals, plus `moon`: `eval <id> moon.name` prints the service name.
+--- Variables assigned by `eval` stay here, for later `eval`s.
+local admin_env = setmetatable({ moon = moon }, { __index = _G })
+
+--- Runs `code` as an expression first, so its value is printed.
+admin_command.eval = function(code)
+    local func = load("return " .. code, "=admin", "t", admin_env)
+    if not func then
+        local err
+        func, err = load(code, "=admin", "t", admin_env)
+        if not func then
+            error(err, 0)
+        end
+    end
+    return func()
+end
+
+admin_command.hotfix = function(name, updatename)
+    local ok, err = require("hotfix").update(name, updatename)
+    if not ok then
+        error(err, 0)
+    end
+    return "ok"
+end
+
+local function admin_output(res)
+    local out = {}
+    for i = 2, res.n do
+        local v = res[i]
+        if type(v) == "table" then
+            local ok, s = pcall(json.encode, v)
+            v = ok and s or v
+        end
+        out[#out + 1] = tostring(v)
+    end
+    return table.concat(out, "\t")
+end
+
+--- A command from the admin console (`admin_token`), as
+--- `{ session, cmd, args }`. The output goes back to the console as text, or
+--- as an error when the command failed.
+system_command.admin = function(sender, req)
+    moon.async(function()
+        local func = admin_command[req.cmd]
+        local res
+        if func then
+            res = table.pack(pcall(func, table.unpack(req.args)))
+        else
+            res = table.pack(false, "unknown admin cmd " .. tostring(req.cmd))
+        end
+        if res[1] then
+            moon.raw_send("text", sender, admin_output(res), -req.session)
+        else
+            moon.raw_send("error", sender, tostring(res[2]), -req.session)
+        end
+    end)
+end
+
+--------------------------HOT RELOAD----------------------------
+
+if _watch_module() then
+    local hotfix = require("hotfix")
+    local search_file = package.searchers[2]
+    hotfix.addsearcher(search_file)
+
+    --- Modules required from a file are watched, and tracked by hotfix.
+    package.searchers[2] = function(name, ...)
+        local loader, path = search_file(name, ...)
+        if type(loader) == "function" and _watch_module(name, path) then
+            hotfix.track(name, loader)
+        end
+        return loader, path
+    end
+
+    local function update(name)
+        local ok, err = hotfix.update(name)
+        if not ok then
+            error(err, 0)
+        end
+    end
+
+    --- A watched module's file changed: patch it and report to bootstrap.
+    system_command._hotfix = function(_, name)
+        if package.loaded[name] == nil then
+            return
+        end
+        local ok, err = pcall(update, name)
+        if ok then
+            moon.info(string.format("hotfix module '%s'", name))
+        else
+            err = tostring(err)
+            moon.error(string.format("hotfix module '%s' failed: %s", name, err))
+        end
+        local report = { id = moon.id, name = moon.name, module = name, ok = ok, error = err }
+        moon.send("system", 1, "hotfix", json.encode(report))
+    end
+end
+
 return moon
@@ -18,6 +18,9 @@ local event_callbacks = {}
 ---@type table<integer, fun(fd: integer, addr: string)?>
 local accept_callbacks = {}
 
+---@type table<integer, fun(data: string, from: string)?>
+local udp_callbacks = {}
+
 local ACCEPT_EVENT = 2
 local MESSAGE_EVENT = 3
 local CLOSE_EVENT = 4
@@ -72,6 +75,13 @@ moon.register_protocol {
     end
 }
 
+moon.dispatch("udp", function(_, _, fd, data, from)
+    local cb = udp_callbacks[fd]
+    if cb then
+        cb(data, from)
+    end
+end)
+
 ---@class socket
 local socket = {
     ---@type fun(fd: integer, data: string|buffer_ptr, max_write_capacity?: integer, close?: boolean) @ Writes data to the socket.
@@ -88,6 +98,7 @@ local socket = {
 ---@param fd integer @ The file descriptor to close.
 function socket.close(fd)
     socket_pool[fd] = nil
+    udp_callbacks[fd] = nil
     core.close(fd)
 end
 
@@ -103,7 +114,8 @@ end
 --- Multiple listeners can coexist, each with its own callback.
 --- @param addr string @ The address to listen on (e.g. "0.0.0.0:8080").
 --- @param on_accept fun(fd: integer, addr: string) @ Callback invoked for each accepted connection.
---- @param opts? table @ `{ max_connections? }` cap on concurrently accepted connections (default 100000).
+--- @param opts? table @ `{ max_connections?, tls? }`. `max_connections` caps concurrently accepted connections (default 100000);
+--- `tls = { cert, key }` (PEM text or file paths) serves TLS, accepted fds then read/write plaintext as usual.
 ---@return integer|false, string? @ Returns the listen fd if successful, or `false` and an error message.
 function socket.listen(addr, on_accept, opts)
     local fd, err = core.listen(addr, opts)
@@ -116,18 +128,34 @@ function socket.listen(addr, on_accept, opts)
 end
 
 --- Connects to a remote address.
+--- `opts.tls = { sni?, ca? }` wraps the connection in TLS: `sni` defaults to the host of `addr`,
+--- and `ca` is PEM text or a file path, defaulting to the bundled webpki roots.
 --- @async
 --- @param addr string @ The remote address in the format of "host:port".
 --- @param timeout? integer @ Optional. The connect timeout in milliseconds. Default is 5000ms.
+--- @param opts? { tls?: { sni?: string, ca?: string } } @ Optional.
 ---@return integer|false, string? @ Returns the file descriptor of the new connection if successful, or `false` and an error message if failed.
-function socket.connect(addr, timeout)
-    local fd, err = moon.wait(core.connect(addr, timeout))
+function socket.connect(addr, timeout, opts)
+    local fd, err = moon.wait(core.connect(addr, timeout, opts))
     if fd then
         socket_pool[fd] = true
     end
     return fd, err
 end
 
+--- Connects to `host` and `port`, see `socket.connect`. An IPv6 host needs no brackets.
+--- @async
+--- @param host string @ The remote host name or IP address.
+--- @param port integer @ The remote port.
+--- @param opts? { timeout?: integer, tls?: { sni?: string, ca?: string } } @ Optional. `timeout` is the connect timeout in milliseconds.
+---@return integer|false, string? @ Returns the file descriptor of the new connection if successful, or `false` and an error message if failed.
+function socket.connect_host(host, port, opts)
+    if host:find(":", 1, true) then
+        host = "[" .. host .. "]"
+    end
+    return socket.connect(host .. ":" .. tostring(port), opts and opts.timeout, opts)
+end
+
 --- Reads data from a socket (TCP raw protocol).
 --- @async
 --- @param fd integer @ The file descriptor of the socket.
@@ -155,6 +183,47 @@ function socket.read_frame(fd, timeout)
     return moon.wait(core.read_frame(fd, timeout))
 end
 
+--- Binds a UDP socket. Every inbound datagram invokes `on_message(data, from_addr)`.
+--- @param on_message fun(data: string, from: string) @ Callback for each received datagram.
+--- @param addr? string @ Local address to bind (default "0.0.0.0:0", an ephemeral port).
+---@return integer|false, string? @ Returns the udp fd if successful, or `false` and an error message.
+function socket.udp(on_message, addr)
+    local fd, err = core.udp_bind(addr)
+    if not fd then
+        return fd, err
+    end
+    socket_pool[fd] = true
+    udp_callbacks[fd] = on_message
+    return fd
+end
+
+--- Sets the default peer of a UDP socket. Afterwards only datagrams from that
+--- peer are delivered and `socket.udp_send` can omit the address.
+--- @async
+--- @param fd integer @ The udp fd.
+--- @param addr string @ The peer address in the format of "host:port".
+---@return integer|false, string? @ Returns the fd if successful, or `false` and an error message.
+function socket.udp_connect(fd, addr)
+    return moon.wait(core.udp_connect(fd, addr))
+end
+
+--- Sends one datagram to `addr`.
+--- @param fd integer @ The udp fd.
+--- @param addr string @ The destination address in the format of "ip:port"; host names are not resolved here.
+--- @param data string|buffer_ptr @ The datagram payload (max 65507 bytes).
+---@return boolean|false, string? @ Returns true if queued, or `false` and an error message.
+function socket.sendto(fd, addr, data)
+    return core.udp_sendto(fd, addr, data)
+end
+
+--- Sends one datagram to the peer set by `socket.udp_connect`.
+--- @param fd integer @ The udp fd.
+--- @param data string|buffer_ptr @ The datagram payload (max 65507 bytes).
+---@return boolean|false, string? @ Returns true if queued, or `false` and an error message.
+function socket.udp_send(fd, data)
+    return core.udp_sendto(fd, nil, data)
+end
+
 ---Register a callback for socket events.
 ---@param name socket_event The socket event type to register for
 ---@param cb fun(fd: integer, ...) The callback function to handle the event